@@ -0,0 +1,49 @@
+// Scheduling a task graph: every node is a task that waits for the results
+// of its predecessors, which are shared futures, since a node can have
+// several successors. Nodes are spawned in topological order, so that every
+// predecessor's future exists by the time a node is spawned.
+//
+//     a
+//    / \
+//   b   c
+//    \ / \
+//     d   e
+//      \ /
+//       f
+//
+//     cargo run --release --example dag
+
+use rusty_tasking::spawn;
+use rusty_tasking::channel::one_shot_channel;
+use rusty_tasking::future::SharedFuture;
+use rusty_tasking::runtime::Runtime;
+use std::collections::HashMap;
+
+fn main() {
+    let graph: &[(&str, &[&str])] = &[
+        ("a", &[]),
+        ("b", &["a"]),
+        ("c", &["a"]),
+        ("d", &["b", "c"]),
+        ("e", &["c"]),
+        ("f", &["d", "e"]),
+    ];
+
+    let runtime = Runtime::init(4);
+
+    let mut nodes: HashMap<&str, SharedFuture<u64>> = HashMap::new();
+    for &(name, preds) in graph {
+        let preds: Vec<_> = preds.iter().map(|p| nodes[p].clone()).collect();
+        // A node's value is one more than the sum of its predecessors'
+        let node = spawn!(one_shot_channel, {
+            1 + preds.iter().map(SharedFuture::wait).sum::<u64>()
+        });
+        nodes.insert(name, node.shared());
+    }
+
+    for &(name, _) in graph {
+        println!("{} = {}", name, nodes[name].wait());
+    }
+    let stats = runtime.join();
+    println!("{} tasks", stats.num_tasks_executed.get());
+}
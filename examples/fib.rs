@@ -0,0 +1,31 @@
+// Parallel Fibonacci with a serial cutoff: below `CUTOFF`, spawning a task
+// costs more than it saves, so we recurse serially.
+//
+//     cargo run --release --example fib -- 35
+
+use rusty_tasking::spawn;
+use rusty_tasking::future::Future;
+use std::env;
+
+const CUTOFF: u64 = 20;
+
+fn fib(n: u64) -> u64 {
+    if n < 2 { return n; }
+    fib(n - 1) + fib(n - 2)
+}
+
+fn parfib(n: u64) -> u64 {
+    if n < CUTOFF { return fib(n); }
+    // A lazy future costs nothing unless its task is stolen
+    let mut x = Future::lazy();
+    let _ = spawn!(&mut x, parfib(n - 1));
+    let y = parfib(n - 2);
+    x.wait() + y
+}
+
+fn main() {
+    let n = env::args().nth(1).map_or(30, |n| n.parse().expect("Usage: fib [N]"));
+    let (result, stats) = rusty_tasking::run_with(4, || parfib(n));
+    println!("fib({}) = {}", n, result);
+    println!("{}", stats);
+}
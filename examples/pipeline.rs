@@ -0,0 +1,42 @@
+// A three-stage pipeline with backpressure: numbers are squared, filtered,
+// and summed up. Every stage holds at most a few items at a time, so a slow
+// stage throttles the stages before it instead of letting work pile up.
+//
+//     cargo run --release --example pipeline
+
+use rusty_tasking::pipeline::stage;
+use rusty_tasking::runtime::Runtime;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+fn main() {
+    let runtime = Runtime::init(4);
+
+    let total = Arc::new(AtomicU64::new(0));
+    let sum = {
+        let total = Arc::clone(&total);
+        stage(8, move |x: u64| {
+            total.fetch_add(x, Relaxed);
+        })
+    };
+    let filter = {
+        let sum = sum.clone();
+        stage(8, move |x: u64| if x.is_multiple_of(3) { sum.send(x) })
+    };
+    let square = {
+        let filter = filter.clone();
+        stage(8, move |x: u64| filter.send(x * x))
+    };
+
+    for x in 0..10_000 {
+        square.send(x);
+    }
+    // Drain the stages in order
+    square.flush();
+    filter.flush();
+    sum.flush();
+
+    println!("Sum of squares divisible by 3: {}", total.load(Relaxed));
+    let stats = runtime.join();
+    println!("{} tasks", stats.num_tasks_executed.get());
+}
@@ -0,0 +1,60 @@
+// Synthetic producer-consumer workloads: SPC (a single producer spawns all
+// tasks) and BPC (a chain of producers, each spawning a batch of consumers
+// and the next producer). Both stress how fast idle workers find tasks.
+//
+//     cargo run --release --example producers
+
+use rusty_tasking::{finish, scoped_spawn};
+use rusty_tasking::runtime::{EmptyDequeResponse, Runtime};
+use std::hint;
+use std::time::{Duration, Instant};
+
+const NUM_TASKS: u32 = 10_000;
+const LEVELS: u32 = 100;
+const TASK_LENGTH: Duration = Duration::from_micros(20);
+
+// A consumer: spin for `TASK_LENGTH`, standing in for real work
+fn consume() {
+    let start = Instant::now();
+    while start.elapsed() < TASK_LENGTH {
+        hint::spin_loop();
+    }
+}
+
+// Single Producer Consumer: one producer spawns all tasks
+fn spc() {
+    for _ in 0..NUM_TASKS {
+        scoped_spawn!(consume());
+    }
+}
+
+// Bouncing Producer Consumer: every producer spawns the next producer,
+// followed by its share of the tasks (producers count towards the total)
+fn bpc(level: u32) {
+    if level == 0 { return; }
+    scoped_spawn!(bpc(level - 1));
+    for _ in 0..NUM_TASKS / LEVELS - 1 {
+        scoped_spawn!(consume());
+    }
+}
+
+fn main() {
+    let runtime = Runtime::init(4);
+    let start = Instant::now();
+    finish! { spc(); }
+    let elapsed = start.elapsed();
+    let stats = runtime.join();
+    println!("SPC: {} tasks in {:?}", stats.num_tasks_executed.get(), elapsed);
+
+    // Producers are busy spawning, so let them ask thieves to come back
+    // rather than send them elsewhere
+    let runtime = Runtime::builder()
+        .num_workers(4)
+        .empty_deque_response(EmptyDequeResponse::Later { patience: 8 })
+        .init();
+    let start = Instant::now();
+    finish! { bpc(LEVELS); }
+    let elapsed = start.elapsed();
+    let stats = runtime.join();
+    println!("BPC: {} tasks in {:?}", stats.num_tasks_executed.get(), elapsed);
+}
@@ -0,0 +1,39 @@
+// Parallel reductions, two ways: recursively splitting a range until it is
+// small enough to sum up serially, and collecting the results of a flat set
+// of tasks through a single channel.
+//
+//     cargo run --release --example reduce
+
+use rusty_tasking::{divide_and_conquer, spawn_collect};
+use rusty_tasking::runtime::Runtime;
+use std::ops::Range;
+
+const N: u64 = 10_000_000;
+const GRAIN: u64 = 100_000;
+
+fn main() {
+    let runtime = Runtime::init(4);
+
+    let sum = divide_and_conquer(
+        0..N,
+        |r: &Range<u64>| r.end - r.start <= GRAIN,
+        |r| r.sum::<u64>(),
+        |r| {
+            let mid = r.start + (r.end - r.start) / 2;
+            (r.start..mid, mid..r.end)
+        },
+        |x, y| x + y,
+    );
+    println!("Sum of 0..{}: {}", N, sum);
+
+    // One task per chunk
+    let chunks = N.div_ceil(GRAIN) as usize;
+    let partial = spawn_collect(chunks, |i| {
+        let start = i as u64 * GRAIN;
+        (start..N.min(start + GRAIN)).sum::<u64>()
+    });
+    assert_eq!(partial.wait().into_iter().sum::<u64>(), sum);
+
+    let stats = runtime.join();
+    println!("{}", stats);
+}
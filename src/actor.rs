@@ -0,0 +1,162 @@
+use crate::affinity::Family;
+use crate::atomic;
+use crate::task::Async;
+use crate::worker::Worker;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender, Receiver};
+
+// Actors
+//
+// An actor owns some state and processes the messages sent to its mailbox one
+// after another. Whenever messages arrive at an idle actor, a task is pushed
+// onto the current worker that processes all messages received so far (one
+// batch). Messages arriving in the meantime are handled by the next batch,
+// which is scheduled as a new task, so that busy actors don't monopolize a
+// worker. Batches form a task family: the next batch goes to the worker that
+// ran the last one, where the actor's state is likely still cached, unless
+// that worker is busy (see `Family`).
+
+pub struct Actor<M> {
+    inner: Arc<Inner<M>>,
+}
+
+struct Inner<M> {
+    mailbox: Mutex<Sender<M>>,
+    state: Mutex<State<M>>,
+    // Number of messages sent but not yet processed
+    pending: atomic::Count,
+    family: Family,
+}
+
+struct State<M> {
+    messages: Receiver<M>,
+    handler: Box<dyn FnMut(M) + Send>,
+}
+
+impl<M> Actor<M> where M: Send + 'static {
+    pub fn new<S, F>(mut state: S, mut handler: F) -> Self
+    where S: Send + 'static, F: FnMut(&mut S, M) + Send + 'static {
+        let (sender, receiver) = channel();
+        Self {
+            inner: Arc::new(Inner {
+                mailbox: Mutex::new(sender),
+                state: Mutex::new(State {
+                    messages: receiver,
+                    handler: Box::new(move |msg| handler(&mut state, msg)),
+                }),
+                pending: atomic::Count::new(0),
+                family: Family::new(),
+            }),
+        }
+    }
+
+    pub fn send(&self, msg: M) {
+        self.inner.mailbox.lock().unwrap().send(msg).unwrap();
+        // Whoever finds the mailbox empty schedules the next batch
+        if self.inner.pending.inc() == 0 {
+            schedule(Arc::clone(&self.inner));
+        }
+    }
+
+    // Number of messages waiting to be processed
+    pub fn pending(&self) -> u32 {
+        self.inner.pending.get()
+    }
+
+    // Help until all messages sent so far have been processed
+    pub fn flush(&self) {
+        Worker::current().help_until(|| self.inner.pending.get() == 0);
+    }
+}
+
+impl<M> Clone for Actor<M> {
+    fn clone(&self) -> Self {
+        Self { inner: Arc::clone(&self.inner) }
+    }
+}
+
+fn schedule<M>(inner: Arc<Inner<M>>) where M: Send + 'static {
+    let actor = Arc::clone(&inner);
+    let task = Async::new(move || {
+        inner.family.enter();
+        let n = inner.pending.get();
+        {
+            let mut state = inner.state.lock().unwrap();
+            for _ in 0..n {
+                let msg = state.messages.recv().unwrap();
+                (state.handler)(msg);
+            }
+        }
+        // More messages have arrived while processing this batch
+        if inner.pending.sub(n) != n {
+            schedule(Arc::clone(&inner));
+        }
+    }, None);
+    Worker::current().push_to_family(&actor.family, task);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+    use super::*;
+
+    #[test]
+    fn counter() {
+        let runtime = Runtime::init(3);
+
+        let total = Arc::new(AtomicU32::new(0));
+        let counter = {
+            let total = Arc::clone(&total);
+            Actor::new(0, move |count: &mut u32, n: u32| {
+                *count += n;
+                total.store(*count, Relaxed);
+            })
+        };
+
+        for i in 1..=10 {
+            counter.send(i);
+        }
+
+        finish! {
+            for _ in 0..10 {
+                let counter = counter.clone();
+                scoped_spawn! {
+                    for i in 1..=10 {
+                        counter.send(i);
+                    }
+                }
+            }
+        }
+
+        counter.flush();
+        assert_eq!(counter.pending(), 0);
+        assert_eq!(total.load(Relaxed), 11 * 55);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn ping_pong() {
+        let runtime = Runtime::init(2);
+
+        let pongs = Arc::new(AtomicU32::new(0));
+        let pong = {
+            let pongs = Arc::clone(&pongs);
+            Actor::new((), move |_, ()| { pongs.fetch_add(1, Relaxed); })
+        };
+        let ping = Actor::new(pong.clone(), |pong: &mut Actor<()>, n: u32| {
+            for _ in 0..n {
+                pong.send(());
+            }
+        });
+
+        ping.send(5);
+        ping.send(10);
+        ping.flush();
+        pong.flush();
+        assert_eq!(pongs.load(Relaxed), 15);
+
+        let _stats = runtime.join();
+    }
+}
@@ -0,0 +1,199 @@
+use crate::worker::Worker;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+// Recurring task families
+//
+// Recurring tasks, such as the message batches of an actor, tend to touch
+// the same data on every activation. A family remembers which worker ran
+// its last activation, so that the next one is scheduled there by default,
+// where the data is likely still cached (see `Worker::push_to_family`). An
+// activation migrates only if that worker has noticeably more tasks queued
+// than the worker scheduling it (see `Config::affinity_slack`).
+//
+// Data locality
+//
+// Similarly, a `Located` value remembers which worker produced it, and
+// tasks spawned with `spawn_located!` go to the worker that produced all of
+// their `Located` inputs, where the data is likely still cached, within the
+// same slack. Locality is opt-in: a task's `Located` inputs must be listed,
+// since `spawn!` can't tell which values a task captures.
+
+// No activation has run yet
+const NONE: usize = usize::MAX;
+
+#[derive(Debug)]
+pub struct Family {
+    last_worker: AtomicUsize,
+}
+
+impl Family {
+    pub fn new() -> Self {
+        Self { last_worker: AtomicUsize::new(NONE) }
+    }
+
+    // The worker that ran the last activation, if any
+    pub fn last_worker(&self) -> Option<usize> {
+        match self.last_worker.load(Relaxed) {
+            NONE => None,
+            id => Some(id),
+        }
+    }
+
+    pub fn record(&self, worker: usize) {
+        self.last_worker.store(worker, Relaxed);
+    }
+
+    // Record that the current worker runs an activation, at the start of
+    // every activation
+    pub fn enter(&self) {
+        self.record(Worker::current().id);
+    }
+}
+
+// A value tagged with the worker that produced it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Located<T> {
+    value: T,
+    worker: Option<usize>,
+}
+
+impl<T> Located<T> {
+    // Tag a value with the current worker, if any
+    pub fn new(value: T) -> Self {
+        Self { value, worker: Worker::try_current().map(|worker| worker.id) }
+    }
+
+    // Tag a value with the given worker
+    pub fn at(worker: usize, value: T) -> Self {
+        Self { value, worker: Some(worker) }
+    }
+
+    pub fn worker(&self) -> Option<usize> {
+        self.worker
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for Located<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Located<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+// The worker that all given locations point at, if there is one
+pub fn colocated<I>(locations: I) -> Option<usize>
+where I: IntoIterator<Item = Option<usize>> {
+    let mut locations = locations.into_iter();
+    let first = locations.next()??;
+    locations.all(|location| location == Some(first)).then_some(first)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::channel::one_shot_channel;
+    use crate::runtime::Runtime;
+    use crate::task::Async;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering::{Acquire, Release}};
+    use super::*;
+
+    // Push an activation of `family` that remembers where it ran
+    fn activate(family: &Arc<Family>, done: &Arc<AtomicBool>) {
+        let task = {
+            let family = Arc::clone(family);
+            let done = Arc::clone(done);
+            Async::detached(move || {
+                family.enter();
+                done.store(true, Release);
+            })
+        };
+        Worker::current().push_to_family(family, task);
+    }
+
+    #[test]
+    fn sticky_activations() {
+        let runtime = Runtime::builder().num_workers(2).affinity_slack(0).init();
+        let leader = Worker::current();
+
+        let family = Arc::new(Family::new());
+        family.record(1);
+        let done = Arc::new(AtomicBool::new(false));
+        activate(&family, &done);
+        leader.help_until(|| done.load(Acquire));
+        assert_eq!(family.last_worker(), Some(1));
+        assert_stat_eq!(leader.stats.num_sticky_tasks.get(), 1);
+
+        // Keep worker 1 busy with queued tasks, so that the next activation
+        // migrates
+        let release = Arc::new(AtomicBool::new(false));
+        let busy = {
+            let release = Arc::clone(&release);
+            Async::detached(move || {
+                for _ in 0..3 {
+                    spawn!();
+                }
+                while !release.load(Acquire) {}
+            })
+        };
+        leader.push_to_worker(1, busy);
+        // Worker 1 may be waiting for us to answer its steal request
+        while leader.coworker(1).unwrap().load().queued() < 3 {
+            leader.try_handle_steal_request();
+        }
+        done.store(false, Release);
+        activate(&family, &done);
+        release.store(true, Release);
+        leader.help_until(|| done.load(Acquire));
+        assert_stat_eq!(leader.stats.num_family_migrations.get(), 1);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn colocation() {
+        assert_eq!(colocated([Some(1), Some(1)]), Some(1));
+        assert_eq!(colocated([Some(1), Some(2)]), None);
+        assert_eq!(colocated([Some(1), None]), None);
+        assert_eq!(colocated([None]), None);
+        assert_eq!(colocated([]), None);
+    }
+
+    #[test]
+    fn located_tasks() {
+        // Worker 2 is on its own, so nobody steals its tasks
+        let runtime = Runtime::builder()
+            .partition("main", 2)
+            .partition("data", 1)
+            .init();
+        let leader = Worker::current();
+        assert_eq!(Located::new(()).worker(), Some(0));
+
+        let a = Located::at(2, vec![1, 2, 3]);
+        let b = Located::at(2, 4);
+        let mut f = spawn_located!([a, b], one_shot_channel, {
+            (Worker::current().id, a.iter().sum::<i32>() + *b)
+        });
+        assert_eq!(f.wait(), (2, 10));
+        assert_stat_eq!(leader.stats.num_located_tasks.get(), 1);
+
+        // Inputs from different workers don't pin the task anywhere
+        let c = Located::at(1, 5);
+        let b = Located::at(2, 4);
+        spawn_located!([b, c], assert_eq!(*b + *c, 9));
+        assert_stat_eq!(leader.stats.num_located_tasks.get(), 1);
+
+        let _stats = runtime.join();
+    }
+}
@@ -0,0 +1,286 @@
+use crate::task::Async;
+use crate::worker::Worker;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering::{Acquire, Release}};
+use std::thread;
+
+// Below this length, `par_merge_sort` sorts serially: spawning a task and
+// merging its result costs about as much as sorting a few thousand small
+// elements. `Runtime::calibrate` adjusts the cutoff to the machine, per
+// runtime (see `Config::serial_cutoff`).
+pub const DEFAULT_SERIAL_CUTOFF: usize = 4096;
+
+// The current runtime's cutoff
+pub fn serial_cutoff() -> usize {
+    Worker::try_current().map_or(DEFAULT_SERIAL_CUTOFF, |worker| worker.scheduler().serial_cutoff)
+}
+
+// Run `a` and `b`, potentially in parallel, and return both results. `a` is
+// spawned as a task, `b` is run by the current task, which then waits for
+// `a` (and helps in the meantime). Unlike tasks spawned with `spawn!`, `a`
+// and `b` may borrow from the caller, since `join` doesn't return before
+// both are done. Without a current worker, `a` and `b` are run one after
+// the other. If `a` panics, the panic is raised again in the caller once
+// `b` is done.
+pub fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where A: FnOnce() -> RA + Send, B: FnOnce() -> RB, RA: Send {
+    let worker = match Worker::try_current() {
+        Some(worker) => worker,
+        None => return (a(), b()),
+    };
+
+    let mut result = None;
+    let slot = Slot(&mut result as *mut Option<thread::Result<RA>>);
+    let done = Arc::new(AtomicBool::new(false));
+    let task = {
+        let done = Arc::clone(&done);
+        move || {
+            // Capture all of `slot`, not just the (non-`Send`) pointer
+            let slot = slot;
+            // Catch a panic, or `done` would never be set
+            let ra = panic::catch_unwind(AssertUnwindSafe(a));
+            unsafe { *slot.0 = Some(ra); }
+            done.store(true, Release);
+        }
+    };
+    // The task may borrow from the caller, but it's done before we return,
+    // even if `b` panics (see `Wait`), so pretending that it doesn't is safe
+    worker.push(unsafe { Async::new_unchecked(task) });
+
+    let wait = Wait { worker, done: &done };
+    let rb = b();
+    drop(wait);
+    match result.take().unwrap() {
+        Ok(ra) => (ra, rb),
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+// Where the spawned half of `join` puts its result
+struct Slot<T>(*mut Option<T>);
+
+unsafe impl<T: Send> Send for Slot<T> {}
+
+// Waits for the spawned half of `join` when dropped
+struct Wait<'a> {
+    worker: &'a Worker,
+    done: &'a AtomicBool,
+}
+
+impl Drop for Wait<'_> {
+    fn drop(&mut self) {
+        self.worker.help_until(|| self.done.load(Acquire));
+    }
+}
+
+// Stable parallel merge sort: sort both halves in parallel (see `join`),
+// then merge them. Recursion stops at `serial_cutoff()`, where `slice::sort`
+// takes over.
+pub fn par_merge_sort<T: Ord + Send>(v: &mut [T]) {
+    if v.len() <= serial_cutoff() {
+        v.sort();
+        return;
+    }
+    let mid = v.len() / 2;
+    let (left, right) = v.split_at_mut(mid);
+    join(|| par_merge_sort(left), || par_merge_sort(right));
+    merge(v, mid);
+}
+
+// Merge the sorted runs `v[..mid]` and `v[mid..]`. The left run is moved to
+// a buffer and merged back into `v` along with the right run, which never
+// gets overwritten before it's been read.
+fn merge<T: Ord>(v: &mut [T], mid: usize) {
+    // Zero-sized elements have nothing to move (and pointer differences
+    // would be undefined, see `Hole`)
+    if mem::size_of::<T>() == 0 {
+        return;
+    }
+    let len = v.len();
+    let mut buf = Vec::with_capacity(mid);
+    unsafe {
+        let v = v.as_mut_ptr();
+        ptr::copy_nonoverlapping(v, buf.as_mut_ptr(), mid);
+        let mut hole = Hole { start: buf.as_mut_ptr(), end: buf.as_mut_ptr().add(mid), dest: v };
+        let mut right = v.add(mid);
+        let end = v.add(len);
+        while hole.start < hole.end && right < end {
+            // Take from the left run on ties, to keep the sort stable
+            if *right < *hole.start {
+                ptr::copy_nonoverlapping(right, hole.dest, 1);
+                right = right.add(1);
+            } else {
+                ptr::copy_nonoverlapping(hole.start, hole.dest, 1);
+                hole.start = hole.start.add(1);
+            }
+            hole.dest = hole.dest.add(1);
+        }
+        // Dropping `hole` moves what's left of the left run into place (what's
+        // left of the right run is already there)
+    }
+}
+
+// The part of the left run that hasn't been merged yet, and where it goes.
+// If a comparison panics, dropping the hole still leaves every element in
+// `v` exactly once.
+struct Hole<T> {
+    start: *mut T,
+    end: *mut T,
+    dest: *mut T,
+}
+
+impl<T> Drop for Hole<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let n = self.end.offset_from(self.start) as usize;
+            ptr::copy_nonoverlapping(self.start, self.dest, n);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use std::cmp::Ordering;
+    use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+    use std::panic::{self, AssertUnwindSafe};
+    use super::*;
+
+    // Pseudo-random numbers below `n` (xorshift)
+    fn random(len: usize, n: u64) -> Vec<u64> {
+        let mut x = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x % n
+            })
+            .collect()
+    }
+
+    #[test]
+    fn join_borrows() {
+        let runtime = Runtime::init(2);
+
+        let v: Vec<u64> = (1..=100).collect();
+        let (left, right) = v.split_at(50);
+        let (x, y) = join(|| left.iter().sum::<u64>(), || right.iter().sum::<u64>());
+        assert_eq!(x + y, 5050);
+
+        let _stats = runtime.join();
+        // Without a worker
+        assert_eq!(join(|| 1, || 2), (1, 2));
+    }
+
+    #[test]
+    fn join_panic() {
+        let runtime = Runtime::init(2);
+
+        let result = panic::catch_unwind(|| join(|| -> u64 { panic!("a") }, || 2));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"a"));
+        assert_eq!(join(|| 1, || 2), (1, 2));
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn merge_sort() {
+        let runtime = Runtime::init(3);
+
+        for len in [0, 1, DEFAULT_SERIAL_CUTOFF, DEFAULT_SERIAL_CUTOFF + 1, 10 * DEFAULT_SERIAL_CUTOFF + 3] {
+            let mut v = random(len, 1000);
+            let mut expected = v.clone();
+            expected.sort();
+            par_merge_sort(&mut v);
+            assert_eq!(v, expected);
+        }
+        let mut units = vec![(); 3 * DEFAULT_SERIAL_CUTOFF];
+        par_merge_sort(&mut units);
+        assert_eq!(units.len(), 3 * DEFAULT_SERIAL_CUTOFF);
+
+        let _stats = runtime.join();
+    }
+
+    // Ordered by key only
+    #[derive(Debug)]
+    struct Item { key: u64, id: usize }
+
+    impl PartialEq for Item {
+        fn eq(&self, other: &Self) -> bool { self.key == other.key }
+    }
+
+    impl Eq for Item {}
+
+    impl PartialOrd for Item {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+    }
+
+    impl Ord for Item {
+        fn cmp(&self, other: &Self) -> Ordering { self.key.cmp(&other.key) }
+    }
+
+    #[test]
+    fn merge_sort_is_stable() {
+        let runtime = Runtime::init(2);
+
+        let mut v: Vec<Item> = random(5 * DEFAULT_SERIAL_CUTOFF, 10)
+            .into_iter()
+            .enumerate()
+            .map(|(id, key)| Item { key, id })
+            .collect();
+        par_merge_sort(&mut v);
+        assert!(v.windows(2).all(|w| (w[0].key, w[0].id) < (w[1].key, w[1].id)));
+
+        let _stats = runtime.join();
+    }
+
+    // Counts drops, and panics when compared after `PANIC_AFTER` comparisons
+    struct Fragile(u64);
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    static COMPARISONS: AtomicUsize = AtomicUsize::new(0);
+    const PANIC_AFTER: usize = 10;
+
+    impl Drop for Fragile {
+        fn drop(&mut self) { DROPS.fetch_add(1, Relaxed); }
+    }
+
+    impl PartialEq for Fragile {
+        fn eq(&self, other: &Self) -> bool { self.0 == other.0 }
+    }
+
+    impl Eq for Fragile {}
+
+    impl PartialOrd for Fragile {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+    }
+
+    impl Ord for Fragile {
+        fn cmp(&self, other: &Self) -> Ordering {
+            if COMPARISONS.fetch_add(1, Relaxed) == PANIC_AFTER {
+                panic!("Comparison failed");
+            }
+            self.0.cmp(&other.0)
+        }
+    }
+
+    #[test]
+    fn merge_panic_safety() {
+        let mut v: Vec<Fragile> = [1, 3, 5, 7, 9, 11, 13, 15, 0, 2, 4, 6, 8, 10, 12, 14]
+            .into_iter()
+            .map(Fragile)
+            .collect();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| merge(&mut v, 8)));
+        assert!(result.is_err());
+        let mut keys: Vec<u64> = v.iter().map(|x| x.0).collect();
+        keys.sort();
+        assert_eq!(keys, (0..16).collect::<Vec<_>>());
+        drop(v);
+        assert_eq!(DROPS.load(Relaxed), 16);
+    }
+}
@@ -1,5 +1,6 @@
-use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use std::sync::atomic::{AtomicU32, Ordering::{AcqRel, Acquire, Relaxed, Release}};
 
+#[derive(Debug)]
 pub struct Count(AtomicU32);
 
 impl Count {
@@ -34,6 +35,39 @@ impl Count {
     pub fn dec(&self) -> u32 {
         self.sub(1)
     }
+
+    // Most counts are statistics, for which `Relaxed` is enough. Counts that
+    // others act upon, like a scope's number of pending tasks, need stronger
+    // orderings: whatever a worker did before decrementing the count (such as
+    // writing a task's result) must be visible to whoever sees the count
+    // drop to zero, also on weakly-ordered architectures.
+    pub fn get_acquire(&self) -> u32 {
+        self.0.load(Acquire)
+    }
+
+    // Returns the previous value
+    pub fn inc_release(&self) -> u32 {
+        self.0.fetch_add(1, Release)
+    }
+
+    // Returns the previous value
+    pub fn dec_acq_rel(&self) -> u32 {
+        self.0.fetch_sub(1, AcqRel)
+    }
+
+    // Sets the count to `new` if it equals `current`. Returns `true` on
+    // success.
+    pub fn compare_and_set(&self, current: u32, new: u32) -> bool {
+        self.0.compare_exchange(current, new, Relaxed, Relaxed).is_ok()
+    }
+
+    // Increments the count only if the result stays within `bound`. Returns
+    // `true` on success.
+    pub fn inc_if_below(&self, bound: u32) -> bool {
+        self.0.fetch_update(Relaxed, Relaxed, |n| {
+            if n < bound { Some(n + 1) } else { None }
+        }).is_ok()
+    }
 }
 
 #[cfg(test)]
@@ -56,7 +90,7 @@ mod tests {
         }
         assert_eq!(a.get(), 0);
         a.dec();
-        assert_eq!(a.get(), std::u32::MAX);
+        assert_eq!(a.get(), u32::MAX);
         a.inc();
         assert_eq!(a.get(), 0);
     }
@@ -96,4 +130,33 @@ mod tests {
 
         assert_eq!(a.get(), 54);
     }
+
+    #[test]
+    fn count_down_and_observe() {
+        use std::sync::atomic::AtomicU32;
+
+        let results = Arc::new((0..4).map(|_| AtomicU32::new(0)).collect::<Vec<_>>());
+        let pending = Arc::new(Count::new(0));
+        let ts: Vec<_> = (0..4)
+            .map(|i| {
+                let results = Arc::clone(&results);
+                let pending = Arc::clone(&pending);
+                pending.inc_release();
+                thread::spawn(move || {
+                    results[i].store(i as u32 + 1, Relaxed);
+                    pending.dec_acq_rel();
+                })
+            })
+            .collect();
+
+        while pending.get_acquire() > 0 {
+            thread::yield_now();
+        }
+        // Every result is visible once the count has dropped to zero
+        assert!(results.iter().enumerate().all(|(i, r)| r.load(Relaxed) == i as u32 + 1));
+
+        for t in ts {
+            t.join().unwrap();
+        }
+    }
 }
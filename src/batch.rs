@@ -0,0 +1,363 @@
+use crate::channel::Receiver;
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::{self, MaybeUninit};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU8, AtomicUsize};
+use std::sync::atomic::Ordering::{Acquire, Relaxed, SeqCst};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+// Batched results of sibling tasks
+//
+// Promoting a task gives its promise a one-shot channel of its own. When a
+// thief steals many sibling tasks at once (see `StealMany`), that means one
+// allocation per task, and every result is synchronized separately on its
+// way back. Instead, the victim promotes all of them into a single batch,
+// with one slot per task, so that results travel back by index through
+// shared memory (see `Worker::respond`). Sending a result is a single atomic
+// operation, and a receiver that is waiting for its result is only woken up
+// if it has registered itself.
+//
+// Siblings need not have the same result type, so every slot has room for
+// any result of up to `INLINE_SIZE` bytes, typed by the sender and receiver
+// that share the slot. Only larger (or more strictly aligned) results are
+// boxed, with the slot holding the pointer.
+
+const EMPTY: u8 = 0;
+const READY: u8 = 1;
+const TAKEN: u8 = 2;
+// The receiver is gone, so the sender drops the result
+const CLOSED: u8 = 3;
+
+const INLINE_SIZE: usize = 32;
+const INLINE_ALIGN: usize = 16;
+
+// Aligned to `INLINE_ALIGN`
+#[repr(C, align(16))]
+struct Inline([MaybeUninit<u8>; INLINE_SIZE]);
+
+// Is a `T` stored in the slot itself?
+const fn fits_inline<T>() -> bool {
+    mem::size_of::<T>() <= INLINE_SIZE && mem::align_of::<T>() <= INLINE_ALIGN
+}
+
+pub struct Batch {
+    slots: Box<[Slot]>,
+    // The next slot to hand out, see `channel`
+    next: AtomicUsize,
+    num_ready: AtomicUsize,
+    // Threads blocked in `BatchReceiver::wait`
+    waiting: Mutex<Vec<Thread>>,
+    num_waiting: AtomicUsize,
+}
+
+struct Slot {
+    state: AtomicU8,
+    // The result (or a pointer to the boxed result, see `fits_inline`), in
+    // state READY
+    value: UnsafeCell<Inline>,
+}
+
+impl Slot {
+    // Safety: only the slot's sender may write, before setting READY
+    unsafe fn write<T>(&self, value: T) {
+        let ptr = self.value.get().cast::<MaybeUninit<u8>>();
+        if fits_inline::<T>() {
+            unsafe { ptr.cast::<T>().write(value) };
+        } else {
+            unsafe { ptr.cast::<*mut T>().write(Box::into_raw(Box::new(value))) };
+        }
+    }
+
+    // Safety: the slot must hold a `T` written by `write`, which is moved out
+    unsafe fn read<T>(&self) -> T {
+        let ptr = self.value.get().cast::<MaybeUninit<u8>>();
+        if fits_inline::<T>() {
+            unsafe { ptr.cast::<T>().read() }
+        } else {
+            *unsafe { Box::from_raw(ptr.cast::<*mut T>().read()) }
+        }
+    }
+}
+
+// Slots are handed out once (see `channel`) and accessed by their sender
+// and receiver according to `state`
+unsafe impl Send for Batch {}
+unsafe impl Sync for Batch {}
+
+impl Batch {
+    pub fn new(len: usize) -> Arc<Self> {
+        let slots = (0..len)
+            .map(|_| Slot {
+                state: AtomicU8::new(EMPTY),
+                value: UnsafeCell::new(Inline([MaybeUninit::uninit(); INLINE_SIZE])),
+            })
+            .collect();
+        Arc::new(Self {
+            slots,
+            next: AtomicUsize::new(0),
+            num_ready: AtomicUsize::new(0),
+            waiting: Mutex::new(vec![]),
+            num_waiting: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    // Number of results sent so far
+    pub fn num_ready(&self) -> usize {
+        self.num_ready.load(Acquire)
+    }
+
+    // Hand out the next slot. Panics if all slots are taken.
+    pub fn channel<T>(self: &Arc<Self>) -> (BatchSender<T>, BatchReceiver<T>) {
+        let index = self.next.fetch_add(1, Relaxed);
+        assert!(index < self.len(), "Batch of {} results is full", self.len());
+        let sender = BatchSender { batch: Arc::clone(self), index, _result: PhantomData };
+        let receiver = BatchReceiver { batch: Arc::clone(self), index, _result: PhantomData };
+        (sender, receiver)
+    }
+
+    fn wake_up_waiting(&self) {
+        if self.num_waiting.load(SeqCst) > 0 {
+            self.waiting.lock().unwrap().iter().for_each(Thread::unpark);
+        }
+    }
+}
+
+impl fmt::Debug for Batch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Batch")
+            .field("len", &self.len())
+            .field("num_ready", &self.num_ready())
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct BatchSender<T> {
+    batch: Arc<Batch>,
+    index: usize,
+    _result: PhantomData<T>,
+}
+
+pub struct BatchReceiver<T> {
+    batch: Arc<Batch>,
+    index: usize,
+    _result: PhantomData<T>,
+}
+
+impl<T> BatchSender<T> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    // Send a result, dropping it if the receiver is gone
+    pub fn send(self, value: T) {
+        let slot = &self.batch.slots[self.index];
+        unsafe { slot.write(value) };
+        // Sequentially consistent, so that either a waiting receiver sees the
+        // result or we see the receiver waiting (see `BatchReceiver::wait`)
+        if slot.state.compare_exchange(EMPTY, READY, SeqCst, Relaxed).is_err() {
+            drop(unsafe { slot.read::<T>() });
+            return;
+        }
+        self.batch.num_ready.fetch_add(1, SeqCst);
+        self.batch.wake_up_waiting();
+    }
+}
+
+impl<T> BatchReceiver<T> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn batch(&self) -> &Arc<Batch> {
+        &self.batch
+    }
+
+    fn slot(&self) -> &Slot {
+        &self.batch.slots[self.index]
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.slot().state.load(Acquire) == READY
+    }
+
+    // Panics if there is no result to receive
+    pub fn receive(&self) -> T {
+        let slot = self.slot();
+        if slot.state.compare_exchange(READY, TAKEN, Acquire, Relaxed).is_err() {
+            panic!("No result available!");
+        }
+        unsafe { slot.read() }
+    }
+
+    // Block (park the calling thread) until the result is available
+    pub fn wait(&self) {
+        self.wait_until(None);
+    }
+
+    // Like `wait`, but give up after `timeout`. Returns whether the result
+    // is available.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.wait_until(Some(Instant::now() + timeout))
+    }
+
+    fn wait_until(&self, deadline: Option<Instant>) -> bool {
+        if self.is_ready() { return true; }
+        let batch = &self.batch;
+        // Register before checking again, so that `send` either sees us
+        // waiting or we see the result
+        batch.waiting.lock().unwrap().push(thread::current());
+        batch.num_waiting.fetch_add(1, SeqCst);
+        while self.slot().state.load(SeqCst) != READY {
+            match deadline {
+                Some(deadline) if Instant::now() >= deadline => break,
+                Some(deadline) => thread::park_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => thread::park(),
+            }
+        }
+        batch.num_waiting.fetch_sub(1, SeqCst);
+        let mut waiting = batch.waiting.lock().unwrap();
+        if let Some(i) = waiting.iter().position(|t| t.id() == thread::current().id()) {
+            waiting.swap_remove(i);
+        }
+        self.is_ready()
+    }
+}
+
+impl<T> Drop for BatchReceiver<T> {
+    fn drop(&mut self) {
+        // Drop the result if it has arrived, otherwise leave that to the
+        // sender
+        if let Err(READY) = self.slot().state.compare_exchange(EMPTY, CLOSED, Acquire, Acquire) {
+            drop(self.receive());
+        }
+    }
+}
+
+impl<T> fmt::Debug for BatchSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BatchSender").field("index", &self.index).finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for BatchReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BatchReceiver").field("index", &self.index).finish_non_exhaustive()
+    }
+}
+
+// The receiving end of a promoted promise, see `Promise::promote_into`
+pub enum RemoteReceiver<T> {
+    Chan(Receiver<T>),
+    Batch(BatchReceiver<T>),
+}
+
+impl<T> RemoteReceiver<T> {
+    pub fn is_ready(&self) -> bool {
+        match self {
+            Self::Chan(chan) => chan.is_ready(),
+            Self::Batch(batch) => batch.is_ready(),
+        }
+    }
+
+    pub fn receive(&self) -> T {
+        match self {
+            Self::Chan(chan) => chan.receive(),
+            Self::Batch(batch) => batch.receive(),
+        }
+    }
+
+    pub fn wait(&self) {
+        match self {
+            Self::Chan(chan) => chan.wait(),
+            Self::Batch(batch) => batch.wait(),
+        }
+    }
+
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        match self {
+            Self::Chan(chan) => chan.wait_timeout(timeout),
+            Self::Batch(batch) => batch.wait_timeout(timeout),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexed_results() {
+        let batch = Batch::new(3);
+        let (a, ra) = batch.channel::<u32>();
+        let (b, rb) = batch.channel::<String>();
+        assert_eq!((a.index(), b.index()), (0, 1));
+        assert!(!ra.is_ready());
+
+        b.send(String::from("b"));
+        assert!(rb.is_ready() && !ra.is_ready());
+        assert_eq!(batch.num_ready(), 1);
+        assert_eq!(rb.receive(), "b");
+
+        let t = thread::spawn(move || a.send(1));
+        ra.wait();
+        assert_eq!(ra.receive(), 1);
+        t.join().unwrap();
+        assert_eq!(batch.num_ready(), 2);
+
+        // Results nobody receives are dropped
+        let (c, rc) = batch.channel::<Arc<()>>();
+        let value = Arc::new(());
+        drop(rc);
+        c.send(Arc::clone(&value));
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn large_results() {
+        assert!(fits_inline::<(String, u64)>() && !fits_inline::<[u64; 8]>());
+        let batch = Batch::new(3);
+        let (a, ra) = batch.channel::<(String, u64)>();
+        let (b, rb) = batch.channel::<[u64; 8]>();
+        a.send((String::from("a"), 1));
+        b.send([7; 8]);
+        assert_eq!(ra.receive(), (String::from("a"), 1));
+        assert_eq!(rb.receive(), [7; 8]);
+
+        // Boxed results nobody receives are dropped, too
+        let (c, rc) = batch.channel::<(Arc<()>, [u64; 8])>();
+        let value = Arc::new(());
+        drop(rc);
+        c.send((Arc::clone(&value), [0; 8]));
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn full() {
+        let batch = Batch::new(1);
+        let _first = batch.channel::<u32>();
+        let _second = batch.channel::<u32>();
+    }
+
+    #[test]
+    fn wait_timeout() {
+        let batch = Batch::new(1);
+        let (sender, receiver) = batch.channel();
+        assert!(!receiver.wait_timeout(Duration::from_millis(1)));
+        let t = thread::spawn(move || sender.send(42));
+        assert!(receiver.wait_timeout(Duration::from_secs(10)));
+        assert_eq!(receiver.receive(), 42);
+        t.join().unwrap();
+    }
+}
@@ -0,0 +1,31 @@
+// Render a binary trace (see `Runtime::write_trace`) as an HTML timeline:
+//
+//     cargo run --bin trace-view -- trace.bin > trace.html
+
+use rusty_tasking::trace::{read_trace, render_html};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::process;
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: trace-view <trace file>");
+            process::exit(2);
+        }
+    };
+    let workers = File::open(&path)
+        .map(BufReader::new)
+        .and_then(|mut input| read_trace(&mut input))
+        .unwrap_or_else(|err| {
+            eprintln!("Cannot read {}: {}", path, err);
+            process::exit(1);
+        });
+    let html = render_html(&workers);
+    if let Err(err) = io::stdout().write_all(html.as_bytes()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,275 @@
+use crate::worker::Worker;
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+// Buffered result channels
+//
+// Tasks that send lots of small results to a collector pay for one channel
+// operation per result, and the collector wakes up just as often. A buffered
+// sender instead collects results per worker and sends them in batches: once
+// a worker has buffered `capacity` results, once its oldest buffered result
+// is `max_delay` old (checked on the next send), and whenever the worker runs
+// out of work, so that nothing is held back while the runtime is idle (see
+// `Flushers`):
+//
+//     let (sender, results) = buffered_channel(64, Duration::from_millis(1));
+//     for item in items {
+//         let sender = sender.clone();
+//         spawn!(sender.send(process(item)));
+//     }
+//     drop(sender);
+//     let processed: Vec<_> = results.iter().flatten().collect();
+//
+// Results sent from outside the runtime, or to a channel created outside
+// the runtime, are sent right away. Batches arrive in no particular order,
+// and dropping the last sender flushes all buffers.
+
+pub fn buffered_channel<T: Send + 'static>(capacity: usize, max_delay: Duration)
+    -> (BufferedSender<T>, Receiver<Vec<T>>)
+{
+    assert!(capacity > 0);
+    let worker = Worker::try_current();
+    let (sender, receiver) = channel();
+    // One buffer per worker, plus one shared by all helpers, and none
+    // without a runtime to flush them
+    let num_buffers = worker.map_or(0, |worker| worker.shared().config.num_workers() + 1);
+    let buffers = Arc::new(Buffers {
+        buffers: (0..num_buffers).map(|_| Mutex::new(Buffer::new())).collect(),
+        sender,
+        capacity,
+        max_delay,
+    });
+    if let Some(worker) = worker {
+        let flush: Arc<dyn Flush> = Arc::<Buffers<T>>::clone(&buffers);
+        worker.shared().flushers.add(Arc::downgrade(&flush));
+    }
+    (BufferedSender { buffers }, receiver)
+}
+
+struct Buffer<T> {
+    results: Vec<T>,
+    // When the oldest buffered result was sent
+    since: Option<Instant>,
+}
+
+impl<T> Buffer<T> {
+    fn new() -> Self {
+        Self { results: vec![], since: None }
+    }
+
+    fn take(&mut self) -> Vec<T> {
+        self.since = None;
+        mem::take(&mut self.results)
+    }
+}
+
+struct Buffers<T> {
+    buffers: Box<[Mutex<Buffer<T>>]>,
+    sender: Sender<Vec<T>>,
+    capacity: usize,
+    max_delay: Duration,
+}
+
+impl<T> Buffers<T> {
+    fn buffer(&self, worker: usize) -> &Mutex<Buffer<T>> {
+        &self.buffers[worker.min(self.buffers.len() - 1)]
+    }
+
+    fn flush_buffer(&self, buffer: &Mutex<Buffer<T>>) {
+        let results = buffer.lock().unwrap().take();
+        if !results.is_empty() {
+            // Nobody is interested if the receiver is gone
+            let _ = self.sender.send(results);
+        }
+    }
+}
+
+// Something that holds results back until flushed, see `Flushers`
+pub trait Flush: Send + Sync {
+    // Flush the buffer of the given worker, or all buffers
+    fn flush(&self, worker: Option<usize>);
+}
+
+impl<T: Send> Flush for Buffers<T> {
+    fn flush(&self, worker: Option<usize>) {
+        match worker {
+            Some(id) => self.flush_buffer(self.buffer(id)),
+            None => self.buffers.iter().for_each(|buffer| self.flush_buffer(buffer)),
+        }
+    }
+}
+
+impl<T> Drop for Buffers<T> {
+    fn drop(&mut self) {
+        for buffer in self.buffers.iter_mut() {
+            let results = buffer.get_mut().unwrap().take();
+            if !results.is_empty() {
+                let _ = self.sender.send(results);
+            }
+        }
+    }
+}
+
+pub struct BufferedSender<T> {
+    buffers: Arc<Buffers<T>>,
+}
+
+impl<T: Send> BufferedSender<T> {
+    pub fn send(&self, value: T) {
+        let buffers = &self.buffers;
+        let worker = Worker::try_current().filter(|_| !buffers.buffers.is_empty());
+        let Some(worker) = worker else {
+            let _ = buffers.sender.send(vec![value]);
+            return;
+        };
+        let mut buffer = buffers.buffer(worker.id).lock().unwrap();
+        buffer.results.push(value);
+        worker.mark_buffered();
+        let since = *buffer.since.get_or_insert_with(Instant::now);
+        if buffer.results.len() >= buffers.capacity || since.elapsed() >= buffers.max_delay {
+            let results = buffer.take();
+            drop(buffer);
+            let _ = buffers.sender.send(results);
+        }
+    }
+
+    // Send all buffered results now
+    pub fn flush(&self) {
+        self.buffers.flush(None);
+    }
+}
+
+impl<T> Clone for BufferedSender<T> {
+    fn clone(&self) -> Self {
+        Self { buffers: Arc::clone(&self.buffers) }
+    }
+}
+
+impl<T> fmt::Debug for BufferedSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufferedSender")
+            .field("capacity", &self.buffers.capacity)
+            .field("max_delay", &self.buffers.max_delay)
+            .finish_non_exhaustive()
+    }
+}
+
+// The buffered channels of a runtime, see `Shared::flushers`. Workers flush
+// their buffers when they run out of work, and all buffers are flushed when
+// the runtime becomes idle (see `Shared::notify_idle` and
+// `Runtime::wait_until_idle`).
+pub struct Flushers {
+    flushers: Mutex<Vec<Weak<dyn Flush>>>,
+    // Registered channels, so that workers can skip the lock when there are
+    // none
+    len: AtomicUsize,
+}
+
+impl Flushers {
+    pub fn new() -> Self {
+        Self { flushers: Mutex::new(vec![]), len: AtomicUsize::new(0) }
+    }
+
+    pub fn add(&self, flush: Weak<dyn Flush>) {
+        let mut flushers = self.flushers.lock().unwrap();
+        flushers.push(flush);
+        self.len.store(flushers.len(), Relaxed);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len.load(Relaxed) == 0
+    }
+
+    // Flush the buffers of the given worker (or all buffers) of all live
+    // channels, forgetting about channels whose senders are gone
+    pub fn flush(&self, worker: Option<usize>) {
+        if self.is_empty() {
+            return;
+        }
+        let live: Vec<_> = {
+            let mut flushers = self.flushers.lock().unwrap();
+            flushers.retain(|flush| flush.strong_count() > 0);
+            self.len.store(flushers.len(), Relaxed);
+            flushers.iter().filter_map(Weak::upgrade).collect()
+        };
+        // Outside of the lock, since dropping the last reference flushes too
+        live.iter().for_each(|flush| flush.flush(worker));
+    }
+}
+
+impl fmt::Debug for Flushers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Flushers").field("len", &self.len.load(Relaxed)).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use super::*;
+
+    #[test]
+    fn batched_results() {
+        let runtime = Runtime::init(1);
+        let (sender, results) = buffered_channel(4, Duration::from_secs(3600));
+        for i in 0..10 {
+            sender.send(i);
+        }
+        // Two full batches, two results buffered
+        let batches: Vec<_> = results.try_iter().collect();
+        assert_eq!(batches, vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7]]);
+        sender.flush();
+        assert_eq!(results.try_recv().unwrap(), vec![8, 9]);
+        assert!(results.try_recv().is_err());
+
+        // Dropping the last sender sends what's left
+        sender.send(10);
+        drop(sender);
+        assert_eq!(results.try_recv().unwrap(), vec![10]);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn max_delay() {
+        let runtime = Runtime::init(1);
+        let (sender, results) = buffered_channel(100, Duration::ZERO);
+        sender.send(1);
+        sender.send(2);
+        assert_eq!(results.try_iter().collect::<Vec<_>>(), vec![vec![1], vec![2]]);
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn outside_runtime() {
+        let (sender, results) = buffered_channel(100, Duration::from_secs(3600));
+        sender.send(1);
+        assert_eq!(results.try_recv().unwrap(), vec![1]);
+
+        // Not even workers buffer results
+        let runtime = Runtime::init(1);
+        sender.send(2);
+        assert_eq!(results.try_recv().unwrap(), vec![2]);
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn flush_when_idle() {
+        let runtime = Runtime::init(3);
+        let (sender, results) = buffered_channel(1000, Duration::from_secs(3600));
+        for i in 0..100 {
+            let sender = sender.clone();
+            spawn!(sender.send(i));
+        }
+        runtime.wait_until_idle();
+        let mut received: Vec<_> = results.try_iter().flatten().collect();
+        received.sort_unstable();
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+        drop(sender);
+        let _stats = runtime.join();
+    }
+}
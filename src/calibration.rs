@@ -0,0 +1,211 @@
+use crate::algorithms::DEFAULT_SERIAL_CUTOFF;
+use crate::channel::one_shot_channel;
+use crate::runtime::{PollInterval, SchedulerConfig, MAX_POLL_BACKOFF};
+use crate::task::Async;
+use crate::worker::Worker;
+use std::hint::black_box;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering::{Acquire, Release}};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Machine calibration
+//
+// `Runtime::calibrate` times a few short synthetic workloads and derives
+// scheduling parameters from the results. The workloads run as ordinary
+// tasks, so they show up in the runtime's statistics.
+
+const NUM_SPAWNS: u32 = 4096;
+const NUM_MESSAGES: u32 = 4096;
+const NUM_STEALS: usize = 16;
+// How long to wait for a thief before running a probe ourselves
+const STEAL_TIMEOUT: Duration = Duration::from_millis(10);
+
+// Bounds of the tuned parameters
+const MAX_STEAL_MANY: usize = 1024;
+const MAX_POLL_TASKS: u32 = 64;
+const MIN_SERIAL_CUTOFF: usize = 1024;
+const MAX_SERIAL_CUTOFF: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    // Spawning and running an empty task on the same worker
+    pub spawn_cost: Duration,
+    // From spawning a task until another worker starts running it (zero
+    // with a single worker, or if no thief showed up in time)
+    pub steal_latency: Duration,
+    // Sending and receiving a message over a one-shot channel
+    pub channel_overhead: Duration,
+    // Sorting `DEFAULT_SERIAL_CUTOFF` integers, per element
+    pub sort_cost: Duration,
+}
+
+impl Calibration {
+    // Run the workloads on `worker`, which should have nothing else to do
+    pub fn measure(worker: &Worker) -> Self {
+        Self {
+            spawn_cost: spawn_cost(worker),
+            steal_latency: steal_latency(worker),
+            channel_overhead: channel_overhead(),
+            sort_cost: sort_cost(),
+        }
+    }
+
+    // Adapt `config` to the measurements:
+    //
+    // - A steal transfers at most as many tasks as could have been spawned
+    //   in the time it took.
+    // - Checking for steal requests costs about as much as a channel
+    //   operation, so busy workers check only every so many tasks to keep
+    //   polling cheaper than spawning.
+    // - An adaptive poll interval grows to at most a few steal latencies.
+    pub fn tune(&self, mut config: SchedulerConfig) -> SchedulerConfig {
+        let spawn = nanos(self.spawn_cost);
+        let poll_tasks = (nanos(self.channel_overhead) / spawn).ceil() as u32;
+        let poll_tasks = poll_tasks.clamp(1, MAX_POLL_TASKS);
+        config.poll_interval = PollInterval::Tasks(poll_tasks);
+
+        if self.steal_latency > Duration::ZERO {
+            let steal = nanos(self.steal_latency);
+            config.max_steal_many = ((steal / spawn).ceil() as usize).clamp(1, MAX_STEAL_MANY);
+            let backoff = (4.0 * steal / (spawn * poll_tasks as f64)).log2().floor();
+            config.max_poll_backoff = (backoff.max(0.0) as u32).min(MAX_POLL_BACKOFF);
+        }
+        config.serial_cutoff = self.serial_cutoff();
+        config
+    }
+
+    // Length below which `par_merge_sort` sorts serially: sorting that many
+    // elements should take much longer than handing the work to another
+    // worker
+    pub fn serial_cutoff(&self) -> usize {
+        let overhead = nanos(self.spawn_cost) + nanos(self.steal_latency);
+        let len = (16.0 * overhead / nanos(self.sort_cost)) as usize;
+        len.clamp(MIN_SERIAL_CUTOFF, MAX_SERIAL_CUTOFF)
+    }
+}
+
+// Durations in nanoseconds, at least one to divide by
+fn nanos(duration: Duration) -> f64 {
+    (duration.as_nanos() as f64).max(1.0)
+}
+
+fn spawn_cost(worker: &Worker) -> Duration {
+    let done = Arc::new(AtomicU32::new(0));
+    let start = Instant::now();
+    for _ in 0..NUM_SPAWNS {
+        let done = Arc::clone(&done);
+        worker.push(Async::detached(move || done.fetch_add(1, Release)));
+    }
+    worker.help_until(|| done.load(Acquire) == NUM_SPAWNS);
+    start.elapsed() / NUM_SPAWNS
+}
+
+// Median time until a probe task is picked up by another worker. The
+// worker keeps answering steal requests but doesn't run the probe itself,
+// unless nobody steals it within `STEAL_TIMEOUT`.
+fn steal_latency(worker: &Worker) -> Duration {
+    let mut samples = Vec::with_capacity(NUM_STEALS);
+    for _ in 0..NUM_STEALS {
+        let started = Arc::new(Mutex::new(None));
+        let done = Arc::new(AtomicBool::new(false));
+        let leader = thread::current().id();
+        let probe = {
+            let started = Arc::clone(&started);
+            let done = Arc::clone(&done);
+            move || {
+                if thread::current().id() != leader {
+                    *started.lock().unwrap() = Some(Instant::now());
+                }
+                done.store(true, Release);
+            }
+        };
+        let start = Instant::now();
+        worker.push(Async::detached(probe));
+        while !done.load(Acquire) && start.elapsed() < STEAL_TIMEOUT {
+            worker.handle_all_steal_requests();
+        }
+        worker.help_until(|| done.load(Acquire));
+        let started = *started.lock().unwrap();
+        if let Some(started) = started {
+            samples.push(started - start);
+        }
+    }
+    samples.sort();
+    samples.get(samples.len() / 2).copied().unwrap_or(Duration::ZERO)
+}
+
+fn channel_overhead() -> Duration {
+    let start = Instant::now();
+    for i in 0..NUM_MESSAGES {
+        let (sender, receiver) = one_shot_channel();
+        sender.send(i);
+        black_box(receiver.recv_blocking());
+    }
+    start.elapsed() / NUM_MESSAGES
+}
+
+fn sort_cost() -> Duration {
+    // Pseudo-random numbers (xorshift)
+    let mut x = 0x2545_f491_4f6c_dd1d_u64;
+    let mut v: Vec<u64> = (0..DEFAULT_SERIAL_CUTOFF)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        })
+        .collect();
+    let start = Instant::now();
+    v.sort();
+    black_box(&v);
+    start.elapsed() / DEFAULT_SERIAL_CUTOFF as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithms::serial_cutoff;
+    use crate::runtime::{Config, Runtime};
+    use super::*;
+
+    #[test]
+    fn tuning() {
+        let calibration = Calibration {
+            spawn_cost: Duration::from_nanos(100),
+            steal_latency: Duration::from_micros(2),
+            channel_overhead: Duration::from_nanos(250),
+            sort_cost: Duration::from_nanos(20),
+        };
+        let config = calibration.tune(SchedulerConfig::from(&Config::new(1)));
+        assert_eq!(config.poll_interval, PollInterval::Tasks(3));
+        assert_eq!(config.max_steal_many, 20);
+        // 4 * 2000 / 300 = 26.7
+        assert_eq!(config.max_poll_backoff, 4);
+        // 16 * 2100 / 20
+        assert_eq!(calibration.serial_cutoff(), 1680);
+        assert_eq!(config.serial_cutoff, 1680);
+
+        // Nothing to steal from
+        let single = Calibration { steal_latency: Duration::ZERO, ..calibration };
+        let config = single.tune(config);
+        assert_eq!(config.max_steal_many, 20);
+        assert_eq!(single.serial_cutoff(), MIN_SERIAL_CUTOFF);
+    }
+
+    #[test]
+    fn calibrate() {
+        let runtime = Runtime::init(2);
+
+        let calibration = runtime.calibrate();
+        assert!(calibration.spawn_cost > Duration::ZERO);
+        assert!(calibration.channel_overhead > Duration::ZERO);
+        assert_eq!(runtime.scheduler_config(), calibration.tune(runtime.scheduler_config()));
+        assert_eq!(serial_cutoff(), calibration.serial_cutoff());
+
+        let stats = runtime.join();
+        assert_stat!(stats.num_tasks_executed.get() >= NUM_SPAWNS);
+        // The cutoff belongs to the runtime
+        assert_eq!(serial_cutoff(), DEFAULT_SERIAL_CUTOFF);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::channel::one_shot_channel;
+use crate::future::{Future, Promise};
+use crate::macros;
+use crate::task::Async;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering::{Acquire, Release}};
+
+// Cancellable future chains
+//
+// A cancellable future is linked to the task that produces its result, and
+// futures built from other futures with `map`, `then`, and `zip` are linked
+// to the futures they were built from. Cancelling a future, or dropping it
+// before its result has been taken, cancels its link and, transitively, the
+// links of all futures it depends on, so that producer tasks that haven't
+// started yet are skipped instead of computing results nobody is waiting for:
+//
+//     let parsed = spawn_cancellable(move || fetch(url)).map(|page| parse(&page));
+//     let totals = parsed.zip(spawn_cancellable(move || fetch_totals()));
+//     drop(totals); // Neither page is fetched, unless already underway
+//
+// Tasks that are already running complete as usual. Waiting for a future
+// whose producer was skipped returns `None`.
+
+#[derive(Clone, Default)]
+pub struct CancelLink {
+    inner: Arc<LinkInner>,
+}
+
+#[derive(Default)]
+struct LinkInner {
+    cancelled: AtomicBool,
+    // Links of the futures this one depends on
+    upstream: Mutex<Vec<CancelLink>>,
+}
+
+impl CancelLink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Acquire)
+    }
+
+    // Make this link depend on `upstream`, which is cancelled right away if
+    // this link has been cancelled already
+    pub fn add_upstream(&self, upstream: CancelLink) {
+        let mut links = self.inner.upstream.lock().unwrap();
+        if self.is_cancelled() {
+            drop(links);
+            upstream.cancel();
+        } else {
+            links.push(upstream);
+        }
+    }
+
+    // Cancel this link and everything upstream of it
+    pub fn cancel(&self) {
+        let mut links = vec![self.clone()];
+        while let Some(link) = links.pop() {
+            let mut upstream = link.inner.upstream.lock().unwrap();
+            if !link.inner.cancelled.swap(true, Release) {
+                links.append(&mut upstream);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for CancelLink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancelLink").field("cancelled", &self.is_cancelled()).finish_non_exhaustive()
+    }
+}
+
+pub struct CancellableFuture<T> {
+    // `None` if the producer was skipped
+    future: Future<Option<T>>,
+    link: CancelLink,
+    taken: bool,
+}
+
+// Spawn a task whose result can be cancelled before the task starts
+pub fn spawn_cancellable<R, F>(f: F) -> CancellableFuture<R>
+where F: FnOnce() -> R + Send + 'static, R: Send + 'static {
+    spawn_linked(CancelLink::new(), |_| Some(f()))
+}
+
+// Spawn a task that is skipped if `link` is cancelled before it starts
+fn spawn_linked<R, F>(link: CancelLink, f: F) -> CancellableFuture<R>
+where F: FnOnce(&CancelLink) -> Option<R> + Send + 'static, R: Send + 'static {
+    let (sender, receiver) = one_shot_channel();
+    let task = {
+        let link = link.clone();
+        Async::new(
+            move || if link.is_cancelled() { None } else { f(&link) },
+            Some(Promise::from(sender)),
+        )
+    };
+    macros::push(task);
+    CancellableFuture { future: Future::Chan(receiver), link, taken: false }
+}
+
+impl<T: Send + 'static> CancellableFuture<T> {
+    pub fn link(&self) -> &CancelLink {
+        &self.link
+    }
+
+    pub fn cancel(&self) {
+        self.link.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.link.is_cancelled()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.future.is_ready()
+    }
+
+    // Wait for the result, helping with tasks in the meantime (see
+    // `Future::wait`). Returns `None` if the producer was skipped.
+    pub fn wait(&mut self) -> Option<T> {
+        self.taken = true;
+        self.future.wait()
+    }
+
+    pub fn get(mut self) -> Option<T> {
+        self.wait()
+    }
+
+    // Apply `f` to the result, in a task of its own
+    pub fn map<U, F>(mut self, f: F) -> CancellableFuture<U>
+    where F: FnOnce(T) -> U + Send + 'static, U: Send + 'static {
+        let link = CancelLink::new();
+        link.add_upstream(self.link.clone());
+        spawn_linked(link, move |_| self.wait().map(f))
+    }
+
+    // Continue with the future that `f` returns for the result, which is
+    // linked to the returned future in turn
+    pub fn then<U, F>(mut self, f: F) -> CancellableFuture<U>
+    where F: FnOnce(T) -> CancellableFuture<U> + Send + 'static, U: Send + 'static {
+        let link = CancelLink::new();
+        link.add_upstream(self.link.clone());
+        spawn_linked(link, move |link| {
+            let mut next = f(self.wait()?);
+            link.add_upstream(next.link.clone());
+            next.wait()
+        })
+    }
+
+    // Combine the results of two futures
+    pub fn zip<U>(mut self, mut other: CancellableFuture<U>) -> CancellableFuture<(T, U)>
+    where U: Send + 'static {
+        let link = CancelLink::new();
+        link.add_upstream(self.link.clone());
+        link.add_upstream(other.link.clone());
+        spawn_linked(link, move |_| {
+            let (a, b) = (self.wait(), other.wait());
+            Some((a?, b?))
+        })
+    }
+}
+
+impl<T> Drop for CancellableFuture<T> {
+    fn drop(&mut self) {
+        if !self.taken {
+            self.link.cancel();
+        }
+    }
+}
+
+impl<T> fmt::Debug for CancellableFuture<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancellableFuture").field("link", &self.link).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+    use super::*;
+
+    #[test]
+    fn combinators() {
+        let runtime = Runtime::init(3);
+        let a = spawn_cancellable(|| 2).map(|x| x * 3);
+        let b = spawn_cancellable(|| 4).then(|x| spawn_cancellable(move || x + 1));
+        assert_eq!(a.zip(b).get(), Some((6, 5)));
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn propagate_cancellation() {
+        // Nobody runs queued tasks until the leader helps
+        let runtime = Runtime::init(1);
+        let ran = Arc::new(AtomicUsize::new(0));
+        let produce = |value| {
+            let ran = Arc::clone(&ran);
+            spawn_cancellable(move || { ran.fetch_add(1, Relaxed); value })
+        };
+
+        let a = produce(1);
+        let b = produce(2);
+        let links = [a.link().clone(), b.link().clone()];
+        let zipped = a.map(|x| x + 1).zip(b);
+        drop(zipped);
+        assert!(links.iter().all(CancelLink::is_cancelled));
+        runtime.wait_until_idle();
+        assert_eq!(ran.load(Relaxed), 0);
+
+        // Cancelling downstream after the producer has run
+        let c = produce(3);
+        let c_link = c.link().clone();
+        runtime.wait_until_idle();
+        let d = c.map(|x| x * 2);
+        d.cancel();
+        assert!(c_link.is_cancelled());
+        assert_eq!(d.get(), None);
+        assert_eq!(ran.load(Relaxed), 1);
+
+        let _stats = runtime.join();
+    }
+}
@@ -1,16 +1,29 @@
 use std::cell::UnsafeCell;
+use std::fmt;
 use std::mem::MaybeUninit;
-use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, AtomicU8};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
 use std::sync::atomic::Ordering::{Acquire, Release, Relaxed};
 
 // One-shot channel from Chapter 5 of Rust Atomics and Locks
 // https://marabos.nl/atomics/building-channels.html#safety-through-types
+//
+// Extended with a third state: a receiver that is no longer interested in
+// the message can close the channel, in which case `Sender::try_send` hands
+// the message back
+
+const EMPTY: u8 = 0;
+const READY: u8 = 1;
+const CLOSED: u8 = 2;
 
 pub fn one_shot_channel<T>() -> (Sender<T>, Receiver<T>) {
     let a = Arc::new(Channel {
         message: UnsafeCell::new(MaybeUninit::uninit()),
-        ready: AtomicBool::new(false),
+        state: AtomicU8::new(EMPTY),
+        waiting: Mutex::new(None),
+        priority: AtomicU32::new(0),
     });
     (Sender { channel: a.clone() }, Receiver { channel: a })
 }
@@ -23,37 +36,135 @@ pub struct Receiver<T> {
 }
 
 impl<T> Sender<T> {
+    // The highest priority the receiver has asked for (zero by default)
+    pub fn priority(&self) -> u32 {
+        self.channel.priority.load(Relaxed)
+    }
+
+    // Has the receiver closed the channel (see `Receiver::close`)?
+    pub fn is_closed(&self) -> bool {
+        self.channel.state.load(Relaxed) == CLOSED
+    }
+
+    // Send a message, dropping it if the receiver has closed the channel
     pub fn send(self, message: T) {
+        let _ = self.try_send(message);
+    }
+
+    // Send a message, or get it back if the receiver has closed the channel
+    pub fn try_send(self, message: T) -> Result<(), T> {
+        // Only possible by sharing the channel in unsafe code
+        invariant!(self.channel.state.load(Relaxed) != READY, "promise set more than once");
         unsafe { (*self.channel.message.get()).write(message) };
-        self.channel.ready.store(true, Release);
+        if self.channel.state.compare_exchange(EMPTY, READY, Release, Relaxed).is_err() {
+            // Closed: the receiver won't touch the message
+            return Err(unsafe { (*self.channel.message.get()).assume_init_read() });
+        }
+        // Wake up a receiver blocked in `wait`
+        if let Some(thread) = self.channel.waiting.lock().unwrap().take() {
+            thread.unpark();
+        }
+        Ok(())
     }
 }
 
 impl<T> Receiver<T> {
+    // Let the sender know that the receiver is waiting at (at least) the
+    // given priority, see `Sender::priority`
+    pub fn raise_priority(&self, priority: u32) {
+        self.channel.priority.fetch_max(priority, Relaxed);
+    }
+
     pub fn is_ready(&self) -> bool {
-        self.channel.ready.load(Relaxed)
+        self.channel.state.load(Relaxed) == READY
+    }
+
+    // Close the channel unless a message has been sent already. Returns
+    // false if there is a message to receive.
+    pub fn close(&self) -> bool {
+        self.channel.state.compare_exchange(EMPTY, CLOSED, Relaxed, Relaxed).is_ok()
+    }
+
+    // Block (park the calling thread) until a message is available
+    pub fn wait(&self) {
+        if self.is_ready() { return; }
+        // Register before checking again, so that `send` either sees us
+        // waiting or we see the message
+        *self.channel.waiting.lock().unwrap() = Some(thread::current());
+        while !self.is_ready() {
+            thread::park();
+        }
+    }
+
+    // Like `wait`, but give up after `timeout`. Returns whether a message is
+    // available.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        if self.is_ready() { return true; }
+        let start = Instant::now();
+        *self.channel.waiting.lock().unwrap() = Some(thread::current());
+        while !self.is_ready() {
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                self.channel.waiting.lock().unwrap().take();
+                return self.is_ready();
+            }
+            thread::park_timeout(timeout - elapsed);
+        }
+        true
+    }
+
+    // Block until a message is available and receive it
+    pub fn recv_blocking(&self) -> T {
+        self.wait();
+        self.receive()
+    }
+
+    // Receive a message if one becomes available within `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        if self.wait_timeout(timeout) {
+            Some(self.receive())
+        } else {
+            None
+        }
     }
 
     // Here we give up safety through types:
     // `receive` can't consume `self` because of its use in `Future::try_get`
     pub fn receive(&self) -> T {
-        if !self.channel.ready.swap(false, Acquire) {
+        if self.channel.state.swap(EMPTY, Acquire) != READY {
             panic!("No message available!");
         }
         unsafe { (*self.channel.message.get()).assume_init_read() }
     }
 }
 
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
 struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
-    ready: AtomicBool,
+    state: AtomicU8,
+    // A receiver blocked in `Receiver::wait`
+    waiting: Mutex<Option<Thread>>,
+    // Priority the receiver asks the sender to run at, see
+    // `Receiver::raise_priority`
+    priority: AtomicU32,
 }
 
 unsafe impl<T> Sync for Channel<T> where T: Send {}
 
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
-        if *self.ready.get_mut() {
+        if *self.state.get_mut() == READY {
             unsafe { self.message.get_mut().assume_init_drop() }
         }
     }
@@ -78,4 +189,36 @@ mod tests {
             t.thread().unpark();
         });
     }
+
+    #[test]
+    fn send_wait_receive() {
+        for _ in 0..100 {
+            let (sender, receiver) = one_shot_channel();
+            let t = thread::spawn(move || receiver.recv_blocking());
+            sender.send(42);
+            assert_eq!(t.join().unwrap(), 42);
+        }
+    }
+
+    #[test]
+    fn receive_timeout() {
+        let (sender, receiver) = one_shot_channel();
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(1)), None);
+        let t = thread::spawn(move || receiver.recv_timeout(Duration::from_secs(10)));
+        thread::sleep(Duration::from_millis(1));
+        sender.send(42);
+        assert_eq!(t.join().unwrap(), Some(42));
+    }
+
+    #[test]
+    fn close() {
+        let (sender, receiver) = one_shot_channel();
+        assert!(receiver.close());
+        assert_eq!(sender.try_send(42), Err(42));
+
+        let (sender, receiver) = one_shot_channel();
+        assert_eq!(sender.try_send(42), Ok(()));
+        assert!(!receiver.close());
+        assert_eq!(receiver.receive(), 42);
+    }
 }
@@ -0,0 +1,86 @@
+use crate::future::Promise;
+use crate::task::Async;
+use crate::worker::Worker;
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver};
+
+// Spawn `n` tasks computing `f(0)`, ..., `f(n - 1)` and return a collector
+// for the results. Rather than allocating a future per task, all tasks send
+// their results through a single channel. See also `spawn_collect!`.
+pub fn spawn_collect<T, F>(n: usize, f: F) -> Collector<T>
+where
+    T: Send + 'static,
+    F: Fn(usize) -> T + Send + Sync + 'static,
+{
+    let (sender, receiver) = channel();
+    let f = Arc::new(f);
+    let worker = Worker::current();
+    for i in 0..n {
+        let f = Arc::clone(&f);
+        let task = Async::new(
+            move || (i, f(i)),
+            Some(Promise::from(sender.clone())),
+        );
+        worker.push(task);
+    }
+    Collector { receiver, results: RefCell::new(vec![]), n }
+}
+
+pub struct Collector<T> {
+    receiver: Receiver<(usize, T)>,
+    // Results received so far, along with their task indices
+    results: RefCell<Vec<(usize, T)>>,
+    n: usize,
+}
+
+impl<T> Collector<T> {
+    // Number of tasks
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    // Receive all results that have arrived; returns true when done
+    fn try_collect(&self) -> bool {
+        let mut results = self.results.borrow_mut();
+        results.extend(self.receiver.try_iter());
+        results.len() == self.n
+    }
+
+    // Wait for all results, helping with tasks in the meantime. Results are
+    // returned in task order.
+    pub fn wait(self) -> Vec<T> {
+        if !self.try_collect() {
+            Worker::current().help_until(|| self.try_collect());
+        }
+        let mut results = self.results.into_inner();
+        results.sort_unstable_by_key(|&(i, _)| i);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use super::*;
+
+    #[test]
+    fn collect_results() {
+        let runtime = Runtime::init(3);
+
+        let collector = spawn_collect(1000, |i| i * i);
+        assert_eq!(collector.len(), 1000);
+        assert_eq!(collector.wait(), (0..1000).map(|i| i * i).collect::<Vec<_>>());
+
+        let collector = spawn_collect!(10, |i| i + 1);
+        assert_eq!(collector.wait(), (1..=10).collect::<Vec<_>>());
+
+        assert!(spawn_collect(0, |i| i).wait().is_empty());
+
+        let _stats = runtime.join();
+    }
+}
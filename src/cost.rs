@@ -0,0 +1,91 @@
+use crate::task::TaskKind;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Task costs
+//
+// Tasks can carry a static cost hint (see `Task::cost`). Named tasks are
+// additionally timed when they run, and the measured run times replace the
+// hints of subsequent tasks with the same name, so that splitting decisions
+// improve over the course of a run. Measurements are per worker, which is
+// enough because a worker only splits its own deque.
+//
+// Costs are run times in microseconds, hinted or measured alike, so that
+// hinted and measured tasks can be weighed against each other. The default
+// hint of 1 stands for a trivial task.
+
+// Measured cost of tasks with the same name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TaskCost {
+    pub samples: u32,
+    // Exponentially weighted moving average of run times
+    pub mean: Duration,
+}
+
+impl TaskCost {
+    fn update(&mut self, run_time: Duration) {
+        self.mean = match self.samples {
+            0 => run_time,
+            // mean = 7/8 * mean + 1/8 * run_time
+            _ => (self.mean * 7 + run_time) / 8,
+        };
+        self.samples = self.samples.saturating_add(1);
+    }
+}
+
+#[derive(Debug)]
+pub struct CostModel {
+    costs: RefCell<HashMap<&'static str, TaskCost>>,
+}
+
+impl CostModel {
+    pub fn new() -> Self {
+        Self { costs: RefCell::new(HashMap::new()) }
+    }
+
+    pub fn record(&self, name: &'static str, run_time: Duration) {
+        self.costs
+            .borrow_mut()
+            .entry(name)
+            .or_insert(TaskCost { samples: 0, mean: Duration::ZERO })
+            .update(run_time);
+    }
+
+    pub fn get(&self, name: &str) -> Option<TaskCost> {
+        self.costs.borrow().get(name).copied()
+    }
+
+    // Estimated cost of `task`: its mean run time if it has been measured,
+    // otherwise its cost hint (in microseconds either way)
+    pub fn estimate(&self, task: &TaskKind) -> u32 {
+        task.name()
+            .and_then(|name| self.get(name))
+            .map(|cost| (cost.mean.as_micros() as u32).max(1))
+            .unwrap_or_else(|| task.cost())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task::Async;
+    use super::*;
+
+    #[test]
+    fn measured_costs() {
+        let model = CostModel::new();
+        let task = TaskKind::from(Async::detached(|| ()).with_name("leaf").with_cost(3));
+        assert_eq!(model.estimate(&task), 3);
+
+        model.record("leaf", Duration::from_micros(80));
+        assert_eq!(model.estimate(&task), 80);
+
+        model.record("leaf", Duration::from_micros(160));
+        assert_eq!(model.get("leaf").unwrap().samples, 2);
+        assert_eq!(model.estimate(&task), 90);
+
+        // Unnamed tasks are never measured
+        let task = TaskKind::from(Async::detached(|| ()).with_cost(5));
+        assert_eq!(model.estimate(&task), 5);
+    }
+}
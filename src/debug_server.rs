@@ -0,0 +1,176 @@
+use crate::runtime::{Shared, WorkerInfo};
+use std::fmt::Write;
+use std::io::{self, Read, Write as _};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::thread;
+use std::time::Duration;
+
+// How often the server checks whether it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+// How long to wait for busy workers to publish their statistics
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_millis(10);
+
+// A tiny HTTP server that answers every request with a JSON document of the
+// runtime's state: the live load of every worker, and the latest published
+// statistics (see `Config::report_interval`). For example:
+//
+//     let runtime = Runtime::builder().debug_server("127.0.0.1:7878").init();
+//     // curl http://127.0.0.1:7878/
+//
+// The server stops when the runtime is joined.
+pub struct DebugServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl DebugServer {
+    pub fn start(
+        addr: impl ToSocketAddrs,
+        shared: Arc<Shared>,
+        workers: Vec<WorkerInfo>
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                while !stop.load(Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            // Not much we can do about broken connections
+                            let _ = serve(stream, &shared, &workers);
+                        }
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(POLL_INTERVAL);
+                        }
+                        Err(err) => {
+                            eprintln!("Debug server: {}", err);
+                            break;
+                        }
+                    }
+                }
+            })
+        };
+        Ok(Self { addr, stop, thread: Some(thread) })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for DebugServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn serve(mut stream: TcpStream, shared: &Shared, workers: &[WorkerInfo]) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    // We answer every request the same way, so the request itself doesn't
+    // matter beyond the first read
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request)?;
+
+    let body = to_json(shared, workers);
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+pub fn to_json(shared: &Shared, workers: &[WorkerInfo]) -> String {
+    let mut json = String::from("{\"workers\":[");
+    for (i, worker) in workers.iter().enumerate() {
+        let load = worker.load();
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            "{{\"id\":{},\"partition\":{},\"queued\":{},\"running\":{},\"created\":{},\
+             \"completed\":{},\"pending_requests\":{},\"parked\":{},\"scope_level\":{}}}",
+            worker.id(),
+            quote(worker.partition()),
+            load.queued,
+            load.running,
+            worker.num_created(),
+            worker.num_completed(),
+            worker.pending_requests(),
+            worker.is_parked(),
+            worker.scope_level(),
+        );
+    }
+    let stats = shared.consistent_snapshot(SNAPSHOT_TIMEOUT);
+    let _ = write!(
+        json,
+        "],\"stats\":{{\"tasks_executed\":{},\"steal_requests\":{},\"steals\":{},\
+         \"idle_ms\":{:.3},\"utilization\":{:.3}}}}}",
+        stats.num_tasks_executed.get(),
+        stats.num_steal_requests.get(),
+        stats.num_steals.get(),
+        stats.idle_time.get().as_secs_f64() * 1e3,
+        stats.utilization(),
+    );
+    json
+}
+
+// A JSON string literal
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => { let _ = write!(quoted, "\\u{:04x}", c as u32); }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("io \"1\"\n"), "\"io \\\"1\\\"\\u000a\"");
+    }
+
+    #[test]
+    fn serve_state() {
+        let runtime = Runtime::builder()
+            .partition("io", 1)
+            .partition("compute", 2)
+            .debug_server("127.0.0.1:0")
+            .init();
+        let addr = runtime.debug_server_addr().unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("{\"workers\":[{\"id\":0,\"partition\":\"io\""));
+        assert!(response.contains("\"partition\":\"compute\""));
+        assert!(response.ends_with("}}"));
+
+        let _stats = runtime.join();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
@@ -1,5 +1,5 @@
+use std::cmp::Reverse;
 use std::collections::VecDeque;
-use std::collections::vec_deque::{Iter, IterMut};
 
 pub trait Steal<T> {
     fn steal(&mut self) -> Option<T>;
@@ -11,44 +11,197 @@ pub trait StealMany<T>: Steal<T> {
     type Loot;
 
     fn steal_many(&mut self) -> Option<Self::Loot>;
+
+    // Like `steal_many`, but steal at most `n` items
+    fn steal_up_to(&mut self, n: usize) -> Option<Self::Loot>;
+
+    // Like `steal_many`, but split by cumulative weight rather than number
+    // of items: steal (from the steal end) about `percent` percent of the
+    // total weight
+    fn steal_weighted<F>(&mut self, percent: u32, weight: F) -> Option<Self::Loot>
+    where F: Fn(&T) -> u32;
+
+    // Steal items (from the steal end) whose combined weight is closest to
+    // `budget`
+    fn steal_budget<F>(&mut self, budget: u64, weight: F) -> Option<Self::Loot>
+    where F: Fn(&T) -> u32;
+
+    // Steal at most `n` items with the smallest keys, preferring items closer
+    // to the steal end in case of ties. Stolen items keep their order.
+    fn steal_lowest<K, F>(&mut self, n: usize, key: F) -> Option<Self::Loot>
+    where K: Ord, F: Fn(&T) -> K;
 }
 
-// See newtype pattern
-pub struct Deque<T>(VecDeque<T>);
+// Items are kept in a sequence of segments, from the push end (first
+// segment) to the steal end (last segment). Splitting off items, as for
+// `steal_many`, hands over whole segments instead of moving items one by
+// one: only the segment at the split point is divided, so the cost of a
+// transfer depends on the number of segments, not on the number of items.
+pub struct Deque<T> {
+    segments: VecDeque<VecDeque<T>>,
+    len: usize,
+    // A segment that was emptied, kept around to avoid reallocating when
+    // pushing and popping across a segment boundary
+    spare: Option<VecDeque<T>>,
+}
+
+// Number of items per segment (when pushing one by one)
+const SEGMENT_LEN: usize = 64;
 
 impl<T> Deque<T> {
     pub fn new() -> Self {
-        Self(VecDeque::new())
+        Self { segments: VecDeque::new(), len: 0, spare: None }
+    }
+
+    fn from_segments(segments: VecDeque<VecDeque<T>>) -> Self {
+        let len = segments.iter().map(VecDeque::len).sum();
+        Self { segments, len, spare: None }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.len == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // Number of segments, which is what handing over items costs
+    pub fn num_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    fn new_segment(&mut self) -> VecDeque<T> {
+        self.spare.take().unwrap_or_else(|| VecDeque::with_capacity(SEGMENT_LEN))
+    }
+
+    // Drop empty segments at either end, but keep the last remaining one
+    fn trim(&mut self) {
+        if self.segments.len() > 1 && self.segments.front().is_some_and(VecDeque::is_empty) {
+            self.spare = self.segments.pop_front();
+        }
+        if self.segments.len() > 1 && self.segments.back().is_some_and(VecDeque::is_empty) {
+            self.spare = self.segments.pop_back();
+        }
     }
 
     pub fn push(&mut self, item: T) {
-        self.0.push_front(item);
+        if self.segments.front().is_none_or(|s| s.len() >= SEGMENT_LEN) {
+            let segment = self.new_segment();
+            self.segments.push_front(segment);
+        }
+        self.segments[0].push_front(item);
+        self.len += 1;
     }
 
     pub fn pop(&mut self) -> Option<T> {
-        self.0.pop_front()
+        let item = self.segments.front_mut()?.pop_front()?;
+        self.len -= 1;
+        self.trim();
+        Some(item)
+    }
+
+    // Push all items, as if pushed one by one (the last item ends up on top)
+    pub fn push_all<I: IntoIterator<Item = T>>(&mut self, items: I) {
+        for item in items {
+            self.push(item);
+        }
     }
 
+    // Push an item at the steal end, behind all other items
+    pub fn push_back(&mut self, item: T) {
+        if self.segments.back().is_none_or(|s| s.len() >= SEGMENT_LEN) {
+            let segment = self.new_segment();
+            self.segments.push_back(segment);
+        }
+        self.segments.back_mut().unwrap().push_back(item);
+        self.len += 1;
+    }
+
+    // Move all items of `other` to the steal end, as a segment of their own
     pub fn append(&mut self, other: &mut VecDeque<T>) {
-        self.0.append(other);
+        if other.is_empty() { return; }
+        self.len += other.len();
+        self.segments.push_back(std::mem::take(other));
+        self.trim();
     }
 
-    pub fn iter(&self) -> Iter<T> {
-        self.0.iter()
+    // Split off the last `len - at` items (at the steal end)
+    pub fn split_off(&mut self, at: usize) -> Self {
+        let mut n = self.len.saturating_sub(at);
+        let mut loot = VecDeque::new();
+        while n > 0 {
+            let segment = self.segments.back_mut().unwrap();
+            if segment.len() <= n {
+                // Hand over the whole segment
+                n -= segment.len();
+                loot.push_front(self.segments.pop_back().unwrap());
+            } else {
+                let at = segment.len() - n;
+                loot.push_front(segment.split_off(at));
+                n = 0;
+            }
+        }
+        let loot = Self::from_segments(loot);
+        self.len -= loot.len;
+        loot
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<T> {
-        self.0.iter_mut()
+    // Split off the first `n` items (at the push end)
+    pub fn split_front(&mut self, n: usize) -> Self {
+        let at = self.len - n.min(self.len);
+        let back = self.split_off(self.len - at);
+        std::mem::replace(self, back)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.segments.iter().map(VecDeque::capacity).sum()
+    }
+
+    // Move all items of `other` to the steal end of this deque, leaving
+    // `other` empty. Few items are moved into this deque's last segment
+    // (rather than replacing it), many items keep their segments.
+    pub fn take_from(&mut self, other: &mut Deque<T>) {
+        if other.is_empty() { return; }
+        match self.segments.back_mut() {
+            Some(segment) if segment.len() + other.len <= segment.capacity().max(SEGMENT_LEN) => {
+                for mut s in other.segments.drain(..) {
+                    segment.append(&mut s);
+                }
+            }
+            _ => self.segments.append(&mut other.segments),
+        }
+        self.len += other.len;
+        other.len = 0;
+        self.trim();
+    }
+
+    // Like `take_from`, but consume `other`
+    pub fn extend(&mut self, mut other: Deque<T>) {
+        self.take_from(&mut other);
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> {
+        self.segments.iter().flatten()
+    }
+
+    pub fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut T> {
+        self.segments.iter_mut().flatten()
+    }
+}
+
+impl<T> Default for Deque<T> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl<T> Steal<T> for Deque<T> {
     fn steal(&mut self) -> Option<T> {
-        self.0.pop_back()
+        let item = self.segments.back_mut()?.pop_back()?;
+        self.len -= 1;
+        self.trim();
+        Some(item)
     }
 }
 
@@ -56,12 +209,76 @@ impl<T> StealMany<T> for Deque<T> {
     type Loot = Self;
 
     fn steal_many(&mut self) -> Option<Self::Loot> {
-        let len = self.0.len();
+        let len = self.len;
         if len == 0 { return None; }
-        let split_deque = self.0.split_off(len / 2);
-        assert!(self.0.len() <= split_deque.len());
-        assert!(self.0.len() + split_deque.len() == len);
-        Some(Deque(split_deque))
+        let split_deque = self.split_off(len / 2);
+        assert!(self.len() <= split_deque.len());
+        assert!(self.len() + split_deque.len() == len);
+        Some(split_deque)
+    }
+
+    fn steal_up_to(&mut self, n: usize) -> Option<Self::Loot> {
+        let len = self.len;
+        if len == 0 || n == 0 { return None; }
+        // Never more than `steal_many` would steal
+        let n = n.min(len - len / 2);
+        Some(self.split_off(len - n))
+    }
+
+    fn steal_weighted<F>(&mut self, percent: u32, weight: F) -> Option<Self::Loot>
+    where F: Fn(&T) -> u32 {
+        let len = self.len;
+        if len == 0 { return None; }
+        let total: u64 = self.iter().map(|x| weight(x) as u64).sum();
+        // Steal at least one item, and stop as soon as the loot weighs at
+        // least `percent` percent of the total (same as `steal_many` for
+        // equal weights and 50 percent)
+        let mut n = 0;
+        let mut loot = 0;
+        for x in self.iter().rev() {
+            n += 1;
+            loot += weight(x) as u64;
+            if 100 * loot >= percent as u64 * total { break; }
+        }
+        Some(self.split_off(len - n))
+    }
+
+    fn steal_budget<F>(&mut self, budget: u64, weight: F) -> Option<Self::Loot>
+    where F: Fn(&T) -> u32 {
+        let len = self.len;
+        let mut n = 0;
+        let mut loot = 0;
+        for x in self.iter().rev() {
+            if loot >= budget { break; }
+            // Take the next item only if that gets us closer to the budget
+            let w = weight(x) as u64;
+            if loot + w > budget && loot + w - budget >= budget - loot { break; }
+            n += 1;
+            loot += w;
+        }
+        if n == 0 { return None; }
+        Some(self.split_off(len - n))
+    }
+
+    fn steal_lowest<K, F>(&mut self, n: usize, key: F) -> Option<Self::Loot>
+    where K: Ord, F: Fn(&T) -> K {
+        if self.is_empty() || n == 0 { return None; }
+        let keys = self.iter().map(&key).collect::<Vec<_>>();
+        let mut indices = (0..self.len).collect::<Vec<_>>();
+        indices.sort_by(|&i, &j| (&keys[i], Reverse(i)).cmp(&(&keys[j], Reverse(j))));
+        let mut selected = vec![false; self.len];
+        for &i in indices.iter().take(n) {
+            selected[i] = true;
+        }
+        // Rebuild both deques in a single pass, keeping the order of items
+        let mut rest = VecDeque::with_capacity(self.len);
+        let mut loot = VecDeque::new();
+        for (i, item) in self.segments.drain(..).flatten().enumerate() {
+            if selected[i] { loot.push_back(item) } else { rest.push_back(item) }
+        }
+        self.len = rest.len();
+        self.segments.push_back(rest);
+        Some(Self::from_segments(VecDeque::from([loot])))
     }
 }
 
@@ -168,4 +385,189 @@ mod tests {
         assert!(deque.is_empty());
         assert!(loot.is_empty());
     }
+
+    #[test]
+    fn deque_steal_up_to() {
+        let mut deque = Deque::new();
+
+        for i in 0..10 {
+            deque.push(i);
+        }
+
+        // deque: [9, 8, 7, 6, 5, 4, 3, 2, 1, 0]
+
+        let mut loot = deque.steal_up_to(3).unwrap();
+
+        // deque: [9, 8, 7, 6, 5, 4, 3]
+        // loot:  [2, 1, 0]
+
+        assert_eq!(deque.len(), 7);
+        assert_eq!(loot.len(), 3);
+        assert_eq!(loot.pop().unwrap(), 2);
+
+        // At most half of the remaining tasks
+        loot = deque.steal_up_to(100).unwrap();
+        assert_eq!(deque.len(), 3);
+        assert_eq!(loot.len(), 4);
+
+        assert!(deque.steal_up_to(0).is_none());
+    }
+
+    #[test]
+    fn deque_push_back() {
+        let mut deque = Deque::new();
+        deque.push(1);
+        deque.push(2);
+        deque.push_back(3);
+        assert_eq!(deque.pop(), Some(2));
+        assert_eq!(deque.steal(), Some(3));
+        assert_eq!(deque.pop(), Some(1));
+    }
+
+    #[test]
+    fn deque_take_from() {
+        let mut deque = Deque::new();
+        let mut other = Deque::new();
+
+        for i in 0..100 {
+            deque.push(i);
+        }
+        while deque.pop().is_some() {}
+        let capacity = deque.capacity();
+
+        for round in 0..3 {
+            for i in 0..10 {
+                other.push(round * 10 + i);
+            }
+            deque.take_from(&mut other);
+            assert!(other.is_empty());
+            assert_eq!(deque.len(), 10);
+            // No reallocation
+            assert_eq!(deque.capacity(), capacity);
+            // Items keep their order
+            assert_eq!(deque.pop().unwrap(), round * 10 + 9);
+            while deque.pop().is_some() {}
+        }
+    }
+
+    #[test]
+    fn deque_steal_weighted() {
+        let mut deque = Deque::new();
+
+        for i in 0..10 {
+            deque.push(i);
+        }
+
+        // deque: [9, 8, 7, 6, 5, 4, 3, 2, 1, 0]
+
+        // Equal weights: same as `steal_many`
+        let loot = deque.steal_weighted(50, |_| 1).unwrap();
+        assert_eq!(loot.len(), 5);
+
+        // deque: [9, 8, 7, 6, 5]
+        // One heavy item at the steal end outweighs the rest
+        let loot = deque.steal_weighted(50, |&x| if x == 5 { 100 } else { 1 }).unwrap();
+        assert_eq!(loot.iter().copied().collect::<Vec<_>>(), vec![5]);
+
+        // deque: [9, 8, 7, 6]
+        let loot = deque.steal_weighted(50, |&x| if x == 9 { 100 } else { 1 }).unwrap();
+        assert_eq!(loot.len(), 4);
+        assert!(deque.is_empty());
+        assert!(deque.steal_weighted(50, |_| 1).is_none());
+
+        // A quarter of the total weight
+        for i in 0..8 {
+            deque.push(i);
+        }
+        let loot = deque.steal_weighted(25, |&x| if x == 1 { 3 } else { 1 }).unwrap();
+        assert_eq!(loot.iter().copied().collect::<Vec<_>>(), vec![1, 0]);
+    }
+
+    #[test]
+    fn deque_steal_budget() {
+        let mut deque = Deque::new();
+
+        for i in 1..=5 {
+            deque.push(i);
+        }
+
+        // deque: [5, 4, 3, 2, 1]
+
+        // 1 + 2 + 3 = 6 is closer to 7 than 1 + 2 + 3 + 4 = 10
+        let loot = deque.steal_budget(7, |&x| x).unwrap();
+        assert_eq!(loot.iter().copied().collect::<Vec<_>>(), vec![3, 2, 1]);
+
+        // deque: [5, 4]
+        // Nothing is closer to 1 than 4, but 4 is closer to 3 than nothing
+        assert!(deque.steal_budget(0, |&x| x).is_none());
+        assert!(deque.steal_budget(1, |&x| x).is_none());
+        let loot = deque.steal_budget(3, |&x| x).unwrap();
+        assert_eq!(loot.len(), 1);
+        assert_eq!(deque.len(), 1);
+    }
+
+    #[test]
+    fn deque_split_front() {
+        let mut deque = Deque::new();
+        deque.push_all(0..5);
+        let front = deque.split_front(2);
+        assert_eq!(front.iter().copied().collect::<Vec<_>>(), vec![4, 3]);
+        assert_eq!(deque.iter().copied().collect::<Vec<_>>(), vec![2, 1, 0]);
+        assert_eq!(deque.split_front(5).len(), 3);
+        assert!(deque.is_empty());
+    }
+
+    #[test]
+    fn deque_push_all() {
+        let mut deque = Deque::new();
+        deque.push(0);
+        deque.push_all(1..4);
+        assert_eq!(deque.len(), 4);
+        assert_eq!(deque.iter().copied().collect::<Vec<_>>(), vec![3, 2, 1, 0]);
+
+        let mut other = Deque::new();
+        other.push_all([5, 4]);
+        deque.extend(other);
+        assert_eq!(deque.iter().copied().collect::<Vec<_>>(), vec![3, 2, 1, 0, 4, 5]);
+    }
+
+    #[test]
+    fn deque_steal_lowest() {
+        let mut deque = Deque::new();
+
+        for x in [3, 1, 2, 1, 0, 2] {
+            deque.push(x);
+        }
+
+        // deque: [2, 0, 1, 2, 1, 3]
+
+        let loot = deque.steal_lowest(3, |&x| x).unwrap();
+        assert_eq!(loot.iter().copied().collect::<Vec<_>>(), vec![0, 1, 1]);
+        assert_eq!(deque.iter().copied().collect::<Vec<_>>(), vec![2, 2, 3]);
+
+        // Ties are broken in favor of the steal end (FIFO)
+        let loot = deque.steal_lowest(1, |_| 0).unwrap();
+        assert_eq!(loot.iter().copied().collect::<Vec<_>>(), vec![3]);
+        assert!(deque.steal_lowest(0, |&x| x).is_none());
+    }
+
+    #[test]
+    fn deque_hand_over_segments() {
+        let mut deque = Deque::new();
+        deque.push_all(0..10 * SEGMENT_LEN);
+        assert_eq!(deque.num_segments(), 10);
+        let oldest = deque.iter().last().unwrap() as *const usize;
+
+        // Whole segments change hands, items stay where they are
+        let mut loot = deque.steal_many().unwrap();
+        assert_eq!(loot.len(), 5 * SEGMENT_LEN);
+        assert_eq!(loot.num_segments(), 5);
+        assert_eq!(loot.iter().last().unwrap() as *const usize, oldest);
+
+        // Only the segment at the split point is divided
+        let loot = loot.steal_up_to(SEGMENT_LEN + 1).unwrap();
+        assert_eq!(loot.num_segments(), 2);
+        assert_eq!(loot.iter().copied().rev().take(3).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(deque.iter().copied().next(), Some(10 * SEGMENT_LEN - 1));
+    }
 }
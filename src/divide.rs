@@ -0,0 +1,99 @@
+use crate::future::Future;
+use std::sync::Arc;
+
+// Divide and conquer
+//
+// Solve `problem` by splitting it in two until `is_base` holds, solving the
+// base cases with `solve_base`, and combining partial results with `merge`.
+// One half of every split is spawned as a task, the other half is solved
+// by the current task, which then waits for the spawned half (and helps in
+// the meantime). For example, the Fibonacci numbers:
+//
+//     let n = divide_and_conquer(
+//         30,
+//         |&n| n < 2,
+//         |n| n,
+//         |n| (n - 1, n - 2),
+//         |x, y| x + y,
+//     );
+
+struct Inner<P, R> {
+    is_base: Box<dyn Fn(&P) -> bool + Send + Sync>,
+    solve_base: Box<dyn Fn(P) -> R + Send + Sync>,
+    split: Box<dyn Fn(P) -> (P, P) + Send + Sync>,
+    merge: Box<dyn Fn(R, R) -> R + Send + Sync>,
+}
+
+pub fn divide_and_conquer<P, R, B, S, D, M>(
+    problem: P,
+    is_base: B,
+    solve_base: S,
+    split: D,
+    merge: M,
+) -> R
+where
+    P: Send + 'static,
+    R: Send + 'static,
+    B: Fn(&P) -> bool + Send + Sync + 'static,
+    S: Fn(P) -> R + Send + Sync + 'static,
+    D: Fn(P) -> (P, P) + Send + Sync + 'static,
+    M: Fn(R, R) -> R + Send + Sync + 'static,
+{
+    let inner = Arc::new(Inner {
+        is_base: Box::new(is_base),
+        solve_base: Box::new(solve_base),
+        split: Box::new(split),
+        merge: Box::new(merge),
+    });
+    solve(problem, &inner)
+}
+
+fn solve<P, R>(problem: P, inner: &Arc<Inner<P, R>>) -> R
+where P: Send + 'static, R: Send + 'static {
+    if (inner.is_base)(&problem) {
+        return (inner.solve_base)(problem);
+    }
+    let (left, right) = (inner.split)(problem);
+    let mut x = Future::lazy();
+    {
+        let inner = Arc::clone(inner);
+        let _ = spawn!(&mut x, solve(right, &inner));
+    }
+    let y = solve(left, inner);
+    (inner.merge)(y, x.wait())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use super::*;
+
+    #[test]
+    fn fib() {
+        let runtime = Runtime::init(3);
+
+        let n = divide_and_conquer(20, |&n| n < 2, |n| n, |n| (n - 1, n - 2), |x, y| x + y);
+        assert_eq!(n, 6765);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn sum_of_range() {
+        let runtime = Runtime::init(3);
+
+        let sum = divide_and_conquer(
+            0..1000u64,
+            |r| r.end - r.start <= 10,
+            |r| r.sum::<u64>(),
+            |r| {
+                let mid = r.start + (r.end - r.start) / 2;
+                (r.start..mid, mid..r.end)
+            },
+            |x, y| x + y,
+        );
+        assert_eq!(sum, 999 * 1000 / 2);
+
+        let _stats = runtime.join();
+    }
+}
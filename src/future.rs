@@ -1,16 +1,56 @@
+use crate::batch::{Batch, BatchSender, RemoteReceiver};
 use crate::channel::{one_shot_channel, Sender, Receiver};
-use crate::worker::{Tasks, Worker};
+use crate::lazy::Lazy;
+use crate::macros;
+use crate::registry::GlobalPromise;
+use crate::task::Async;
+use crate::worker::Worker;
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::time::Duration;
 
 // Futures and promises
 
 pub enum Future<T> {
-    Lazy(Option<T>),
+    // Receives the result in place, unless the task is stolen (see `lazy`)
+    Lazy(Lazy<T>),
     Chan(Receiver<T>),
 }
 
 pub enum Promise<T> {
-    Lazy(*mut Future<T>),
+    Lazy(*const Lazy<T>),
     Chan(Sender<T>),
+    // Deliver the result through a slot of a batch shared with sibling
+    // tasks, see `batch`
+    Batch(BatchSender<T>),
+    // Deliver the result through the spawning worker's `Completions`
+    Local(LocalPromise<T>),
+    // Deliver the result to an external receiver, for example, an event loop
+    Mpsc(mpsc::Sender<T>),
+    // Deliver the result through the process-wide registry, see `registry`
+    Global(GlobalPromise<T>),
+    #[cfg(feature = "tokio")]
+    Tokio(tokio::sync::oneshot::Sender<T>),
+}
+
+// Result of `Future::race`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+// Returned by `Future::get_timeout` if the result is not available in time,
+// handing back the future so that it can be retried later
+pub struct Timeout<T>(pub Future<T>);
+
+impl<T> fmt::Debug for Timeout<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Timeout")
+    }
 }
 
 // Rustonomicon: "Raw pointers are neither `Send` nor `Sync` (because they
@@ -23,30 +63,73 @@ pub enum Promise<T> {
 unsafe impl<T> Send for Promise<T> {}
 
 impl<T> Future<T> {
+    // A lazy future whose promise is yet to be created, see `Promise::from`
+    pub fn lazy() -> Self {
+        Self::Lazy(Lazy::new())
+    }
+
+    // A future that holds its result already
+    pub fn ready(value: T) -> Self {
+        Self::Lazy(Lazy::ready(value))
+    }
+
+    // A lazy future that must not be moved, because its promise still
+    // refers to it
+    fn is_pending_lazy(&self) -> bool {
+        matches!(self, Self::Lazy(lazy) if lazy.is_pending())
+    }
+
     // Block until result is available
     pub fn get(self) -> T {
         match self {
-            // Panic if opt.is_none() (better than waiting forever)
-            Self::Lazy(opt) => opt.unwrap(),
-            Self::Chan(chan) => {
-                while !chan.is_ready() {
-                    std::hint::spin_loop();
+            Self::Lazy(lazy) => {
+                if let Some(chan) = lazy.receiver() {
+                    chan.wait();
                 }
+                // Panic if the promise hasn't been set (better than waiting
+                // forever)
+                lazy.try_take().unwrap()
+            }
+            Self::Chan(chan) => {
+                chan.wait();
                 chan.receive()
             }
         }
     }
 
+    // Like `get`, but give up after `timeout`. Parks the calling thread until
+    // the sender wakes it up or the timeout expires.
+    // Lazy futures can't be handed back while their promise is pending, so
+    // they must have been resolved or promoted already (see `race`).
+    pub fn get_timeout(self, timeout: Duration) -> Result<T, Timeout<T>> {
+        assert!(!self.is_pending_lazy());
+        match self {
+            Self::Lazy(lazy) => {
+                if let Some(chan) = lazy.receiver() {
+                    if !chan.wait_timeout(timeout) {
+                        return Err(Timeout(Self::Lazy(lazy)));
+                    }
+                }
+                // Either ready or promoted and received in time
+                Ok(lazy.try_take().unwrap())
+            }
+            Self::Chan(chan) => match chan.recv_timeout(timeout) {
+                Some(result) => Ok(result),
+                None => Err(Timeout(Self::Chan(chan))),
+            },
+        }
+    }
+
     pub fn is_ready(&self) -> bool {
         match self {
-            Self::Lazy(opt) => opt.is_some(),
+            Self::Lazy(lazy) => lazy.is_ready(),
             Self::Chan(chan) => chan.is_ready(),
         }
     }
 
     fn try_get(&mut self) -> Option<T> {
         match self {
-            Self::Lazy(opt) => opt.take(),
+            Self::Lazy(lazy) => lazy.try_take(),
             Self::Chan(chan) => {
                 match chan.is_ready() {
                     true => Some(chan.receive()),
@@ -56,93 +139,510 @@ impl<T> Future<T> {
         }
     }
 
-    // Try to overlap waiting with useful work
+    // Try to overlap waiting with useful work. Threads without a worker
+    // have no tasks to help with, so they block instead (see `block`).
     // NOTE: We cannot consume the future because the associated promise
     // relies on the future's stack address!
     pub fn wait(&mut self) -> T {
         if let Some(val) = self.try_get() {
             return val;
         }
+        let Some(worker) = Worker::try_current() else {
+            return self.block();
+        };
+
+        // Priority inheritance: the task that computes our result runs at
+        // (at least) the priority of the task waiting for it, wherever it is
+        // queued, so that it isn't passed over in favor of unrelated urgent
+        // tasks (see `StealPolicy::LowestPriority`)
+        if let Self::Chan(chan) = self {
+            chan.raise_priority(worker.current_priority());
+        }
 
-        let worker = Worker::current();
-        let mut num_tasks_executed = 0;
+        // The associated promise may be promoted while we wait (when the
+        // task is stolen), which the lazy future handles internally. Until
+        // then, we are blocked on the critical path, so our steal requests
+        // take precedence over others.
+        worker.help_until_urgent(|| self.is_ready());
+        self.try_get().unwrap()
+    }
 
-        while let Some(task) = worker.pop() {
-            worker.try_handle_steal_request();
-            task.run();
-            num_tasks_executed += 1;
-            if let Some(val) = self.try_get() {
-                worker.stats.num_tasks_executed.add(num_tasks_executed);
-                return val;
+    // Like `get`, but without consuming the future: park the calling thread
+    // until the result is available
+    fn block(&mut self) -> T {
+        match self {
+            Self::Lazy(lazy) => {
+                if let Some(chan) = lazy.receiver() {
+                    chan.wait();
+                }
+                // Panic if the promise hasn't been set (see `get`)
+                lazy.try_take().unwrap()
             }
+            Self::Chan(chan) => chan.recv_blocking(),
         }
+    }
 
-        loop {
-            match worker.steal_one().wait() {
-                Tasks::None => (),
-                Tasks::One(task) => {
-                    task.run();
-                    num_tasks_executed += 1;
+    // Wait for whichever of two futures completes first, helping with tasks
+    // in the meantime. The other future is dropped along with its result.
+    // Lazy futures can't be moved while their promise is pending, so they
+    // must have been resolved already (see `get`).
+    pub fn race<U>(mut self, mut other: Future<U>) -> Future<Either<T, U>> {
+        assert!(!self.is_pending_lazy() && !other.is_pending_lazy());
+        if !self.is_ready() && !other.is_ready() {
+            Worker::current().help_until(|| self.is_ready() || other.is_ready());
+        }
+        let result = match self.try_get() {
+            Some(a) => Either::Left(a),
+            None => Either::Right(other.try_get().unwrap()),
+        };
+        Future::ready(result)
+    }
+
+    // Combine the results of two futures, without waiting for them: a task
+    // waits for both and delivers the pair (like `CancellableFuture::zip`).
+    // Lazy futures can't be moved to that task while their promise is
+    // pending, so they must have been resolved already (see `race`).
+    pub fn zip<U>(mut self, mut other: Future<U>) -> Future<(T, U)>
+    where T: Send + 'static, U: Send + 'static {
+        assert!(!self.is_pending_lazy() && !other.is_pending_lazy());
+        if self.is_ready() && other.is_ready() {
+            return Future::ready((self.try_get().unwrap(), other.try_get().unwrap()));
+        }
+        let (sender, receiver) = one_shot_channel();
+        let task = Async::new(move || (self.wait(), other.wait()), Some(Promise::from(sender)));
+        macros::push(task);
+        Future::Chan(receiver)
+    }
+
+    // Turn this future into one that can be cloned and waited for any number
+    // of times, see `SharedFuture`. Lazy futures must have been resolved
+    // already (see `race`).
+    pub fn shared(self) -> SharedFuture<T> where T: Clone {
+        assert!(!self.is_pending_lazy());
+        SharedFuture {
+            inner: Arc::new(Shared { future: Mutex::new(Some(self)), value: OnceLock::new() }),
+        }
+    }
+}
+
+// A future with any number of consumers, each of which receives a clone of
+// the result:
+//
+//     let f = spawn!(one_shot_channel, compute()).shared();
+//     for _ in 0..n {
+//         let f = f.clone();
+//         spawn!(use_result(f.wait()));
+//     }
+pub struct SharedFuture<T> {
+    inner: Arc<Shared<T>>,
+}
+
+struct Shared<T> {
+    // Taken by whoever picks up the result first
+    future: Mutex<Option<Future<T>>>,
+    value: OnceLock<T>,
+}
+
+impl<T> Clone for SharedFuture<T> {
+    fn clone(&self) -> Self {
+        Self { inner: Arc::clone(&self.inner) }
+    }
+}
+
+impl<T: Clone> SharedFuture<T> {
+    pub fn is_ready(&self) -> bool {
+        // A missing future without a value means someone else is receiving
+        // the result (and may be blocked until its task has run)
+        self.inner.value.get().is_some()
+            || self.inner.future.lock().unwrap().as_ref().is_some_and(Future::is_ready)
+    }
+
+    // Wait for the result, helping with tasks in the meantime (threads
+    // without a worker block instead)
+    pub fn wait(&self) -> T {
+        if !self.is_ready() {
+            if let Some(worker) = Worker::try_current() {
+                worker.help_until(|| self.is_ready());
+            }
+        }
+        self.get()
+    }
+
+    // Block until the result is available
+    pub fn get(&self) -> T {
+        self.inner.value.get_or_init(|| {
+            // Whoever gets here first receives the result on behalf of all
+            // consumers, while the others wait in `get_or_init`
+            let future = self.inner.future.lock().unwrap().take();
+            future.unwrap().get()
+        }).clone()
+    }
+}
+
+// Wait for all futures, collecting results in order. Instead of waiting for
+// one future after the other, we check all pending futures between tasks, so
+// that results are picked up as soon as they arrive.
+pub fn wait_all<T>(futures: &mut [Future<T>]) -> Vec<T> {
+    let mut results = futures.iter().map(|_| None).collect::<Vec<_>>();
+    let mut pending = futures.len();
+    loop {
+        for (fut, result) in futures.iter_mut().zip(results.iter_mut()) {
+            if result.is_none() {
+                *result = fut.try_get();
+                if result.is_some() {
+                    pending -= 1;
                 }
-                _ => panic!(),
             }
-            if let Some(res) = self.try_get() {
-                worker.stats.num_tasks_executed.add(num_tasks_executed);
-                return res;
+        }
+        if pending == 0 {
+            return results.into_iter().map(Option::unwrap).collect();
+        }
+        let Some(worker) = Worker::try_current() else {
+            // Without a worker, block on the pending futures in order
+            for (fut, result) in futures.iter_mut().zip(results.iter_mut()) {
+                result.get_or_insert_with(|| fut.block());
+            }
+            return results.into_iter().map(Option::unwrap).collect();
+        };
+        let futs = &*futures;
+        worker.help_until(|| {
+            futs.iter().zip(&results).any(|(fut, result)| result.is_none() && fut.is_ready())
+        });
+    }
+}
+
+// A collection of futures, typically created by spawning tasks in a loop:
+//
+//     let mut set: FutureSet<_> = (0..n)
+//         .map(|i| spawn!(one_shot_channel, work(i)))
+//         .collect();
+//     while let Some(result) = set.wait_any() { ... }
+//
+// Futures are moved around, so lazy futures must have been resolved already
+// (see `Future::race`).
+pub struct FutureSet<T> {
+    futures: Vec<Future<T>>,
+}
+
+impl<T> FutureSet<T> {
+    pub fn len(&self) -> usize {
+        self.futures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.futures.is_empty()
+    }
+
+    pub fn push(&mut self, future: Future<T>) {
+        assert!(!future.is_pending_lazy());
+        self.futures.push(future);
+    }
+
+    // Wait for all futures, returning results in order (see `wait_all`)
+    pub fn wait_all(mut self) -> Vec<T> {
+        wait_all(&mut self.futures)
+    }
+
+    // Wait for any future and remove it from the set, returning its result,
+    // or `None` if the set is empty. Results are returned in the order they
+    // become available.
+    pub fn wait_any(&mut self) -> Option<T> {
+        if self.futures.is_empty() {
+            return None;
+        }
+        loop {
+            if let Some(i) = self.futures.iter().position(|f| f.is_ready()) {
+                return self.futures.swap_remove(i).try_get();
             }
+            let futures = &self.futures;
+            Worker::current().help_until(|| futures.iter().any(|f| f.is_ready()));
         }
     }
 }
 
+impl<T> FromIterator<Future<T>> for FutureSet<T> {
+    fn from_iter<I: IntoIterator<Item = Future<T>>>(iter: I) -> Self {
+        let mut set = Self { futures: vec![] };
+        for future in iter {
+            set.push(future);
+        }
+        set
+    }
+}
+
 impl<T> Promise<T> {
+    // Priority inherited from a waiting task, see `Future::wait`
+    pub fn inherited_priority(&self) -> u32 {
+        match self {
+            Self::Chan(chan) => chan.priority(),
+            _ => 0,
+        }
+    }
+
+    // Lazy promises can be promoted on any thread, local promises only on
+    // the spawning worker's thread
     pub fn promote(&mut self) {
-        match *self {
-            Self::Lazy(fut) => {
-                let (sender, receiver) = one_shot_channel();
-                unsafe { *fut = Future::Chan(receiver); }
-                *self = Self::Chan(sender);
-            },
-            Self::Chan(_) => (),
+        self.promote_into(None);
+    }
+
+    // Like `promote`, but deliver the result through a slot of `batch`, if
+    // given, instead of a channel of its own
+    pub fn promote_into(&mut self, batch: Option<&Arc<Batch>>) {
+        // Only lazy and local promises need promotion
+        match (&*self, batch) {
+            (Self::Lazy(lazy), None) => *self = Self::Chan(unsafe { (**lazy).promote() }),
+            (Self::Lazy(lazy), Some(batch)) => *self = Self::Batch(unsafe { (**lazy).promote_batched(batch) }),
+            (Self::Local(local), None) => *self = Self::Chan(local.promote()),
+            (Self::Local(local), Some(batch)) => *self = Self::Batch(local.promote_batched(batch)),
+            _ => (),
         }
     }
 
     pub fn set(self, value: T) {
         match self {
-            Self::Lazy(fut) => unsafe {
-                match *fut {
-                    Future::Lazy(ref mut opt) => {
-                        assert!(opt.is_none());
-                        *opt = Some(value);
-                    }
-                    Future::Chan(_) => {
-                        // Something went wrong
-                        panic!();
-                    }
-                }
-            }
+            Self::Lazy(lazy) => unsafe { (*lazy).set(value) },
             Self::Chan(chan) => {
                 chan.send(value);
             }
+            Self::Batch(batch) => batch.send(value),
+            Self::Local(local) => local.set(value),
+            Self::Global(global) => global.set(value),
+            // The receiver may have been dropped in the meantime
+            Self::Mpsc(chan) => {
+                let _ = chan.send(value);
+            }
+            #[cfg(feature = "tokio")]
+            Self::Tokio(chan) => {
+                let _ = chan.send(value);
+            }
         }
     }
 }
 
+// Results of tasks that are run by the worker that spawned them, which is
+// the common case, don't need to cross threads. Such results are handed over
+// through a table of the worker, without the synchronization of a one-shot
+// channel (see `Scope::spawn`). Promoting a task (when it's stolen) gives its
+// slot a channel instead, whose receiving end is kept in the slot.
+#[derive(Default)]
+pub struct Completions {
+    slots: Vec<Completion>,
+    free: Vec<usize>,
+}
+
+enum Completion {
+    Pending,
+    // The result, once the task has run
+    Ready(Box<dyn Any>),
+    // The receiving end of the task's channel (a `RemoteReceiver`), after
+    // promotion
+    Promoted(Box<dyn Any>),
+    // Nobody is interested in the result anymore
+    Abandoned,
+    Free,
+}
+
+impl Completions {
+    pub fn reserve(&mut self) -> usize {
+        match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot] = Completion::Pending;
+                slot
+            }
+            None => {
+                self.slots.push(Completion::Pending);
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, slot: usize) {
+        self.slots[slot] = Completion::Free;
+        self.free.push(slot);
+    }
+
+    // Number of slots in use
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn set<T: 'static>(&mut self, slot: usize, value: T) {
+        match self.slots[slot] {
+            Completion::Pending => self.slots[slot] = Completion::Ready(Box::new(value)),
+            Completion::Abandoned => self.release(slot),
+            _ => panic!("Completion {} is not pending", slot),
+        }
+    }
+
+    pub fn promote<T: Send + 'static>(&mut self, slot: usize) -> Sender<T> {
+        let (sender, receiver) = one_shot_channel::<T>();
+        self.install(slot, RemoteReceiver::Chan(receiver));
+        sender
+    }
+
+    pub fn promote_batched<T: Send + 'static>(&mut self, slot: usize, batch: &Arc<Batch>) -> BatchSender<T> {
+        let (sender, receiver) = batch.channel::<T>();
+        self.install(slot, RemoteReceiver::Batch(receiver));
+        sender
+    }
+
+    fn install<T: 'static>(&mut self, slot: usize, receiver: RemoteReceiver<T>) {
+        match self.slots[slot] {
+            Completion::Pending => self.slots[slot] = Completion::Promoted(Box::new(receiver)),
+            // The result will be dropped along with the channel
+            Completion::Abandoned => self.release(slot),
+            _ => panic!("Completion {} is not pending", slot),
+        }
+    }
+
+    pub fn is_ready<T: 'static>(&self, slot: usize) -> bool {
+        match &self.slots[slot] {
+            Completion::Ready(_) => true,
+            Completion::Promoted(receiver) => receiver.downcast_ref::<RemoteReceiver<T>>().unwrap().is_ready(),
+            _ => false,
+        }
+    }
+
+    // Take the result if it's there, releasing the slot
+    pub fn take<T: 'static>(&mut self, slot: usize) -> Option<T> {
+        if !self.is_ready::<T>(slot) {
+            return None;
+        }
+        let result = match std::mem::replace(&mut self.slots[slot], Completion::Free) {
+            Completion::Ready(value) => *value.downcast::<T>().unwrap(),
+            Completion::Promoted(receiver) => receiver.downcast::<RemoteReceiver<T>>().unwrap().receive(),
+            _ => unreachable!(),
+        };
+        self.free.push(slot);
+        Some(result)
+    }
+
+    // Give up on a result, which is dropped whenever it arrives
+    pub fn abandon(&mut self, slot: usize) {
+        match self.slots[slot] {
+            Completion::Pending => self.slots[slot] = Completion::Abandoned,
+            _ => self.release(slot),
+        }
+    }
+}
+
+// A promise backed by a slot in the `Completions` of the spawning worker.
+// Until the task is promoted, it stays on the spawning worker (tasks that
+// leave their worker are always promoted first), so the promise is only
+// ever used, and dropped, on the worker's thread.
+pub struct LocalPromise<T> {
+    completions: Rc<RefCell<Completions>>,
+    slot: usize,
+    // Instantiated where `T: 'static` is known, see `new`
+    set: fn(&mut Completions, usize, T),
+    promote: fn(&mut Completions, usize) -> Sender<T>,
+    promote_batched: fn(&mut Completions, usize, &Arc<Batch>) -> BatchSender<T>,
+}
+
+impl<T: Send + 'static> LocalPromise<T> {
+    pub fn new(completions: Rc<RefCell<Completions>>) -> Self {
+        let slot = completions.borrow_mut().reserve();
+        Self {
+            completions,
+            slot,
+            set: Completions::set,
+            promote: Completions::promote,
+            promote_batched: Completions::promote_batched,
+        }
+    }
+}
+
+impl<T> LocalPromise<T> {
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    fn set(self, value: T) {
+        (self.set)(&mut self.completions.borrow_mut(), self.slot, value);
+    }
+
+    fn promote(&self) -> Sender<T> {
+        (self.promote)(&mut self.completions.borrow_mut(), self.slot)
+    }
+
+    fn promote_batched(&self, batch: &Arc<Batch>) -> BatchSender<T> {
+        (self.promote_batched)(&mut self.completions.borrow_mut(), self.slot, batch)
+    }
+}
+
+impl<T> From<LocalPromise<T>> for Promise<T> {
+    fn from(value: LocalPromise<T>) -> Self {
+        Promise::Local(value)
+    }
+}
+
 impl<T> From<Sender<T>> for Promise<T> {
     fn from(value: Sender<T>) -> Self {
         Promise::Chan(value)
     }
 }
 
+impl<T> From<GlobalPromise<T>> for Promise<T> {
+    fn from(promise: GlobalPromise<T>) -> Self {
+        Self::Global(promise)
+    }
+}
+
+impl<T> From<mpsc::Sender<T>> for Promise<T> {
+    fn from(value: mpsc::Sender<T>) -> Self {
+        Promise::Mpsc(value)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> From<tokio::sync::oneshot::Sender<T>> for Promise<T> {
+    fn from(value: tokio::sync::oneshot::Sender<T>) -> Self {
+        Promise::Tokio(value)
+    }
+}
+
+// The future must stay in place until the promise is set or promoted
 impl<T> From<&mut Future<T>> for Promise<T> {
     fn from(value: &mut Future<T>) -> Self {
-        Promise::Lazy(value)
+        match value {
+            Future::Lazy(lazy) => Promise::Lazy(lazy),
+            Future::Chan(_) => panic!("Promise of a channel future"),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::thread;
     use super::*;
+    use std::thread;
+
+    #[test]
+    fn completions() {
+        let mut completions = Completions::default();
+        let a = completions.reserve();
+        let b = completions.reserve();
+        assert_ne!(a, b);
+        assert!(!completions.is_ready::<u32>(a));
+        assert_eq!(completions.take::<u32>(a), None);
+        completions.set(a, 1_u32);
+        assert_eq!(completions.take::<u32>(a), Some(1));
+        // Released slots are reused
+        assert_eq!(completions.reserve(), a);
+
+        let sender = completions.promote::<String>(b);
+        assert!(!completions.is_ready::<String>(b));
+        thread::spawn(move || sender.send(String::from("b"))).join().unwrap();
+        assert_eq!(completions.take::<String>(b).as_deref(), Some("b"));
+
+        // Abandoned results are dropped when they arrive
+        completions.abandon(a);
+        completions.set(a, 2_u32);
+        assert!(completions.is_empty());
+    }
 
     #[test]
     fn future_promise() {
@@ -151,14 +651,201 @@ mod tests {
         assert_eq!(Future::Chan(receiver).get(), 1);
     }
 
+    #[test]
+    fn mpsc_promise() {
+        let (sender, receiver) = mpsc::channel();
+        let senders = (0..3).map(|_| sender.clone()).collect::<Vec<_>>();
+        let threads = senders
+            .into_iter()
+            .enumerate()
+            .map(|(i, sender)| {
+                let mut p = Promise::from(sender);
+                p.promote();
+                thread::spawn(move || p.set(i))
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+        let mut results = receiver.try_iter().collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, vec![0, 1, 2]);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_promise() {
+        let (sender, mut receiver) = tokio::sync::oneshot::channel();
+        let p = Promise::from(sender);
+        thread::spawn(move || p.set(42)).join().unwrap();
+        assert_eq!(receiver.try_recv().unwrap(), 42);
+    }
+
+    #[test]
+    fn future_get_timeout() {
+        let (sender, receiver) = one_shot_channel();
+        let f = Future::Chan(receiver);
+        let f = match f.get_timeout(Duration::from_millis(1)) {
+            Err(Timeout(f)) => f,
+            Ok(_) => panic!(),
+        };
+        let t = thread::spawn(|| Promise::Chan(sender).set(1));
+        assert_eq!(f.get_timeout(Duration::from_secs(10)).unwrap(), 1);
+        t.join().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "is_pending_lazy")]
+    fn future_get_timeout_pending_lazy() {
+        assert_eq!(Future::ready(1).get_timeout(Duration::ZERO).unwrap(), 1);
+        let f = Future::<i32>::lazy();
+        let _ = f.get_timeout(Duration::from_millis(1));
+    }
+
+    #[test]
+    fn future_race() {
+        let runtime = crate::runtime::Runtime::init(2);
+
+        // Never completes
+        let (_sender, receiver) = one_shot_channel::<i32>();
+        let f = spawn!(one_shot_channel, 42);
+        assert_eq!(Future::Chan(receiver).race(f).get(), Either::Right(42));
+
+        let (_sender, receiver) = one_shot_channel::<i32>();
+        let f = spawn!(one_shot_channel, "done");
+        assert_eq!(f.race(Future::Chan(receiver)).get(), Either::Left("done"));
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn future_zip() {
+        let runtime = crate::runtime::Runtime::init(2);
+
+        let f = spawn!(one_shot_channel, 1);
+        let g = spawn!(one_shot_channel, "two");
+        assert_eq!(f.zip(g).wait(), (1, "two"));
+
+        let f = spawn!(one_shot_channel, 1);
+        let g = spawn!(one_shot_channel, 2);
+        let h = Future::ready(3);
+        assert_eq!(f.zip(g).zip(h).wait(), ((1, 2), 3));
+
+        // Zipping doesn't wait
+        let (sender, receiver) = one_shot_channel();
+        let mut fg = Future::ready(1).zip(Future::Chan(receiver));
+        assert!(!fg.is_ready());
+        sender.send(2);
+        assert_eq!(fg.wait(), (1, 2));
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn shared_future() {
+        let runtime = crate::runtime::Runtime::init(2);
+
+        let f = spawn!(one_shot_channel, String::from("result")).shared();
+        let futures = (0..10)
+            .map(|_| {
+                let f = f.clone();
+                spawn!(one_shot_channel, f.wait().len())
+            })
+            .collect::<FutureSet<_>>();
+        let thread = {
+            let f = f.clone();
+            thread::spawn(move || f.get())
+        };
+        assert_eq!(futures.wait_all(), vec![6; 10]);
+        assert_eq!(thread.join().unwrap(), "result");
+        assert!(f.is_ready());
+        assert_eq!(f.wait(), "result");
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn future_wait_without_worker() {
+        let runtime = crate::runtime::Runtime::init(2);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for i in 0..4 {
+            sender.send(spawn!(one_shot_channel, { thread::sleep(Duration::from_millis(10)); i })).unwrap();
+        }
+        drop(sender);
+        // An application thread has no worker to help, so it blocks
+        let thread = thread::spawn(move || {
+            assert!(Worker::try_current().is_none());
+            let mut futures = receiver.iter().collect::<Vec<_>>();
+            let first = futures[0].wait();
+            (first, wait_all(&mut futures[1..]))
+        });
+        runtime.wait_until_idle();
+        assert_eq!(thread.join().unwrap(), (0, vec![1, 2, 3]));
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn future_wait_all() {
+        let runtime = crate::runtime::Runtime::init(2);
+
+        let mut futures = (0..10)
+            .map(|i| match i % 2 {
+                0 => spawn!(one_shot_channel, i),
+                _ => Future::ready(i),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(wait_all(&mut futures), (0..10).collect::<Vec<_>>());
+        assert!(wait_all::<i32>(&mut []).is_empty());
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn future_set() {
+        let runtime = crate::runtime::Runtime::init(2);
+
+        let set: FutureSet<_> = (0..10).map(|i| spawn!(one_shot_channel, i)).collect();
+        assert_eq!(set.len(), 10);
+        assert_eq!(set.wait_all(), (0..10).collect::<Vec<_>>());
+
+        let mut set: FutureSet<_> = (0..10).map(|i| spawn!(one_shot_channel, i)).collect();
+        let mut results = vec![];
+        while let Some(result) = set.wait_any() {
+            results.push(result);
+        }
+        results.sort();
+        assert_eq!(results, (0..10).collect::<Vec<_>>());
+        assert!(set.is_empty());
+
+        let _stats = runtime.join();
+    }
+
     #[test]
     fn future_promise_lazy() {
-        let mut f = Future::Lazy(None);
-        let p = Promise::Lazy(&mut f);
+        let mut f = Future::lazy();
+        let p = Promise::from(&mut f);
         p.set(1);
         assert_eq!(f.get(), 1);
     }
 
+    // Promotion no longer has to happen on the owner's thread
+    #[test]
+    fn future_promise_lazy_promoted_elsewhere() {
+        let mut f = Future::lazy();
+        let mut p = Promise::from(&mut f);
+        thread::scope(|s| {
+            s.spawn(move || {
+                p.promote();
+                p.set(1);
+            });
+            while !f.is_ready() {}
+        });
+        assert!(matches!(&f, Future::Lazy(lazy) if lazy.is_promoted()));
+        assert_eq!(f.get(), 1);
+    }
+
     #[test]
     fn future_promise_thread() {
         let (sender1, receiver1) = one_shot_channel();
@@ -177,13 +864,13 @@ mod tests {
 
     #[test]
     fn future_promise_lazy_thread() {
-        let mut f1 = Future::Lazy(None);
-        let mut p1 = Promise::Lazy(&mut f1);
+        let mut f1 = Future::lazy();
+        let mut p1 = Promise::from(&mut f1);
         p1.promote();
 
         let t = thread::spawn(|| {
-            let mut f2 = Future::Lazy(None);
-            let mut p2 = Promise::Lazy(&mut f2);
+            let mut f2 = Future::lazy();
+            let mut p2 = Promise::from(&mut f2);
             p2.promote();
             p1.set(("ping", p2));
             assert_eq!(f2.get(), "pong");
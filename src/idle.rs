@@ -0,0 +1,165 @@
+use crate::worker::Worker;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+// What a worker does when it has nothing to do, that is, while waiting for a
+// steal response, or after a steal attempt came back empty-handed. Spinning
+// minimizes latency, sleeping and parking minimize CPU usage.
+pub trait IdleStrategy: Send + Sync + fmt::Debug {
+    // Called after `attempt` consecutive unsuccessful attempts to find work
+    // (starting from zero)
+    fn idle(&self, attempt: u32);
+}
+
+// Busy-wait (the default)
+#[derive(Debug, Clone, Copy)]
+pub struct Spin;
+
+impl IdleStrategy for Spin {
+    fn idle(&self, _attempt: u32) {
+        std::hint::spin_loop();
+    }
+}
+
+// Give up the time slice to other threads
+#[derive(Debug, Clone, Copy)]
+pub struct Yield;
+
+impl IdleStrategy for Yield {
+    fn idle(&self, _attempt: u32) {
+        thread::yield_now();
+    }
+}
+
+// Sleep for exponentially increasing durations, starting from one
+// microsecond, up to `max`
+#[derive(Debug, Clone, Copy)]
+pub struct Sleep {
+    pub max: Duration,
+}
+
+impl IdleStrategy for Sleep {
+    fn idle(&self, attempt: u32) {
+        let duration = Duration::from_micros(1 << attempt.min(20));
+        thread::sleep(duration.min(self.max));
+    }
+}
+
+// Park the thread for at most `timeout` (or until it is unparked)
+#[derive(Debug, Clone, Copy)]
+pub struct Park {
+    pub timeout: Duration,
+}
+
+impl IdleStrategy for Park {
+    fn idle(&self, _attempt: u32) {
+        thread::park_timeout(self.timeout);
+    }
+}
+
+// Save energy while the runtime is mostly idle: yield at first, then sleep
+// for exponentially increasing durations, and finally park until there is
+// work again. Only the first `scouts` workers (including the leader) never
+// park; they keep polling, sleeping at most `max_sleep` at a time, and wake
+// up parked workers when they see queued tasks. Victims that still have
+// tasks to spare after answering a steal request wake up parked workers,
+// too, so that the runtime gradually comes back to full strength.
+#[derive(Debug, Clone, Copy)]
+pub struct EnergySaving {
+    pub scouts: usize,
+    // Attempts to yield before starting to sleep
+    pub yield_attempts: u32,
+    // Attempts to sleep before parking
+    pub sleep_attempts: u32,
+    pub max_sleep: Duration,
+}
+
+impl EnergySaving {
+    pub fn new(scouts: usize) -> Self {
+        Self {
+            scouts: scouts.max(1),
+            yield_attempts: 64,
+            sleep_attempts: 16,
+            max_sleep: Duration::from_millis(1),
+        }
+    }
+}
+
+impl IdleStrategy for EnergySaving {
+    fn idle(&self, attempt: u32) {
+        let worker = Worker::current();
+        if attempt < self.yield_attempts {
+            return thread::yield_now();
+        }
+        let attempt = attempt - self.yield_attempts;
+        if worker.id < self.scouts {
+            worker.wake_sleeper();
+            return Sleep { max: self.max_sleep }.idle(attempt);
+        }
+        match attempt < self.sleep_attempts {
+            true => Sleep { max: self.max_sleep }.idle(attempt),
+            false => worker.park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use super::*;
+
+    fn run_with(strategy: impl IdleStrategy + 'static) {
+        let runtime = Runtime::builder()
+            .num_workers(3)
+            .idle_strategy(strategy)
+            .init();
+
+        finish! {
+            for _ in 0..100 {
+                scoped_spawn!();
+            }
+        }
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 100);
+    }
+
+    #[test]
+    fn strategies() {
+        run_with(Spin);
+        run_with(Yield);
+        run_with(Sleep { max: Duration::from_micros(100) });
+        run_with(Park { timeout: Duration::from_micros(100) });
+        run_with(EnergySaving { yield_attempts: 0, sleep_attempts: 0, ..EnergySaving::new(1) });
+    }
+
+    #[test]
+    fn energy_saving() {
+        let runtime = Runtime::builder()
+            .num_workers(4)
+            .idle_strategy(EnergySaving { yield_attempts: 1, sleep_attempts: 1, ..EnergySaving::new(2) })
+            .init();
+        let loads = runtime.workers();
+
+        // With nothing to do, all workers but the scout park eventually
+        let start = std::time::Instant::now();
+        while !loads[2..].iter().all(|w| w.is_parked()) {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!loads[1].is_parked());
+        // Parked workers don't hold up snapshots
+        assert_eq!(runtime.snapshot().num_tasks_executed.get(), 0);
+
+        // Parked workers take part again once there is work
+        finish! {
+            for _ in 0..200 {
+                scoped_spawn!(thread::sleep(Duration::from_micros(200)));
+            }
+        }
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 200);
+        assert_stat!(stats.workers()[2..].iter().any(|w| w.num_tasks_executed.get() > 0));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::batch::{Batch, BatchSender, RemoteReceiver};
+use crate::channel::{one_shot_channel, Sender};
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering::{Acquire, Release, Relaxed};
+
+// The slot of a lazy future
+//
+// A lazy future lives on the spawner's stack and receives its result in
+// place, unless the task is stolen, in which case its promise is promoted to
+// a one-shot channel (or a slot of a batch, see `batch`). Promotion and
+// setting the result may happen on any thread while the owner checks the
+// future, so the slot is a small state machine rather than an enum that is
+// overwritten behind the owner's back:
+//
+//     PENDING --(set)------> BUSY --> READY ----(take)--> TAKEN
+//     PENDING --(promote)--> BUSY --> PROMOTED
+//
+// Only the promise moves the slot out of PENDING, and only the owner takes
+// the result, so every transition is a single atomic operation on either
+// side and nobody ever waits for the other (a slot that is BUSY is simply
+// not ready yet). After promotion, the slot holds the receiving end of the
+// channel, which the owner checks instead.
+
+const PENDING: u8 = 0;
+// The promise is writing the result or the receiver
+const BUSY: u8 = 1;
+const READY: u8 = 2;
+const PROMOTED: u8 = 3;
+const TAKEN: u8 = 4;
+
+pub struct Lazy<T> {
+    state: AtomicU8,
+    // Written by `set`, valid in state READY
+    value: UnsafeCell<MaybeUninit<T>>,
+    // Written by `promote`, valid in state PROMOTED
+    receiver: UnsafeCell<MaybeUninit<RemoteReceiver<T>>>,
+}
+
+impl<T> Lazy<T> {
+    pub fn new() -> Self {
+        Self {
+            state: AtomicU8::new(PENDING),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            receiver: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    // A slot that holds its result already
+    pub fn ready(value: T) -> Self {
+        Self {
+            state: AtomicU8::new(READY),
+            value: UnsafeCell::new(MaybeUninit::new(value)),
+            receiver: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    // Neither set nor promoted yet, so the promise still refers to the slot
+    // and the slot must not be moved
+    pub fn is_pending(&self) -> bool {
+        matches!(self.state.load(Acquire), PENDING | BUSY)
+    }
+
+    pub fn is_promoted(&self) -> bool {
+        self.state.load(Acquire) == PROMOTED
+    }
+
+    pub fn is_ready(&self) -> bool {
+        match self.state.load(Acquire) {
+            READY => true,
+            PROMOTED => unsafe { (*self.receiver.get()).assume_init_ref() }.is_ready(),
+            _ => false,
+        }
+    }
+
+    // The receiving end of the channel after promotion
+    pub fn receiver(&self) -> Option<&RemoteReceiver<T>> {
+        match self.state.load(Acquire) {
+            PROMOTED => Some(unsafe { (*self.receiver.get()).assume_init_ref() }),
+            _ => None,
+        }
+    }
+
+    // Take the result if it's there
+    pub fn try_take(&self) -> Option<T> {
+        match self.state.load(Acquire) {
+            READY => {
+                // Only the owner takes the result
+                self.state.store(TAKEN, Relaxed);
+                Some(unsafe { (*self.value.get()).assume_init_read() })
+            }
+            PROMOTED => {
+                let receiver = unsafe { (*self.receiver.get()).assume_init_ref() };
+                receiver.is_ready().then(|| receiver.receive())
+            }
+            _ => None,
+        }
+    }
+
+    // Called by the promise, on any thread
+    pub fn set(&self, value: T) {
+        let claimed = self.state.compare_exchange(PENDING, BUSY, Acquire, Relaxed).is_ok();
+        assert!(claimed, "promise set more than once (lazy future)");
+        unsafe { (*self.value.get()).write(value) };
+        self.state.store(READY, Release);
+    }
+
+    // Called by the promise, on any thread. Returns the sending end of the
+    // channel that replaces the slot.
+    pub fn promote(&self) -> Sender<T> {
+        let (sender, receiver) = one_shot_channel();
+        self.install(RemoteReceiver::Chan(receiver));
+        sender
+    }
+
+    // Like `promote`, but receive the result through a slot of `batch`
+    pub fn promote_batched(&self, batch: &Arc<Batch>) -> BatchSender<T> {
+        let (sender, receiver) = batch.channel();
+        self.install(RemoteReceiver::Batch(receiver));
+        sender
+    }
+
+    fn install(&self, receiver: RemoteReceiver<T>) {
+        let claimed = self.state.compare_exchange(PENDING, BUSY, Acquire, Relaxed).is_ok();
+        assert!(claimed, "promise promoted after it was set (lazy future)");
+        unsafe { (*self.receiver.get()).write(receiver) };
+        self.state.store(PROMOTED, Release);
+    }
+}
+
+// The owner and the promise access the slot concurrently, coordinated by
+// `state`
+unsafe impl<T> Sync for Lazy<T> where T: Send {}
+
+impl<T> fmt::Debug for Lazy<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state.load(Relaxed) {
+            PENDING | BUSY => "pending",
+            READY => "ready",
+            PROMOTED => "promoted",
+            _ => "taken",
+        };
+        f.debug_struct("Lazy").field("state", &state).finish()
+    }
+}
+
+impl<T> Drop for Lazy<T> {
+    fn drop(&mut self) {
+        match *self.state.get_mut() {
+            READY => unsafe { self.value.get_mut().assume_init_drop() },
+            PROMOTED => unsafe { self.receiver.get_mut().assume_init_drop() },
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn set_take() {
+        let lazy = Lazy::new();
+        assert!(lazy.is_pending());
+        assert_eq!(lazy.try_take(), None);
+        lazy.set(String::from("result"));
+        assert!(lazy.is_ready() && !lazy.is_pending());
+        assert_eq!(lazy.try_take().as_deref(), Some("result"));
+        assert!(!lazy.is_ready());
+
+        // Results that are never taken are dropped along with the slot
+        let lazy = Lazy::ready(String::from("dropped"));
+        assert!(lazy.is_ready());
+    }
+
+    #[test]
+    fn promote_take() {
+        let lazy = Lazy::new();
+        let sender = lazy.promote();
+        assert!(lazy.is_promoted() && !lazy.is_ready());
+        sender.send(1);
+        assert!(lazy.is_ready());
+        assert_eq!(lazy.try_take(), Some(1));
+    }
+
+    #[test]
+    fn promote_batched() {
+        let batch = Batch::new(2);
+        let (a, b) = (Lazy::new(), Lazy::new());
+        let (sa, sb) = (a.promote_batched(&batch), b.promote_batched(&batch));
+        assert!(a.is_promoted() && b.is_promoted());
+        sb.send("b");
+        assert_eq!(a.try_take(), None);
+        assert_eq!(b.try_take(), Some("b"));
+        sa.send("a");
+        assert_eq!(a.try_take(), Some("a"));
+        assert_eq!(batch.num_ready(), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn promote_after_set() {
+        let lazy = Lazy::new();
+        lazy.set(1);
+        lazy.promote();
+    }
+
+    // The owner keeps checking while another thread promotes the slot and
+    // sends the result
+    #[test]
+    fn concurrent_promotion() {
+        for i in 0..1000 {
+            let lazy = Lazy::new();
+            thread::scope(|s| {
+                s.spawn(|| match i % 2 {
+                    0 => lazy.promote().send(i),
+                    _ => lazy.set(i),
+                });
+                loop {
+                    if let Some(value) = lazy.try_take() {
+                        assert_eq!(value, i);
+                        break;
+                    }
+                }
+            });
+        }
+    }
+}
@@ -1,13 +1,44 @@
 #![allow(clippy::new_without_default)]
+// `try_spawn!` hands back rejected tasks as is, and `TaskKind` is deliberately
+// unboxed
+#![allow(clippy::result_large_err)]
 
 #[macro_use]
 pub mod macros;
+pub mod actor;
+pub mod affinity;
+pub mod algorithms;
 pub mod atomic;
+pub mod batch;
+pub mod buffer;
+pub mod calibration;
+pub mod cancel;
 pub mod channel;
+pub mod collect;
+pub mod cost;
+#[cfg(feature = "debug-server")]
+pub mod debug_server;
 pub mod deque;
+pub mod divide;
 pub mod future;
+pub mod idle;
+pub mod lazy;
+pub mod local;
+pub mod pipeline;
+pub mod pool;
+pub mod priority;
+pub mod quiescence;
+pub mod registry;
 pub mod runtime;
 pub mod scope;
+pub mod source;
 pub mod stats;
+pub mod sync;
 pub mod task;
+pub mod trace;
+pub mod watermark;
 pub mod worker;
+
+pub use collect::spawn_collect;
+pub use divide::divide_and_conquer;
+pub use runtime::run_with;
@@ -0,0 +1,95 @@
+use crate::worker::Worker;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Local-only tasks
+//
+// A local task is never stolen, so it can produce results that aren't
+// `Send`, like `Rc`-based data structures. Local tasks are run by the worker
+// that spawned them whenever it runs out of other (stealable) work, for
+// example, while waiting for a future.
+
+pub struct LocalFuture<T> {
+    result: Rc<RefCell<Option<T>>>,
+}
+
+impl<T> LocalFuture<T> {
+    pub fn is_ready(&self) -> bool {
+        self.result.borrow().is_some()
+    }
+
+    // Help executing tasks until the result is available
+    pub fn wait(self) -> T {
+        if !self.is_ready() {
+            Worker::current().help_until(|| self.is_ready());
+        }
+        self.result.borrow_mut().take().unwrap()
+    }
+}
+
+// Spawn a task that runs on the current worker, or right away if there is no
+// current worker (see `macros::push`)
+pub fn spawn_local<T, F>(task: F) -> LocalFuture<T>
+where T: 'static, F: FnOnce() -> T + 'static {
+    let result = Rc::new(RefCell::new(None));
+    let promise = Rc::clone(&result);
+    let task = move || *promise.borrow_mut() = Some(task());
+    match Worker::try_current() {
+        Some(worker) => worker.push_local(Box::new(task)),
+        #[cfg(not(feature = "strict-spawn"))]
+        None => task(),
+        #[cfg(feature = "strict-spawn")]
+        None => panic!("Cannot spawn tasks without a current worker"),
+    }
+    LocalFuture { result }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use std::thread;
+    use super::*;
+
+    enum Tree {
+        Leaf,
+        Node(Rc<Tree>, Rc<Tree>),
+    }
+
+    fn build(depth: u32) -> Rc<Tree> {
+        if depth == 0 {
+            return Rc::new(Tree::Leaf);
+        }
+        let left = spawn_local(move || build(depth - 1));
+        let right = build(depth - 1);
+        Rc::new(Tree::Node(left.wait(), right))
+    }
+
+    fn size(tree: &Tree) -> u32 {
+        match tree {
+            Tree::Leaf => 1,
+            Tree::Node(left, right) => 1 + size(left) + size(right),
+        }
+    }
+
+    #[test]
+    fn local_tasks() {
+        let runtime = Runtime::init(3);
+
+        // Meanwhile, other workers try to steal
+        for _ in 0..10 {
+            spawn!();
+        }
+        let tree = build(8);
+        assert_eq!(size(&tree), (1 << 9) - 1);
+
+        // Local tasks don't leave the current thread
+        let me = thread::current().id();
+        let futures = (0..100)
+            .map(|_| spawn_local(|| thread::current().id()))
+            .collect::<Vec<_>>();
+        assert!(futures.into_iter().all(|f| f.wait() == me));
+
+        runtime.wait_until_idle();
+        let _stats = runtime.join();
+    }
+}
@@ -1,93 +1,1018 @@
+use crate::scope::{Scope, ScopeFailure, ScopeStats};
+use crate::task::{SpawnHint, TaskId, TaskKind};
+use crate::worker::{AsWorker, Worker};
+use std::time::Duration;
+
+// Check a scheduler invariant, but only with the `debug-invariants` feature.
+// Unlike `debug_assert!`, this catches mistakes that release builds silently
+// tolerate (such as a scope's task count wrapping around), which typically
+// show up in downstream code that integrates with the runtime through its
+// lower-level APIs.
+macro_rules! invariant {
+    ($cond: expr, $($msg: tt)+) => {
+        #[cfg(feature = "debug-invariants")]
+        assert!($cond, "Scheduler invariant violated: {}", format_args!($($msg)+));
+        // Never called, but keeps variables that are only checked here used
+        #[cfg(not(feature = "debug-invariants"))]
+        let _ = || $cond;
+    }
+}
+
+// Check statistics in tests, but only without the `no-stats` feature, which
+// leaves every counter at zero
+#[cfg(test)]
+macro_rules! assert_stat {
+    ($cond: expr) => {
+        #[cfg(not(feature = "no-stats"))]
+        assert!($cond);
+        #[cfg(feature = "no-stats")]
+        let _ = || $cond;
+    }
+}
+
+#[cfg(test)]
+macro_rules! assert_stat_eq {
+    ($left: expr, $right: expr) => {
+        #[cfg(not(feature = "no-stats"))]
+        assert_eq!($left, $right);
+        #[cfg(feature = "no-stats")]
+        let _ = || ($left, $right);
+    }
+}
+
+// Helpers used by the macros below, so that callers don't need to import
+// crate internals
+
+// Spawning from a thread without a current worker runs the task right away,
+// unless the `strict-spawn` feature is enabled, in which case we panic.
+// Returns the task's id (see `Runtime::find_task`), which only tasks spawned
+// by a worker have.
+#[doc(hidden)]
+pub fn push(task: impl Into<TaskKind>) -> Option<TaskId> {
+    match Worker::try_current() {
+        Some(worker) => {
+            let mut task = task.into();
+            let id = worker.task_id(&mut task);
+            worker.spawn(task);
+            Some(id)
+        }
+        #[cfg(not(feature = "strict-spawn"))]
+        None => {
+            task.into().run();
+            None
+        }
+        #[cfg(feature = "strict-spawn")]
+        None => panic!("Cannot spawn tasks without a current worker"),
+    }
+}
+
+// Like `push`, but bypassing the admission policy (see `Worker::spawn`),
+// for tasks that somebody is going to wait for
+#[doc(hidden)]
+pub fn push_unchecked(task: impl Into<TaskKind>) {
+    match Worker::try_current() {
+        Some(worker) => worker.push(task),
+        None => {
+            push(task);
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn try_push(task: impl Into<TaskKind>) -> Result<(), TaskKind> {
+    match Worker::try_current() {
+        Some(worker) => worker.try_push(task),
+        None => {
+            push(task);
+            Ok(())
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn push_background(task: impl Into<TaskKind>) {
+    match Worker::try_current() {
+        Some(worker) => worker.push_background(task),
+        None => {
+            push(task);
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn push_with_hint(task: impl Into<TaskKind>, hint: SpawnHint) -> Option<TaskId> {
+    match Worker::try_current() {
+        Some(worker) => {
+            let mut task = task.into();
+            let id = worker.task_id(&mut task);
+            worker.push_with_hint(task, hint);
+            Some(id)
+        }
+        None => push(task),
+    }
+}
+
+#[doc(hidden)]
+pub fn push_batch<I>(tasks: I)
+where I: IntoIterator, I::Item: Into<TaskKind> {
+    match Worker::try_current() {
+        Some(worker) => worker.push_batch(tasks),
+        None => tasks.into_iter().for_each(|task| {
+            push(task);
+        }),
+    }
+}
+
+#[doc(hidden)]
+pub fn push_located(worker: Option<usize>, task: impl Into<TaskKind>) {
+    match Worker::try_current() {
+        Some(current) => current.push_located(worker, task),
+        None => {
+            push(task);
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn push_on<W: AsWorker>(worker: &W, task: impl Into<TaskKind>) {
+    worker.as_worker().push(task);
+}
+
+#[doc(hidden)]
+pub fn enter_scope() {
+    Scope::enter();
+}
+
+#[doc(hidden)]
+pub fn enter_scope_with_deadline(timeout: Duration) {
+    Scope::enter_with_deadline(timeout);
+}
+
+#[doc(hidden)]
+pub fn enter_scope_with_limit(limit: u32) {
+    Scope::enter_with_limit(limit);
+}
+
+#[doc(hidden)]
+pub fn leave_scope() -> ScopeStats {
+    Scope::leave()
+}
+
+#[doc(hidden)]
+pub fn try_leave_scope() -> Result<ScopeStats, ScopeFailure> {
+    Scope::try_leave()
+}
+
+#[doc(hidden)]
+pub fn leave_scope_on<W: AsWorker>(worker: &W) -> ScopeStats {
+    Scope::leave_on(worker.as_worker())
+}
+
 #[macro_export]
 macro_rules! async_closure {
     // `tt` is a token tree
-    ($($body: tt)*) => (Box::new(move || { $($body)* }))
+    ($($body: tt)*) => (move || { $($body)* })
 }
 
 #[macro_export]
 macro_rules! spawn {
     // `tt` is a token tree
+    // `hint` is either `Serial` or `Parallel`, see `SpawnHint`
+    (hint = $h: ident, $i: ident, $($body: tt)*) => {
+        {
+            // $i is supposed to be `channel`
+            let (sender, receiver) = $i();
+            let task = $crate::task::Async::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from(sender))
+            );
+            $crate::macros::push_with_hint(task, $crate::task::SpawnHint::$h);
+            $crate::future::Future::Chan(receiver)
+        }
+    };
+
+    (hint = $h: ident, $e: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::Async::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from($e))
+            );
+            $crate::macros::push_with_hint(task, $crate::task::SpawnHint::$h);
+            $e
+        }
+    };
+
+    (hint = $h: ident, $($body: tt)*) => {
+        {
+            let task = $crate::task::Detached::new(move || { $($body)* });
+            $crate::macros::push_with_hint(task, $crate::task::SpawnHint::$h);
+            // No return value
+        }
+    };
+
+    // `prio` sets the task's priority, see `StealPolicy`
+    (prio = $p: expr, $i: ident, $($body: tt)*) => {
+        {
+            // $i is supposed to be `channel`
+            let (sender, receiver) = $i();
+            let task = $crate::task::Async::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from(sender))
+            );
+            $crate::macros::push(task.with_priority($p));
+            $crate::future::Future::Chan(receiver)
+        }
+    };
+
+    (prio = $p: expr, $e: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::Async::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from($e))
+            );
+            $crate::macros::push(task.with_priority($p));
+            $e
+        }
+    };
+
+    (prio = $p: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::Detached::new(move || { $($body)* });
+            $crate::macros::push(task.with_priority($p));
+            // No return value
+        }
+    };
+
+    (name = $n: expr, $i: ident, $($body: tt)*) => {
+        {
+            // $i is supposed to be `channel`
+            let (sender, receiver) = $i();
+            let task = $crate::task::Async::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from(sender))
+            );
+            $crate::macros::push(task.with_name($n));
+            $crate::future::Future::Chan(receiver)
+        }
+    };
+
+    (name = $n: expr, $e: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::Async::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from($e))
+            );
+            $crate::macros::push(task.with_name($n));
+            $e
+        }
+    };
+
+    (name = $n: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::Detached::new(move || { $($body)* });
+            $crate::macros::push(task.with_name($n));
+            // No return value
+        }
+    };
+
+    // Evaluate to the task's id, see `macros::push`
+    (id, $($body: tt)*) => {
+        $crate::macros::push($crate::task::Detached::new(move || { $($body)* }))
+    };
+
     ($i: ident, $($body: tt)*) => {
         {
             // $i is supposed to be `channel`
             let (sender, receiver) = $i();
-            let task = Async::new(async_closure! { $($body)* }, Some(Promise::from(sender)));
-            Worker::current().push(Box::new(task));
-            Future::Chan(receiver)
+            let task = $crate::task::Async::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from(sender))
+            );
+            $crate::macros::push(task);
+            $crate::future::Future::Chan(receiver)
         }
     };
 
     ($e: expr, $($body: tt)*) => {
         {
-            let task = Async::new(async_closure! { $($body)* }, Some(Promise::from($e)));
-            Worker::current().push(Box::new(task));
+            let task = $crate::task::Async::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from($e))
+            );
+            $crate::macros::push(task);
             $e
         }
     };
 
     ($($body: tt)*) => {
         {
-            let task = Async::new(async_closure! { $($body)* }, None);
-            Worker::current().push(Box::new(task));
+            let task = $crate::task::Detached::new(move || { $($body)* });
+            $crate::macros::push(task);
             // No return value
         }
     }
 }
 
+// Like `spawn!(...)`, but evaluate to `Err(task)` rather than running the
+// task right away if the current worker's queue is full (see
+// `Config::queue_capacity`), so callers can shed or retry work themselves.
+// The task can still be run with `task.run()`. There is no scoped variant,
+// since a task that is handed back would keep its scope from ever finishing.
+#[macro_export]
+macro_rules! try_spawn {
+    ($($body: tt)*) => {
+        $crate::macros::try_push($crate::task::Detached::new(move || { $($body)* }))
+    }
+}
+
+// Like `spawn!(...)`, but queue the task on the current worker's background
+// queue, which is only drained when the worker has nothing else to do (see
+// `Worker::push_background`). Meant for maintenance work, such as flushing
+// caches or collecting garbage, that should never delay foreground tasks.
+#[macro_export]
+macro_rules! spawn_background {
+    ($($body: tt)*) => {
+        $crate::macros::push_background($crate::task::Detached::new(move || { $($body)* }))
+    }
+}
+
 #[macro_export]
 macro_rules! scoped_spawn {
     // `tt` is a token tree
+    // `hint` is either `Serial` or `Parallel`, see `SpawnHint`
+    (hint = $h: ident, $i: ident, $($body: tt)*) => {
+        {
+            // $i is supposed to be `channel`
+            let (sender, receiver) = $i();
+            let task = $crate::task::ScopedAsync::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from(sender))
+            );
+            $crate::macros::push_with_hint(task, $crate::task::SpawnHint::$h);
+            $crate::future::Future::Chan(receiver)
+        }
+    };
+
+    (hint = $h: ident, $e: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::ScopedAsync::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from($e))
+            );
+            $crate::macros::push_with_hint(task, $crate::task::SpawnHint::$h);
+            $e
+        }
+    };
+
+    (hint = $h: ident, $($body: tt)*) => {
+        {
+            let task = $crate::task::ScopedAsync::detached(move || { $($body)* });
+            $crate::macros::push_with_hint(task, $crate::task::SpawnHint::$h);
+            // No return value
+        }
+    };
+
+    // `prio` sets the task's priority, see `StealPolicy`
+    (prio = $p: expr, $i: ident, $($body: tt)*) => {
+        {
+            // $i is supposed to be `channel`
+            let (sender, receiver) = $i();
+            let task = $crate::task::ScopedAsync::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from(sender))
+            );
+            $crate::macros::push(task.with_priority($p));
+            $crate::future::Future::Chan(receiver)
+        }
+    };
+
+    (prio = $p: expr, $e: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::ScopedAsync::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from($e))
+            );
+            $crate::macros::push(task.with_priority($p));
+            $e
+        }
+    };
+
+    (prio = $p: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::ScopedAsync::detached(move || { $($body)* });
+            $crate::macros::push(task.with_priority($p));
+            // No return value
+        }
+    };
+
+    (name = $n: expr, $i: ident, $($body: tt)*) => {
+        {
+            // $i is supposed to be `channel`
+            let (sender, receiver) = $i();
+            let task = $crate::task::ScopedAsync::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from(sender))
+            );
+            $crate::macros::push(task.with_name($n));
+            $crate::future::Future::Chan(receiver)
+        }
+    };
+
+    (name = $n: expr, $e: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::ScopedAsync::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from($e))
+            );
+            $crate::macros::push(task.with_name($n));
+            $e
+        }
+    };
+
+    (name = $n: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::ScopedAsync::detached(move || { $($body)* });
+            $crate::macros::push(task.with_name($n));
+            // No return value
+        }
+    };
+
+    // Evaluate to the task's `ScopedFuture`, see `Scope::spawn`. The scope
+    // is the one lent out by `finish! { |scope| ... }`.
+    (future in $s: expr, $($body: tt)*) => {
+        $s.spawn(move || { $($body)* })
+    };
+
+    // Evaluate to the task's id, see `macros::push`
+    (id, $($body: tt)*) => {
+        $crate::macros::push($crate::task::ScopedAsync::detached(move || { $($body)* }))
+    };
+
     ($i: ident, $($body: tt)*) => {
         {
             // $i is supposed to be `channel`
             let (sender, receiver) = $i();
-            let task = ScopedAsync::new(async_closure! { $($body)* }, Some(Promise::from(sender)));
-            Worker::current().push(Box::new(task));
-            Future::Chan(receiver)
+            let task = $crate::task::ScopedAsync::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from(sender))
+            );
+            $crate::macros::push(task);
+            $crate::future::Future::Chan(receiver)
         }
     };
 
     ($e: expr, $($body: tt)*) => {
         {
-            let task = ScopedAsync::new(async_closure! { $($body)* }, Some(Promise::from($e)));
-            Worker::current().push(Box::new(task));
+            let task = $crate::task::ScopedAsync::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from($e))
+            );
+            $crate::macros::push(task);
             $e
         }
     };
 
     ($($body: tt)*) => {
         {
-            let task = ScopedAsync::new(async_closure! { $($body)* }, None);
-            Worker::current().push(Box::new(task));
+            let task = $crate::task::ScopedAsync::detached(move || { $($body)* });
+            $crate::macros::push(task);
             // No return value
         }
     }
 }
 
+// Spawn a task for every item of an iterator, pushing all tasks at once:
+//
+//     spawn_batch!(0..n, |i| work(i));
+//
+// Every task gets its own copy of the body's captures, so they must be
+// `Copy` (like references to statics)
+#[macro_export]
+macro_rules! spawn_batch {
+    ($iter: expr, |$i: ident| $($body: tt)*) => {
+        $crate::macros::push_batch(
+            ::std::iter::IntoIterator::into_iter($iter)
+                .map(|$i| $crate::task::Detached::new(move || { $($body)* }))
+        )
+    }
+}
+
+// Like `spawn_batch!`, but spawn the tasks as a gang (see `task::Gang`):
+// they are stolen all together or not at all, and run back-to-back
+//
+//     gang_spawn!(0..4, |i| update_block(i));
+#[macro_export]
+macro_rules! gang_spawn {
+    ($iter: expr, |$i: ident| $($body: tt)*) => {
+        $crate::macros::push(Box::new($crate::task::Gang::new(
+            ::std::iter::IntoIterator::into_iter($iter)
+                .map(|$i| $crate::task::Detached::new(move || { $($body)* }))
+        )))
+    }
+}
+
+// Like `spawn_batch!`, but for scoped tasks
+#[macro_export]
+macro_rules! scoped_spawn_batch {
+    ($iter: expr, |$i: ident| $($body: tt)*) => {
+        $crate::macros::push_batch(
+            ::std::iter::IntoIterator::into_iter($iter)
+                .map(|$i| $crate::task::ScopedAsync::detached(move || { $($body)* }))
+        )
+    }
+}
+
 #[macro_export]
 macro_rules! finish {
+    // Lend the scope to the body, see `Scope::with`
+    (|$s: ident| $($body: tt)*) => {
+        $crate::scope::Scope::with(|$s| { $($body)* })
+    };
+
     // `tt` is a token tree
     ($($body: tt)*) => {
         {
-            Scope::enter();
+            $crate::macros::enter_scope();
+            $($body)*
+            $crate::macros::leave_scope();
+        }
+    }
+}
+
+// Like `finish_with_stats!`, but evaluate to `Err` with the panic of the
+// task that made the scope fail, see `PanicPolicy::FailScope`
+#[macro_export]
+macro_rules! try_finish {
+    // `tt` is a token tree
+    ($($body: tt)*) => {
+        {
+            $crate::macros::enter_scope();
+            $($body)*
+            $crate::macros::try_leave_scope()
+        }
+    }
+}
+
+// Like `finish_with_stats!`, but cancel tasks that haven't started within
+// `timeout`, see `Scope::enter_with_deadline`
+#[macro_export]
+macro_rules! finish_with_deadline {
+    // `tt` is a token tree
+    ($timeout: expr; $($body: tt)*) => {
+        {
+            $crate::macros::enter_scope_with_deadline($timeout);
+            $($body)*
+            $crate::macros::leave_scope()
+        }
+    }
+}
+
+// Like `finish_with_stats!`, but run at most `limit` tasks of the scope at a
+// time, see `Scope::enter_with_limit`
+#[macro_export]
+macro_rules! finish_with_limit {
+    // `tt` is a token tree
+    ($limit: expr; $($body: tt)*) => {
+        {
+            $crate::macros::enter_scope_with_limit($limit);
+            $($body)*
+            $crate::macros::leave_scope()
+        }
+    }
+}
+
+// Like `finish!`, but evaluate to the scope's statistics (`ScopeStats`)
+#[macro_export]
+macro_rules! finish_with_stats {
+    // `tt` is a token tree
+    ($($body: tt)*) => {
+        {
+            $crate::macros::enter_scope();
+            $($body)*
+            $crate::macros::leave_scope()
+        }
+    }
+}
+
+// Like `spawn!`, but with a cost hint (an estimated run time in
+// microseconds, see `CostModel`), so that thieves steal about half of the
+// work rather than half of the tasks
+#[macro_export]
+macro_rules! spawn_weighted {
+    // `tt` is a token tree
+    ($c: expr, $i: ident, $($body: tt)*) => {
+        {
+            // $i is supposed to be `channel`
+            let (sender, receiver) = $i();
+            let task = $crate::task::Async::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from(sender))
+            );
+            $crate::macros::push(task.with_cost($c));
+            $crate::future::Future::Chan(receiver)
+        }
+    };
+
+    ($c: expr, $e: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::Async::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from($e))
+            );
+            $crate::macros::push(task.with_cost($c));
+            $e
+        }
+    };
+
+    ($c: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::Detached::new(move || { $($body)* });
+            $crate::macros::push(task.with_cost($c));
+            // No return value
+        }
+    }
+}
+
+// Like `spawn!`, but for operations that may fail temporarily: the body
+// evaluates to a `Result` and is run again after errors, up to `$n` times,
+// with backoff in between (see `RetryingTask`). Since the body may run more
+// than once, it can't move captured values out.
+//
+//     let page = spawn_retrying!(3, one_shot_channel, fetch(&url));
+#[macro_export]
+macro_rules! spawn_retrying {
+    // `tt` is a token tree
+    ($n: expr, $i: ident, $($body: tt)*) => {
+        {
+            // $i is supposed to be `channel`
+            let (sender, receiver) = $i();
+            let task = $crate::task::RetryingTask::new(
+                $n,
+                move || { $($body)* },
+                Some($crate::future::Promise::from(sender))
+            );
+            $crate::macros::push(Box::new(task));
+            $crate::future::Future::Chan(receiver)
+        }
+    };
+
+    // The error of the last attempt is dropped (see
+    // `Stats::num_retries_exhausted`)
+    ($n: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::RetryingTask::new($n, move || { $($body)* }, None);
+            $crate::macros::push(Box::new(task));
+            // No return value
+        }
+    }
+}
+
+// Like `spawn!`, but run the task on the worker that produced the given
+// `Located` values, if they were all produced by the same worker, and that
+// worker isn't too busy (see `Worker::push_located`):
+//
+//     let rows = Located::new(load_rows(path));
+//     let sum = spawn_located!([rows], one_shot_channel, rows.iter().sum());
+#[macro_export]
+macro_rules! spawn_located {
+    // `tt` is a token tree
+    ([$($x: ident),+], $i: ident, $($body: tt)*) => {
+        {
+            let worker = $crate::affinity::colocated([$($x.worker()),+]);
+            // $i is supposed to be `channel`
+            let (sender, receiver) = $i();
+            let task = $crate::task::Async::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from(sender))
+            );
+            $crate::macros::push_located(worker, task);
+            $crate::future::Future::Chan(receiver)
+        }
+    };
+
+    ([$($x: ident),+], $($body: tt)*) => {
+        {
+            let worker = $crate::affinity::colocated([$($x.worker()),+]);
+            let task = $crate::task::Detached::new(move || { $($body)* });
+            $crate::macros::push_located(worker, task);
+            // No return value
+        }
+    }
+}
+
+// Variants of the above that take an explicit worker (`&Worker`) or runtime
+// handle (`MasterHandle`) instead of relying on `Worker::current()`. Neither
+// can be sent to another thread, so these are for the worker's own thread;
+// other threads go through `RuntimeHandle::submit`.
+
+#[macro_export]
+macro_rules! spawn_on {
+    // `tt` is a token tree
+    ($w: expr, $i: ident, $($body: tt)*) => {
+        {
+            // $i is supposed to be `channel`
+            let (sender, receiver) = $i();
+            let task = $crate::task::Async::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from(sender))
+            );
+            $crate::macros::push_on(&$w, task);
+            $crate::future::Future::Chan(receiver)
+        }
+    };
+
+    ($w: expr, $e: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::Async::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from($e))
+            );
+            $crate::macros::push_on(&$w, task);
+            $e
+        }
+    };
+
+    ($w: expr; $($body: tt)*) => {
+        {
+            let task = $crate::task::Detached::new(move || { $($body)* });
+            $crate::macros::push_on(&$w, task);
+            // No return value
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! scoped_spawn_on {
+    // `tt` is a token tree
+    ($w: expr, $i: ident, $($body: tt)*) => {
+        {
+            // $i is supposed to be `channel`
+            let (sender, receiver) = $i();
+            let task = $crate::task::ScopedAsync::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from(sender))
+            );
+            $crate::macros::push_on(&$w, task);
+            $crate::future::Future::Chan(receiver)
+        }
+    };
+
+    ($w: expr, $e: expr, $($body: tt)*) => {
+        {
+            let task = $crate::task::ScopedAsync::new(
+                $crate::async_closure! { $($body)* },
+                Some($crate::future::Promise::from($e))
+            );
+            $crate::macros::push_on(&$w, task);
+            $e
+        }
+    };
+
+    ($w: expr; $($body: tt)*) => {
+        {
+            let task = $crate::task::ScopedAsync::detached(move || { $($body)* });
+            $crate::macros::push_on(&$w, task);
+            // No return value
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! finish_on {
+    // `tt` is a token tree
+    ($w: expr; $($body: tt)*) => {
+        {
+            $crate::macros::enter_scope();
             $($body)*
-            Scope::leave();
+            $crate::macros::leave_scope_on(&$w);
         }
     }
 }
 
+// Spawn `n` tasks and collect their results through a single channel, see
+// `collect::spawn_collect`
+#[macro_export]
+macro_rules! spawn_collect {
+    ($n: expr, |$i: ident| $($body: tt)*) => {
+        $crate::collect::spawn_collect($n, move |$i: usize| { $($body)* })
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::future::{Future, Promise};
+    use crate::channel::one_shot_channel;
+    use crate::future::Future;
     use crate::runtime::Runtime;
-    use crate::scope::Scope;
-    use crate::task::{Async, ScopedAsync};
     use crate::worker::Worker;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+
+    // Test threads don't have a worker of their own
+    #[cfg(not(feature = "strict-spawn"))]
+    #[test]
+    fn spawn_without_worker() {
+        let mut f = spawn!(one_shot_channel, 1 + 1);
+        assert_eq!(f.wait(), 2);
+
+        let mut x = Future::lazy();
+        let _ = spawn!(&mut x, 3);
+        assert_eq!(x.wait(), 3);
+
+        let mut g = Future::lazy();
+        finish! {
+            let _ = scoped_spawn!(&mut g, 4);
+            scoped_spawn!(spawn!());
+        }
+        assert_eq!(g.get(), 4);
+    }
+
+    #[cfg(not(feature = "strict-spawn"))]
+    #[test]
+    fn yield_without_worker() {
+        use crate::task::{Step, YieldingTask};
+
+        let (sender, receiver) = one_shot_channel();
+        let mut polls = 0;
+        let task = YieldingTask::new(move || {
+            polls += 1;
+            if polls < 5 { Step::Yield } else { Step::Done(polls) }
+        }, Some(crate::future::Promise::from(sender)));
+        super::push(Box::new(task));
+        assert_eq!(Future::Chan(receiver).get(), 5);
+    }
+
+    #[test]
+    fn spawn_batch() {
+        use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+        static SUM: AtomicUsize = AtomicUsize::new(0);
+
+        let runtime = Runtime::init(3);
+
+        finish! {
+            scoped_spawn_batch!(0..1000, |i| { SUM.fetch_add(i, Relaxed); });
+        }
+        assert_eq!(SUM.load(Relaxed), 999 * 1000 / 2);
+
+        spawn_batch!(0..10, |_i| { SUM.fetch_sub(1, Relaxed); });
+        runtime.wait_until_idle();
+        assert_eq!(SUM.load(Relaxed), 999 * 1000 / 2 - 10);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn move_captures() {
+        let runtime = Runtime::init(2);
+
+        // Tasks are `FnOnce`, so they can give up ownership of captures
+        let v = vec![1, 2, 3];
+        let mut f = spawn!(one_shot_channel, v);
+        assert_eq!(f.wait(), vec![1, 2, 3]);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn finish_with_stats() {
+        let runtime = Runtime::init(3);
+
+        let stats = finish_with_stats! {
+            for _ in 0..10 {
+                scoped_spawn! {
+                    for _ in 0..10 {
+                        scoped_spawn!(utils::compute(std::time::Duration::from_micros(10)));
+                    }
+                }
+            }
+            // Not part of the scope
+            spawn!();
+        };
+        assert_eq!(stats.num_tasks_spawned, 110);
+        assert_eq!(stats.num_tasks_executed, 110);
+        assert!(stats.task_time >= std::time::Duration::from_micros(1000));
+        assert!(stats.elapsed > std::time::Duration::ZERO);
+        assert!(stats.num_tasks_stolen <= 110);
+        assert!(stats.wait_time <= stats.elapsed);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn scope_level_stats() {
+        let runtime = Runtime::init(2);
+
+        for _ in 0..3 {
+            finish! {
+                for _ in 0..4 {
+                    scoped_spawn! {
+                        finish! {
+                            scoped_spawn!(utils::compute(std::time::Duration::from_micros(10)));
+                        }
+                    }
+                }
+            }
+        }
+
+        let stats = runtime.join();
+        let levels = stats.scope_levels();
+        assert_eq!(levels.iter().map(|&(level, _)| level).collect::<Vec<_>>(), vec![1, 2]);
+        let (_, outer) = levels[0];
+        assert_eq!((outer.num_scopes, outer.num_tasks_spawned), (3, 12));
+        assert!(outer.num_tasks_stolen <= 12);
+        let (_, inner) = levels[1];
+        assert_eq!((inner.num_scopes, inner.num_tasks_spawned), (12, 12));
+
+        // The worker runs the first task while it waits in the second task's
+        // inner scope, but that doesn't make the first task's scope deeper
+        let runtime = Runtime::init(1);
+        let done = Arc::new(AtomicBool::new(false));
+        finish! {
+            let flag = Arc::clone(&done);
+            scoped_spawn! {
+                finish! { scoped_spawn!(); }
+                flag.store(true, Relaxed);
+            }
+            let done = Arc::clone(&done);
+            scoped_spawn! {
+                finish! { scoped_spawn!(Worker::current().help_until(|| done.load(Relaxed))); }
+            }
+        }
+
+        let stats = runtime.join();
+        let levels = stats.scope_levels();
+        assert_eq!(levels.iter().map(|&(level, _)| level).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(levels[1].1.num_scopes, 2);
+    }
+
+    #[test]
+    fn finish_with_deadline() {
+        let runtime = Runtime::init(2);
+
+        let stats = finish_with_deadline! { std::time::Duration::from_millis(5);
+            for _ in 0..100 {
+                scoped_spawn!(utils::compute(std::time::Duration::from_millis(1)));
+            }
+        };
+        assert!(stats.deadline_exceeded);
+        assert!(stats.num_tasks_cancelled > 0);
+        assert_eq!(stats.num_tasks_executed + stats.num_tasks_cancelled, 100);
+
+        let stats = finish_with_deadline! { std::time::Duration::from_secs(60);
+            scoped_spawn!();
+        };
+        assert!(!stats.deadline_exceeded);
+        assert_eq!((stats.num_tasks_executed, stats.num_tasks_cancelled), (1, 0));
+
+        // Tasks that are done in time don't overrun the deadline, no matter
+        // how long it takes to leave the scope
+        let stats = finish_with_deadline! { std::time::Duration::from_millis(50);
+            scoped_spawn!();
+            crate::scope::Scope::current().wait();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        };
+        assert!(!stats.deadline_exceeded);
+        assert_eq!((stats.num_tasks_executed, stats.num_tasks_cancelled), (1, 0));
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn finish_with_limit() {
+        let runtime = Runtime::init(4);
+
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let stats = finish_with_limit! { 2;
+            for _ in 0..40 {
+                let running = Arc::clone(&running);
+                let max_running = Arc::clone(&max_running);
+                scoped_spawn!({
+                    let n = running.fetch_add(1, Relaxed) + 1;
+                    max_running.fetch_max(n, Relaxed);
+                    std::thread::sleep(std::time::Duration::from_micros(200));
+                    running.fetch_sub(1, Relaxed);
+                });
+            }
+        };
+        assert_eq!(stats.num_tasks_executed, 40);
+        assert!(max_running.load(Relaxed) <= 2);
+
+        // Tasks wait for a permit instead of being run over and over
+        let stats = runtime.join();
+        assert!(stats.num_tasks_executed.get() <= 3 * 40);
+    }
+
+    #[cfg(feature = "strict-spawn")]
+    #[test]
+    #[should_panic]
+    fn spawn_without_worker() {
+        spawn!();
+    }
 
     #[test]
     fn async_tasks() {
         let runtime = Runtime::init(3);
-        let leader = runtime.leader;
+        let leader = runtime.master();
 
         for _ in 0..5 {
             spawn! {
@@ -101,24 +1026,13 @@ mod tests {
             }
         }
 
-        let mut num_tasks_executed = 0;
-        while leader.has_tasks() {
-            leader.try_handle_steal_request();
-            match leader.pop() {
-                Some(task) => {
-                    task.run();
-                    num_tasks_executed += 1;
-                }
-                None => break,
-            }
-        }
-
-        leader.stats.num_tasks_executed.add(num_tasks_executed);
+        let num_tasks_executed = leader.drain().count();
+        assert!(num_tasks_executed <= 105);
 
         // TODO Task barrier needed
 
         let stats = runtime.join();
-        assert_eq!(stats.num_tasks_executed.get(), 105);
+        assert_stat_eq!(stats.num_tasks_executed.get(), 105);
     }
 
     #[test]
@@ -140,26 +1054,263 @@ mod tests {
         } // Implicit barrier
 
         let stats = runtime.join();
-        assert_eq!(stats.num_tasks_executed.get(), 105);
+        assert_stat_eq!(stats.num_tasks_executed.get(), 105);
     }
 
     fn sum(n: u32) -> u32 {
         if n <= 1 { n }
         else {
-            let mut f = Future::Lazy(None);
+            let mut f = Future::lazy();
             n + spawn!(&mut f, sum(n - 1)).wait()
         }
     }
 
+    #[test]
+    fn channel_futures() {
+        let runtime = Runtime::init(3);
+
+        let mut f = spawn!(one_shot_channel, 1 + 1);
+        let mut g = scoped_spawn!(one_shot_channel, 2 + 2);
+        assert_eq!(f.wait() + g.wait(), 6);
+        let h = finish! { |scope|
+            let h = scoped_spawn!(future in scope, 3 + 3);
+            // No need to help after waiting for the scope
+            scope.wait();
+            h.get()
+        };
+        assert_eq!(h, 6);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn named_tasks() {
+        let runtime = Runtime::init(2);
+
+        let mut f = spawn!(name = "two", one_shot_channel, 1 + 1);
+        let mut g = Future::lazy();
+        let _ = spawn!(name = "three", &mut g, 1 + 2);
+        finish! {
+            scoped_spawn!(name = "leaf", ());
+        }
+        assert_eq!(f.wait() + g.wait(), 5);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn prioritized_tasks() {
+        use std::sync::{Arc, Mutex};
+
+        let priorities = Arc::new(Mutex::new(vec![]));
+        let runtime = {
+            let priorities = Arc::clone(&priorities);
+            Runtime::builder()
+                .num_workers(2)
+                .middleware(move |info, next| {
+                    priorities.lock().unwrap().push(info.priority);
+                    next.run();
+                })
+                .init()
+        };
+
+        let mut f = spawn!(prio = 3, one_shot_channel, 1 + 1);
+        let mut g = Future::lazy();
+        let _ = spawn!(prio = 2, &mut g, 1 + 2);
+        finish! {
+            scoped_spawn!(prio = 1, ());
+        }
+        assert_eq!(f.wait() + g.wait(), 5);
+        runtime.wait_until_idle();
+
+        let mut priorities = priorities.lock().unwrap().clone();
+        priorities.sort();
+        assert_eq!(priorities, vec![1, 2, 3]);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn hinted_tasks() {
+        // Without thieves asking for work, serial tasks are always inlined
+        let runtime = Runtime::init(1);
+
+        let mut f = spawn!(hint = Serial, one_shot_channel, 1 + 1);
+        assert!(f.is_ready());
+        let mut g = Future::lazy();
+        let _ = spawn!(hint = Serial, &mut g, 1 + 2);
+        assert!(g.is_ready());
+        finish! {
+            scoped_spawn!(hint = Serial, ());
+            scoped_spawn!(hint = Parallel, ());
+        }
+        let mut h = spawn!(hint = Parallel, one_shot_channel, 1 + 3);
+        assert_eq!(f.wait() + g.wait() + h.wait(), 9);
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_inlined.get(), 3);
+        assert_stat_eq!(stats.num_tasks_executed.get(), 2);
+    }
+
+    #[test]
+    fn bounded_queues() {
+        let runtime = Runtime::builder().num_workers(1).queue_capacity(2).init();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let c = Arc::clone(&count);
+        assert!(try_spawn!(c.fetch_add(1, Relaxed)).is_ok());
+        let c = Arc::clone(&count);
+        assert!(try_spawn!(c.fetch_add(1, Relaxed)).is_ok());
+        assert!(Worker::current().is_full());
+        let c = Arc::clone(&count);
+        let task = try_spawn!(c.fetch_add(1, Relaxed)).unwrap_err();
+        assert_eq!(count.load(Relaxed), 0);
+        task.run();
+        assert_eq!(count.load(Relaxed), 1);
+
+        // Blocking spawns run the task right away
+        let c = Arc::clone(&count);
+        spawn!(c.fetch_add(1, Relaxed));
+        assert_eq!(count.load(Relaxed), 2);
+        runtime.wait_until_idle();
+        assert_eq!(count.load(Relaxed), 4);
+        assert!(try_spawn!().is_ok());
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_inlined.get(), 1);
+    }
+
+    #[test]
+    fn background_tasks() {
+        use std::sync::Mutex;
+
+        let runtime = Runtime::init(1);
+        let log = Arc::new(Mutex::new(vec![]));
+
+        for i in 0..3 {
+            let log = Arc::clone(&log);
+            spawn_background!(log.lock().unwrap().push(("background", i)));
+        }
+        for i in 0..3 {
+            let log = Arc::clone(&log);
+            spawn! {
+                log.lock().unwrap().push(("foreground", i));
+                if i == 0 {
+                    // Even tasks spawned later run before background tasks
+                    let log = Arc::clone(&log);
+                    spawn!(log.lock().unwrap().push(("foreground", 3)));
+                }
+            }
+        }
+        assert_eq!(Worker::current().num_background_tasks(), 3);
+        runtime.wait_until_idle();
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 7);
+        assert!(log[..4].iter().all(|&(kind, _)| kind == "foreground"));
+        assert_eq!(log[4..], [("background", 0), ("background", 1), ("background", 2)]);
+
+        drop(log);
+
+        // Not while a task waits
+        let waiting = Arc::new(AtomicUsize::new(0));
+        {
+            let waiting = Arc::clone(&waiting);
+            spawn! {
+                let ran = Arc::new(AtomicUsize::new(0));
+                let ran_clone = Arc::clone(&ran);
+                spawn_background!(ran_clone.fetch_add(1, Relaxed));
+                let deadline = std::time::Instant::now() + std::time::Duration::from_millis(20);
+                Worker::current().help_until(|| std::time::Instant::now() >= deadline);
+                waiting.store(1 + ran.load(Relaxed), Relaxed);
+            }
+        }
+        runtime.wait_until_idle();
+        assert_eq!(waiting.load(Relaxed), 1);
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_background_tasks.get(), 4);
+        assert_stat_eq!(stats.num_tasks_executed.get(), 9);
+    }
+
+    #[test]
+    fn gang_tasks() {
+        use std::sync::Mutex;
+
+        let runtime = Runtime::init(3);
+        let log: &'static Mutex<Vec<(u32, u32, usize)>> = Box::leak(Box::new(Mutex::new(vec![])));
+
+        for gang in 0..20 {
+            gang_spawn!(0..5, |i| {
+                log.lock().unwrap().push((gang, i, Worker::current().id));
+                utils::compute(std::time::Duration::from_micros(50));
+            });
+        }
+        runtime.wait_until_idle();
+
+        // Every gang ran on one worker, its members back-to-back and in order
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 100);
+        for worker in 0..3 {
+            let members: Vec<_> = log.iter().filter(|e| e.2 == worker).map(|e| (e.0, e.1)).collect();
+            for chunk in members.chunks(5) {
+                assert!(chunk.iter().enumerate().all(|(i, &(gang, j))| gang == chunk[0].0 && j == i as u32));
+            }
+        }
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 20);
+    }
+
+    #[test]
+    fn weighted_tasks() {
+        let runtime = Runtime::init(3);
+
+        let mut f = spawn_weighted!(10, one_shot_channel, 1 + 1);
+        for i in 0..10 {
+            spawn_weighted!(i, ());
+        }
+        assert_eq!(f.wait(), 2);
+        runtime.wait_until_idle();
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 11);
+    }
+
+    #[test]
+    fn explicit_handles() {
+        let outer = Runtime::init(2);
+        let inner = Runtime::init(3);
+
+        // The inner runtime's leader is the current worker
+        let master = outer.master();
+        assert!(!master.is_current());
+
+        finish_on! { master;
+            for _ in 0..10 {
+                scoped_spawn_on!(master;);
+            }
+        }
+        let mut f = spawn_on!(master, one_shot_channel, 1 + 1);
+
+        // Waiting for `f` requires the outer runtime's leader to be current
+        let _stats = inner.join();
+        assert!(master.is_current());
+        assert_eq!(f.wait(), 2);
+
+        let stats = outer.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 11);
+    }
+
     #[test]
     fn async_futures() {
         let runtime = Runtime::init(3);
 
-        let mut n = Future::Lazy(None);
+        let mut n = Future::lazy();
         let _ = spawn!(&mut n, sum(10));
         assert_eq!(n.wait(), 55);
 
         let stats = runtime.join();
-        assert_eq!(stats.num_tasks_executed.get(), 10);
+        assert_stat_eq!(stats.num_tasks_executed.get(), 10);
     }
 }
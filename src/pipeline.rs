@@ -0,0 +1,143 @@
+use crate::atomic;
+use crate::task::Async;
+use crate::worker::Worker;
+use std::sync::Arc;
+
+// Bounded pipeline stages
+//
+// A stage applies its function to every item it receives, running each item
+// as a separate task. At most `capacity` items can be in flight (queued or
+// running) per stage. A producer that finds the stage full helps executing
+// tasks until an item has left the stage, so that a slow downstream stage
+// throttles its upstream producers (backpressure).
+//
+// Stages are connected by sending to the next stage from within the stage
+// function:
+//
+//     let sink = stage(4, |x| println!("{}", x));
+//     let source = stage(4, move |x| sink.send(x * 2));
+
+pub struct Stage<T> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    capacity: u32,
+    in_flight: atomic::Count,
+    func: Box<dyn Fn(T) + Send + Sync>,
+}
+
+pub fn stage<T, F>(capacity: usize, func: F) -> Stage<T>
+where F: Fn(T) + Send + Sync + 'static {
+    assert!(capacity > 0);
+    Stage {
+        inner: Arc::new(Inner {
+            capacity: capacity as u32,
+            in_flight: atomic::Count::new(0),
+            func: Box::new(func),
+        }),
+    }
+}
+
+impl<T> Stage<T> where T: Send + 'static {
+    // Number of items that have been sent but not yet processed
+    pub fn in_flight(&self) -> u32 {
+        self.inner.in_flight.get()
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.inner.capacity
+    }
+
+    // Block until there is room in the stage, then enqueue `item` as a new
+    // task on the current worker
+    pub fn send(&self, item: T) {
+        let inner = &self.inner;
+        Worker::current().help_until(|| inner.in_flight.inc_if_below(inner.capacity));
+
+        let in_flight = InFlight(Arc::clone(&self.inner));
+        let task = Async::new(move || (in_flight.0.func)(item), None);
+        Worker::current().push(task);
+    }
+
+    // Help until all items sent so far have been processed
+    pub fn flush(&self) {
+        Worker::current().help_until(|| self.inner.in_flight.get() == 0);
+    }
+}
+
+// An item in a stage, which leaves the stage when the item's task is done
+// with it, even if the stage function panics (and the panic is caught, see
+// `PanicPolicy`), so that producers aren't held up forever
+struct InFlight<T>(Arc<Inner<T>>);
+
+impl<T> Drop for InFlight<T> {
+    fn drop(&mut self) {
+        self.0.in_flight.dec();
+    }
+}
+
+impl<T> Clone for Stage<T> {
+    fn clone(&self) -> Self {
+        Self { inner: Arc::clone(&self.inner) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+    use super::*;
+
+    #[test]
+    fn two_stages() {
+        let runtime = Runtime::init(3);
+
+        let sum = Arc::new(AtomicU32::new(0));
+        let max_in_flight = Arc::new(AtomicU32::new(0));
+
+        let sink = {
+            let sum = Arc::clone(&sum);
+            stage(4, move |x: u32| { sum.fetch_add(x, Relaxed); })
+        };
+        let source = {
+            let sink = sink.clone();
+            let max_in_flight = Arc::clone(&max_in_flight);
+            stage(2, move |x: u32| {
+                sink.send(x * 2);
+                max_in_flight.fetch_max(sink.in_flight(), Relaxed);
+            })
+        };
+
+        for i in 1..=100 {
+            source.send(i);
+            assert!(source.in_flight() <= source.capacity());
+        }
+        source.flush();
+        sink.flush();
+
+        assert_eq!(sum.load(Relaxed), 2 * 5050);
+        assert!(max_in_flight.load(Relaxed) <= sink.capacity());
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 200);
+    }
+
+    #[test]
+    fn panicking_stage() {
+        use crate::runtime::PanicPolicy;
+
+        let runtime = Runtime::builder().num_workers(2).panic_policy(PanicPolicy::LogAndContinue).init();
+
+        // Items leave the stage even if processing them panics
+        let sink = stage(1, |x: u32| if x.is_multiple_of(2) { panic!("even") });
+        for i in 0..10 {
+            sink.send(i);
+        }
+        sink.flush();
+        assert_eq!(sink.in_flight(), 0);
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_panicked.get(), 5);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::channel::one_shot_channel;
+use crate::future::{Future, Promise};
+use crate::task::{Async, TaskKind};
+use crate::worker::Worker;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+// Task pools
+//
+// Named queues layered over the runtime's workers, one per application
+// subsystem, so that several subsystems can share the workers without one
+// monopolizing them. Workers take pooled tasks when they run out of local
+// work, before trying to steal, and choose among the pools by stride
+// scheduling: taking a task advances the pool's virtual clock by the
+// inverse of its weight, and the nonempty pool with the earliest clock goes
+// next. A pool with weight 3 thus gets three times as many turns as a pool
+// with weight 1 while both have tasks queued. For example:
+//
+//     let io = Pool::new("io", 1);
+//     let compute = Pool::new("compute", 3);
+//     let page = io.spawn(move || fetch(url));
+//     let sum = compute.spawn(move || checksum(&data));
+//
+// Tasks that pooled tasks spawn go to the worker's deque as usual.
+
+// Virtual time a pool with weight 1 spends per task
+const STRIDE: u64 = 1 << 20;
+
+// A handle to a pool of the current runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pool {
+    index: usize,
+}
+
+impl Pool {
+    // Register a new pool with the current worker's runtime. Panics if a
+    // pool named `name` exists already.
+    pub fn new(name: &str, weight: u32) -> Self {
+        let index = Worker::current().shared().pools.add(name, weight);
+        Self { index }
+    }
+
+    // A handle to an existing pool
+    pub fn get(name: &str) -> Option<Self> {
+        Worker::current().shared().pools.find(name).map(|index| Self { index })
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn name(&self) -> String {
+        Worker::current().shared().pools.with(self.index, |pool| pool.name.clone())
+    }
+
+    pub fn weight(&self) -> u32 {
+        Worker::current().shared().pools.with(self.index, |pool| pool.weight)
+    }
+
+    // Number of tasks waiting in the pool
+    pub fn queued(&self) -> usize {
+        Worker::current().shared().pools.with(self.index, |pool| pool.tasks.len())
+    }
+
+    // Number of tasks taken from the pool so far
+    pub fn num_taken(&self) -> u64 {
+        Worker::current().shared().pools.with(self.index, |pool| pool.taken)
+    }
+
+    // Queue a task in the pool and return a future of its result
+    pub fn spawn<R, F>(&self, f: F) -> Future<R>
+    where F: FnOnce() -> R + Send + 'static, R: Send + 'static {
+        let (sender, receiver) = one_shot_channel();
+        let task = Async::new(f, Some(Promise::from(sender)));
+        Worker::current().push_to_pool(self.index, task);
+        Future::Chan(receiver)
+    }
+}
+
+#[derive(Debug)]
+struct PoolState {
+    name: String,
+    weight: u32,
+    tasks: VecDeque<TaskKind>,
+    // Virtual time at which the pool gets its next turn
+    pass: u64,
+    taken: u64,
+}
+
+// The pools of a runtime, see `Shared::pools`
+#[derive(Debug)]
+pub struct Pools {
+    pools: Mutex<PoolStates>,
+    // Tasks queued in all pools, so that workers can skip the lock when
+    // there are none
+    queued: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct PoolStates {
+    pools: Vec<PoolState>,
+    // Clock of the pool that was chosen last
+    now: u64,
+}
+
+impl Pools {
+    pub fn new() -> Self {
+        Self {
+            pools: Mutex::new(PoolStates { pools: vec![], now: 0 }),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn add(&self, name: &str, weight: u32) -> usize {
+        assert!(weight > 0);
+        let mut state = self.pools.lock().unwrap();
+        assert!(state.pools.iter().all(|pool| pool.name != name), "Pool {:?} exists already", name);
+        let now = state.now;
+        state.pools.push(PoolState {
+            name: String::from(name),
+            weight,
+            tasks: VecDeque::new(),
+            pass: now,
+            taken: 0,
+        });
+        state.pools.len() - 1
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.pools.lock().unwrap().pools.iter().position(|pool| pool.name == name)
+    }
+
+    fn with<R>(&self, index: usize, f: impl FnOnce(&PoolState) -> R) -> R {
+        f(&self.pools.lock().unwrap().pools[index])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.load(Relaxed) == 0
+    }
+
+    pub fn push(&self, index: usize, task: TaskKind) {
+        let mut state = self.pools.lock().unwrap();
+        let now = state.now;
+        let pool = &mut state.pools[index];
+        // A pool that had nothing to do doesn't get to catch up on the turns
+        // it missed
+        if pool.tasks.is_empty() {
+            pool.pass = pool.pass.max(now);
+        }
+        pool.tasks.push_back(task);
+        self.queued.fetch_add(1, Relaxed);
+    }
+
+    // Take the oldest task of the pool whose turn it is
+    pub fn take(&self) -> Option<TaskKind> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut state = self.pools.lock().unwrap();
+        let pool = state.pools
+            .iter_mut()
+            .filter(|pool| !pool.tasks.is_empty())
+            .min_by_key(|pool| pool.pass)?;
+        let task = pool.tasks.pop_front();
+        pool.pass += STRIDE / pool.weight as u64;
+        pool.taken += 1;
+        let now = pool.pass;
+        state.now = now;
+        self.queued.fetch_sub(1, Relaxed);
+        task
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::future::wait_all;
+    use crate::runtime::Runtime;
+    use std::sync::Arc;
+    use super::*;
+
+    #[test]
+    fn weighted_pools() {
+        let runtime = Runtime::init(1);
+
+        let io = Pool::new("io", 1);
+        let compute = Pool::new("compute", 3);
+        assert_eq!(Pool::get("compute"), Some(compute));
+        assert_eq!(Pool::get("ui"), None);
+
+        let order = Arc::new(Mutex::new(vec![]));
+        let mut futures = vec![];
+        for _ in 0..20 {
+            for (pool, name) in [(io, "io"), (compute, "compute")] {
+                let order = Arc::clone(&order);
+                futures.push(pool.spawn(move || order.lock().unwrap().push(name)));
+            }
+        }
+        assert_eq!(io.queued(), 20);
+        wait_all(&mut futures);
+
+        // While both pools have tasks, compute gets three out of four turns
+        let order = order.lock().unwrap();
+        let first = &order[..20];
+        assert_eq!(first.iter().filter(|&&name| name == "compute").count(), 15);
+        assert_eq!(io.num_taken(), 20);
+        assert_eq!(compute.num_taken(), 20);
+        drop(order);
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_pooled_tasks.get(), 40);
+    }
+
+    #[test]
+    fn idle_pools_dont_catch_up() {
+        let pools = Pools::new();
+        let a = pools.add("a", 1);
+        let b = pools.add("b", 1);
+        for _ in 0..4 {
+            pools.push(a, TaskKind::from(Async::detached(|| ())));
+        }
+        for _ in 0..4 {
+            pools.take().unwrap();
+        }
+        // `b` was idle while `a` ran, so the two now take turns
+        for _ in 0..2 {
+            pools.push(a, TaskKind::from(Async::detached(|| ())));
+            pools.push(b, TaskKind::from(Async::detached(|| ())));
+        }
+        pools.take().unwrap();
+        pools.take().unwrap();
+        assert_eq!(pools.with(a, |pool| pool.taken), 5);
+        assert_eq!(pools.with(b, |pool| pool.taken), 1);
+        assert!(!pools.is_empty());
+    }
+}
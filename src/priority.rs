@@ -0,0 +1,129 @@
+use std::io;
+
+// OS scheduling priority of worker threads, see `Builder::thread_priority`.
+// Only supported on Linux, where nice levels and scheduling policies apply to
+// individual threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadPriority {
+    // Nice level, from -20 (highest priority) to 19 (lowest priority). Raising
+    // the priority above the default requires privileges.
+    Nice(i32),
+    // SCHED_BATCH with the given nice level, for CPU-bound background work
+    Batch(i32),
+    // SCHED_IDLE, run only when nothing else wants to run
+    Idle,
+    // SCHED_FIFO with the given real-time priority (1 to 99), requires
+    // privileges
+    Fifo(i32),
+}
+
+impl ThreadPriority {
+    // Apply the priority to the calling thread
+    #[cfg(target_os = "linux")]
+    pub fn apply(self) -> io::Result<()> {
+        let (policy, priority, nice) = match self {
+            Self::Nice(nice) => (libc::SCHED_OTHER, 0, Some(nice)),
+            Self::Batch(nice) => (libc::SCHED_BATCH, 0, Some(nice)),
+            Self::Idle => (libc::SCHED_IDLE, 0, None),
+            Self::Fifo(priority) => (libc::SCHED_FIFO, priority, None),
+        };
+        let param = libc::sched_param { sched_priority: priority };
+        // Zero means the calling thread
+        if unsafe { libc::sched_setscheduler(0, policy, &param) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if let Some(nice) = nice {
+            let tid = unsafe { libc::gettid() } as libc::id_t;
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, nice) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(self) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Thread priorities require Linux"))
+    }
+}
+
+// Pin the calling thread to the given CPU, see `Builder::pin_workers`
+#[cfg(target_os = "linux")]
+pub fn pin_to_cpu(cpu: usize) -> io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        // Zero means the calling thread
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_to_cpu(_cpu: usize) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Pinning threads requires Linux"))
+}
+
+// The CPUs the calling thread may run on, in ascending order. Unless
+// restricted (by `taskset`, cgroups, and the like), these are all CPUs.
+#[cfg(target_os = "linux")]
+pub fn allowed_cpus() -> Vec<usize> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return all_cpus();
+        }
+        (0..libc::CPU_SETSIZE as usize).filter(|&cpu| libc::CPU_ISSET(cpu, &set)).collect()
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn allowed_cpus() -> Vec<usize> {
+    all_cpus()
+}
+
+fn all_cpus() -> Vec<usize> {
+    (0..std::thread::available_parallelism().map_or(1, |n| n.get())).collect()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    fn nice() -> i32 {
+        let tid = unsafe { libc::gettid() } as libc::id_t;
+        unsafe { libc::getpriority(libc::PRIO_PROCESS, tid) }
+    }
+
+    fn policy() -> i32 {
+        unsafe { libc::sched_getscheduler(0) }
+    }
+
+    #[test]
+    fn lower_priority() {
+        thread::spawn(|| {
+            ThreadPriority::Nice(5).apply().unwrap();
+            assert_eq!(nice(), 5);
+            ThreadPriority::Batch(10).apply().unwrap();
+            assert_eq!((policy(), nice()), (libc::SCHED_BATCH, 10));
+            ThreadPriority::Idle.apply().unwrap();
+            assert_eq!(policy(), libc::SCHED_IDLE);
+        }).join().unwrap();
+    }
+
+    #[test]
+    fn pinning() {
+        thread::spawn(|| {
+            let cpus = allowed_cpus();
+            assert!(cpus.contains(&(unsafe { libc::sched_getcpu() } as usize)));
+            // Not necessarily CPU 0
+            let cpu = *cpus.last().unwrap();
+            pin_to_cpu(cpu).unwrap();
+            assert_eq!(unsafe { libc::sched_getcpu() } as usize, cpu);
+            assert_eq!(allowed_cpus(), vec![cpu]);
+        }).join().unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+use crate::atomic;
+use std::fmt;
+use std::sync::Arc;
+
+// Quiescence (termination) detection
+//
+// How the runtime decides that all tasks are done (see `Worker::quiescent`
+// and `Runtime::join`), for anyone who coordinates work of their own, such
+// as the rounds of a phased graph algorithm, and would otherwise reach for
+// a shared atomic counter that every thread hammers:
+//
+//     let detector = QuiescenceDetector::new(num_threads);
+//     // On thread `i`, whenever it creates or finishes a work item
+//     detector.counter(i).create();
+//     detector.counter(i).complete();
+//     // On any thread, once in a while
+//     if let Some(num_items) = detector.quiescent() { ... }
+//
+// Every participant counts the items it creates and completes in counters
+// of its own. Items may change hands freely: an item created by one
+// participant and completed by another (a stolen task) still adds one to
+// both sums, so unlike classic termination detection, nothing needs to
+// travel with a stolen item. A `Token` is only a convenience: it counts an
+// item's completion when dropped, wherever that is, for items whose
+// completion is easy to miss (early returns, panics).
+//
+// The detector reads all completion counts before all creation counts:
+// since every item is created before it completes, and creating items
+// counts before completing the item that created them, equal sums imply
+// that there was a point in time when no item was outstanding. Unequal sums
+// only mean "not yet"; nothing is ever reported too early.
+
+// What the detector needs to know about a participant, see `check`
+pub trait Counts {
+    fn created(&self) -> u32;
+    // Must be read with acquire ordering (pairing with a release increment)
+    // for `check` to be exact on weakly-ordered architectures
+    fn completed(&self) -> u32;
+}
+
+// If all items created so far have been completed, returns the number of
+// items created. `counts` is called twice and must yield the same
+// participants both times.
+pub fn check<'a, C, I>(counts: impl Fn() -> I) -> Option<u32>
+where C: Counts + ?Sized + 'a, I: Iterator<Item = &'a C> {
+    let completed = counts().fold(0u32, |n, c| n.wrapping_add(c.completed()));
+    let created = counts().fold(0u32, |n, c| n.wrapping_add(c.created()));
+    if completed == created { Some(created) } else { None }
+}
+
+// One participant's counts. Counts wrap around, which is fine as long as
+// fewer than 2^32 items are outstanding at any time.
+#[derive(Debug)]
+pub struct QuiescenceCounter {
+    created: atomic::Count,
+    completed: atomic::Count,
+}
+
+impl QuiescenceCounter {
+    pub fn new() -> Self {
+        Self { created: atomic::Count::new(0), completed: atomic::Count::new(0) }
+    }
+
+    pub fn create(&self) {
+        self.created.inc();
+    }
+
+    pub fn complete(&self) {
+        self.completed.inc_release();
+    }
+
+    // Count an item's creation now and its completion when the token is
+    // dropped. The token may be sent to, and dropped on, another thread.
+    pub fn token(self: &Arc<Self>) -> Token {
+        self.create();
+        Token { counter: Arc::clone(self) }
+    }
+}
+
+impl Counts for QuiescenceCounter {
+    fn created(&self) -> u32 {
+        self.created.get()
+    }
+
+    fn completed(&self) -> u32 {
+        self.completed.get_acquire()
+    }
+}
+
+// An outstanding item, see `QuiescenceCounter::token`
+pub struct Token {
+    counter: Arc<QuiescenceCounter>,
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        self.counter.complete();
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Token")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QuiescenceDetector {
+    counters: Vec<Arc<QuiescenceCounter>>,
+}
+
+impl QuiescenceDetector {
+    // A detector for `n` participants, numbered from zero
+    pub fn new(n: usize) -> Self {
+        Self { counters: (0..n).map(|_| Arc::new(QuiescenceCounter::new())).collect() }
+    }
+
+    pub fn counter(&self, i: usize) -> &Arc<QuiescenceCounter> {
+        &self.counters[i]
+    }
+
+    pub fn len(&self) -> usize {
+        self.counters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+
+    // See `check`
+    pub fn quiescent(&self) -> Option<u32> {
+        check(|| self.counters.iter().map(|c| &**c))
+    }
+
+    // Number of items created but not yet completed, as far as we can tell
+    // while participants are busy
+    pub fn outstanding(&self) -> u32 {
+        let created = self.counters.iter().fold(0u32, |n, c| n.wrapping_add(c.created()));
+        let completed = self.counters.iter().fold(0u32, |n, c| n.wrapping_add(c.completed()));
+        created.wrapping_sub(completed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    #[test]
+    fn detect_quiescence() {
+        let detector = QuiescenceDetector::new(2);
+        assert_eq!(detector.quiescent(), Some(0));
+        detector.counter(0).create();
+        detector.counter(0).create();
+        assert_eq!(detector.quiescent(), None);
+        assert_eq!(detector.outstanding(), 2);
+        // Items are completed by a participant other than their creator
+        detector.counter(1).complete();
+        detector.counter(1).complete();
+        assert_eq!(detector.quiescent(), Some(2));
+    }
+
+    #[test]
+    fn tokens() {
+        let detector = QuiescenceDetector::new(4);
+        let (sender, receiver) = channel();
+        for i in 0..4 {
+            sender.send(detector.counter(i).token()).unwrap();
+        }
+        drop(sender);
+        assert_eq!(detector.outstanding(), 4);
+        // Items (and their tokens) move between threads
+        let handles = (0..2)
+            .map(|_| {
+                let token = receiver.recv().unwrap();
+                thread::spawn(move || drop(token))
+            })
+            .collect::<Vec<_>>();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+        assert_eq!(detector.outstanding(), 2);
+        drop(receiver);
+        assert_eq!(detector.quiescent(), Some(4));
+    }
+}
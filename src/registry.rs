@@ -0,0 +1,274 @@
+use crate::future::Promise;
+use crate::macros;
+use crate::task::Async;
+use crate::worker::Worker;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::{Acquire, Relaxed, Release}};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+// Futures that can be waited for anywhere
+//
+// Regular futures are tied to the runtime that created them: lazy futures
+// live on the spawner's stack, and waiting for a future means helping the
+// current worker. A global future instead receives its result through a
+// process-wide registry, keyed by a unique id, so that it can be sent to and
+// waited for on another runtime or on a plain thread. This allows, for
+// example, a control runtime to hand work to a separate compute runtime:
+//
+//     // On the compute runtime
+//     let result = spawn_global(move || simulate(input));
+//     control.send(result).unwrap();
+//
+//     // On the control runtime, or any other thread
+//     let output = results.recv().unwrap().get();
+//
+// All global futures share one lock, which is fine for coarse-grained
+// hand-offs, but not meant to replace regular futures within a runtime.
+// Checking whether a result is available doesn't take the lock, though.
+
+static REGISTRY: Mutex<BTreeMap<u64, Entry>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+enum State {
+    Pending,
+    Ready(Box<dyn Any + Send>),
+    // The promise was dropped without a value, for example, because the
+    // task panicked or was rejected
+    Broken,
+}
+
+struct Entry {
+    state: State,
+    // Set (with `state`) once the future is ready, see `GlobalFuture::is_ready`
+    ready: Arc<AtomicBool>,
+    // Threads waiting in `GlobalFuture::get`: plain threads park, and so
+    // may workers while they help (see `IdleStrategy`)
+    waiters: Vec<Thread>,
+}
+
+pub fn global_channel<T: Send + 'static>() -> (GlobalPromise<T>, GlobalFuture<T>) {
+    let id = NEXT_ID.fetch_add(1, Relaxed);
+    let ready = Arc::new(AtomicBool::new(false));
+    let entry = Entry { state: State::Pending, ready: Arc::clone(&ready), waiters: vec![] };
+    REGISTRY.lock().unwrap().insert(id, entry);
+    let promise = GlobalPromise { id, deliver: deliver::<T>, set: false };
+    (promise, GlobalFuture { id, ready, _result: PhantomData })
+}
+
+// Spawn a task whose result can be waited for from anywhere, see
+// `GlobalFuture`
+pub fn spawn_global<R, F>(f: F) -> GlobalFuture<R>
+where F: FnOnce() -> R + Send + 'static, R: Send + 'static {
+    let (promise, future) = global_channel();
+    let task = Async::new(f, Some(Promise::from(promise)));
+    macros::push(task);
+    future
+}
+
+fn deliver<T: Send + 'static>(id: u64, value: T) {
+    complete(id, State::Ready(Box::new(value)));
+}
+
+fn complete(id: u64, state: State) {
+    // Nobody is interested if the future has been dropped
+    if let Some(entry) = REGISTRY.lock().unwrap().get_mut(&id) {
+        entry.state = state;
+        entry.ready.store(true, Release);
+        entry.waiters.drain(..).for_each(|thread| thread.unpark());
+    }
+}
+
+pub struct GlobalPromise<T> {
+    id: u64,
+    // Boxes the value for the registry, without requiring `T: Send +
+    // 'static` wherever promises are set (see `Promise::set`)
+    deliver: fn(u64, T),
+    set: bool,
+}
+
+impl<T> GlobalPromise<T> {
+    pub fn set(mut self, value: T) {
+        self.set = true;
+        (self.deliver)(self.id, value);
+    }
+}
+
+impl<T> Drop for GlobalPromise<T> {
+    fn drop(&mut self) {
+        if !self.set {
+            complete(self.id, State::Broken);
+        }
+    }
+}
+
+// Unlike `Future`, this can be sent to other threads as long as the result
+// can be
+pub struct GlobalFuture<T> {
+    id: u64,
+    ready: Arc<AtomicBool>,
+    _result: PhantomData<fn() -> T>,
+}
+
+impl<T: 'static> GlobalFuture<T> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Acquire)
+    }
+
+    // Block until the result is available. Workers (of any runtime) help
+    // their own runtime in the meantime, other threads park. Panics if the
+    // promise was dropped without a result.
+    pub fn get(self) -> T {
+        match Worker::try_current() {
+            // Wake us up if we park while helping
+            Some(worker) => if !self.register() {
+                worker.help_until(|| self.is_ready());
+            },
+            None => {
+                while !self.park_until(None) {}
+            }
+        }
+        self.take()
+    }
+
+    // Like `get`, but give up after `timeout`
+    pub fn get_timeout(self, timeout: Duration) -> Result<T, Self> {
+        let deadline = Instant::now() + timeout;
+        match Worker::try_current() {
+            Some(worker) => if !self.register() {
+                worker.help_until(|| self.is_ready() || Instant::now() >= deadline);
+            },
+            None => {
+                while !self.park_until(Some(deadline)) && Instant::now() < deadline {}
+            }
+        }
+        match self.is_ready() {
+            true => Ok(self.take()),
+            false => Err(self),
+        }
+    }
+
+    // Have `complete` unpark the current thread, unless the result is
+    // available already (returns true). Either `complete` sees us waiting,
+    // or we see the result.
+    fn register(&self) -> bool {
+        let mut registry = REGISTRY.lock().unwrap();
+        let entry = registry.get_mut(&self.id).unwrap();
+        if !matches!(entry.state, State::Pending) {
+            return true;
+        }
+        entry.waiters.push(thread::current());
+        false
+    }
+
+    // Park once, unless the result is available already (returns true) or
+    // the deadline has passed
+    fn park_until(&self, deadline: Option<Instant>) -> bool {
+        // Register before parking
+        if self.register() {
+            return true;
+        }
+        match deadline {
+            Some(deadline) => thread::park_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => thread::park(),
+        }
+        false
+    }
+
+    fn take(self) -> T {
+        let entry = REGISTRY.lock().unwrap().remove(&self.id).unwrap();
+        match entry.state {
+            State::Ready(value) => *value.downcast().unwrap(),
+            State::Broken => panic!("Global future {} will never be ready", self.id),
+            State::Pending => unreachable!(),
+        }
+    }
+}
+
+impl<T> Drop for GlobalFuture<T> {
+    fn drop(&mut self) {
+        REGISTRY.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use std::panic;
+    use std::sync::mpsc;
+    use super::*;
+
+    fn fib(n: u64) -> u64 {
+        if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+    }
+
+    #[test]
+    fn across_runtimes() {
+        let (sender, receiver) = mpsc::channel();
+        let compute = thread::spawn(move || {
+            let runtime = Runtime::init(2);
+            for n in 20..30 {
+                sender.send(spawn_global(move || fib(n))).unwrap();
+            }
+            let _stats = runtime.join();
+        });
+
+        let runtime = Runtime::init(2);
+        let results: Vec<u64> = receiver.iter().map(GlobalFuture::get).collect();
+        assert_eq!(results, (20..30).map(fib).collect::<Vec<_>>());
+        let _stats = runtime.join();
+        compute.join().unwrap();
+    }
+
+    #[test]
+    fn plain_thread() {
+        let runtime = Runtime::init(2);
+        let future = spawn_global(|| fib(25));
+        let waiter = thread::spawn(move || future.get());
+        let _stats = runtime.join();
+        assert_eq!(waiter.join().unwrap(), fib(25));
+
+        // Nobody to set the promise
+        let (promise, future) = global_channel::<u64>();
+        let future = future.get_timeout(Duration::from_millis(10)).unwrap_err();
+        promise.set(1);
+        assert!(future.is_ready());
+        assert_eq!(future.get(), 1);
+    }
+
+    #[test]
+    fn parked_worker() {
+        use crate::idle::Park;
+
+        // The leader parks while it waits, until the result arrives
+        let runtime = Runtime::builder()
+            .num_workers(1)
+            .idle_strategy(Park { timeout: Duration::from_secs(3600) })
+            .init();
+        let (promise, future) = global_channel::<u64>();
+        let setter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            promise.set(1);
+        });
+        assert_eq!(future.get(), 1);
+        setter.join().unwrap();
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn broken_promise() {
+        let (promise, future) = global_channel::<u64>();
+        let id = future.id();
+        drop(promise);
+        assert!(future.is_ready());
+        assert!(panic::catch_unwind(move || future.get()).is_err());
+        assert!(!REGISTRY.lock().unwrap().contains_key(&id));
+    }
+}
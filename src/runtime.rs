@@ -1,106 +1,3406 @@
-use crate::scope::Scope;
+use crate::algorithms;
+use crate::atomic;
+use crate::buffer::Flushers;
+use crate::calibration::Calibration;
+#[cfg(feature = "debug-server")]
+use crate::debug_server::DebugServer;
+#[cfg(feature = "debug-server")]
+use std::net::SocketAddr;
+use crate::idle::{IdleStrategy, Spin};
+use crate::future::Promise;
+use crate::pool::Pools;
+use crate::registry::{global_channel, GlobalFuture};
+use crate::priority::{allowed_cpus, pin_to_cpu, ThreadPriority};
+use crate::scope::{self, Scope, ScopeCounters, ScopeHandle};
+use crate::source::{Sources, TaskSource};
 use crate::stats::*;
+use crate::task::{Async, TaskId, TaskInfo, TaskKind};
+use crate::trace::Traces;
+use crate::watermark::{Occupancy, Watermarks};
 use crate::worker::*;
-use std::sync::{Arc, Barrier, Mutex};
-use std::sync::mpsc::{channel, Sender, Receiver};
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::panic;
+use std::process;
+use std::str::FromStr;
+use std::sync::{Arc, Barrier, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::thread;
+use std::time::{Duration, Instant};
+
+// A named group of workers. Workers only steal from workers of the same
+// partition.
+#[derive(Debug, Clone)]
+pub struct Partition {
+    pub name: String,
+    pub workers: Range<usize>,
+}
+
+// Which tasks a victim hands out to thieves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StealPolicy {
+    // The oldest tasks, which suits depth-first recursive workloads, where
+    // the oldest tasks tend to be the biggest
+    Oldest,
+    // The oldest tasks (the default), like `Oldest`, but a steal-many
+    // response transfers `steal_percent` percent of the estimated work (see
+    // `CostModel`) rather than of the tasks, so that a thief doesn't walk
+    // away with either all the heavy tasks or only trivial ones
+    CostWeighted,
+    // The newest tasks, which suits breadth-first producers, where the
+    // newest tasks are the ones furthest from being run
+    Newest,
+    // The tasks with the lowest priority, keeping urgent tasks local (see
+    // `Task::priority`), or the oldest tasks among equals
+    LowestPriority,
+    // The tasks that have moved between workers the least (see
+    // `Task::migrations`), or the oldest tasks among equals. Tasks that have
+    // been stolen before stay put as long as there are others to hand out,
+    // rather than ping-ponging between workers and their caches.
+    FewestMigrations,
+}
+
+// The kind of core a worker runs on, on processors with heterogeneous cores
+// (e.g., big.LITTLE). Workers are assumed to be pinned accordingly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreClass {
+    // Fast cores (the default)
+    Performance,
+    // Slower, power-efficient cores
+    Efficiency,
+}
+
+// Which end of its deque a worker takes its own tasks from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopOrder {
+    // The newest task first (the default), for locality and bounded memory
+    // use in recursive workloads
+    Lifo,
+    // The oldest task first, for fairness
+    Fifo,
+}
+
+// What a thief does when its victim's queue of steal requests is full, for
+// example, because the victim is stuck in a long-running task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StealRequestOverflow {
+    // Try the other potential victims, one after the other (the default)
+    Retry,
+    // Give up right away, as if the steal attempt had failed
+    Shed,
+}
+
+// How a victim answers a steal request when its deque is empty
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyDequeResponse {
+    // Send the thief away empty-handed, to try another victim (the default)
+    None,
+    // If the victim is running a task, which may well spawn more, promise
+    // future work (`Tasks::Later`): the thief then sends its next request to
+    // the same victim rather than to a random one, up to `patience` times in
+    // a row. Saves steal traffic in producer-consumer phases, where one
+    // worker produces all the tasks.
+    Later { patience: u32 },
+}
+
+// How often a busy worker checks for steal requests between tasks (idle
+// workers always check)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollInterval {
+    // After every `n` tasks (the default is after every task)
+    Tasks(u32),
+    // After the first task that finishes at least this long after the last
+    // check
+    Time(Duration),
+}
+
+// When a busy worker answers steal requests, relative to taking its next
+// task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestServicing {
+    // After popping the next task, as often as `PollInterval` says (the
+    // default, which favors throughput)
+    AfterPop,
+    // Before popping the next task, every time, so that a thief gets served
+    // as soon as possible and may even get the last queued task (which
+    // favors latency)
+    BeforePop,
+}
+
+// Longest an adaptive poll interval can grow by default, as a power of two
+// multiple of the configured interval (see `Config::max_poll_backoff`)
+pub const MAX_POLL_BACKOFF: u32 = 6;
+
+// See `Config::retry_backoff`
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(1);
+pub const DEFAULT_MAX_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+// Runtime configuration shared by all workers
+#[derive(Debug)]
+pub struct Config {
+    partitions: Vec<Partition>,
+    pub idle: Box<dyn IdleStrategy>,
+    // Sizes of nested locality groups, see `Builder::locality_groups`
+    pub locality_groups: Vec<usize>,
+    pub attempts_per_group: u32,
+    // Maximum number of tasks transferred by a single steal-many response
+    pub max_steal_many: usize,
+    // Percentage of the queued tasks a steal-many response may transfer
+    pub steal_percent: u32,
+    // Idle workers ask for a batch of tasks (see `steal_percent`) rather
+    // than a single task. Workers waiting for a scope that is almost done
+    // still steal one task at a time, see `Scope::wait_on`.
+    pub steal_many: bool,
+    pub steal_policy: StealPolicy,
+    // Maximum number of queued steal requests per worker (`None` for one per
+    // worker)
+    pub steal_request_capacity: Option<usize>,
+    pub steal_request_overflow: StealRequestOverflow,
+    pub empty_deque_response: EmptyDequeResponse,
+    // Leave a victim out of the next `blacklist_for` steal attempts after it
+    // came back empty-handed `blacklist_after` times in a row, unless all
+    // candidates are left out (`None` to always consider every victim)
+    pub blacklist_after: Option<u32>,
+    pub blacklist_for: u32,
+    // Maximum number of consecutive tasks a worker pops from its own end of
+    // the deque before it runs the oldest queued (or an injected) task
+    pub max_lifo_streak: u32,
+    pub pop_order: PopOrder,
+    // Length below which `algorithms::par_merge_sort` sorts serially, see
+    // `Runtime::calibrate`
+    pub serial_cutoff: usize,
+    pub poll_interval: PollInterval,
+    // Double the poll interval (up to `max_poll_backoff` times) whenever a
+    // check finds no steal requests, and go back to the configured interval
+    // as soon as one does
+    pub adaptive_polling: bool,
+    pub max_poll_backoff: u32,
+    pub request_servicing: RequestServicing,
+    // Also check for steal requests whenever a task is spawned and whenever
+    // a scope is entered or left, so that a worker busy running user code
+    // (typically the leader, outside of any task) still shares its tasks
+    // with idle workers
+    pub service_on_spawn: bool,
+    // How often workers publish snapshots of their statistics, if at all
+    pub report_interval: Option<Duration>,
+    // OS scheduling priority of the worker threads, except for the leader
+    pub thread_priority: Option<ThreadPriority>,
+    // Pin worker threads (except the leader) to CPUs by worker id
+    pub pin_workers: bool,
+    pub middleware: MiddlewareChain,
+    pub admission: AdmissionControl,
+    // Workers on efficiency cores, see `CoreClass`
+    pub efficiency_workers: Vec<Range<usize>>,
+    // Tasks whose estimated cost in microseconds (see `CostModel`) reaches
+    // this threshold count as heavy: efficiency workers pass them on to
+    // performance workers, and victims don't hand them to efficiency workers
+    pub heavy_task_cost: u32,
+    // Schedule the next activation of a recurring task family on the worker
+    // that ran the last one (or a task on the worker that produced its
+    // data), unless that worker has more than this many tasks queued beyond
+    // those of the scheduling worker, see `Family` and `Located`
+    pub affinity_slack: u32,
+    // Backoff before the first retry of a failed `RetryingTask`, doubling
+    // with every further retry up to `max_retry_backoff`
+    pub retry_backoff: Duration,
+    pub max_retry_backoff: Duration,
+    // Queue at most this many tasks per worker. Spawning into a full queue
+    // runs the task right away, like a blocked producer would, unless it's
+    // spawned with `try_spawn!` (see `Worker::try_push`), which hands the
+    // task back instead.
+    pub queue_capacity: Option<usize>,
+    // Keep track of where every queued or running task is, for
+    // `Runtime::find_task`, and of which task spawned which, for
+    // `Stats::critical_path`. Costs a few locks per spawn and per task run.
+    pub track_tasks: bool,
+    // Record the most recent scheduler events of every worker, to be dumped
+    // when a task panics (see `trace`) or saved with `Runtime::write_trace`.
+    // Costs a lock per task run and per steal request.
+    pub trace: bool,
+    pub panic_policy: PanicPolicy,
+    // Deque occupancy levels, and who reacts to them, see `watermark`
+    pub watermarks: Watermarks,
+    // Where to serve the runtime's state, see `debug_server::DebugServer`
+    #[cfg(feature = "debug-server")]
+    pub debug_server: Option<String>,
+}
+
+impl Config {
+    // All workers in one partition
+    pub fn new(num_workers: usize) -> Self {
+        Self {
+            partitions: vec![Partition {
+                name: String::from("default"),
+                workers: 0..num_workers,
+            }],
+            idle: Box::new(Spin),
+            locality_groups: vec![],
+            attempts_per_group: 1,
+            max_steal_many: usize::MAX,
+            steal_percent: 50,
+            steal_many: false,
+            steal_policy: StealPolicy::CostWeighted,
+            steal_request_capacity: None,
+            steal_request_overflow: StealRequestOverflow::Retry,
+            empty_deque_response: EmptyDequeResponse::None,
+            blacklist_after: None,
+            blacklist_for: 0,
+            max_lifo_streak: u32::MAX,
+            pop_order: PopOrder::Lifo,
+            serial_cutoff: algorithms::DEFAULT_SERIAL_CUTOFF,
+            poll_interval: PollInterval::Tasks(1),
+            adaptive_polling: false,
+            max_poll_backoff: MAX_POLL_BACKOFF,
+            request_servicing: RequestServicing::AfterPop,
+            service_on_spawn: false,
+            report_interval: None,
+            thread_priority: None,
+            pin_workers: false,
+            middleware: MiddlewareChain::default(),
+            admission: AdmissionControl::default(),
+            efficiency_workers: vec![],
+            heavy_task_cost: u32::MAX,
+            affinity_slack: 4,
+            retry_backoff: DEFAULT_RETRY_BACKOFF,
+            max_retry_backoff: DEFAULT_MAX_RETRY_BACKOFF,
+            queue_capacity: None,
+            track_tasks: false,
+            trace: false,
+            panic_policy: PanicPolicy::default(),
+            watermarks: Watermarks::default(),
+            #[cfg(feature = "debug-server")]
+            debug_server: None,
+        }
+    }
+
+    pub fn num_workers(&self) -> usize {
+        self.partitions.last().unwrap().workers.end
+    }
+
+    pub fn partitions(&self) -> &[Partition] {
+        &self.partitions
+    }
+
+    // Index of the partition containing worker `id`
+    pub fn partition_of(&self, id: usize) -> usize {
+        // Helper threads (see `Helper`) join the first partition
+        if id >= self.num_workers() {
+            return 0;
+        }
+        self.partitions
+            .iter()
+            .position(|p| p.workers.contains(&id))
+            .unwrap()
+    }
+
+    pub fn partition_name(&self, index: usize) -> &str {
+        &self.partitions[index].name
+    }
+
+    pub fn core_class(&self, id: usize) -> CoreClass {
+        match self.efficiency_workers.iter().any(|w| w.contains(&id)) {
+            true => CoreClass::Efficiency,
+            false => CoreClass::Performance,
+        }
+    }
+
+    pub fn partition_workers(&self, name: &str) -> Option<Range<usize>> {
+        self.partitions
+            .iter()
+            .find(|p| p.name == name)
+            .map(|p| p.workers.clone())
+    }
+}
+
+// The scheduling parameters that can be changed while the runtime is
+// running, see `Runtime::reconfigure`. Initially taken from `Config`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedulerConfig {
+    pub max_steal_many: usize,
+    pub steal_percent: u32,
+    pub steal_policy: StealPolicy,
+    pub attempts_per_group: u32,
+    pub poll_interval: PollInterval,
+    pub adaptive_polling: bool,
+    pub max_poll_backoff: u32,
+    pub request_servicing: RequestServicing,
+    pub max_lifo_streak: u32,
+    pub pop_order: PopOrder,
+    pub serial_cutoff: usize,
+}
+
+impl SchedulerConfig {
+    fn validate(&self) {
+        assert!(self.max_steal_many > 0);
+        assert!((1..=100).contains(&self.steal_percent));
+        assert!(self.attempts_per_group > 0);
+        assert!(self.max_lifo_streak > 0);
+        assert!(self.serial_cutoff > 0);
+        assert!(self.max_poll_backoff < u32::BITS);
+        match self.poll_interval {
+            PollInterval::Tasks(n) => assert!(n > 0),
+            PollInterval::Time(t) => assert!(t > Duration::ZERO),
+        }
+    }
+
+    // Number of tasks a steal-many response hands out of `len` queued tasks
+    // (before applying `max_steal_many`). With the default of 50 percent,
+    // the thief gets the larger half.
+    pub fn steal_share(&self, len: usize) -> usize {
+        len - len * (100 - self.steal_percent as usize) / 100
+    }
+}
+
+impl From<&Config> for SchedulerConfig {
+    fn from(config: &Config) -> Self {
+        Self {
+            max_steal_many: config.max_steal_many,
+            steal_percent: config.steal_percent,
+            steal_policy: config.steal_policy,
+            attempts_per_group: config.attempts_per_group,
+            poll_interval: config.poll_interval,
+            adaptive_polling: config.adaptive_polling,
+            max_poll_backoff: config.max_poll_backoff,
+            request_servicing: config.request_servicing,
+            max_lifo_streak: config.max_lifo_streak,
+            pop_order: config.pop_order,
+            serial_cutoff: config.serial_cutoff,
+        }
+    }
+}
+
+// State shared by all workers of a runtime
+#[derive(Debug)]
+pub struct Shared {
+    pub config: Config,
+    idle_listeners: Mutex<Vec<Sender<()>>>,
+    num_idle_listeners: atomic::Count,
+    // Number of tasks created when listeners were last notified
+    last_idle: atomic::Count,
+    // Latest published statistics, one per worker
+    snapshots: Vec<Mutex<Snapshot>>,
+    // Latest epoch that snapshots were requested for, see `request_snapshot`
+    snapshot_epoch: atomic::Count,
+    // Number of times the workers have been asked to stop
+    stops: atomic::Count,
+    // Recent scheduler events, one ring per worker
+    pub traces: Arc<Traces>,
+    // Current scheduling parameters, and how often they have been changed
+    scheduler: Mutex<SchedulerConfig>,
+    reconfigurations: atomic::Count,
+    // Number of parked workers, see `Worker::park`
+    pub num_parked: atomic::Count,
+    // Load shared by all helpers, the number of helpers so far, and
+    // statistics of helpers that have left, see `Helper`
+    pub helper_load: Arc<Load>,
+    pub num_helpers: atomic::Count,
+    helper_stats: Mutex<Vec<Stats>>,
+    // Queued and running tasks, see `Config::track_tasks`
+    tasks: Mutex<HashMap<TaskId, TaskStatus>>,
+    task_graph: Mutex<TaskGraph>,
+    // Task pools of application subsystems, see `Pool`
+    pub pools: Pools,
+    // Generators of tasks, see `TaskSource`
+    pub sources: Sources,
+    // Buffered result channels, see `buffered_channel`
+    pub flushers: Flushers,
+    // Tasks submitted through handles since the workers last stopped, see
+    // `RuntimeHandle`
+    pub submitted: atomic::Count,
+    // Channels of retired workers, until a surviving worker adopts them, see
+    // `Worker::retire`
+    pub orphans: Mutex<Vec<Inbox>>,
+    pub num_orphans: atomic::Count,
+}
+
+impl Shared {
+    pub fn new(config: Config) -> Self {
+        let snapshots = (0..config.num_workers())
+            .map(|id| Mutex::new(Snapshot { epoch: 0, parked: false, stats: Stats::with_id(id) }))
+            .collect();
+        let traces = match config.trace {
+            true => Traces::new(config.num_workers()),
+            false => Traces::disabled(),
+        };
+        let scheduler = Mutex::new(SchedulerConfig::from(&config));
+        Self {
+            config,
+            snapshots,
+            snapshot_epoch: atomic::Count::new(0),
+            idle_listeners: Mutex::new(vec![]),
+            num_idle_listeners: atomic::Count::new(0),
+            last_idle: atomic::Count::new(0),
+            stops: atomic::Count::new(0),
+            traces,
+            scheduler,
+            reconfigurations: atomic::Count::new(0),
+            num_parked: atomic::Count::new(0),
+            helper_load: Arc::new(Load::new()),
+            num_helpers: atomic::Count::new(0),
+            helper_stats: Mutex::new(vec![]),
+            tasks: Mutex::new(HashMap::new()),
+            task_graph: Mutex::new(TaskGraph::new()),
+            pools: Pools::new(),
+            sources: Sources::new(),
+            flushers: Flushers::new(),
+            submitted: atomic::Count::new(0),
+            orphans: Mutex::new(vec![]),
+            num_orphans: atomic::Count::new(0),
+        }
+    }
+
+    pub fn track(&self, id: TaskId, status: TaskStatus) {
+        self.tasks.lock().unwrap().insert(id, status);
+    }
+
+    pub fn untrack(&self, id: TaskId) {
+        self.tasks.lock().unwrap().remove(&id);
+    }
+
+    // Where task `id` is, if it's queued or running
+    pub fn tracked(&self, id: TaskId) -> Option<TaskStatus> {
+        self.tasks.lock().unwrap().get(&id).copied()
+    }
+
+    pub fn task_graph(&self) -> MutexGuard<'_, TaskGraph> {
+        self.task_graph.lock().unwrap()
+    }
+
+    pub fn scheduler(&self) -> SchedulerConfig {
+        *self.scheduler.lock().unwrap()
+    }
+
+    // Statistics of helpers that have left since the last call
+    pub fn take_helper_stats(&self) -> Vec<Stats> {
+        std::mem::take(&mut *self.helper_stats.lock().unwrap())
+    }
+
+    // Workers pick up the new parameters the next time they check, see
+    // `Worker::refresh_scheduler`
+    pub fn reconfigure(&self, config: SchedulerConfig) {
+        config.validate();
+        let mut scheduler = self.scheduler.lock().unwrap();
+        *scheduler = config;
+        self.reconfigurations.inc();
+    }
+
+    pub fn reconfigurations(&self) -> u32 {
+        self.reconfigurations.get()
+    }
+
+    // Ask all workers to leave `Worker::go` once they run out of work
+    pub fn request_stop(&self) {
+        self.stops.inc();
+    }
+
+    pub fn stops(&self) -> u32 {
+        self.stops.get()
+    }
+
+    pub fn subscribe_idle(&self) -> Receiver<()> {
+        let (sender, receiver) = channel();
+        self.idle_listeners.lock().unwrap().push(sender);
+        self.num_idle_listeners.inc();
+        receiver
+    }
+
+    pub fn has_idle_listeners(&self) -> bool {
+        self.num_idle_listeners.get() > 0
+    }
+
+    // Notify listeners once per quiescent period, that is, only if new tasks
+    // have been created since the last notification
+    pub fn notify_idle(&self, num_tasks: u32) {
+        let last_idle = self.last_idle.get();
+        if last_idle == num_tasks { return; }
+        if !self.last_idle.compare_and_set(last_idle, num_tasks) { return; }
+
+        // Listeners may look for results right away
+        self.flushers.flush(None);
+        let mut listeners = self.idle_listeners.lock().unwrap();
+        listeners.retain(|listener| listener.send(()).is_ok());
+        self.num_idle_listeners.set(listeners.len() as u32);
+    }
+
+    pub fn publish(&self, snapshot: Snapshot) {
+        // Helpers don't publish snapshots
+        if let Some(slot) = self.snapshots.get(snapshot.stats.id.unwrap()) {
+            *slot.lock().unwrap() = snapshot;
+        }
+    }
+
+    pub fn snapshot_epoch(&self) -> u32 {
+        self.snapshot_epoch.get()
+    }
+
+    // Ask every worker to publish its statistics at its next opportunity,
+    // that is, between two tasks or steal attempts, regardless of
+    // `Config::report_interval`. Returns the epoch to pass to `snapshot_at`.
+    pub fn request_snapshot(&self) -> u32 {
+        self.snapshot_epoch.inc() + 1
+    }
+
+    // Statistics as of the workers' latest snapshots, which may have been
+    // taken at different times
+    pub fn snapshot(&self) -> Stats {
+        let stats = Stats::new();
+        for snapshot in self.snapshots.iter() {
+            stats.collect(&snapshot.lock().unwrap().stats);
+        }
+        stats
+    }
+
+    // Statistics of all workers, if all of them have published a snapshot
+    // for `epoch` (or a later one) or are parked. Every worker's share is
+    // taken at a single point in time, so that the totals add up.
+    pub fn snapshot_at(&self, epoch: u32) -> Option<Stats> {
+        let stats = Stats::new();
+        for snapshot in self.snapshots.iter() {
+            let snapshot = snapshot.lock().unwrap();
+            if snapshot.epoch < epoch && !snapshot.parked {
+                return None;
+            }
+            stats.collect(&snapshot.stats);
+        }
+        Some(stats)
+    }
+
+    // Request snapshots and wait up to `timeout` for all workers to publish
+    // them. Falls back to the latest snapshots of workers that are busy
+    // running a long task (or, like the leader, running application code).
+    pub fn consistent_snapshot(&self, timeout: Duration) -> Stats {
+        let epoch = self.request_snapshot();
+        let start = Instant::now();
+        loop {
+            if let Some(stats) = self.snapshot_at(epoch) {
+                return stats;
+            }
+            if start.elapsed() >= timeout {
+                return self.snapshot();
+            }
+            thread::sleep(SNAPSHOT_POLL_INTERVAL);
+        }
+    }
+}
+
+// How often `Shared::consistent_snapshot` checks for new snapshots
+const SNAPSHOT_POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+// A worker's statistics, published for the reporter and the debug server
+#[derive(Debug)]
+pub struct Snapshot {
+    // The latest requested epoch when the statistics were taken, see
+    // `Shared::request_snapshot`
+    pub epoch: u32,
+    // The worker parked right after, so its statistics stay current until
+    // it publishes again
+    pub parked: bool,
+    pub stats: Stats,
+}
+
+// Receives periodic snapshots of the runtime's statistics
+pub type Reporter = Box<dyn FnMut(&Stats) + Send>;
+
+// Wraps the execution of every task, for cross-cutting concerns like timing,
+// logging, or setting up thread-local context. Middleware must call
+// `next.run()` exactly once to run the task (or the next middleware).
+pub type Middleware = Arc<dyn Fn(&TaskInfo, Next) + Send + Sync>;
+
+// The rest of a middleware chain, ending with the task itself
+pub struct Next<'a>(&'a mut dyn FnMut());
+
+impl<'a> Next<'a> {
+    pub fn run(self) {
+        (self.0)();
+    }
+}
+
+// Middleware in the order added, the first being the outermost
+#[derive(Clone, Default)]
+pub struct MiddlewareChain(Vec<Middleware>);
+
+impl MiddlewareChain {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn push(&mut self, middleware: Middleware) {
+        self.0.push(middleware);
+    }
+
+    pub fn run(&self, info: &TaskInfo, task: impl FnOnce()) {
+        let mut task = Some(task);
+        run_chain(&self.0, info, &mut || task.take().unwrap()());
+    }
+}
+
+fn run_chain(chain: &[Middleware], info: &TaskInfo, task: &mut dyn FnMut()) {
+    match chain.split_first() {
+        Some((middleware, rest)) => middleware(info, Next(&mut || run_chain(rest, info, task))),
+        None => task(),
+    }
+}
+
+impl fmt::Debug for MiddlewareChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MiddlewareChain({})", self.0.len())
+    }
+}
+
+// Where a task is, see `Runtime::find_task`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskStatus {
+    // In the queue (or inbox) of a worker
+    Queued { worker: usize },
+    Running { worker: usize },
+    Done,
+    // Not issued yet, or the runtime doesn't track tasks
+    Unknown,
+}
+
+// What to do with a task that is being spawned, see `Builder::admission`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Queue,
+    // Run the task right away, like a function call
+    Inline,
+    // Drop the task without running it. Scoped tasks count as cancelled
+    // (see `ScopeStats::num_tasks_cancelled`); futures of rejected tasks are
+    // never fulfilled, so only reject tasks that nobody waits for.
+    Reject,
+}
+
+// The load of the whole pool at the time a task is spawned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolLoad {
+    // Tasks queued and running on all workers
+    pub queued: u32,
+    pub running: u32,
+    // Tasks queued on the spawning worker
+    pub local_queued: u32,
+}
+
+pub type AdmissionPolicy = Arc<dyn Fn(&TaskInfo, &PoolLoad) -> Admission + Send + Sync>;
+
+// The admission policy, if any, see `Builder::admission`
+#[derive(Clone, Default)]
+pub struct AdmissionControl(Option<AdmissionPolicy>);
+
+impl AdmissionControl {
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn decide(&self, info: &TaskInfo, load: &PoolLoad) -> Admission {
+        match &self.0 {
+            Some(policy) => policy(info, load),
+            None => Admission::Queue,
+        }
+    }
+}
+
+impl fmt::Debug for AdmissionControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AdmissionControl({})", if self.is_enabled() { "enabled" } else { "disabled" })
+    }
+}
+
+// What to do with a panic that escapes a task, see `Builder::panic_policy`.
+// Except for `Unwind`, workers catch panics and keep going. Futures of tasks
+// that panicked are never fulfilled, so waiting for them hangs.
+#[derive(Clone, Default)]
+pub enum PanicPolicy {
+    // Let the panic unwind the worker's thread, as if the task had been
+    // called directly
+    #[default]
+    Unwind,
+    // Abort the process (after the panic hook has reported the panic)
+    Abort,
+    // Report the panic and move on to the next task
+    LogAndContinue,
+    // Like `LogAndContinue`, but also cancel the rest of the task's scope:
+    // the scope's tasks that haven't started yet and whose results nobody
+    // waits for are skipped, see `ScopeStats::cancelled`. Panics of tasks
+    // outside of scopes are only reported.
+    CancelScope,
+    // Like `CancelScope`, but also make the scope fail: once its running
+    // tasks are done, leaving the scope re-raises the first panic on the
+    // thread that left it, so that `finish!` either completes all of its
+    // tasks or none of its results count (see `Scope::try_leave` and
+    // `try_finish!` to get the panic as an error instead)
+    FailScope,
+    // Pass the panic to a handler, on the worker that caught it
+    Handler(PanicHandler),
+}
+
+pub type PanicHandler = Arc<dyn Fn(&TaskInfo, Box<dyn Any + Send>) + Send + Sync>;
+
+impl PanicPolicy {
+    // Deal with the panic of a task, which belongs to the scope with the
+    // given counters, if any
+    pub fn handle(&self, info: &TaskInfo, scope: Option<&ScopeCounters>, payload: Box<dyn Any + Send>) {
+        match self {
+            Self::Unwind => panic::resume_unwind(payload),
+            Self::Abort => process::abort(),
+            Self::LogAndContinue => log_panic(info, &*payload),
+            Self::CancelScope => {
+                log_panic(info, &*payload);
+                if let Some(scope) = scope {
+                    scope.cancel();
+                }
+            }
+            Self::FailScope => match scope {
+                Some(scope) => scope.fail(payload),
+                None => log_panic(info, &*payload),
+            },
+            Self::Handler(handler) => handler(info, payload),
+        }
+    }
+}
+
+fn log_panic(info: &TaskInfo, payload: &(dyn Any + Send)) {
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>");
+    let name = info.name.unwrap_or("task");
+    match info.id {
+        Some(id) => eprintln!("Worker {}: {} {} panicked: {}", info.worker, name, id, message),
+        None => eprintln!("Worker {}: {} panicked: {}", info.worker, name, message),
+    }
+}
+
+impl fmt::Debug for PanicPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unwind => write!(f, "Unwind"),
+            Self::Abort => write!(f, "Abort"),
+            Self::LogAndContinue => write!(f, "LogAndContinue"),
+            Self::CancelScope => write!(f, "CancelScope"),
+            Self::FailScope => write!(f, "FailScope"),
+            Self::Handler(_) => write!(f, "Handler"),
+        }
+    }
+}
+
+// Called on a worker's thread with the worker's id, see
+// `Builder::on_worker_start` and `Builder::on_worker_exit`
+pub type WorkerHook = Arc<dyn Fn(usize) + Send + Sync>;
+
+#[derive(Clone, Default)]
+struct WorkerHooks {
+    start: Option<WorkerHook>,
+    exit: Option<WorkerHook>,
+}
+
+impl WorkerHooks {
+    fn start(&self, id: usize) {
+        if let Some(hook) = &self.start {
+            hook(id);
+        }
+    }
+
+    fn exit(&self, id: usize) {
+        if let Some(hook) = &self.exit {
+            hook(id);
+        }
+    }
+}
+
+pub struct Builder {
+    num_workers: Option<usize>,
+    partitions: Vec<(String, usize)>,
+    // Everything but the partitions, which are determined in `init`
+    config: Config,
+    reporter: Option<Reporter>,
+    hooks: WorkerHooks,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self {
+            num_workers: None,
+            partitions: vec![],
+            config: Config::new(1),
+            reporter: None,
+            hooks: WorkerHooks::default(),
+        }
+    }
+
+    pub fn num_workers(mut self, num_workers: usize) -> Self {
+        self.num_workers = Some(num_workers);
+        self
+    }
+
+    // Add a partition of `num_workers` workers. Worker ids are assigned in
+    // the order partitions are added, so that the first partition contains
+    // the leader.
+    pub fn partition(mut self, name: &str, num_workers: usize) -> Self {
+        assert!(num_workers > 0);
+        assert!(self.partitions.iter().all(|(n, _)| n != name));
+        self.partitions.push((String::from(name), num_workers));
+        self
+    }
+
+    pub fn idle_strategy(mut self, idle: impl IdleStrategy + 'static) -> Self {
+        self.config.idle = Box::new(idle);
+        self
+    }
+
+    // Nested locality groups given by their sizes, from the closest (e.g.,
+    // SMT siblings) to the widest (e.g., sockets). Groups are formed by
+    // consecutive worker ids. Thieves look for victims in their closest group
+    // first and move on to the next wider group after `attempts` failed steal
+    // attempts per group.
+    pub fn locality_groups(mut self, sizes: &[usize], attempts: u32) -> Self {
+        assert!(sizes.windows(2).all(|w| w[0] < w[1]));
+        assert!(attempts > 0);
+        self.config.locality_groups = sizes.to_vec();
+        self.config.attempts_per_group = attempts;
+        self
+    }
+
+    // Limit the number of tasks a thief can take in one go, so that a thief
+    // doesn't drain a victim with lots of queued tasks and then becomes the
+    // new bottleneck
+    pub fn max_steal_many(mut self, max: usize) -> Self {
+        assert!(max > 0);
+        self.config.max_steal_many = max;
+        self
+    }
+
+    // Let a thief take up to `percent` percent of a victim's queued tasks
+    // (50 by default)
+    pub fn steal_percent(mut self, percent: u32) -> Self {
+        assert!((1..=100).contains(&percent));
+        self.config.steal_percent = percent;
+        self
+    }
+
+    // See `Config::steal_many`
+    pub fn steal_many(mut self, steal_many: bool) -> Self {
+        self.config.steal_many = steal_many;
+        self
+    }
+
+    // Queue at most `capacity` steal requests per worker. Thieves deal with
+    // full queues according to `overflow`.
+    pub fn steal_requests(mut self, capacity: usize, overflow: StealRequestOverflow) -> Self {
+        assert!(capacity > 0);
+        self.config.steal_request_capacity = Some(capacity);
+        self.config.steal_request_overflow = overflow;
+        self
+    }
+
+    // See `EmptyDequeResponse`
+    pub fn empty_deque_response(mut self, response: EmptyDequeResponse) -> Self {
+        self.config.empty_deque_response = response;
+        self
+    }
+
+    // See `Config::blacklist_after`. Concentrates steal attempts on workers
+    // that actually have tasks, which saves round trips when most workers
+    // are idle.
+    pub fn blacklist_victims(mut self, after: u32, attempts: u32) -> Self {
+        assert!(after > 0);
+        self.config.blacklist_after = Some(after);
+        self.config.blacklist_for = attempts;
+        self
+    }
+
+    // Mark the given workers as running on efficiency cores (the others run
+    // on performance cores). May be called for several ranges of workers.
+    pub fn efficiency_cores(mut self, workers: Range<usize>) -> Self {
+        self.config.efficiency_workers.push(workers);
+        self
+    }
+
+    // Tasks with an estimated cost of at least `cost` gravitate to
+    // performance workers: efficiency workers pass them on when they spawn
+    // them, and victims keep them from efficiency thieves, giving them the
+    // cheapest tasks instead. See `efficiency_cores`.
+    pub fn heavy_task_cost(mut self, cost: u32) -> Self {
+        self.config.heavy_task_cost = cost;
+        self
+    }
+
+    // See `Config::affinity_slack`. Zero migrates activations as soon as
+    // their worker is any busier than the one scheduling them, `u32::MAX`
+    // never migrates them.
+    pub fn affinity_slack(mut self, slack: u32) -> Self {
+        self.config.affinity_slack = slack;
+        self
+    }
+
+    // See `Config::retry_backoff`
+    pub fn retry_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        assert!(initial <= max);
+        self.config.retry_backoff = initial;
+        self.config.max_retry_backoff = max;
+        self
+    }
+
+    // Choose which end of the deque workers take their own tasks from
+    pub fn pop_order(mut self, order: PopOrder) -> Self {
+        self.config.pop_order = order;
+        self
+    }
+
+    // See `Config::serial_cutoff`
+    pub fn serial_cutoff(mut self, len: usize) -> Self {
+        assert!(len > 0);
+        self.config.serial_cutoff = len;
+        self
+    }
+
+    // Switch between classic work stealing, where workers take their newest
+    // task and thieves the oldest (the default), and FIFO scheduling, where
+    // workers take their oldest task and thieves the newest
+    pub fn fifo(self, fifo: bool) -> Self {
+        match fifo {
+            true => self.pop_order(PopOrder::Fifo).steal_policy(StealPolicy::Newest),
+            false => self.pop_order(PopOrder::Lifo).steal_policy(StealPolicy::CostWeighted),
+        }
+    }
+
+    // Choose which tasks to hand out to thieves (see also `steal_percent`
+    // for how many)
+    pub fn steal_policy(mut self, policy: StealPolicy) -> Self {
+        self.config.steal_policy = policy;
+        self
+    }
+
+    // Check for steal requests only every so often, rather than after every
+    // task, which is noticeable overhead for very short tasks. If `adaptive`,
+    // check less and less often while nobody is trying to steal.
+    pub fn poll_interval(mut self, interval: PollInterval, adaptive: bool) -> Self {
+        match interval {
+            PollInterval::Tasks(n) => assert!(n > 0),
+            PollInterval::Time(t) => assert!(t > Duration::ZERO),
+        }
+        self.config.poll_interval = interval;
+        self.config.adaptive_polling = adaptive;
+        self
+    }
+
+    // Let an adaptive poll interval grow to at most `2^max` times the
+    // configured interval (see `poll_interval`)
+    pub fn max_poll_backoff(mut self, max: u32) -> Self {
+        assert!(max < u32::BITS);
+        self.config.max_poll_backoff = max;
+        self
+    }
+
+    // Trade throughput for lower thief latency, or vice versa
+    pub fn request_servicing(mut self, servicing: RequestServicing) -> Self {
+        self.config.request_servicing = servicing;
+        self
+    }
+
+    // See `Config::service_on_spawn`
+    pub fn service_on_spawn(mut self, service: bool) -> Self {
+        self.config.service_on_spawn = service;
+        self
+    }
+
+    // See `Config::track_tasks`
+    pub fn track_tasks(mut self, track: bool) -> Self {
+        self.config.track_tasks = track;
+        self
+    }
+
+    // See `Config::trace`
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.config.trace = trace;
+        self
+    }
+
+    // Choose what happens when a task panics, for example:
+    //
+    //     Runtime::builder()
+    //         .panic_policy(PanicPolicy::Handler(Arc::new(|info, _| {
+    //             eprintln!("Task {:?} failed", info.name);
+    //         })))
+    //         .init();
+    //
+    // Caught panics are counted in `Stats::num_tasks_panicked`.
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.config.panic_policy = policy;
+        self
+    }
+
+    // Every `max` consecutive tasks taken from the top of the local deque,
+    // let the oldest queued task (or a task pushed by another worker) run
+    // first, so that newly spawned tasks can't starve older ones forever
+    pub fn max_lifo_streak(mut self, max: u32) -> Self {
+        assert!(max > 0);
+        self.config.max_lifo_streak = max;
+        self
+    }
+
+    // Bound every worker's queue to `capacity` tasks, see
+    // `Config::queue_capacity`
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0);
+        self.config.queue_capacity = Some(capacity);
+        self
+    }
+
+    // Classify every worker's deque as low when it holds fewer than `low`
+    // tasks, and as high when it holds more than `high` tasks, see
+    // `watermark`
+    pub fn watermarks(mut self, low: usize, high: usize) -> Self {
+        self.config.watermarks.set_levels(low, high);
+        self
+    }
+
+    // Call `listener` whenever a worker's deque occupancy changes (requires
+    // `watermarks`)
+    pub fn on_watermark<F>(mut self, listener: F) -> Self
+    where F: Fn(usize, Occupancy) + Send + Sync + 'static {
+        self.config.watermarks.subscribe(Arc::new(listener));
+        self
+    }
+
+    // Steal batches of tasks while a worker's occupancy is low, see
+    // `Watermarks::steal_many_when_low`
+    pub fn steal_many_when_low(mut self) -> Self {
+        self.config.watermarks.steal_many_when_low = true;
+        self
+    }
+
+    // Run spawned tasks right away while a worker's occupancy is high, see
+    // `Watermarks::inline_when_high`
+    pub fn inline_when_high(mut self) -> Self {
+        self.config.watermarks.inline_when_high = true;
+        self
+    }
+
+    // Call `report` with a snapshot of the runtime's statistics every
+    // `interval` while the runtime is running. Snapshots are taken by a
+    // separate thread, so `report` should not block for long.
+    pub fn report_stats<F>(mut self, interval: Duration, report: F) -> Self
+    where F: FnMut(&Stats) + Send + 'static {
+        self.config.report_interval = Some(interval);
+        self.reporter = Some(Box::new(report));
+        self
+    }
+
+    // Serve the runtime's state as JSON at `addr` (e.g., "127.0.0.1:7878").
+    // Workers publish their statistics every 100 ms, unless configured
+    // otherwise with `report_stats`. Failing to start the server is not
+    // fatal, but reported on stderr.
+    #[cfg(feature = "debug-server")]
+    pub fn debug_server(mut self, addr: &str) -> Self {
+        self.config.debug_server = Some(String::from(addr));
+        self.config.report_interval.get_or_insert(Duration::from_millis(100));
+        self
+    }
+
+    // Run worker threads (but not the leader, which runs on the application's
+    // thread) with the given OS scheduling priority, for example, to keep a
+    // pool of background workers from competing with latency-critical
+    // threads. Failing to set the priority is not fatal, but reported on
+    // stderr.
+    pub fn thread_priority(mut self, priority: ThreadPriority) -> Self {
+        self.config.thread_priority = Some(priority);
+        self
+    }
+
+    // Pin worker `i` (except the leader, which runs on the application's
+    // thread) to the `i`th CPU the application may run on (modulo the number
+    // of such CPUs). Failing to pin is not fatal, but reported on stderr.
+    pub fn pin_workers(mut self, pin: bool) -> Self {
+        self.config.pin_workers = pin;
+        self
+    }
+
+    // Override settings with environment variables, so that programs can be
+    // reconfigured without recompiling:
+    //
+    //     RUSTY_TASKING_NUM_WORKERS      number of workers
+    //     RUSTY_TASKING_STEAL_POLICY     oldest, cost-weighted, newest,
+    //                                    lowest-priority, or fewest-migrations
+    //     RUSTY_TASKING_STEAL_PERCENT    see `steal_percent`
+    //     RUSTY_TASKING_MAX_STEAL_MANY   see `max_steal_many`
+    //     RUSTY_TASKING_FIFO             true or false, see `fifo`
+    //     RUSTY_TASKING_PIN              true or false, see `pin_workers`
+    //
+    // Panics on values that can't be parsed.
+    pub fn env_overrides(self) -> Self {
+        self.overrides(|name| env::var(name).ok())
+    }
+
+    fn overrides(mut self, var: impl Fn(&str) -> Option<String>) -> Self {
+        fn parse<T: FromStr>(name: &str, value: &str) -> T {
+            value.trim().parse().unwrap_or_else(|_| panic!("Invalid value for {}: {:?}", name, value))
+        }
+        let get = |name: &str| var(name).map(|value| (name.to_string(), value));
+
+        if let Some((name, value)) = get("RUSTY_TASKING_NUM_WORKERS") {
+            self = self.num_workers(parse(&name, &value));
+        }
+        if let Some((name, value)) = get("RUSTY_TASKING_STEAL_POLICY") {
+            self = self.steal_policy(match value.trim() {
+                "oldest" => StealPolicy::Oldest,
+                "cost-weighted" => StealPolicy::CostWeighted,
+                "newest" => StealPolicy::Newest,
+                "lowest-priority" => StealPolicy::LowestPriority,
+                "fewest-migrations" => StealPolicy::FewestMigrations,
+                _ => panic!("Invalid value for {}: {:?}", name, value),
+            });
+        }
+        if let Some((name, value)) = get("RUSTY_TASKING_STEAL_PERCENT") {
+            self = self.steal_percent(parse(&name, &value));
+        }
+        if let Some((name, value)) = get("RUSTY_TASKING_MAX_STEAL_MANY") {
+            self = self.max_steal_many(parse(&name, &value));
+        }
+        if let Some((name, value)) = get("RUSTY_TASKING_FIFO") {
+            self = self.fifo(parse(&name, &value));
+        }
+        if let Some((name, value)) = get("RUSTY_TASKING_PIN") {
+            self = self.pin_workers(parse(&name, &value));
+        }
+        self
+    }
+
+    // Call `start` on every worker thread before the worker starts running
+    // tasks, for example, to set thread priorities or initialize thread-local
+    // state. The leader's hook runs on the thread that initializes the
+    // runtime.
+    pub fn on_worker_start<F>(mut self, start: F) -> Self
+    where F: Fn(usize) + Send + Sync + 'static {
+        self.hooks.start = Some(Arc::new(start));
+        self
+    }
+
+    // Run every task through `middleware`, for example, to time or log
+    // tasks. Middleware added first runs outermost.
+    //
+    //     .middleware(|info, next| {
+    //         let start = Instant::now();
+    //         next.run();
+    //         println!("{:?} took {:?}", info.name, start.elapsed());
+    //     })
+    pub fn middleware<F>(mut self, middleware: F) -> Self
+    where F: Fn(&TaskInfo, Next) + Send + Sync + 'static {
+        self.config.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    // Decide for every spawned task whether to queue it, run it right away,
+    // or reject it, based on the task and the pool's load, for example, to
+    // shed low-priority work when the runtime is overloaded:
+    //
+    //     .admission(|info, load| match load.queued {
+    //         n if n > 10_000 && info.priority == 0 => Admission::Reject,
+    //         _ => Admission::Queue,
+    //     })
+    //
+    // Rejected tasks are counted in `Stats::num_tasks_rejected`, inlined
+    // tasks in `Stats::num_tasks_inlined`. `try_spawn!` hands rejected tasks
+    // back instead of dropping them. Only tasks spawned with the spawn macros
+    // (or `Worker::spawn`) are subject to admission; tasks pushed otherwise,
+    // including the runtime's own, are always queued.
+    pub fn admission<F>(mut self, policy: F) -> Self
+    where F: Fn(&TaskInfo, &PoolLoad) -> Admission + Send + Sync + 'static {
+        self.config.admission = AdmissionControl(Some(Arc::new(policy)));
+        self
+    }
+
+    // Call `exit` on every worker thread after the worker has stopped for
+    // good, that is, after its statistics have been collected. The leader's
+    // hook runs on the thread that joins the runtime.
+    pub fn on_worker_exit<F>(mut self, exit: F) -> Self
+    where F: Fn(usize) + Send + Sync + 'static {
+        self.hooks.exit = Some(Arc::new(exit));
+        self
+    }
+
+    fn config(self) -> Config {
+        let layout = self.layout();
+        Config { partitions: layout.partitions, ..self.config }
+    }
+
+    // Assign workers to partitions
+    fn layout(&self) -> Config {
+        if self.partitions.is_empty() {
+            return Config::new(self.num_workers.unwrap_or(1));
+        }
+
+        let mut start = 0;
+        let partitions = self.partitions
+            .iter()
+            .map(|(name, n)| {
+                let workers = start..start + n;
+                start += n;
+                Partition { name: name.clone(), workers }
+            })
+            .collect::<Vec<Partition>>();
+
+        if let Some(n) = self.num_workers {
+            assert_eq!(n, start, "Partitions must cover all workers");
+        }
+
+        Config { partitions, ..Config::new(start) }
+    }
+
+    pub fn init(mut self) -> Runtime {
+        let reporter = self.reporter.take();
+        let hooks = self.hooks.clone();
+        Runtime::with_config(self.config(), reporter, hooks)
+    }
+}
+
+// A handle for application threads that would otherwise block waiting for
+// the runtime, to help executing tasks in the meantime:
+//
+//     let helper = runtime.helper();
+//     thread::spawn(move || helper.help_current_thread(|| done.load(Acquire)));
+//
+// While helping, the thread acts as a worker that steals tasks from the other
+// workers (but not the other way round). Helpers must leave before the
+// runtime is joined; their statistics are included in the runtime's.
+#[derive(Clone)]
+pub struct Helper {
+    shared: Arc<Shared>,
+    coworkers: Vec<Coworker>,
+}
+
+impl Helper {
+    // Register the calling thread as a worker, help until `until` returns
+    // true (and tasks taken on in the meantime have been run), and
+    // deregister again. Panics if the calling thread is a worker already.
+    pub fn help_current_thread(&self, until: impl Fn() -> bool) {
+        assert!(Worker::try_current().is_none(), "Already a worker");
+        Worker::helper(self.coworkers.clone(), Arc::clone(&self.shared)).make_current();
+        Worker::current().help_and_leave(until);
+        let mut worker = Worker::release_current().unwrap();
+        worker.finalize();
+        self.shared.helper_stats.lock().unwrap().push(worker.take_stats());
+    }
+}
+
+// A handle for submitting work to a runtime from anywhere, without owning
+// it. Unlike `Runtime`, handles are cheap to clone and can be stored in
+// application structs and sent to other threads:
+//
+//     let handle = runtime.handle();
+//     thread::spawn(move || {
+//         let result = handle.spawn(move || compute(input));
+//         println!("{}", result.get());
+//     });
+//
+// Submitted tasks are delivered to the workers other than the leader in
+// turn (see `Coworker::inject`), unless the leader is the only worker. Once
+// the runtime has been joined, submitting fails.
+#[derive(Clone)]
+pub struct RuntimeHandle {
+    shared: Arc<Shared>,
+    coworkers: Vec<Coworker>,
+    // Where the next submitted task goes
+    next: Arc<AtomicUsize>,
+}
+
+impl RuntimeHandle {
+    pub fn num_workers(&self) -> usize {
+        self.coworkers.len()
+    }
+
+    // Queue a task on one of the workers. Hands the task back if the
+    // runtime has been joined.
+    pub fn submit(&self, task: impl Into<TaskKind>) -> Result<(), TaskKind> {
+        let mut task = task.into();
+        task.promote();
+        let n = self.coworkers.len();
+        // Skip retired workers, see `Runtime::retire`
+        let id = (1..n)
+            .map(|_| 1 + self.next.fetch_add(1, Relaxed) % (n - 1))
+            .find(|&id| !self.coworkers[id].load().is_retired())
+            .unwrap_or(0);
+        // Count the task before a worker can complete it, see
+        // `Worker::quiescent`
+        self.shared.helper_load.count_created();
+        match self.coworkers[id].try_inject(task) {
+            Ok(()) => {
+                self.shared.submitted.inc();
+                Ok(())
+            }
+            Err(task) => {
+                self.shared.helper_load.uncount_created();
+                Err(task)
+            }
+        }
+    }
+
+    // Spawn a task whose result can be waited for from anywhere, see
+    // `GlobalFuture`. If the runtime has been joined, the task is dropped,
+    // and so is its promise.
+    pub fn spawn<R, F>(&self, f: F) -> GlobalFuture<R>
+    where F: FnOnce() -> R + Send + 'static, R: Send + 'static {
+        let (promise, future) = global_channel();
+        let task = Async::new(f, Some(Promise::from(promise)));
+        let _ = self.submit(task);
+        future
+    }
+
+    // Statistics as of the workers' latest snapshots, see `Shared::snapshot`
+    pub fn stats(&self) -> Stats {
+        self.shared.snapshot()
+    }
+
+    // Approximate number of queued and running tasks per worker
+    pub fn load(&self) -> Vec<WorkerLoad> {
+        self.coworkers
+            .iter()
+            .map(|c| WorkerLoad {
+                id: c.id(),
+                queued: c.load().queued(),
+                running: c.load().running(),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for RuntimeHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RuntimeHandle").field("num_workers", &self.num_workers()).finish_non_exhaustive()
+    }
+}
+
+// A handle to the master, that is, the leader worker (id 0) that runs on the
+// thread that initialized the runtime. The handle borrows the runtime, so it
+// cannot outlive the leader, which is released in `Runtime::join`. Like the
+// leader, the handle is neither `Send` nor `Sync`: it's for code on the
+// leader's thread that holds a handle rather than relying on
+// `Worker::current()`. Other threads submit tasks through a `RuntimeHandle`.
+pub struct MasterHandle<'a> {
+    leader: &'a Worker,
+}
+
+impl<'a> MasterHandle<'a> {
+    pub fn id(&self) -> usize {
+        self.leader.id
+    }
+
+    pub fn partition(&self) -> &str {
+        self.leader.partition()
+    }
+
+    // Is the leader the current worker of the calling thread?
+    pub fn is_current(&self) -> bool {
+        std::ptr::eq(Worker::current(), self.leader)
+    }
+
+    pub fn has_tasks(&self) -> bool {
+        self.leader.has_tasks()
+    }
+
+    pub fn push(&self, task: impl Into<TaskKind>) {
+        self.leader.push(task);
+    }
+
+    pub fn push_to(&self, partition: &str, task: impl Into<TaskKind>) {
+        self.leader.push_to(partition, task);
+    }
+
+    // Take a task to run on the caller's own, see `Worker::take`
+    pub fn pop(&self) -> Option<TaskKind> {
+        self.leader.take()
+    }
+
+    pub fn try_handle_steal_request(&self) {
+        self.leader.try_handle_steal_request();
+    }
+
+    // Run the leader's queued tasks, see `Drain`
+    pub fn drain(&self) -> Drain<'a> {
+        self.leader.drain()
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.leader.stats
+    }
+}
+
+impl<'a> AsWorker for MasterHandle<'a> {
+    fn as_worker(&self) -> &Worker {
+        self.leader
+    }
+}
+
+// Returned by `Runtime::join_with_timeout` if the runtime didn't become
+// quiescent in time
+#[derive(Debug)]
+pub struct JoinTimeout {
+    // Statistics as of the workers' latest snapshots, which are out of date
+    // for workers stuck in a task
+    pub stats: Stats,
+    // Tasks created but not completed
+    pub num_outstanding_tasks: u32,
+    // Workers with tasks queued or running
+    pub busy_workers: Vec<WorkerLoad>,
+}
+
+impl fmt::Display for JoinTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Timed out with {} outstanding tasks", self.num_outstanding_tasks)?;
+        for w in &self.busy_workers {
+            write!(f, "\n  Worker {}: {} queued, {} running", w.id, w.queued, w.running)?;
+        }
+        Ok(())
+    }
+}
+
+// How long `Runtime::join_with_timeout` waits for workers to publish their
+// statistics after timing out
+const JOIN_SNAPSHOT_TIMEOUT: Duration = Duration::from_millis(10);
+
+// A snapshot of a worker's load
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerLoad {
+    pub id: usize,
+    pub queued: u32,
+    pub running: u32,
+}
+
+// A lightweight handle to a worker for monitoring code. It can be cloned and
+// sent to other threads, and keeps reporting the worker's load for as long
+// as the runtime is running.
+#[derive(Debug, Clone)]
+pub struct WorkerInfo {
+    id: usize,
+    partition: String,
+    load: Arc<Load>,
+}
+
+impl WorkerInfo {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    // The leader runs on the thread that initialized the runtime
+    pub fn is_leader(&self) -> bool {
+        self.id == 0
+    }
+
+    pub fn partition(&self) -> &str {
+        &self.partition
+    }
+
+    pub fn load(&self) -> WorkerLoad {
+        WorkerLoad {
+            id: self.id,
+            queued: self.load.queued(),
+            running: self.load.running(),
+        }
+    }
+
+    pub fn num_created(&self) -> u32 {
+        self.load.created()
+    }
+
+    pub fn num_completed(&self) -> u32 {
+        self.load.completed()
+    }
+
+    pub fn pending_requests(&self) -> u32 {
+        self.load.pending_requests()
+    }
+
+    // See `idle::EnergySaving`
+    pub fn is_parked(&self) -> bool {
+        self.load.is_parked()
+    }
+
+    // Nesting level of the worker's current scope (zero for the root scope)
+    #[cfg(feature = "debug-server")]
+    pub fn scope_level(&self) -> u32 {
+        self.load.scope_level()
+    }
+}
+
+// Statistics of a finished phase, see `Runtime::new_phase`
+#[derive(Debug, Clone)]
+pub struct Phase {
+    pub label: String,
+    pub stats: Stats,
+}
+
+// Statistics of one run of a region, see `Runtime::region`
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub label: String,
+    pub stats: Stats,
+}
 
 pub struct Runtime {
-    pub leader: &'static Worker,
+    // Points into the thread-local storage of the thread that initialized
+    // the runtime (which also makes `Runtime` neither `Send` nor `Sync`)
+    leader: *const Worker,
+    // Threads of the workers other than the leader, unless retired
     workers: Vec<thread::JoinHandle<()>>,
-    barrier: Arc<Barrier>,
-    stats: Arc<Mutex<Stats>>,
+    // Handles to all workers, including the leader and retired workers
+    coworkers: Vec<Coworker>,
+    // Statistics sent by the other workers whenever they stop, and those of
+    // workers retired during the current phase, see `retire`
+    stats: Receiver<Stats>,
+    retired: Vec<Stats>,
+    // Stops the reporter thread when dropped
+    reporter: Option<(Sender<()>, thread::JoinHandle<()>)>,
+    hooks: WorkerHooks,
+    // Wake up suspended workers, which then wait for each other at the given
+    // barrier, or let them terminate (when dropped)
+    resume: Vec<Sender<Arc<Barrier>>>,
+    running: bool,
+    suspended: bool,
+    #[cfg(feature = "debug-server")]
+    debug_server: Option<DebugServer>,
+    // Label of the current phase, and finished phases in order
+    phase: String,
+    phases: Vec<Phase>,
+    // Regions run so far, in order
+    regions: Vec<Region>,
+}
+
+impl Runtime {
+    pub fn init(num_workers: usize) -> Self {
+        Builder::new().num_workers(num_workers).init()
+    }
+
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    // A worker per CPU, unless configured otherwise with environment
+    // variables, see `Builder::env_overrides`
+    pub fn init_default() -> Self {
+        let num_workers = thread::available_parallelism().map_or(1, |n| n.get());
+        Builder::new().num_workers(num_workers).env_overrides().init()
+    }
+
+    fn with_config(config: Config, reporter: Option<Reporter>, hooks: WorkerHooks) -> Self {
+        let num_workers = config.num_workers();
+        assert!(num_workers > 0);
+
+        let shared = Arc::new(Shared::new(config));
+        let mut workers = Vec::with_capacity(num_workers - 1);
+        let mut resume: Vec<Sender<Arc<Barrier>>> = Vec::with_capacity(num_workers - 1);
+
+        // `N` workers communicate using `N` channels
+        let capacity = shared.config.steal_request_capacity.unwrap_or(num_workers);
+        let (mut channels, coworkers) = connect_with_capacity(num_workers, capacity);
+
+        let barrier = Arc::new(Barrier::new(num_workers));
+        let (stats_tx, stats) = channel();
+        let cpus = if shared.config.pin_workers { allowed_cpus() } else { vec![] };
+
+        let reporter = reporter.map(|mut report| {
+            let interval = shared.config.report_interval.unwrap();
+            let shared = Arc::clone(&shared);
+            let (stop, stopped) = channel();
+            let reporter = thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    report(&shared.consistent_snapshot(interval / 2));
+                }
+            });
+            (stop, reporter)
+        });
+
+        for i in 1..num_workers {
+            let (resume_tx, resume_rx) = channel();
+            resume.push(resume_tx);
+            let channel = channels.remove(1);
+            let coworkers = coworkers.clone();
+            let barrier = Arc::clone(&barrier);
+            let stats = Sender::clone(&stats_tx);
+            let shared = Arc::clone(&shared);
+            let hooks = hooks.clone();
+            let cpu = (!cpus.is_empty()).then(|| cpus[i % cpus.len()]);
+            workers.push(thread::spawn(move || {
+                if let Some(priority) = shared.config.thread_priority {
+                    if let Err(err) = priority.apply() {
+                        eprintln!("Worker {}: failed to set {:?}: {}", i, priority, err);
+                    }
+                }
+                if let Some(cpu) = cpu {
+                    if let Err(err) = pin_to_cpu(cpu) {
+                        eprintln!("Worker {}: failed to pin to CPU {}: {}", i, cpu, err);
+                    }
+                }
+                let mut worker = Worker::with_shared(i, channel, coworkers, shared);
+                Scope::init();
+                hooks.start(i);
+                barrier.wait();
+                loop {
+                    // The worker is current only while it runs, so that we
+                    // can move its statistics out afterwards
+                    worker.make_current();
+                    Worker::current().go();
+                    worker = Worker::release_current().unwrap();
+                    worker.finalize();
+                    if worker.load().is_retired() {
+                        stats.send(worker.retire()).unwrap();
+                        break;
+                    }
+                    stats.send(worker.take_stats()).unwrap();
+
+                    // Park until the runtime is resumed or shut down
+                    match resume_rx.recv() {
+                        Ok(barrier) => {
+                            worker.resume();
+                            barrier.wait();
+                        }
+                        Err(_) => break,
+                    }
+                }
+                hooks.exit(i);
+            }));
+        }
+
+        Worker::with_shared(0, channels.remove(0), coworkers.clone(), Arc::clone(&shared))
+            .make_current();
+        let leader = Worker::current() as *const Worker;
+        Scope::init();
+        hooks.start(0);
+        barrier.wait();
+
+        #[allow(unused_mut)]
+        let mut runtime = Self {
+            leader,
+            workers,
+            coworkers,
+            stats,
+            retired: vec![],
+            reporter,
+            hooks,
+            resume,
+            running: true,
+            suspended: false,
+            #[cfg(feature = "debug-server")]
+            debug_server: None,
+            phase: String::from("initial"),
+            phases: vec![],
+            regions: vec![],
+        };
+
+        #[cfg(feature = "debug-server")]
+        if let Some(addr) = &shared.config.debug_server {
+            match DebugServer::start(addr.as_str(), Arc::clone(&shared), runtime.workers()) {
+                Ok(server) => runtime.debug_server = Some(server),
+                Err(err) => eprintln!("Debug server: cannot listen on {}: {}", addr, err),
+            }
+        }
+
+        runtime
+    }
+
+    // Help executing tasks until all tasks created so far have completed
+    pub fn wait_until_idle(&self) {
+        let leader = self.leader();
+        leader.help_until(|| leader.quiescent().is_some());
+        leader.shared().flushers.flush(None);
+    }
+
+    // The runtime's statistics so far, while it's running. Every worker
+    // contributes a snapshot taken between two tasks or steal attempts (see
+    // `Shared::snapshot_at`), so the totals are consistent, unlike adding up
+    // counters while workers keep changing them. The leader helps until all
+    // workers have published their snapshot.
+    pub fn snapshot(&self) -> Stats {
+        let leader = self.leader();
+        let shared = leader.shared();
+        let epoch = shared.request_snapshot();
+        leader.publish_snapshot(false);
+        leader.help_until(|| shared.snapshot_at(epoch).is_some());
+        shared.snapshot_at(epoch).unwrap()
+    }
+
+    // Let idle workers pull tasks from `source` until it's exhausted, see
+    // `TaskSource`
+    pub fn add_source(&self, source: impl TaskSource + 'static) {
+        self.leader().add_source(source);
+    }
+
+    // Returns a channel that receives a message whenever the runtime becomes
+    // quiescent after executing tasks
+    pub fn on_idle(&self) -> Receiver<()> {
+        self.leader().shared().subscribe_idle()
+    }
+
+    // Run `f(id)` once on every worker, including the leader, and wait for
+    // all of them to finish. Tasks are delivered to workers directly rather
+    // than stolen, for per-worker setup and teardown, like warming caches or
+    // flushing worker-local buffers.
+    pub fn broadcast<F>(&self, f: F)
+    where F: Fn(usize) + Send + Sync + 'static {
+        let leader = self.leader();
+        let f = Arc::new(f);
+        let (done_tx, done) = channel();
+        let ids = self.worker_ids();
+        for &id in &ids[1..] {
+            let f = Arc::clone(&f);
+            let done = Sender::clone(&done_tx);
+            leader.push_to_worker(id, Async::detached(move || {
+                f(id);
+                done.send(()).unwrap();
+            }));
+        }
+        f(0);
+        let remaining = Cell::new(ids.len() - 1);
+        leader.help_until(|| {
+            remaining.set(remaining.get() - done.try_iter().count());
+            remaining.get() == 0
+        });
+    }
+
+    // Run `f` with a new scope on the leader and wait for all tasks spawned
+    // into the scope, see `scope::scope`
+    pub fn scope<R>(&self, f: impl FnOnce(&ScopeHandle) -> R) -> R {
+        scope::scope_on(self.leader(), f)
+    }
+
+    pub fn num_workers(&self) -> usize {
+        self.coworkers.len()
+    }
+
+    // A handle for application threads to join the runtime temporarily
+    pub fn helper(&self) -> Helper {
+        let shared = self.leader().shared_handle();
+        Helper { shared, coworkers: self.coworkers.clone() }
+    }
+
+    // A handle for submitting work from other threads, see `RuntimeHandle`
+    pub fn handle(&self) -> RuntimeHandle {
+        let shared = self.leader().shared_handle();
+        RuntimeHandle { shared, coworkers: self.coworkers.clone(), next: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    // Ids of the workers that haven't retired, see `retire`
+    pub fn worker_ids(&self) -> Vec<usize> {
+        self.coworkers
+            .iter()
+            .filter(|c| !c.load().is_retired())
+            .map(Coworker::id)
+            .collect()
+    }
+
+    // Retire worker `id` for the rest of the runtime's lifetime, for example,
+    // to give its CPU back to the system when there is less work to do. The
+    // worker finishes the task it is running, hands everything else to a
+    // surviving worker (see `Worker::retire`), and only then is its thread
+    // joined, with the leader helping in the meantime. Nobody steals from or
+    // submits to it anymore. Its statistics count towards the current phase.
+    // The leader cannot retire.
+    pub fn retire(&mut self, id: usize) {
+        assert!(id > 0 && id < self.coworkers.len(), "Cannot retire worker {}", id);
+        let load = self.coworkers[id].load();
+        assert!(!load.is_retired(), "Worker {} has already retired", id);
+        let index = (1..id).filter(|&i| !self.coworkers[i].load().is_retired()).count();
+        load.retire();
+        let worker = self.workers.remove(index);
+        self.resume.remove(index);
+        let leader = self.leader();
+        leader.help_until(|| worker.is_finished());
+        worker.join().unwrap();
+        self.retired.push(self.stats.recv().unwrap());
+    }
+
+    pub fn workers(&self) -> Vec<WorkerInfo> {
+        let config = &self.leader().shared().config;
+        self.coworkers
+            .iter()
+            .filter(|c| !c.load().is_retired())
+            .map(|c| WorkerInfo {
+                id: c.id(),
+                partition: config.partition_name(config.partition_of(c.id())).to_string(),
+                load: c.load_handle(),
+            })
+            .collect()
+    }
+
+    // Address of the debug server, if it is running
+    #[cfg(feature = "debug-server")]
+    pub fn debug_server_addr(&self) -> Option<SocketAddr> {
+        self.debug_server.as_ref().map(DebugServer::local_addr)
+    }
+
+    // The current scheduling parameters
+    pub fn scheduler_config(&self) -> SchedulerConfig {
+        self.leader().shared().scheduler()
+    }
+
+    // Change scheduling parameters without restarting the runtime. Every
+    // worker adopts the new parameters at its next safe point, that is,
+    // between two tasks or steal attempts. For example:
+    //
+    //     let mut config = runtime.scheduler_config();
+    //     config.max_steal_many = 8;
+    //     runtime.reconfigure(config);
+    pub fn reconfigure(&self, config: SchedulerConfig) {
+        let leader = self.leader();
+        leader.shared().reconfigure(config);
+        leader.refresh_scheduler();
+    }
+
+    // Measure spawn cost, steal latency, and channel overhead on this
+    // machine (see `Calibration`), and tune the scheduling parameters and
+    // the cutoff of `algorithms::par_merge_sort` accordingly. Best called
+    // right after `init`, while the runtime has nothing else to do.
+    pub fn calibrate(&self) -> Calibration {
+        let calibration = Calibration::measure(self.leader());
+        self.reconfigure(calibration.tune(self.scheduler_config()));
+        calibration
+    }
+
+    // Let thieves take up to `percent` percent of a victim's queued tasks,
+    // but at most `max` tasks, see `reconfigure`
+    pub fn set_steal_batch(&self, percent: u32, max: usize) {
+        let mut config = self.scheduler_config();
+        config.steal_percent = percent;
+        config.max_steal_many = max;
+        self.reconfigure(config);
+    }
+
+    // Approximate number of queued and running tasks per worker
+    pub fn load(&self) -> Vec<WorkerLoad> {
+        self.coworkers
+            .iter()
+            .map(|c| WorkerLoad {
+                id: c.id(),
+                queued: c.load().queued(),
+                running: c.load().running(),
+            })
+            .collect()
+    }
+
+    // Save the most recent scheduler events of all workers in the binary
+    // trace format, for viewing with `cargo run --bin trace-view`. Requires
+    // `Builder::trace`, without which there are no events to save.
+    pub fn write_trace(&self, out: &mut impl io::Write) -> io::Result<()> {
+        self.leader().shared().traces.write_to(out)
+    }
+
+    // Where task `id` is right now. Requires `Builder::track_tasks`, without
+    // which every task is `Unknown`. A task that has been issued but is
+    // neither queued nor running anymore is `Done` (or was rejected, see
+    // `Admission::Reject`).
+    pub fn find_task(&self, id: TaskId) -> TaskStatus {
+        let shared = self.leader().shared();
+        if !shared.config.track_tasks {
+            return TaskStatus::Unknown;
+        }
+        if let Some(status) = shared.tracked(id) {
+            return status;
+        }
+        let load = match self.coworkers.get(id.worker) {
+            Some(c) => c.load(),
+            None => &shared.helper_load,
+        };
+        if id.seq < load.issued() {
+            TaskStatus::Done
+        } else {
+            TaskStatus::Unknown
+        }
+    }
+
+    pub fn master(&self) -> MasterHandle<'_> {
+        MasterHandle { leader: self.leader() }
+    }
+
+    // Run the leader's queued tasks while answering steal requests, see
+    // `Drain`. Unlike `join`, draining doesn't wait for tasks that other
+    // workers have stolen.
+    pub fn drain(&self) -> Drain<'_> {
+        self.leader().drain()
+    }
+
+    fn leader(&self) -> &Worker {
+        // The leader stays in thread-local storage until `shutdown`
+        unsafe { &*self.leader }
+    }
+
+    // Wait for all tasks to complete, with the leader helping, then shut down
+    // the runtime, returning the collected statistics
+    pub fn join(mut self) -> Stats {
+        self.wait_until_idle();
+        self.shutdown()
+    }
+
+    // Like `join`, but give up if the runtime doesn't become quiescent within
+    // `timeout` (checked between tasks), for test harnesses and services
+    // that would rather fail than hang on a task that never completes. On
+    // timeout, workers are asked to stop once they run out of tasks, but
+    // nobody waits for them, and the report holds whatever statistics they
+    // have published so far, see `JoinTimeout`.
+    pub fn join_with_timeout(mut self, timeout: Duration) -> Result<Stats, JoinTimeout> {
+        let deadline = Instant::now() + timeout;
+        let leader = self.leader();
+        leader.help_until_deadline(|| leader.quiescent().is_some(), deadline);
+        if leader.quiescent().is_some() {
+            leader.shared().flushers.flush(None);
+            return Ok(self.shutdown());
+        }
+        let report = self.outstanding();
+        self.abandon();
+        Err(report)
+    }
+
+    // What keeps the runtime from becoming quiescent, see `join_with_timeout`
+    fn outstanding(&self) -> JoinTimeout {
+        let leader = self.leader();
+        let shared = leader.shared();
+        leader.publish_snapshot(false);
+        let stats = shared.consistent_snapshot(JOIN_SNAPSHOT_TIMEOUT);
+        let loads = || self.coworkers.iter().map(Coworker::load).chain([&*shared.helper_load]);
+        let completed = loads().fold(0u32, |n, l| n.wrapping_add(l.completed()));
+        let created = loads().fold(0u32, |n, l| n.wrapping_add(l.created()));
+        let busy_workers = self.load().into_iter().filter(|w| w.queued > 0 || w.running > 0).collect();
+        JoinTimeout { stats, num_outstanding_tasks: created.wrapping_sub(completed), busy_workers }
+    }
+
+    // Shut down without waiting for the workers: they stop once they run
+    // out of tasks, and a thread of their own joins them. The leader, along
+    // with its queued tasks, is leaked, since workers that are still busy
+    // may yet send it steal requests.
+    fn abandon(&mut self) {
+        assert!(self.running);
+        self.running = false;
+
+        #[cfg(feature = "debug-server")]
+        drop(self.debug_server.take());
+        if let Some((stop, _reporter)) = self.reporter.take() {
+            drop(stop);
+        }
+
+        let leader = unsafe { &*self.leader };
+        leader.shared().request_stop();
+        leader.wake_all();
+        // Stopped workers terminate instead of parking
+        self.resume.clear();
+        let workers = std::mem::take(&mut self.workers);
+        let (_, stats) = channel();
+        let stats = std::mem::replace(&mut self.stats, stats);
+        thread::spawn(move || {
+            for worker in workers {
+                let _ = worker.join();
+            }
+            drop(stats);
+        });
+
+        // See `shutdown`
+        let (leader, n) = Worker::release_worker(leader).unwrap();
+        std::mem::forget(leader);
+        Scope::remove_root(n).unwrap();
+        self.hooks.exit(0);
+    }
+
+    // Like `join`, but keep the worker threads around (parked) for the next
+    // phase of an iterative application, see `SuspendedRuntime::resume`.
+    // Waits for all tasks to complete before suspending the workers.
+    pub fn suspend(mut self) -> (Stats, SuspendedRuntime) {
+        self.wait_until_idle();
+        let stats = self.stop();
+        self.suspended = true;
+        (stats, SuspendedRuntime { runtime: self })
+    }
+
+    // Wait for all tasks to complete, then close the current phase and start
+    // a new one labeled `label`, with fresh statistics. Tasks spawned from
+    // now on count towards the new phase. Returns the statistics of the
+    // phase just finished (which are also kept, see `phases`), for example,
+    // to compare the iterations of an iterative solver. `join` returns the
+    // statistics of the last phase only.
+    pub fn new_phase(&mut self, label: &str) -> Stats {
+        self.wait_until_idle();
+        let stats = self.stop();
+        self.wake();
+        let label = std::mem::replace(&mut self.phase, String::from(label));
+        self.phases.push(Phase { label, stats: stats.clone() });
+        stats
+    }
+
+    pub fn phase(&self) -> &str {
+        &self.phase
+    }
+
+    // Finished phases, oldest first
+    pub fn phases(&self) -> &[Phase] {
+        &self.phases
+    }
+
+    // Run `f` on the leader, with all workers helping as usual, and record
+    // what happened in the meantime under `label`, to compare different
+    // parallel implementations within one program:
+    //
+    //     runtime.region("recursive", || fib(40));
+    //     runtime.region("iterative", || fib_iter(40));
+    //     for region in runtime.regions() { ... }
+    //
+    // Waits for all tasks to complete before and after `f`, so that the
+    // statistics cover the tasks `f` spawns and nothing else.
+    pub fn region<R>(&mut self, label: &str, f: impl FnOnce() -> R) -> R {
+        self.wait_until_idle();
+        let before = self.snapshot();
+        let result = f();
+        self.wait_until_idle();
+        let stats = self.snapshot().diff(&before);
+        self.regions.push(Region { label: String::from(label), stats });
+        result
+    }
+
+    // Regions run so far, oldest first
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    // Statistics of all runs of the region labeled `label` combined, or
+    // `None` if there were none
+    pub fn region_stats(&self, label: &str) -> Option<Stats> {
+        let mut regions = self.regions.iter().filter(|r| r.label == label).peekable();
+        regions.peek()?;
+        let stats = Stats::new();
+        for region in regions {
+            stats.update(&region.stats);
+        }
+        Some(stats)
+    }
+
+    // Let stopped workers enter their worker loops again
+    fn wake(&mut self) {
+        // Retired workers don't come back
+        let barrier = Arc::new(Barrier::new(self.workers.len() + 1));
+        for resume in self.resume.iter() {
+            resume.send(Arc::clone(&barrier)).unwrap();
+        }
+        self.leader().resume();
+        barrier.wait();
+    }
+
+    // Like `join`, but also print a summary of the collected statistics,
+    // including how evenly the workers shared the work (see `Balance`)
+    pub fn report(self) -> Stats {
+        let stats = self.join();
+        println!("{}", stats);
+        println!("{}", stats.balance());
+        stats
+    }
+
+    // Ask workers to leave their worker loops and collect their statistics
+    fn stop(&mut self) -> Stats {
+        let leader = unsafe { &*self.leader };
+        assert_eq!(leader.id, 0);
+
+        leader.shared().request_stop();
+        leader.wake_all();
+        leader.finalize();
+
+        let stats = Stats::new();
+        stats.collect(&leader.stats);
+        for worker in self.stats.iter().take(self.workers.len()) {
+            stats.collect(&worker);
+        }
+        for worker in self.retired.drain(..) {
+            stats.collect(&worker);
+        }
+        // Retired workers' final snapshots belong to the phase just finished
+        for c in self.coworkers.iter().filter(|c| c.load().is_retired()) {
+            leader.shared().publish(Snapshot {
+                epoch: leader.shared().snapshot_epoch(),
+                parked: true,
+                stats: Stats::with_id(c.id()),
+            });
+        }
+        for helper in leader.shared().take_helper_stats() {
+            stats.collect(&helper);
+        }
+        let submitted = leader.shared().submitted.get();
+        leader.shared().submitted.sub(submitted);
+        stats.num_submitted_tasks.add(submitted);
+        stats.num_tasks_created.add(submitted);
+        // Every phase starts with an empty task graph
+        let mut graph = leader.shared().task_graph();
+        stats.critical_path.set(graph.critical_path());
+        graph.clear();
+        stats
+    }
+
+    fn shutdown(&mut self) -> Stats {
+        assert!(self.running);
+        self.running = false;
+
+        let leader = unsafe { &*self.leader };
+        assert_eq!(leader.id, 0);
+
+        #[cfg(feature = "debug-server")]
+        drop(self.debug_server.take());
+
+        if let Some((stop, reporter)) = self.reporter.take() {
+            drop(stop);
+            let result = reporter.join();
+            if !thread::panicking() {
+                result.unwrap();
+            }
+        }
+
+        // Ask workers to terminate (suspended workers have already stopped)
+        let stats = match self.suspended {
+            true => Stats::new(),
+            false => self.stop(),
+        };
+        self.resume.clear();
+
+        // Join workers, but don't panic again if we are already unwinding
+        for worker in self.workers.drain(..) {
+            let result = worker.join();
+            if !thread::panicking() {
+                result.unwrap();
+            }
+        }
+
+        // Remove the leader and its root scope from thread-local storage, so
+        // that another runtime's leader becomes current again. Runtimes on
+        // the same thread may be joined in any order, for example, when they
+        // are dropped during a panic.
+        let (_, n) = Worker::release_worker(leader).unwrap();
+        Scope::remove_root(n).unwrap();
+        self.hooks.exit(0);
+
+        stats
+    }
+}
+
+// A runtime whose workers are parked between two phases of an application.
+// Dropping a suspended runtime shuts it down.
+pub struct SuspendedRuntime {
+    runtime: Runtime,
+}
+
+impl SuspendedRuntime {
+    // Wake up the workers again, starting over with fresh statistics
+    pub fn resume(self) -> Runtime {
+        let mut runtime = self.runtime;
+        runtime.wake();
+        runtime.suspended = false;
+        runtime
+    }
+
+    pub fn join(self) {
+        let _ = self.runtime.join();
+    }
+}
+
+// Shut down the runtime if it goes out of scope without calling `join`, for
+// example, because of an early return or a panic, so that worker threads
+// don't outlive the runtime
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        if self.running {
+            let _ = self.shutdown();
+        }
+    }
 }
 
-impl Runtime {
-    pub fn init(num_workers: usize) -> Self {
-        assert!(num_workers > 0);
+// Initialize a runtime with `num_workers` workers, run `f` on the leader,
+// help until all tasks spawned along the way have completed, and shut down
+// the runtime again. Returns the result of `f` and the collected statistics.
+pub fn run_with<T, F>(num_workers: usize, f: F) -> (T, Stats)
+where F: FnOnce() -> T {
+    let runtime = Runtime::init(num_workers);
+    let result = f();
+    runtime.wait_until_idle();
+    (result, runtime.join())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task::ScopedAsync;
+    use std::collections::HashSet;
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn init_and_join() {
+        for n in 1..4 {
+            let stats = Runtime::init(n).join();
+            assert_eq!(stats.num_tasks_executed.get(), 0);
+            assert_eq!(stats.workers().len(), n);
+        }
+    }
+
+    #[test]
+    fn nested_runtimes() {
+        let outer = Runtime::init(2);
+        assert!(outer.master().is_current());
+
+        let inner = Runtime::init(3);
+        assert!(inner.master().is_current());
+        finish! {
+            for _ in 0..10 {
+                scoped_spawn!();
+            }
+        }
+        assert_stat_eq!(inner.join().num_tasks_executed.get(), 10);
+
+        assert!(outer.master().is_current());
+        finish! {
+            for _ in 0..20 {
+                scoped_spawn!();
+            }
+        }
+        assert_stat_eq!(outer.join().num_tasks_executed.get(), 20);
+        assert!(Worker::release_current().is_none());
+    }
+
+    #[test]
+    fn runtimes_joined_out_of_order() {
+        let first = Runtime::init(2);
+        let second = Runtime::init(2);
+        assert_eq!(first.join().num_tasks_executed.get(), 0);
+
+        // The second runtime stays current
+        assert!(std::ptr::eq(Worker::current(), second.leader));
+        finish! {
+            for _ in 0..10 {
+                scoped_spawn!();
+            }
+        }
+        assert_stat_eq!(second.join().num_tasks_executed.get(), 10);
+        assert!(Worker::release_current().is_none());
+
+        // Dropping runtimes while unwinding doesn't abort
+        let result = panic::catch_unwind(|| {
+            let first = Runtime::init(2);
+            let _second = Runtime::init(2);
+            drop(first);
+            panic!("runtime dropped out of order");
+        });
+        assert!(result.is_err());
+        assert!(Worker::release_current().is_none());
+    }
+
+    #[test]
+    fn concurrent_runtimes() {
+        let threads = (1..4)
+            .map(|n| {
+                thread::spawn(move || {
+                    let runtime = Runtime::init(n);
+                    finish! {
+                        for _ in 0..100 {
+                            scoped_spawn!();
+                        }
+                    }
+                    runtime.join().num_tasks_executed.get()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads {
+            assert_stat_eq!(t.join().unwrap(), 100);
+        }
+    }
+
+    #[test]
+    fn drop_without_join() {
+        {
+            let _runtime = Runtime::init(3);
+            finish! {
+                for _ in 0..10 {
+                    scoped_spawn!();
+                }
+            }
+            // Implicit shutdown
+        }
+        assert!(Worker::release_current().is_none());
+
+        let result = thread::spawn(|| {
+            let _runtime = Runtime::init(3);
+            panic!("Early exit");
+        }).join();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn locality_groups() {
+        let runtime = Runtime::builder()
+            .num_workers(8)
+            .locality_groups(&[2, 4], 2)
+            .init();
+        let leader = Worker::current();
+
+        assert_eq!(leader.victim_candidates(), vec![1]);
+        for expected in [vec![1], vec![1, 2, 3], vec![1, 2, 3]] {
+            // Nobody has any work
+            assert!(matches!(leader.steal_one().wait(), Tasks::None));
+            assert_eq!(leader.victim_candidates(), expected);
+        }
+        for _ in 0..2 {
+            assert!(matches!(leader.steal_one().wait(), Tasks::None));
+        }
+        assert_eq!(leader.victim_candidates(), (1..8).collect::<Vec<_>>());
 
-        let mut workers = Vec::with_capacity(num_workers - 1);
+        let _stats = runtime.join();
+    }
 
-        // `N` workers communicate using `N` channels
-        let channels = (0..num_workers)
-            .map(|_| channel())
-            .collect::<Vec<(Sender<StealRequest>, _)>>();
+    #[test]
+    fn load() {
+        let runtime = Runtime::init(2);
+        let idle = vec![
+            WorkerLoad { id: 0, queued: 0, running: 0 },
+            WorkerLoad { id: 1, queued: 0, running: 0 },
+        ];
+        assert_eq!(runtime.load(), idle);
 
-        let coworkers = channels
-            .iter()
-            .enumerate()
-            .map(|(i, (chan, _))| Coworker::new(i, Sender::clone(chan)))
-            .collect::<Vec<Coworker>>();
+        finish! {
+            for _ in 0..5 {
+                scoped_spawn! {
+                    assert!(Worker::current().load().running() >= 1);
+                }
+            }
+            // Nobody is serving steal requests yet
+            assert_eq!(runtime.load()[0], WorkerLoad { id: 0, queued: 5, running: 0 });
+        }
+        assert_eq!(runtime.load()[0], idle[0]);
 
-        let mut channels = channels
-            .into_iter()
-            .map(|(_, r)| r)
-            .collect::<Vec<Receiver<StealRequest>>>();
+        let _stats = runtime.join();
+    }
 
-        let barrier = Arc::new(Barrier::new(num_workers));
-        let stats = Arc::new(Mutex::new(Stats::new()));
+    fn spawn_tree(depth: u32) {
+        if depth > 0 {
+            for _ in 0..3 {
+                spawn!(spawn_tree(depth - 1));
+            }
+        }
+    }
 
-        for i in 1..num_workers {
-            let channel = channels.remove(1);
-            let coworkers = coworkers.clone();
-            let barrier = Arc::clone(&barrier);
-            let stats = Arc::clone(&stats);
-            workers.push(thread::spawn(move || {
-                Worker::new(i, channel, coworkers).make_current();
+    #[test]
+    fn run_with() {
+        let (n, stats) = super::run_with(3, || {
+            spawn!(spawn_tree(3));
+            42
+        });
+        assert_eq!(n, 42);
+        assert_stat_eq!(stats.num_tasks_executed.get(), 1 + 3 + 9 + 27);
+        // Every task's scheduling delay has been recorded
+        assert_stat_eq!(stats.latency.count(), 1 + 3 + 9 + 27);
+        assert_stat!(stats.max_latency.get() > Duration::ZERO);
+        if cfg!(target_os = "linux") {
+            assert_stat!(stats.cpu_time.get() > Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn reconfigure() {
+        let runtime = Runtime::builder().num_workers(3).max_steal_many(4).init();
+        let mut config = runtime.scheduler_config();
+        assert_eq!(config.max_steal_many, 4);
+
+        config.max_steal_many = 2;
+        config.poll_interval = PollInterval::Tasks(8);
+        config.request_servicing = RequestServicing::BeforePop;
+        runtime.reconfigure(config);
+        assert_eq!(runtime.scheduler_config(), config);
+
+        let (sender, receiver) = channel();
+        runtime.broadcast(move |_| {
+            sender.send(Worker::current().scheduler()).unwrap();
+        });
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![config; 3]);
+
+        spawn!(spawn_tree(4));
+        runtime.wait_until_idle();
+        let stats = runtime.join();
+        // The leader's part of `broadcast` doesn't run as a task
+        assert_stat_eq!(stats.num_tasks_executed.get(), 1 + 3 + 9 + 27 + 81 + 2);
+    }
+
+    #[test]
+    fn priority_inheritance() {
+        use crate::channel::one_shot_channel;
+        let runtime = Runtime::init(1);
+        let (sender, receiver) = channel();
+        runtime.master().push(Async::detached(move || {
+            // The child runs while we wait for it, at our priority
+            let mut child = spawn!(one_shot_channel, Worker::current().current_priority());
+            sender.send(child.wait()).unwrap();
+        }).with_priority(5));
+        runtime.wait_until_idle();
+        assert_eq!(receiver.recv().unwrap(), 5);
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn middleware() {
+        let (sender, receiver) = channel();
+        let log = Mutex::new(sender);
+        let runtime = Runtime::builder()
+            .num_workers(2)
+            .middleware(move |info, next| {
+                log.lock().unwrap().send(("outer", info.name)).unwrap();
+                next.run();
+            })
+            .middleware(|info, next| {
+                assert_eq!(info.worker, Worker::current().id);
+                next.run();
+            })
+            .init();
+        runtime.master().push(Async::detached(|| ()).with_name("a"));
+        runtime.master().push(Async::detached(|| ()));
+        runtime.wait_until_idle();
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 2);
+
+        let mut log = receiver.try_iter().collect::<Vec<_>>();
+        log.sort();
+        assert_eq!(log, vec![("outer", None), ("outer", Some("a"))]);
+    }
+
+    #[test]
+    fn custom_counters() {
+        let retries = Stats::register_counter("retries");
+        let runtime = Runtime::init(3);
+        for i in 0..100 {
+            spawn!(if i % 4 == 0 { retries.inc() } else { retries.add(2) });
+        }
+        runtime.wait_until_idle();
+        let stats = runtime.join();
+        assert_eq!(stats.counter("retries"), 25 + 75 * 2);
+        assert_eq!(stats.counters(), vec![("retries", 175)]);
+        let per_worker: u64 = stats.workers().iter().map(|w| w.counter("retries")).sum();
+        assert_eq!(per_worker, 175);
+        assert_eq!(stats.counter("misses"), 0);
+    }
+
+    #[test]
+    fn env_overrides() {
+        let vars = [
+            ("RUSTY_TASKING_NUM_WORKERS", "3"),
+            ("RUSTY_TASKING_STEAL_POLICY", "newest"),
+            ("RUSTY_TASKING_STEAL_PERCENT", "25"),
+            ("RUSTY_TASKING_FIFO", "true"),
+            ("RUSTY_TASKING_PIN", "false"),
+        ];
+        let var = |name: &str| vars.iter().find(|&&(n, _)| n == name).map(|&(_, v)| v.to_string());
+        let config = Builder::new().num_workers(1).overrides(var).config();
+        assert_eq!(config.num_workers(), 3);
+        assert_eq!(config.steal_policy, StealPolicy::Newest);
+        assert_eq!(config.steal_percent, 25);
+        assert_eq!(config.pop_order, PopOrder::Fifo);
+        assert!(!config.pin_workers);
+
+        // Unset variables leave the defaults alone
+        let config = Builder::new().num_workers(2).overrides(|_| None).config();
+        assert_eq!(config.num_workers(), 2);
+        assert_eq!(config.steal_policy, StealPolicy::CostWeighted);
+    }
+
+    #[test]
+    #[should_panic(expected = "RUSTY_TASKING_STEAL_POLICY")]
+    fn invalid_env_override() {
+        let _ = Builder::new().overrides(|name| {
+            (name == "RUSTY_TASKING_STEAL_POLICY").then(|| String::from("youngest"))
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pinned_workers() {
+        let cpus = Arc::new(Mutex::new(vec![]));
+        let runtime = {
+            let cpus = Arc::clone(&cpus);
+            Runtime::builder()
+                .num_workers(2)
+                .pin_workers(true)
+                .on_worker_start(move |id| if id > 0 {
+                    cpus.lock().unwrap().push(unsafe { libc::sched_getcpu() } as usize);
+                })
+                .init()
+        };
+        // The CPU we may run on after the first one, if any
+        let allowed = allowed_cpus();
+        assert_eq!(*cpus.lock().unwrap(), vec![allowed[1 % allowed.len()]]);
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn join_waits_for_tasks() {
+        let runtime = Runtime::init(3);
+        for _ in 0..10 {
+            spawn! {
+                for _ in 0..10 {
+                    spawn!(utils::compute(Duration::from_micros(100)));
+                }
+            }
+        }
+        // No `wait_until_idle`
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 110);
+    }
+
+    #[test]
+    fn runtime_handles() {
+        fn send_sync<T: Send + Sync>(_: &T) {}
+
+        let runtime = Runtime::init(3);
+        let handle = runtime.handle();
+        send_sync(&handle);
+        let submitters: Vec<_> = (0..4u64)
+            .map(|i| {
+                let handle = handle.clone();
+                thread::spawn(move || {
+                    let results: Vec<_> = (0..25).map(|j| handle.spawn(move || i * 25 + j)).collect();
+                    results.into_iter().map(GlobalFuture::get).sum::<u64>()
+                })
+            })
+            .collect();
+        let sum: u64 = submitters.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(sum, (0..100).sum());
+        runtime.wait_until_idle();
+        assert_eq!(handle.load().len(), 3);
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_submitted_tasks.get(), 100);
+        assert_stat_eq!(stats.num_tasks_executed.get(), 100);
+        assert!(handle.submit(Async::detached(|| ())).is_err());
+    }
+
+    #[test]
+    fn helper_threads() {
+        use std::sync::atomic::AtomicU32;
+
+        let runtime = Runtime::init(1);
+        let done = Arc::new(AtomicU32::new(0));
+        for _ in 0..100 {
+            let done = Arc::clone(&done);
+            spawn! {
+                utils::compute(Duration::from_millis(1));
+                done.fetch_add(1, Relaxed);
+            }
+        }
+        let helpers: Vec<_> = (0..2)
+            .map(|_| {
+                let helper = runtime.helper();
+                let done = Arc::clone(&done);
+                thread::spawn(move || helper.help_current_thread(|| done.load(Relaxed) == 100))
+            })
+            .collect();
+        // Make sure the helpers get something to do
+        while runtime.workers()[0].pending_requests() == 0 {
+            thread::yield_now();
+        }
+        runtime.wait_until_idle();
+        helpers.into_iter().for_each(|h| h.join().unwrap());
+        assert!(Worker::try_current().is_some_and(|w| !w.is_helper()));
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 100);
+        // Helpers have ids of their own
+        let workers = stats.workers();
+        assert_eq!(workers.iter().map(|w| w.id).collect::<Vec<_>>(), vec![Some(0), Some(1), Some(2)]);
+        assert_stat!(workers[1..].iter().map(|w| w.num_tasks_executed.get()).sum::<u32>() > 0);
+    }
+
+    #[test]
+    fn idle_worker_utilization() {
+        let idle = crate::idle::Park { timeout: Duration::from_millis(5) };
+        let runtime = Runtime::builder().num_workers(2).idle_strategy(idle).init();
+        // Decline steal requests, so that the other worker idles in between
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(50) {
+            Worker::current().try_handle_steal_request();
+            thread::yield_now();
+        }
+        let stats = runtime.join();
+        assert!(stats.workers()[1].utilization() < 0.5);
+    }
+
+    #[test]
+    fn service_on_spawn() {
+        use std::sync::atomic::AtomicUsize;
+
+        let runtime = Runtime::builder().num_workers(2).service_on_spawn(true).init();
+        while runtime.workers()[0].pending_requests() == 0 {
+            thread::yield_now();
+        }
+        // The leader never waits or helps while the tasks run, but answers
+        // steal requests whenever it spawns
+        let stolen = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100 {
+            let stolen = Arc::clone(&stolen);
+            spawn! {
+                if Worker::current().id != 0 {
+                    stolen.fetch_add(1, Relaxed);
+                }
+            }
+        }
+        let start = std::time::Instant::now();
+        while stolen.load(Relaxed) == 0 && start.elapsed() < Duration::from_secs(10) {
+            thread::yield_now();
+        }
+        assert!(stolen.load(Relaxed) > 0);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn find_task() {
+        use crate::task::TaskId;
+        use std::sync::atomic::AtomicBool;
+
+        let runtime = Runtime::builder().num_workers(2).track_tasks(true).init();
+        let go = Arc::new(AtomicBool::new(false));
+
+        Scope::enter();
+        let future = {
+            let go = Arc::clone(&go);
+            Scope::current().spawn(move || {
+                while !go.load(Relaxed) {
+                    thread::yield_now();
+                }
                 let worker = Worker::current();
-                Scope::init();
-                barrier.wait();
-                worker.go();
-                worker.finalize();
-                {
-                    let stats = stats.lock().unwrap();
-                    stats.update(&worker.stats);
+                let id = worker.current_task().unwrap();
+                (id, worker.shared().tracked(id), worker.id)
+            })
+        };
+        let id = future.id();
+        assert_eq!(id.worker, 0);
+        // Nobody helps the leader, so the task stays put
+        assert_eq!(runtime.find_task(id), TaskStatus::Queued { worker: 0 });
+        go.store(true, Relaxed);
+        Scope::leave();
+
+        let (seen, status, worker) = future.get();
+        assert_eq!(seen, id);
+        assert_eq!(status, Some(TaskStatus::Running { worker }));
+        assert_eq!(runtime.find_task(id), TaskStatus::Done);
+        assert_eq!(runtime.find_task(TaskId { worker: 1, seq: 1000 }), TaskStatus::Unknown);
+
+        // Detached tasks have ids too, and stolen tasks are tracked by the thief
+        let go = Arc::new(AtomicBool::new(false));
+        let id = {
+            let go = Arc::clone(&go);
+            spawn!(id, while !go.load(Relaxed) { thread::yield_now(); }).unwrap()
+        };
+        assert_eq!(runtime.find_task(id), TaskStatus::Queued { worker: 0 });
+        while runtime.find_task(id) != (TaskStatus::Running { worker: 1 }) {
+            Worker::current().try_handle_steal_request();
+        }
+        go.store(true, Relaxed);
+        runtime.wait_until_idle();
+        assert_eq!(runtime.find_task(id), TaskStatus::Done);
+
+        let _stats = runtime.join();
+
+        // Not tracked
+        let runtime = Runtime::init(1);
+        Scope::enter();
+        let future = Scope::current().spawn(|| ());
+        Scope::leave();
+        assert_eq!(runtime.find_task(future.id()), TaskStatus::Unknown);
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn critical_path() {
+        fn chain(n: u32) {
+            thread::sleep(Duration::from_millis(10));
+            if n > 1 {
+                spawn!(chain(n - 1));
+            }
+        }
+
+        let runtime = Runtime::builder().num_workers(2).track_tasks(true).init();
+        spawn!(chain(3));
+        for _ in 0..4 {
+            spawn!(thread::sleep(Duration::from_millis(10)));
+        }
+        let stats = runtime.join();
+        let (work, critical_path) = (stats.task_work.get(), stats.critical_path.get());
+        assert_stat!(work >= Duration::from_millis(70));
+        assert_stat!(critical_path >= Duration::from_millis(30) && critical_path < work);
+        assert_stat!(stats.parallelism() > 1.0);
+
+        // Not tracked
+        let runtime = Runtime::init(1);
+        spawn!(chain(2));
+        let stats = runtime.join();
+        assert_eq!(stats.critical_path.get(), Duration::ZERO);
+        assert_eq!(stats.parallelism(), 0.0);
+    }
+
+    #[test]
+    fn throttled_steals() {
+        let runtime = Runtime::builder()
+            .num_workers(2)
+            .steal_many(true)
+            .service_on_spawn(true)
+            .init();
+
+        Scope::enter();
+        while runtime.workers()[0].pending_requests() == 0 {
+            thread::yield_now();
+        }
+        // Handed to worker 1 right away
+        scoped_spawn!(thread::sleep(Duration::from_millis(20)));
+        // Waiting for the last task, the leader only asks for single tasks
+        let stats = Scope::leave();
+        assert_eq!(stats.num_tasks_stolen, 1);
+
+        let stats = runtime.join();
+        assert_stat!(stats.num_steals_throttled.get() > 0);
+    }
+
+    #[test]
+    fn panic_policies() {
+        use crate::task::Detached;
+        use std::sync::atomic::AtomicUsize;
+
+        let runtime = Runtime::builder()
+            .num_workers(2)
+            .panic_policy(PanicPolicy::LogAndContinue)
+            .init();
+        let count = Arc::new(AtomicUsize::new(0));
+        for i in 0..10 {
+            let count = Arc::clone(&count);
+            spawn! {
+                if i % 2 == 0 {
+                    panic!("Task {} failed", i);
                 }
-                barrier.wait();
-                // worker.stats
-                // ^^^^^^^^^^^^ cannot move out of borrowed content
-            }));
+                count.fetch_add(1, Relaxed);
+            }
         }
+        runtime.wait_until_idle();
+        assert_eq!(count.load(Relaxed), 5);
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_panicked.get(), 5);
 
-        Worker::new(0, channels.remove(0), coworkers).make_current();
-        let leader = Worker::current();
-        Scope::init();
-        barrier.wait();
+        let failures = Arc::new(Mutex::new(vec![]));
+        let runtime = {
+            let failures = Arc::clone(&failures);
+            Runtime::builder()
+                .num_workers(2)
+                .panic_policy(PanicPolicy::Handler(Arc::new(move |info, payload| {
+                    let message = *payload.downcast::<&str>().unwrap();
+                    failures.lock().unwrap().push((info.name, message));
+                })))
+                .init()
+        };
+        Worker::current().push(Detached::new(|| panic!("Oops")).with_name("fail"));
+        runtime.wait_until_idle();
+        assert_eq!(*failures.lock().unwrap(), [(Some("fail"), "Oops")]);
+        let _stats = runtime.join();
 
-        Self { leader, workers, barrier, stats }
+        // With one worker, the panicking task (spawned last) runs first
+        let runtime = Runtime::builder().num_workers(1).panic_policy(PanicPolicy::CancelScope).init();
+        Scope::enter();
+        for _ in 0..9 {
+            let count = Arc::clone(&count);
+            scoped_spawn!(count.fetch_add(1, Relaxed));
+        }
+        scoped_spawn!(panic!("Cancel"));
+        let stats = Scope::leave();
+        assert!(stats.cancelled);
+        assert_eq!(stats.num_tasks_cancelled, 9);
+        assert_eq!(count.load(Relaxed), 5);
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_panicked.get(), 1);
     }
 
-    pub fn join(self) -> Stats {
-        let leader = self.leader;
-        assert_eq!(leader.id, 0);
+    #[test]
+    fn fail_scope() {
+        let runtime = Runtime::builder().num_workers(3).panic_policy(PanicPolicy::FailScope).init();
+        let count = Arc::new(AtomicUsize::new(0));
 
-        // Ask workers to terminate
-        leader.finalize();
-        {
-            let stats = self.stats.lock().unwrap();
-            stats.update(&leader.stats);
+        let result = panic::catch_unwind(|| {
+            finish! {
+                for i in 0..100 {
+                    let count = Arc::clone(&count);
+                    scoped_spawn!({
+                        if i == 0 { panic!("First"); }
+                        count.fetch_add(1, Relaxed);
+                    });
+                }
+            }
+        });
+        let payload = result.unwrap_err();
+        assert_eq!(*payload.downcast::<&str>().unwrap(), "First");
+        assert!(count.load(Relaxed) < 100);
+
+        // Panics of nested scopes fail the enclosing scopes in turn
+        let result = try_finish! {
+            scoped_spawn!(finish! { scoped_spawn!(panic!("Nested")); });
+        };
+        assert_eq!(*result.unwrap_err().downcast::<&str>().unwrap(), "Nested");
+
+        let stats = try_finish! { scoped_spawn!(()); }.unwrap();
+        assert!(!stats.cancelled);
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_panicked.get(), 3);
+    }
+
+    #[test]
+    fn watermarks() {
+        use crate::task::Detached;
+        use crate::watermark::Occupancy::*;
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let runtime = {
+            let events = Arc::clone(&events);
+            Runtime::builder()
+                .num_workers(1)
+                .watermarks(1, 8)
+                .inline_when_high()
+                .on_watermark(move |id, occupancy| events.lock().unwrap().push((id, occupancy)))
+                .init()
+        };
+        let count = Arc::new(AtomicUsize::new(0));
+        for _ in 0..20 {
+            let count = Arc::clone(&count);
+            Worker::current().push(Detached::new(move || { count.fetch_add(1, Relaxed); }));
         }
-        self.barrier.wait();
+        assert_eq!(*events.lock().unwrap(), [(0, High)]);
+        assert_eq!(Worker::current().occupancy(), High);
+        runtime.wait_until_idle();
+        assert_eq!(count.load(Relaxed), 20);
+        assert_eq!(*events.lock().unwrap(), [(0, High), (0, Normal), (0, Low)]);
 
-        // Join workers
-        for worker in self.workers {
-            worker.join().unwrap();
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_inlined.get(), 11);
+
+        // Batches are stolen while the thief's deque is low, even before it
+        // runs dry
+        let runtime = Runtime::builder().num_workers(3).watermarks(4, usize::MAX).steal_many_when_low().init();
+        for _ in 0..64 {
+            spawn!(thread::sleep(Duration::from_micros(100)));
         }
+        spawn!(spawn_tree(5));
+        runtime.wait_until_idle();
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 64 + 364);
+        assert_stat!(stats.num_early_steals.get() > 0);
+        assert_stat!(stats.num_batch_steals.get() > 0);
+    }
 
-        // Unpack self.stats
-        Arc::try_unwrap(self.stats)
-            .expect("There should be only one reference left")
-            .into_inner() // Consumes mutex, returning inner data
-            .unwrap()
+    #[test]
+    fn admission() {
+        use crate::task::Detached;
+        use std::sync::atomic::AtomicBool;
+
+        let shed_all = Arc::new(AtomicBool::new(false));
+        let runtime = Runtime::builder()
+            .num_workers(2)
+            .admission({
+                let shed_all = Arc::clone(&shed_all);
+                move |info, load| match info.name {
+                    Some("shed") => Admission::Reject,
+                    Some("inline") => Admission::Inline,
+                    _ if shed_all.load(Relaxed) => Admission::Reject,
+                    _ if load.local_queued > 1000 => Admission::Reject,
+                    _ => Admission::Queue,
+                }
+            })
+            .init();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let stats = finish_with_stats! {
+            for name in ["shed", "inline", "queue"] {
+                let count = Arc::clone(&count);
+                scoped_spawn!(name = name, count.fetch_add(1, Relaxed));
+            }
+        };
+        assert_eq!(count.load(Relaxed), 2);
+        assert_eq!(stats.num_tasks_cancelled, 1);
+
+        let task = Detached::new(|| ()).with_name("shed");
+        assert!(Worker::current().try_push(task).is_err());
+
+        // Tasks that others wait for aren't subject to admission
+        shed_all.store(true, Relaxed);
+        let sum = runtime.scope(|scope| {
+            let sum = Arc::new(AtomicUsize::new(0));
+            for i in 0..3 {
+                let sum = Arc::clone(&sum);
+                scope.spawn(move |_| { sum.fetch_add(i, Relaxed); });
+            }
+            sum
+        });
+        assert_eq!(sum.load(Relaxed), 3);
+        assert_eq!(crate::algorithms::join(|| 1, || 2), (1, 2));
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_rejected.get(), 2);
+        assert_stat_eq!(stats.num_tasks_inlined.get(), 1);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn write_trace() {
+        // A single worker, whose tasks aren't crowded out of its trace by
+        // steal requests and responses
+        let runtime = Runtime::builder().num_workers(1).trace(true).init();
+        finish! {
+            for _ in 0..10 {
+                scoped_spawn!(name = "leaf", ());
+            }
+        }
+
+        let mut bytes = vec![];
+        runtime.write_trace(&mut bytes).unwrap();
+        let workers = crate::trace::read_trace(&mut bytes.as_slice()).unwrap();
+        assert_eq!(workers.len(), 1);
+        let runs = workers.iter().flatten().filter(|(_, event)| {
+            matches!(event, crate::trace::Recorded::Run { name: Some(name), .. } if name == "leaf")
+        });
+        assert_eq!(runs.count(), 10);
+
+        let _stats = runtime.join();
+    }
 
     #[test]
-    fn init_and_join() {
-        for n in 1..4 {
-            let stats = Runtime::init(n).join();
-            assert_eq!(stats.num_tasks_executed.get(), 0);
+    fn introspection() {
+        let runtime = Runtime::builder()
+            .partition("io", 1)
+            .partition("compute", 2)
+            .init();
+        assert_eq!(runtime.num_workers(), 3);
+        assert_eq!(runtime.worker_ids(), vec![0, 1, 2]);
+
+        let workers = runtime.workers();
+        assert!(workers[0].is_leader());
+        assert_eq!(workers[0].partition(), "io");
+        assert_eq!(workers[2].partition(), "compute");
+
+        spawn!(spawn_tree(2));
+        runtime.wait_until_idle();
+        let info = workers.clone();
+        let _stats = runtime.join();
+        let completed: u32 = info.iter().map(WorkerInfo::num_completed).sum();
+        assert_eq!(completed, 1 + 3 + 9);
+        assert!(info.iter().all(|w| w.load().queued == 0));
+    }
+
+    #[test]
+    fn efficiency_cores() {
+        let runtime = Runtime::builder()
+            .num_workers(3)
+            .efficiency_cores(1..3)
+            .heavy_task_cost(100)
+            .init();
+        let (sender, receiver) = channel();
+        let heavy = Mutex::new(sender);
+        // Heavy tasks spawned on efficiency workers end up on the leader
+        runtime.broadcast(move |id| {
+            if id > 0 {
+                let heavy = heavy.lock().unwrap().clone();
+                Worker::current().push(Async::detached(move || {
+                    heavy.send(Worker::current().id).unwrap();
+                }).with_cost(100));
+            }
+        });
+        runtime.wait_until_idle();
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![0, 0]);
+        let _stats = runtime.join();
+
+        let builder = Runtime::builder().efficiency_cores(1..2).efficiency_cores(3..4);
+        assert_eq!(builder.config.efficiency_workers, vec![1..2, 3..4]);
+    }
+
+    #[test]
+    fn fifo() {
+        let runtime = Runtime::builder().num_workers(1).fifo(true).init();
+        let config = runtime.scheduler_config();
+        assert_eq!((config.pop_order, config.steal_policy), (PopOrder::Fifo, StealPolicy::Newest));
+
+        let (sender, receiver) = channel();
+        for i in 0..5 {
+            let sender = sender.clone();
+            runtime.master().push(Async::detached(move || sender.send(i).unwrap()));
+        }
+        runtime.wait_until_idle();
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn polling() {
+        for (interval, servicing) in [
+            (PollInterval::Tasks(4), RequestServicing::AfterPop),
+            (PollInterval::Time(Duration::from_micros(50)), RequestServicing::AfterPop),
+            (PollInterval::Tasks(1), RequestServicing::BeforePop),
+        ] {
+            let runtime = Runtime::builder()
+                .num_workers(3)
+                .poll_interval(interval, true)
+                .request_servicing(servicing)
+                .init();
+            spawn!(spawn_tree(4));
+            runtime.wait_until_idle();
+            let stats = runtime.join();
+            assert_stat_eq!(stats.num_tasks_executed.get(), 1 + 3 + 9 + 27 + 81);
+        }
+    }
+
+    #[test]
+    fn report_stats() {
+        let (sender, receiver) = channel();
+        let runtime = Runtime::builder()
+            .num_workers(3)
+            .report_stats(Duration::from_millis(1), move |stats| {
+                let _ = sender.send(stats.clone());
+            })
+            .init();
+
+        for _ in 0..10 {
+            spawn!(spawn_tree(4));
+            runtime.wait_until_idle();
+        }
+        thread::sleep(Duration::from_millis(10));
+
+        let stats = runtime.join();
+        let snapshots = receiver.iter().collect::<Vec<_>>();
+        assert!(!snapshots.is_empty());
+        for snapshot in snapshots.iter() {
+            assert_eq!(snapshot.workers().len(), 3);
+            assert!(snapshot.num_tasks_executed.get() <= stats.num_tasks_executed.get());
+        }
+    }
+
+    #[test]
+    fn worker_hooks() {
+        thread_local! {
+            static ID: Cell<Option<usize>> = const { Cell::new(None) };
+        }
+        let exits = Arc::new(Mutex::new(vec![]));
+        let e = Arc::clone(&exits);
+        let runtime = Runtime::builder()
+            .num_workers(3)
+            .on_worker_start(|id| ID.with(|i| i.set(Some(id))))
+            .on_worker_exit(move |id| e.lock().unwrap().push(id))
+            .init();
+
+        // Start hooks have run on the worker threads
+        runtime.broadcast(|id| assert_eq!(ID.with(|i| i.get()), Some(id)));
+        assert!(exits.lock().unwrap().is_empty());
+
+        let _stats = runtime.join();
+        let mut exits = exits.lock().unwrap().clone();
+        exits.sort();
+        assert_eq!(exits, vec![0, 1, 2]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn thread_priority() {
+        let runtime = Runtime::builder()
+            .num_workers(3)
+            .thread_priority(ThreadPriority::Nice(5))
+            .init();
+
+        let nice = Arc::new(Mutex::new(vec![]));
+        let n = Arc::clone(&nice);
+        runtime.broadcast(move |id| {
+            let tid = unsafe { libc::gettid() } as libc::id_t;
+            n.lock().unwrap().push((id, unsafe { libc::getpriority(libc::PRIO_PROCESS, tid) }));
+        });
+        // The leader keeps its priority
+        let mut nice = nice.lock().unwrap().clone();
+        nice.sort();
+        assert_eq!(&nice[1..], &[(1, 5), (2, 5)]);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn phases() {
+        let mut runtime = Runtime::init(3);
+        assert_eq!(runtime.phase(), "initial");
+        for i in 1..4 {
+            spawn!(spawn_tree(i));
+            let stats = runtime.new_phase(&format!("iteration {}", i));
+            assert_stat_eq!(stats.num_tasks_executed.get(), (0..=i).map(|d| 3u32.pow(d)).sum::<u32>());
+        }
+        assert_eq!(runtime.phase(), "iteration 3");
+        let labels = runtime.phases().iter().map(|p| p.label.as_str()).collect::<Vec<_>>();
+        assert_eq!(labels, ["initial", "iteration 1", "iteration 2"]);
+        assert_stat_eq!(runtime.phases()[2].stats.num_tasks_executed.get(), 1 + 3 + 9 + 27);
+
+        spawn!(spawn_tree(1));
+        runtime.wait_until_idle();
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 1 + 3);
+    }
+
+    #[test]
+    fn suspend_and_resume() {
+        let mut runtime = Runtime::init(3);
+        let threads = runtime.workers.iter().map(|w| w.thread().id()).collect::<Vec<_>>();
+
+        for _ in 0..3 {
+            spawn!(spawn_tree(3));
+            let (stats, suspended) = runtime.suspend();
+            assert_stat_eq!(stats.num_tasks_executed.get(), 1 + 3 + 9 + 27);
+            assert_eq!(stats.workers().len(), 3);
+            runtime = suspended.resume();
+        }
+
+        // Same threads as before
+        assert!(runtime.workers.iter().map(|w| w.thread().id()).eq(threads));
+        let stats = runtime.join();
+        assert_eq!(stats.num_tasks_executed.get(), 0);
+
+        // Shut down while suspended
+        let (_stats, suspended) = Runtime::init(2).suspend();
+        suspended.join();
+    }
+
+    #[test]
+    fn promise_work() {
+        let runtime = Runtime::builder()
+            .num_workers(4)
+            .empty_deque_response(EmptyDequeResponse::Later { patience: 4 })
+            .init();
+        // One producer, many consumers
+        spawn!(for _ in 0..100 {
+            spawn!(spawn_tree(1));
+        });
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 1 + 100 * 4);
+    }
+
+    #[test]
+    fn retire_workers() {
+        let mut runtime = Runtime::init(4);
+        for _ in 0..4 {
+            spawn!(spawn_tree(4));
+        }
+        runtime.retire(1);
+        runtime.retire(3);
+        assert_eq!(runtime.worker_ids(), vec![0, 2]);
+        spawn!(spawn_tree(3));
+        let (stats, suspended) = runtime.suspend();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 4 * 121 + 40);
+        assert_eq!(stats.workers().len(), 4);
+
+        // Only the survivors come back
+        let runtime = suspended.resume();
+        let (sender, receiver) = channel();
+        runtime.broadcast(move |id| sender.send(id).unwrap());
+        let mut ids: Vec<_> = receiver.try_iter().collect();
+        ids.sort();
+        assert_eq!(ids, vec![0, 2]);
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 1);
+    }
+
+    #[test]
+    fn snapshot_after_retire() {
+        let mut runtime = Runtime::init(3);
+        spawn!(spawn_tree(3));
+        runtime.wait_until_idle();
+        runtime.retire(1);
+        // The retired worker's final snapshot still counts
+        let stats = runtime.snapshot();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 40);
+        runtime.region("after", || spawn!(spawn_tree(2)));
+        assert_stat_eq!(runtime.region_stats("after").unwrap().num_tasks_executed.get(), 13);
+        assert_stat_eq!(runtime.join().num_tasks_executed.get(), 53);
+    }
+
+    #[test]
+    fn broadcast() {
+        let runtime = Runtime::init(4);
+
+        let threads = Arc::new(Mutex::new(vec![]));
+        let t = Arc::clone(&threads);
+        runtime.broadcast(move |id| {
+            t.lock().unwrap().push((id, thread::current().id()));
+        });
+        let mut threads = threads.lock().unwrap().clone();
+        threads.sort_by_key(|&(id, _)| id);
+        assert_eq!(threads.iter().map(|&(id, _)| id).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        let distinct = threads.iter().map(|&(_, thread)| thread).collect::<HashSet<_>>();
+        assert_eq!(distinct.len(), 4);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn wait_until_idle() {
+        let runtime = Runtime::init(3);
+        let idle = runtime.on_idle();
+
+        for _ in 0..2 {
+            // 3 + 9 + 27 + 81 tasks
+            spawn!(spawn_tree(4));
+            runtime.wait_until_idle();
+            let load = runtime.load();
+            assert!(load.iter().all(|w| w.queued == 0 && w.running == 0));
+            idle.recv_timeout(Duration::from_secs(10)).unwrap();
+        }
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 2 * 121);
+    }
+
+    #[test]
+    fn snapshot() {
+        let runtime = Runtime::init(3);
+
+        spawn!(spawn_tree(4));
+        runtime.wait_until_idle();
+        let stats = runtime.snapshot();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 121);
+        assert_eq!(stats.num_tasks_executed.get(), stats.num_tasks_created.get());
+
+        // Snapshots while tasks are running
+        spawn!(spawn_tree(5));
+        let stats = runtime.snapshot();
+        assert!(stats.num_tasks_executed.get() <= 121 + 364);
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 121 + 364);
+    }
+
+    #[test]
+    fn regions() {
+        let mut runtime = Runtime::init(3);
+
+        spawn!(spawn_tree(3));
+        let x = runtime.region("small", || { spawn!(spawn_tree(4)); 42 });
+        assert_eq!(x, 42);
+        runtime.region("large", || spawn!(spawn_tree(5)));
+        runtime.region("small", || spawn!(spawn_tree(4)));
+
+        let labels: Vec<_> = runtime.regions().iter().map(|r| r.label.as_str()).collect();
+        assert_eq!(labels, ["small", "large", "small"]);
+        assert_stat_eq!(runtime.regions()[0].stats.num_tasks_executed.get(), 121);
+        assert_stat_eq!(runtime.regions()[1].stats.num_tasks_executed.get(), 364);
+        assert_stat_eq!(runtime.region_stats("small").unwrap().num_tasks_executed.get(), 2 * 121);
+        assert!(runtime.region_stats("medium").is_none());
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 40 + 2 * 121 + 364);
+    }
+
+    #[test]
+    fn join_with_timeout() {
+        let runtime = Runtime::init(2);
+        spawn!(spawn_tree(3));
+        let stats = runtime.join_with_timeout(Duration::from_secs(60)).unwrap();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 40);
+
+        // A task on the other worker that doesn't complete until we let it
+        let runtime = Runtime::init(2);
+        let (release, blocked) = channel::<()>();
+        let blocked = Mutex::new(blocked);
+        Worker::current().push_to_worker(1, Async::detached(move || blocked.lock().unwrap().recv()));
+        spawn!(spawn_tree(2));
+        let report = runtime.join_with_timeout(Duration::from_millis(50)).unwrap_err();
+        assert_eq!(report.num_outstanding_tasks, 1);
+        assert_eq!(report.busy_workers, [WorkerLoad { id: 1, queued: 0, running: 1 }]);
+        assert_stat!(report.stats.num_tasks_executed.get() >= 13);
+        assert!(report.to_string().starts_with("Timed out with 1 outstanding tasks"));
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn task_context() {
+        let runtime = Runtime::init(2);
+        assert_eq!(Worker::current_task_context(), None);
+        let contexts = Arc::new(Mutex::new(vec![]));
+        let root = {
+            let contexts = Arc::clone(&contexts);
+            Async::detached(move || {
+                let worker = Worker::current();
+                assert_eq!(Worker::current_task_context().unwrap().parent, None);
+                // Runs on the other worker
+                worker.push_to_worker(1, Async::detached(move || {
+                    let child = Worker::current_task_context().unwrap();
+                    contexts.lock().unwrap().push((Worker::current().current_task(), child));
+                    spawn!({
+                        let grandchild = Worker::current_task_context().unwrap();
+                        contexts.lock().unwrap().push((Worker::current().current_task(), grandchild));
+                    });
+                }).with_label("child"));
+            })
+            .with_label("request")
+            .with_correlation(42)
+        };
+        Worker::current().push(root);
+        runtime.join();
+
+        let contexts = contexts.lock().unwrap();
+        let (child_id, child) = contexts[0];
+        assert_eq!(child.label, Some("child"));
+        assert_eq!(child.correlation, Some(42));
+        assert!(child.parent.is_some() && child.parent != child_id);
+        let (_, grandchild) = contexts[1];
+        assert_eq!(grandchild.label, Some("child"));
+        assert_eq!(grandchild.correlation, Some(42));
+        assert_eq!(grandchild.parent, child_id);
+    }
+
+    #[test]
+    fn partitions() {
+        let runtime = Runtime::builder()
+            .partition("latency", 2)
+            .partition("batch", 2)
+            .init();
+        let leader = runtime.master();
+        assert_eq!(leader.partition(), "latency");
+
+        let executed_by = Arc::new(Mutex::new(vec![]));
+
+        finish! {
+            for i in 0..100 {
+                let executed_by = Arc::clone(&executed_by);
+                let task = ScopedAsync::new(async_closure! {
+                    let worker = Worker::current();
+                    let partition = String::from(worker.partition());
+                    executed_by.lock().unwrap().push((i % 2, worker.id, partition));
+                }, None);
+                if i % 2 == 0 {
+                    leader.push(task);
+                } else {
+                    leader.push_to("batch", task);
+                }
+            }
+        }
+
+        let executed_by = executed_by.lock().unwrap();
+        assert_eq!(executed_by.len(), 100);
+        for (kind, id, partition) in executed_by.iter() {
+            match kind {
+                0 => assert!(*id < 2 && partition == "latency"),
+                _ => assert!(*id >= 2 && partition == "batch"),
+            }
+        }
+        drop(executed_by);
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 100);
+    }
+
+    #[test]
+    fn drain() {
+        let runtime = Runtime::init(1);
+
+        for name in ["a", "b", "c"] {
+            runtime.master().push(Async::detached(|| ()).with_name(name));
         }
+        let names: Vec<_> = runtime.drain().map(|task| task.name.unwrap()).collect();
+        assert_eq!(names, ["c", "b", "a"]);
+        assert!(!runtime.master().has_tasks());
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 3);
     }
 }
@@ -1,30 +1,41 @@
 use crate::atomic;
-use crate::stats;
-use crate::worker::{Tasks, Worker};
-use std::cell::{Ref, RefMut, RefCell};
-use std::collections::LinkedList;
-use std::sync::Arc;
+use crate::future::{Completions, LocalPromise, Promise};
+use crate::macros::{push, push_unchecked};
+use crate::task::{Async, ScopedAsync, Task, TaskId, TaskKind};
+use crate::worker::Worker;
+use std::any::Any;
+use std::cell::{Cell, Ref, RefMut, RefCell};
+use std::collections::{LinkedList, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::marker::PhantomData;
+use std::panic;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::{Acquire, Relaxed, Release}};
+use std::time::{Duration, Instant};
 
 // We use a linked list to avoid invalidating references returned by
 // Scope::current()
 thread_local! {
-    static SCOPE: RefCell<LinkedList<Scope>> = RefCell::new(LinkedList::new());
+    static SCOPE: RefCell<LinkedList<Scope>> = const { RefCell::new(LinkedList::new()) };
 }
 
 pub enum TaskCount {
-    Private(stats::Count),
+    Private(Cell<u32>),
+    // Decremented by thieves with release ordering and read with acquire
+    // ordering, so that a scope seen empty in `Scope::wait` has all of its
+    // tasks' effects visible, see `atomic::Count::dec_acq_rel`
     Shared(Arc<atomic::Count>),
 }
 
 impl TaskCount {
     pub fn new() -> Self {
-        Self::Private(stats::Count::new(0))
+        Self::Private(Cell::new(0))
     }
 
     pub fn get(&self) -> u32 {
         match self {
             Self::Private(count) => count.get(),
-            Self::Shared(count) => count.get(),
+            Self::Shared(count) => count.get_acquire(),
         }
     }
 
@@ -33,11 +44,11 @@ impl TaskCount {
         match self {
             Self::Private(count) => {
                 let n = count.get();
-                count.inc();
+                count.set(n + 1);
                 n
             }
             Self::Shared(count) => {
-                count.inc()
+                count.inc_release()
             }
         }
     }
@@ -47,11 +58,14 @@ impl TaskCount {
         match self {
             Self::Private(count) => {
                 let n = count.get();
-                count.dec();
+                invariant!(n > 0, "scope task count underflow (private scope)");
+                count.set(n - 1);
                 n
             }
             Self::Shared(count) => {
-                count.dec()
+                let n = count.dec_acq_rel();
+                invariant!(n > 0, "scope task count underflow (shared scope)");
+                n
             }
         }
     }
@@ -82,18 +96,237 @@ impl NumTasks {
         self.0.borrow().dec()
     }
 
-    pub fn borrow(&self) -> Ref<TaskCount> {
+    pub fn borrow(&self) -> Ref<'_, TaskCount> {
         self.0.borrow()
     }
 
-    pub fn borrow_mut(&self) -> RefMut<TaskCount> {
+    pub fn borrow_mut(&self) -> RefMut<'_, TaskCount> {
         self.0.borrow_mut()
     }
 }
 
+// What happened within a scope, returned by `Scope::leave` (and `finish!`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScopeStats {
+    // Tasks spawned in the scope, including tasks spawned by those tasks
+    pub num_tasks_spawned: u32,
+    pub num_tasks_executed: u32,
+    // Tasks stolen away from the worker that spawned them
+    pub num_tasks_stolen: u32,
+    // Combined run time of the scope's tasks
+    pub task_time: Duration,
+    // Wall-clock time from entering to leaving the scope
+    pub elapsed: Duration,
+    // Time spent in `Scope::leave` waiting for the scope's tasks
+    pub wait_time: Duration,
+    // Tasks skipped because the scope's deadline had passed
+    pub num_tasks_cancelled: u32,
+    // Did the scope's tasks overrun its deadline (that is, was a task skipped
+    // or did one finish after it), see `Scope::enter_with_deadline`?
+    pub deadline_exceeded: bool,
+    // Was the scope cancelled because one of its tasks panicked, see
+    // `PanicPolicy::CancelScope`?
+    pub cancelled: bool,
+}
+
+// The panic of a scope's task, see `PanicPolicy::FailScope`
+pub type ScopeFailure = Box<dyn Any + Send>;
+
+// Counters shared by all tasks of a scope, no matter which worker runs them
+#[derive(Debug)]
+pub struct ScopeCounters {
+    spawned: atomic::Count,
+    executed: atomic::Count,
+    stolen: atomic::Count,
+    // In nanoseconds
+    task_time: AtomicU64,
+    cancelled: atomic::Count,
+    deadline: Option<Instant>,
+    // Set once one of the scope's tasks is skipped, or finishes, after the
+    // deadline, see `ScopeStats::deadline_exceeded`
+    overrun: AtomicBool,
+    // Set once the scope has been left, see `ScopedFuture`
+    left: AtomicBool,
+    // See `cancel`
+    cancel_requested: AtomicBool,
+    // The first panic of one of the scope's tasks, see `fail`
+    failure: Mutex<Option<ScopeFailure>>,
+    // Run at most this many of the scope's tasks at a time, see
+    // `Scope::enter_with_limit`
+    limit: Option<u32>,
+    running: atomic::Count,
+    // Tasks waiting for a permit, see `wait_for_permit`
+    waiting: Mutex<VecDeque<TaskKind>>,
+}
+
+impl ScopeCounters {
+    pub fn new() -> Self {
+        Self {
+            spawned: atomic::Count::new(0),
+            executed: atomic::Count::new(0),
+            stolen: atomic::Count::new(0),
+            task_time: AtomicU64::new(0),
+            cancelled: atomic::Count::new(0),
+            deadline: None,
+            overrun: AtomicBool::new(false),
+            left: AtomicBool::new(false),
+            cancel_requested: AtomicBool::new(false),
+            failure: Mutex::new(None),
+            limit: None,
+            running: atomic::Count::new(0),
+            waiting: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn with_deadline(deadline: Instant) -> Self {
+        Self { deadline: Some(deadline), ..Self::new() }
+    }
+
+    pub fn with_limit(limit: u32) -> Self {
+        assert!(limit > 0);
+        Self { limit: Some(limit), ..Self::new() }
+    }
+
+    pub fn limit(&self) -> Option<u32> {
+        self.limit
+    }
+
+    // Number of the scope's tasks holding a `Permit`
+    pub fn num_running(&self) -> u32 {
+        self.running.get()
+    }
+
+    // Permission to run one of the scope's tasks, unless `limit` tasks are
+    // running already. Scopes without a limit don't hand out permits.
+    pub fn try_acquire(self: &Arc<Self>) -> Option<Permit> {
+        let limit = self.limit?;
+        self.running.inc_if_below(limit).then(|| Permit(Arc::clone(self)))
+    }
+
+    // Set aside a task that found no permit until a running task of the
+    // scope drops its permit, instead of queueing it again and again. The
+    // task is promoted first, since it may be released on another worker.
+    pub fn wait_for_permit(&self, mut task: TaskKind) {
+        task.promote();
+        Worker::current().hold();
+        self.waiting.lock().unwrap().push_back(task);
+        // The last permit may have been dropped in the meantime
+        if self.limit.is_some_and(|limit| self.running.get() < limit) {
+            self.release_waiter();
+        }
+    }
+
+    // Queue the task that has waited longest for a permit, if any, on the
+    // current worker (or run it right away without a worker)
+    fn release_waiter(&self) {
+        let task = self.waiting.lock().unwrap().pop_front();
+        match (task, Worker::try_current()) {
+            (Some(task), Some(worker)) => worker.release(task),
+            (Some(task), None) => task.run(),
+            (None, _) => (),
+        }
+    }
+
+    // Has the deadline passed?
+    pub fn deadline_exceeded(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() > deadline)
+    }
+
+    // Did any of the scope's tasks overrun the deadline? The deadline may
+    // well pass after the last task is done, while the scope is still busy
+    // with other things, which doesn't count.
+    pub fn is_overrun(&self) -> bool {
+        self.overrun.load(Relaxed)
+    }
+
+    fn check_deadline(&self) {
+        if self.deadline_exceeded() {
+            self.overrun.store(true, Relaxed);
+        }
+    }
+
+    // Skip the scope's remaining tasks, like after its deadline
+    pub fn cancel(&self) {
+        self.cancel_requested.store(true, Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel_requested.load(Relaxed)
+    }
+
+    // Cancel the scope because one of its tasks panicked, keeping the first
+    // panic to be re-raised when the scope is left
+    pub fn fail(&self, payload: ScopeFailure) {
+        self.failure.lock().unwrap().get_or_insert(payload);
+        self.cancel();
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.failure.lock().unwrap().is_some()
+    }
+
+    fn take_failure(&self) -> Option<ScopeFailure> {
+        self.failure.lock().unwrap().take()
+    }
+
+    // Should tasks that haven't started yet be skipped?
+    pub fn is_cancelling(&self) -> bool {
+        self.is_cancelled() || self.deadline_exceeded()
+    }
+
+    pub fn is_left(&self) -> bool {
+        self.left.load(Acquire)
+    }
+
+    pub fn cancelled(&self) {
+        self.cancelled.inc();
+        self.check_deadline();
+    }
+
+    pub fn spawned(&self) {
+        self.spawned.inc();
+    }
+
+    pub fn stolen(&self) {
+        self.stolen.inc();
+    }
+
+    pub fn executed(&self, time: Duration) {
+        self.executed.inc();
+        let nanos = time.as_nanos().min(u64::MAX as u128) as u64;
+        self.task_time.fetch_add(nanos, Relaxed);
+        self.check_deadline();
+    }
+}
+
+// Held by a running task of a scope with a concurrency limit
+#[derive(Debug)]
+pub struct Permit(Arc<ScopeCounters>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.running.dec();
+        self.0.release_waiter();
+    }
+}
+
 pub struct Scope {
+    // Position in the thread's list of scopes, see `at_level`
     level: u32,
+    // Number of `finish!` blocks the scope is nested in, counting those
+    // around its tasks no matter which worker runs them. Unlike `level`, a
+    // copy of a scope (see `with_num_tasks`) has the nesting of the scope
+    // itself. See `Stats::scope_levels`.
+    nesting: u32,
     pub num_tasks: NumTasks,
+    // Spawn depth of the task currently running in this scope (zero outside
+    // of tasks), see `depth`
+    depth: Cell<u32>,
+    counters: Arc<ScopeCounters>,
+    entered: Instant,
+    // Exited when the scope is left (and dropped)
+    #[cfg(feature = "tracing")]
+    span: Option<tracing::span::EnteredSpan>,
 }
 
 impl Scope {
@@ -102,59 +335,286 @@ impl Scope {
     }
 
     fn with_level(level: u32) -> Self {
-        Self { level, num_tasks: NumTasks::new() }
+        Self {
+            level,
+            nesting: level,
+            num_tasks: NumTasks::new(),
+            depth: Cell::new(0),
+            counters: Arc::new(ScopeCounters::new()),
+            entered: Instant::now(),
+            #[cfg(feature = "tracing")]
+            span: None,
+        }
     }
 
-    pub fn with_num_tasks(num_tasks: NumTasks) -> Self {
+    // The scope of a stolen task, on the thief's side
+    pub fn with_num_tasks(
+        num_tasks: NumTasks,
+        counters: Arc<ScopeCounters>,
+        nesting: u32,
+    ) -> Self {
         let scope = Self::current();
-        assert_ne!(scope as *const Scope, std::ptr::null());
-        Self { level: scope.level + 1, num_tasks }
-
+        Self {
+            nesting,
+            num_tasks,
+            depth: Cell::new(scope.depth()),
+            counters,
+            ..Self::with_level(scope.level + 1)
+        }
     }
 
     pub fn new() -> Self {
         let scope = Self::current();
-        assert_ne!(scope as *const Self, std::ptr::null());
-        Self::with_level(scope.level + 1)
+        Self {
+            nesting: scope.nesting + 1,
+            depth: Cell::new(scope.depth()),
+            ..Self::with_level(scope.level + 1)
+        }
+    }
+
+    // Number of nested scoped spawns leading to the currently running task:
+    // 1 for a task spawned outside of any task, 2 for its children, and so
+    // on, no matter which worker ends up running them
+    pub fn depth(&self) -> u32 {
+        self.depth.get()
+    }
+
+    // Make `depth` the current spawn depth, returning the previous one
+    pub fn set_depth(&self, depth: u32) -> u32 {
+        self.depth.replace(depth)
+    }
+
+    pub fn counters(&self) -> &Arc<ScopeCounters> {
+        &self.counters
+    }
+
+    pub fn stats(&self) -> ScopeStats {
+        ScopeStats {
+            num_tasks_spawned: self.counters.spawned.get(),
+            num_tasks_executed: self.counters.executed.get(),
+            num_tasks_stolen: self.counters.stolen.get(),
+            task_time: Duration::from_nanos(self.counters.task_time.load(Relaxed)),
+            elapsed: self.entered.elapsed(),
+            wait_time: Duration::ZERO,
+            num_tasks_cancelled: self.counters.cancelled.get(),
+            deadline_exceeded: self.counters.is_overrun(),
+            cancelled: self.counters.is_cancelled(),
+        }
     }
 
     pub fn push(self) {
+        #[cfg(feature = "debug-server")]
+        publish_level(self.level);
         SCOPE.with(|scope| {
             let mut scope = scope.borrow_mut();
             scope.push_front(self);
         });
     }
 
+    // Remove the root scope (see `init`) of the runtime that was created
+    // before the `n` most recent ones on this thread, see `Worker::release_worker`
+    pub fn remove_root(n: usize) -> Option<Self> {
+        SCOPE.with(|scope| {
+            let mut scope = scope.borrow_mut();
+            let index = scope
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.level == 0)
+                .nth(n)
+                .map(|(i, _)| i)?;
+            let mut rest = scope.split_off(index);
+            let removed = rest.pop_front();
+            scope.append(&mut rest);
+            #[cfg(feature = "debug-server")]
+            publish_level(scope.front().map_or(0, |s| s.level));
+            removed
+        })
+    }
+
     pub fn pop() -> Option<Self> {
         SCOPE.with(|scope| {
             let mut scope = scope.borrow_mut();
-            scope.pop_front()
+            let popped = scope.pop_front();
+            #[cfg(feature = "debug-server")]
+            publish_level(scope.front().map_or(0, |s| s.level));
+            popped
         })
     }
 
     pub fn enter() {
-        Self::new().push();
+        // Threads that have no worker of their own start with an empty list
+        // of scopes
+        match SCOPE.with(|scope| scope.borrow().is_empty()) {
+            true => Self::with_level(0).push(),
+            false => Self::new().push(),
+        }
+        if let Some(worker) = Worker::try_current() {
+            worker.service_in_background();
+        }
+        #[cfg(feature = "tracing")]
+        SCOPE.with(|scope| {
+            let mut scope = scope.borrow_mut();
+            let scope = scope.front_mut().unwrap();
+            scope.span = Some(tracing::trace_span!("scope", level = scope.level).entered());
+        });
+    }
+
+    // Like `enter`, but give the scope a deadline: once it has passed, tasks
+    // of the scope that haven't started yet are cancelled (that is, skipped
+    // when they would run). Tasks with futures are run anyway, since someone
+    // may be waiting for their results. See `ScopeStats::deadline_exceeded`.
+    pub fn enter_with_deadline(timeout: Duration) {
+        Self::enter();
+        let deadline = Instant::now() + timeout;
+        SCOPE.with(|scope| {
+            let mut scope = scope.borrow_mut();
+            scope.front_mut().unwrap().counters = Arc::new(ScopeCounters::with_deadline(deadline));
+        });
+    }
+
+    // Like `enter`, but run at most `limit` tasks of the scope at a time,
+    // across all workers, to bound the resources they use. The others stay
+    // queued until it's their turn. Since a task that waits for another task
+    // of the scope occupies one of the turns while waiting, nested tasks
+    // should wait for each other in nested scopes. Tasks spawned without a
+    // worker (see `spawn!`) run right away regardless.
+    pub fn enter_with_limit(limit: u32) {
+        Self::enter();
+        SCOPE.with(|scope| {
+            let mut scope = scope.borrow_mut();
+            scope.front_mut().unwrap().counters = Arc::new(ScopeCounters::with_limit(limit));
+        });
     }
 
-    pub fn leave() {
-        Self::current().wait();
+    // Wait for the scope's tasks and leave the scope. If one of the tasks
+    // panicked and made the scope fail (see `PanicPolicy::FailScope`), the
+    // panic continues from here.
+    pub fn leave() -> ScopeStats {
+        Self::try_leave().unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    // Like `leave`, but return the panic that made the scope fail, if any
+    pub fn try_leave() -> Result<ScopeStats, ScopeFailure> {
+        match Worker::try_current() {
+            Some(worker) => Self::try_leave_on(worker),
+            // Without a worker, tasks have been run inline (see
+            // `macros::push`), so there is nothing to wait for
+            None => {
+                assert_eq!(Self::current().num_tasks.get(), 0);
+                let scope = Self::pop().unwrap();
+                scope.counters.left.store(true, Release);
+                Ok(scope.stats())
+            }
+        }
+    }
+
+    // Like `leave`, but wait for the scope's tasks on the given worker. The
+    // scope's statistics are also added to the worker's, by scope level (see
+    // `Stats::scope_levels`).
+    pub fn leave_on(worker: &Worker) -> ScopeStats {
+        Self::try_leave_on(worker).unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    pub fn try_leave_on(worker: &Worker) -> Result<ScopeStats, ScopeFailure> {
+        let start = Instant::now();
+        Self::current().wait_on(worker);
+        let wait_time = start.elapsed();
         assert_eq!(Self::current().num_tasks.get(), 0);
-        Self::pop().unwrap();
+        let scope = Self::pop().unwrap();
+        scope.counters.left.store(true, Release);
+        let stats = ScopeStats { wait_time, ..scope.stats() };
+        worker.stats.record_scope(scope.nesting, &stats);
+        worker.service_in_background();
+        match scope.counters.take_failure() {
+            Some(payload) => Err(payload),
+            None => Ok(stats),
+        }
     }
 
-    // Get a reference to the current scope
-    pub fn current<'a>() -> &'a Self {
+    // Get a reference to the current scope. Threads without a worker (and
+    // thus without a root scope) get a root scope on first use. Since the
+    // caller picks the lifetime, this stays within the crate: scoped futures
+    // borrow the scope they were spawned from, which only `with` lends out.
+    pub(crate) fn current<'a>() -> &'a Self {
         SCOPE.with(|scope| {
+            if scope.borrow().is_empty() {
+                scope.borrow_mut().push_front(Self::with_level(0));
+            }
             // See `Worker::current`
-            let ptr = match scope.borrow().front() {
-                Some(scope) => scope as *const Self,
-                None => std::ptr::null(),
-            };
+            let ptr = scope.borrow().front().unwrap() as *const Self;
             // Convert this pointer to a borrowed reference
             unsafe { &*ptr }
         })
     }
 
+    // The scope at `level` in the current thread's list of scopes (levels
+    // increase from the root scope to the current scope, so there is at most
+    // one), falling back to the current scope. Tasks remember the level of
+    // the scope they were spawned into, which by the time they run or are
+    // stolen need not be the current scope anymore: the spawning worker may
+    // be waiting in a nested scope, running other tasks.
+    pub(crate) fn at_level<'a>(level: u32) -> &'a Self {
+        let found = SCOPE.with(|scope| {
+            scope.borrow().iter().find(|s| s.level == level).map(|s| s as *const Self)
+        });
+        match found {
+            // See `current`
+            Some(ptr) => unsafe { &*ptr },
+            None => Self::current(),
+        }
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn nesting(&self) -> u32 {
+        self.nesting
+    }
+
+    // Run `f` in a new scope, like `finish! { |scope| ... }`, lending it the
+    // scope to spawn tasks with futures into (see `spawn`). `f` accepts a
+    // borrow of any lifetime, so the futures can't escape from it.
+    pub fn with<R>(f: impl FnOnce(&Scope) -> R) -> R {
+        Self::enter();
+        let result = f(Self::current());
+        Self::leave();
+        result
+    }
+
+    // Spawn a scoped task computing `f()`, like `scoped_spawn!(future in
+    // scope, ...)`. Tasks are always spawned into the current scope, so
+    // `self` must be the current scope (and not one `with` was nested in).
+    pub fn spawn<T, F>(&self, f: F) -> ScopedFuture<'_, T>
+    where T: Send + 'static, F: FnOnce() -> T + Send + 'static {
+        debug_assert!(std::ptr::eq(self, Self::current()));
+        let worker = Worker::current();
+        let completions = Rc::clone(worker.completions());
+        let promise = LocalPromise::new(Rc::clone(&completions));
+        let slot = promise.slot();
+        let mut task = ScopedAsync::new(f, Some(Promise::from(promise)));
+        let id = worker.next_task_id();
+        task.set_id(id);
+        push(task);
+        ScopedFuture {
+            id,
+            completions,
+            slot: Some(slot),
+            counters: Arc::clone(&self.counters),
+            _scope: PhantomData,
+            _result: PhantomData,
+        }
+    }
+
+    // Like `spawn`, but without a result, like `scoped_spawn!(...)`
+    pub fn spawn_detached<R, F>(&self, f: F)
+    where F: FnOnce() -> R + Send + 'static {
+        debug_assert!(std::ptr::eq(self, Self::current()));
+        push(ScopedAsync::detached(f));
+    }
+
+    // Switch to a shared task count, for tasks leaving this worker. The
+    // count travels with the stolen task, whose channel publishes it.
     pub fn share(&self) -> Arc<atomic::Count> {
         let count = match &*self.num_tasks.borrow() {
             TaskCount::Private(count) => count.get(),
@@ -166,42 +626,171 @@ impl Scope {
         clone
     }
 
+    // The scope's task count, if shared between workers
+    pub fn shared_count(&self) -> Option<Arc<atomic::Count>> {
+        match &*self.num_tasks.borrow() {
+            TaskCount::Private(_) => None,
+            TaskCount::Shared(count) => Some(Arc::clone(count)),
+        }
+    }
+
+    // Does the scope share `count`?
+    pub fn shares(&self, count: &Arc<atomic::Count>) -> bool {
+        match &*self.num_tasks.borrow() {
+            TaskCount::Private(_) => false,
+            TaskCount::Shared(shared) => Arc::ptr_eq(shared, count),
+        }
+    }
+
     pub fn wait(&self) {
-        if self.num_tasks.get() == 0 { return; }
+        self.wait_on(Worker::current());
+    }
 
-        let worker = Worker::current();
-        let mut num_tasks_executed = 0;
-
-        while let Some(task) = worker.pop() {
-            worker.try_handle_steal_request();
-            task.run();
-            num_tasks_executed += 1;
-            if self.num_tasks.get() == 0 {
-                worker.stats.num_tasks_executed.add(num_tasks_executed);
-                return;
-            }
+    // Once the scope is down to a few tasks (no more than there are
+    // workers), stealing a batch of tasks would mostly hoard unrelated work
+    // right before the scope is done, delaying whatever comes after it. So
+    // we steal one task at a time from then on, see `Config::steal_many`.
+    pub fn wait_on(&self, worker: &Worker) {
+        let few = worker.shared().config.num_workers() as u32;
+        worker.help_until_batched(|| self.num_tasks.get() == 0, || self.num_tasks.get() > few);
+    }
+}
+
+// The future of a scoped task, see `Scope::spawn`. Waiting for the scope
+// (`Scope::wait`, as leaving the scope does) waits for all of its tasks, so
+// afterwards the result is known to be there, and `get` takes it without
+// helping. Scoped futures are neither `Send` nor `Sync`: they can't be handed
+// to other tasks, which might outlive the scope. That's also why the result
+// can be handed over through the spawning worker's `Completions`. Nor can
+// they outlive the borrow of the scope they were spawned from, which
+// `Scope::with` lends out for its closure only:
+///
+/// ```compile_fail
+/// use rusty_tasking::{finish, scoped_spawn};
+///
+/// let mut out = None;
+/// finish! { |scope| out = Some(scoped_spawn!(future in scope, 41 + 1)); }
+/// out.unwrap().get();
+/// ```
+pub struct ScopedFuture<'a, T> {
+    id: TaskId,
+    completions: Rc<RefCell<Completions>>,
+    // The task's slot in `Completions`, until the result has been taken
+    slot: Option<usize>,
+    counters: Arc<ScopeCounters>,
+    _scope: PhantomData<&'a Scope>,
+    _result: PhantomData<T>,
+}
+
+impl<T: 'static> ScopedFuture<'_, T> {
+    // See `Runtime::find_task`
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    fn slot(&self) -> usize {
+        self.slot.expect("Result has been taken already")
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.completions.borrow().is_ready::<T>(self.slot())
+    }
+
+    // Has the task's scope been left (in which case the result is ready)?
+    pub fn is_scope_left(&self) -> bool {
+        self.counters.is_left()
+    }
+
+    // Wait for the result, helping with tasks in the meantime, see
+    // `Future::wait`. Only needed before the scope has been left.
+    pub fn wait(&mut self) -> T {
+        let slot = self.slot();
+        if !self.is_ready() {
+            Worker::current().help_until(|| self.is_ready());
         }
+        self.slot = None;
+        self.completions.borrow_mut().take(slot).unwrap()
+    }
 
-        loop {
-            match worker.steal_one().wait() {
-                Tasks::None => (),
-                Tasks::One(task) => {
-                    task.run();
-                    num_tasks_executed += 1;
-                }
-                _ => panic!(),
-            }
-            if self.num_tasks.get() == 0 {
-                worker.stats.num_tasks_executed.add(num_tasks_executed);
-                return;
-            }
+    pub fn get(mut self) -> T {
+        debug_assert!(!self.is_scope_left() || self.is_ready());
+        self.wait()
+    }
+}
+
+impl<T> Drop for ScopedFuture<'_, T> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            self.completions.borrow_mut().abandon(slot);
         }
     }
 }
 
+// An explicit alternative to `finish!` and `scoped_spawn!`: rather than
+// looking up the current scope in thread-local storage, tasks are spawned
+// through a handle, which is passed on to every task of the scope. Tasks
+// therefore always spawn into the right scope, no matter which worker ends up
+// running them. See `scope` and `Runtime::scope`.
+#[derive(Clone)]
+pub struct ScopeHandle {
+    num_tasks: Arc<atomic::Count>,
+}
+
+impl ScopeHandle {
+    fn new() -> Self {
+        Self { num_tasks: Arc::new(atomic::Count::new(0)) }
+    }
+
+    // Spawn a task into this scope. The task gets its own handle to the
+    // scope, to spawn more tasks.
+    pub fn spawn<F>(&self, f: F)
+    where F: FnOnce(&ScopeHandle) + Send + 'static {
+        self.num_tasks.inc_release();
+        let handle = self.clone();
+        // Nothing would decrement the count if the task were rejected
+        push_unchecked(Async::detached(move || {
+            f(&handle);
+            handle.num_tasks.dec_acq_rel();
+        }));
+    }
+
+    // Number of tasks spawned into this scope that haven't completed yet
+    pub fn num_tasks(&self) -> u32 {
+        self.num_tasks.get_acquire()
+    }
+}
+
+// Run `f` with a new scope, then help the current worker until all tasks
+// spawned into the scope (directly or indirectly) have completed:
+//
+//     scope(|s| {
+//         s.spawn(|s| s.spawn(|_| work()));
+//         s.spawn(|_| work());
+//     });
+pub fn scope<R>(f: impl FnOnce(&ScopeHandle) -> R) -> R {
+    scope_on(Worker::current(), f)
+}
+
+// Like `scope`, but wait for the scope's tasks on the given worker
+pub fn scope_on<R>(worker: &Worker, f: impl FnOnce(&ScopeHandle) -> R) -> R {
+    let handle = ScopeHandle::new();
+    let result = f(&handle);
+    if handle.num_tasks() > 0 {
+        worker.help_until(|| handle.num_tasks() == 0);
+    }
+    result
+}
+
+// Let the debug server know how deeply nested the current worker is
+#[cfg(feature = "debug-server")]
+fn publish_level(level: u32) {
+    if let Some(worker) = Worker::try_current() {
+        worker.load().set_scope_level(level);
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::thread;
     use super::*;
 
     #[test]
@@ -234,12 +823,30 @@ mod tests {
         assert_eq!(scope.num_tasks.get(), 0);
     }
 
+    #[cfg(feature = "debug-invariants")]
+    #[test]
+    #[should_panic(expected = "scope task count underflow (shared scope)")]
+    fn count_underflow() {
+        let count = TaskCount::Shared(Arc::new(atomic::Count::new(1)));
+        count.dec();
+        count.dec();
+    }
+
     #[test]
     fn inc_dec_threads() {
+        use std::thread;
+
         Scope::init();
         let scope = Scope::current();
         let mut threads = Vec::with_capacity(3);
 
+        // Enough tasks for the decrements below, which may run ahead of the
+        // other threads' increments, so the count never drops below zero
+        // (see `debug-invariants`)
+        for _ in 0..50 {
+            scope.num_tasks.inc();
+        }
+
         for _ in 0..3 {
             let count = scope.share();
             threads.push(thread::spawn(move || {
@@ -260,6 +867,157 @@ mod tests {
             t.join().unwrap();
         }
 
-        assert_eq!(scope.num_tasks.get(), 100);
+        assert_eq!(scope.num_tasks.get(), 150);
+    }
+
+    #[test]
+    fn spawn_depth() {
+        use crate::runtime::Runtime;
+        use std::sync::Mutex;
+
+        let runtime = Runtime::init(3);
+        let depths = Arc::new(Mutex::new(vec![]));
+
+        let record = |depths: &Arc<Mutex<Vec<u32>>>| {
+            depths.lock().unwrap().push(Scope::current().depth());
+        };
+        finish! {
+            let depths = Arc::clone(&depths);
+            scoped_spawn! {
+                record(&depths);
+                for _ in 0..2 {
+                    let depths = Arc::clone(&depths);
+                    scoped_spawn! {
+                        record(&depths);
+                        for _ in 0..2 {
+                            let depths = Arc::clone(&depths);
+                            scoped_spawn!(record(&depths));
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(Scope::current().depth(), 0);
+
+        let mut depths = depths.lock().unwrap().clone();
+        depths.sort();
+        assert_eq!(depths, vec![1, 2, 2, 3, 3, 3, 3]);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn nested_scopes() {
+        use crate::runtime::Runtime;
+        use std::sync::atomic::AtomicUsize;
+
+        let runtime = Runtime::init(4);
+        let count = Arc::new(AtomicUsize::new(0));
+
+        // Workers waiting in an inner scope run (and steal) tasks of the outer
+        // scope, and of other inner scopes, in the meantime
+        for _ in 0..10 {
+            finish! {
+                for _ in 0..8 {
+                    let count = Arc::clone(&count);
+                    scoped_spawn! {
+                        let before = count.load(Relaxed);
+                        finish! {
+                            for _ in 0..8 {
+                                let count = Arc::clone(&count);
+                                scoped_spawn!(count.fetch_add(1, Relaxed));
+                            }
+                        }
+                        assert!(count.load(Relaxed) >= before + 8);
+                    }
+                }
+            }
+        }
+        assert_eq!(count.load(Relaxed), 640);
+        assert_eq!(Scope::current().level, 0);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn spawn_methods() {
+        use crate::runtime::Runtime;
+        use std::sync::atomic::AtomicUsize;
+
+        let runtime = Runtime::init(2);
+        let count = Arc::new(AtomicUsize::new(0));
+
+        Scope::enter();
+        let scope = Scope::current();
+        let futures: Vec<_> = (0..10).map(|i| scope.spawn(move || i * i)).collect();
+        let mut first = scope.spawn(|| 1);
+        assert_eq!(first.wait(), 1);
+        assert!(!futures[0].is_scope_left());
+        for _ in 0..10 {
+            let count = Arc::clone(&count);
+            scope.spawn_detached(move || count.fetch_add(1, Relaxed));
+        }
+        let stats = Scope::leave();
+        assert_eq!(stats.num_tasks_spawned, 21);
+        assert_eq!(count.load(Relaxed), 10);
+        assert!(futures.iter().all(|f| f.is_scope_left() && f.is_ready()));
+        assert_eq!(futures.into_iter().map(ScopedFuture::get).sum::<i32>(), 285);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn local_completions() {
+        use crate::runtime::Runtime;
+
+        let runtime = Runtime::builder().num_workers(2).service_on_spawn(true).init();
+        let completions = Rc::clone(Worker::current().completions());
+
+        Scope::enter();
+        let scope = Scope::current();
+        while runtime.workers()[0].pending_requests() == 0 {
+            std::thread::yield_now();
+        }
+        let futures: Vec<_> = (0..100).map(|i| scope.spawn(move || i)).collect();
+        let unused = scope.spawn(|| String::from("unused"));
+        assert_eq!(completions.borrow().len(), 101);
+        let stats = Scope::leave();
+        // Results of stolen tasks are delivered through channels
+        assert!(stats.num_tasks_stolen > 0);
+        assert_eq!(futures.into_iter().map(ScopedFuture::get).sum::<i32>(), 4950);
+        assert_eq!(completions.borrow().len(), 1);
+        drop(unused);
+        assert!(completions.borrow().is_empty());
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn scope_handles() {
+        use crate::runtime::Runtime;
+        use std::sync::atomic::AtomicUsize;
+
+        let runtime = Runtime::init(3);
+        let sum = Arc::new(AtomicUsize::new(0));
+
+        // Every task spawns two more, down to a depth of 5
+        fn tree(s: &ScopeHandle, depth: usize, sum: Arc<AtomicUsize>) {
+            sum.fetch_add(1, Relaxed);
+            if depth < 5 {
+                for _ in 0..2 {
+                    let sum = Arc::clone(&sum);
+                    s.spawn(move |s| tree(s, depth + 1, sum));
+                }
+            }
+        }
+        let n = runtime.scope(|s| {
+            let sum = Arc::clone(&sum);
+            s.spawn(move |s| tree(s, 1, sum));
+            42
+        });
+        assert_eq!(n, 42);
+        assert_eq!(sum.load(Relaxed), 31);
+
+        let _stats = runtime.join();
     }
 }
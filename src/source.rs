@@ -0,0 +1,152 @@
+use crate::task::Task;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Mutex, TryLockError};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+// Task sources
+//
+// A source generates tasks on demand, rather than all at once, for example,
+// one task per line of a file or per message from a socket. Workers that
+// run out of local work pull the next task from a registered source (after
+// taking pooled tasks and before stealing), so that millions of tasks never
+// have to be materialized up front:
+//
+//     let mut lines = BufReader::new(file).lines();
+//     runtime.add_source(move || {
+//         let line = lines.next()?.unwrap();
+//         Some(Box::new(Async::detached(move || process(line))) as Box<dyn Task>)
+//     });
+//
+// A source that returns `None` is exhausted and removed. Workers take turns
+// with the sources, one task at a time, and a runtime isn't idle (see
+// `Runtime::wait_until_idle`) while it has sources left. All sources share
+// one lock, held while a source generates a task, so sources should hand
+// out tasks rather than do the work themselves. Workers don't wait for the
+// lock: a worker that finds it taken moves on to stealing, so a source that
+// blocks (on a socket, say) holds up only the worker that called it.
+
+pub trait TaskSource: Send {
+    fn next_task(&mut self) -> Option<Box<dyn Task>>;
+}
+
+impl<F> TaskSource for F
+where F: FnMut() -> Option<Box<dyn Task>> + Send {
+    fn next_task(&mut self) -> Option<Box<dyn Task>> {
+        self()
+    }
+}
+
+// The sources of a runtime, see `Shared::sources`
+pub struct Sources {
+    sources: Mutex<VecDeque<Box<dyn TaskSource>>>,
+    // Sources not yet exhausted, so that workers can skip the lock when
+    // there are none
+    active: AtomicUsize,
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Self { sources: Mutex::new(VecDeque::new()), active: AtomicUsize::new(0) }
+    }
+
+    pub fn add(&self, source: Box<dyn TaskSource>) {
+        self.sources.lock().unwrap().push_back(source);
+        self.active.fetch_add(1, Relaxed);
+    }
+
+    pub fn len(&self) -> usize {
+        self.active.load(Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Pass the next task of the source whose turn it is to `f`, dropping
+    // exhausted sources along the way. `f` runs before the lock is released,
+    // so that the task is accounted for (see `Worker::quiescent`) before the
+    // last source can be found exhausted. Returns `None` right away if
+    // another thread is taking a task.
+    pub fn take<R>(&self, f: impl FnOnce(Box<dyn Task>) -> R) -> Option<R> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut sources = match self.sources.try_lock() {
+            Ok(sources) => sources,
+            Err(TryLockError::WouldBlock) => return None,
+            Err(TryLockError::Poisoned(err)) => panic!("{}", err),
+        };
+        while let Some(mut source) = sources.pop_front() {
+            if let Some(task) = source.next_task() {
+                sources.push_back(source);
+                return Some(f(task));
+            }
+            self.active.fetch_sub(1, Release);
+        }
+        None
+    }
+}
+
+impl fmt::Debug for Sources {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sources").field("active", &self.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use crate::task::Async;
+    use std::sync::Arc;
+    use super::*;
+
+    fn counting(n: usize, count: &Arc<AtomicUsize>) -> impl TaskSource {
+        let count = Arc::clone(count);
+        let mut i = 0;
+        move || {
+            if i == n { return None; }
+            i += 1;
+            let count = Arc::clone(&count);
+            Some(Box::new(Async::detached(move || { count.fetch_add(1, Relaxed); })) as Box<dyn Task>)
+        }
+    }
+
+    #[test]
+    fn take_turns() {
+        let sources = Sources::new();
+        let (a, b) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        sources.add(Box::new(counting(1, &a)));
+        sources.add(Box::new(counting(3, &b)));
+        assert_eq!(sources.len(), 2);
+        // Someone else is taking a task
+        let guard = sources.sources.lock().unwrap();
+        assert!(sources.take(|task| task.run()).is_none());
+        assert_eq!(sources.len(), 2);
+        drop(guard);
+        for _ in 0..2 {
+            sources.take(|task| task.run()).unwrap();
+        }
+        assert_eq!((a.load(Relaxed), b.load(Relaxed)), (1, 1));
+        // `a` is exhausted on its next turn
+        for _ in 0..2 {
+            sources.take(|task| task.run()).unwrap();
+        }
+        assert_eq!(sources.len(), 1);
+        assert!(sources.take(|task| task.run()).is_none());
+        assert!(sources.is_empty());
+    }
+
+    #[test]
+    fn pull_when_idle() {
+        let runtime = Runtime::init(3);
+        let count = Arc::new(AtomicUsize::new(0));
+        runtime.add_source(counting(1000, &count));
+        runtime.wait_until_idle();
+        assert_eq!(count.load(Relaxed), 1000);
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_source_tasks.get(), 1000);
+        assert_stat_eq!(stats.num_tasks_executed.get(), 1000);
+    }
+}
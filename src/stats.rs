@@ -1,9 +1,19 @@
+#[cfg(not(feature = "no-stats"))]
 use std::cell::Cell;
+use crate::scope::ScopeStats;
+use crate::task::TaskId;
+use crate::worker::Worker;
+use std::cell::{Ref, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::ops::AddAssign;
+use std::time::Duration;
 
-#[derive(Debug)]
+#[cfg(not(feature = "no-stats"))]
+#[derive(Debug, Clone)]
 pub struct Count(Cell<u32>);
 
+#[cfg(not(feature = "no-stats"))]
 impl Count {
     pub fn new(value: u32) -> Self {
         Self(Cell::new(value))
@@ -32,23 +42,877 @@ impl Count {
     pub fn dec(&self) {
         self.sub(1);
     }
+
+    pub fn max(&self, value: u32) {
+        self.set(self.get().max(value));
+    }
 }
 
-#[derive(Debug)]
+// Like `Count`, but for amounts that outgrow a `u32`, such as bytes
+#[cfg(not(feature = "no-stats"))]
+#[derive(Debug, Clone)]
+pub struct Bytes(Cell<u64>);
+
+#[cfg(not(feature = "no-stats"))]
+impl Bytes {
+    pub fn new(value: u64) -> Self {
+        Self(Cell::new(value))
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    pub fn set(&self, value: u64) {
+        self.0.set(value);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.set(self.get().saturating_add(value));
+    }
+}
+
+#[cfg(not(feature = "no-stats"))]
+#[derive(Debug, Clone)]
+pub struct Time(Cell<Duration>);
+
+#[cfg(not(feature = "no-stats"))]
+impl Time {
+    pub fn new() -> Self {
+        Self(Cell::new(Duration::ZERO))
+    }
+
+    pub fn get(&self) -> Duration {
+        self.0.get()
+    }
+
+    pub fn set(&self, duration: Duration) {
+        self.0.set(duration);
+    }
+
+    pub fn add(&self, duration: Duration) {
+        self.0.set(self.get() + duration);
+    }
+
+    // Keep the longer of the current and the given duration
+    pub fn max(&self, duration: Duration) {
+        self.0.set(self.get().max(duration));
+    }
+}
+
+#[cfg(not(feature = "no-stats"))]
+const NUM_BUCKETS: usize = 40;
+
+// Histogram of durations with power-of-two buckets: bucket `i` counts
+// durations of less than 2^i nanoseconds (the last bucket counts the rest)
+#[cfg(not(feature = "no-stats"))]
+#[derive(Debug, Clone)]
+pub struct Histogram([Cell<u32>; NUM_BUCKETS]);
+
+#[cfg(not(feature = "no-stats"))]
+impl Histogram {
+    pub fn new() -> Self {
+        Self(std::array::from_fn(|_| Cell::new(0)))
+    }
+
+    pub fn record(&self, duration: Duration) {
+        let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+        let bucket = (u64::BITS - nanos.leading_zeros()) as usize;
+        let count = &self.0[bucket.min(NUM_BUCKETS - 1)];
+        count.set(count.get() + 1);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.0.iter().map(Cell::get).sum()
+    }
+
+    // Upper bound of the `p`-th percentile (0 < p <= 100), or `None` if
+    // nothing has been recorded
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 { return None; }
+        let rank = ((p / 100.0 * count as f64).ceil() as u32).clamp(1, count);
+        let mut seen = 0;
+        for (i, n) in self.0.iter().enumerate() {
+            seen += n.get();
+            if seen >= rank {
+                return Some(Duration::from_nanos(1 << i));
+            }
+        }
+        unreachable!()
+    }
+
+    pub fn add(&self, other: &Self) {
+        for (n, m) in self.0.iter().zip(other.0.iter()) {
+            n.set(n.get() + m.get());
+        }
+    }
+
+    // Remove the durations recorded in `other`, an earlier copy of this
+    // histogram
+    pub fn sub(&self, other: &Self) {
+        for (n, m) in self.0.iter().zip(other.0.iter()) {
+            n.set(n.get().saturating_sub(m.get()));
+        }
+    }
+
+    pub fn reset(&self) {
+        for n in self.0.iter() {
+            n.set(0);
+        }
+    }
+}
+
+// With the `no-stats` feature, counters are empty, and updating them compiles
+// to nothing. Reading them always returns zero.
+
+#[cfg(feature = "no-stats")]
+#[derive(Debug, Clone)]
+pub struct Count;
+
+#[cfg(feature = "no-stats")]
+impl Count {
+    pub fn new(_value: u32) -> Self { Self }
+    #[inline(always)]
+    pub fn get(&self) -> u32 { 0 }
+    #[inline(always)]
+    pub fn set(&self, _value: u32) {}
+    #[inline(always)]
+    pub fn add(&self, _value: u32) {}
+    #[inline(always)]
+    pub fn sub(&self, _value: u32) {}
+    #[inline(always)]
+    pub fn inc(&self) {}
+    #[inline(always)]
+    pub fn dec(&self) {}
+
+    pub fn max(&self, _value: u32) {}
+}
+
+#[cfg(feature = "no-stats")]
+#[derive(Debug, Clone)]
+pub struct Bytes;
+
+#[cfg(feature = "no-stats")]
+impl Bytes {
+    pub fn new(_value: u64) -> Self { Self }
+    #[inline(always)]
+    pub fn get(&self) -> u64 { 0 }
+    #[inline(always)]
+    pub fn set(&self, _value: u64) {}
+    #[inline(always)]
+    pub fn add(&self, _value: u64) {}
+}
+
+#[cfg(feature = "no-stats")]
+#[derive(Debug, Clone)]
+pub struct Time;
+
+#[cfg(feature = "no-stats")]
+impl Time {
+    pub fn new() -> Self { Self }
+    #[inline(always)]
+    pub fn get(&self) -> Duration { Duration::ZERO }
+    #[inline(always)]
+    pub fn set(&self, _duration: Duration) {}
+    #[inline(always)]
+    pub fn add(&self, _duration: Duration) {}
+    #[inline(always)]
+    pub fn max(&self, _duration: Duration) {}
+}
+
+#[cfg(feature = "no-stats")]
+#[derive(Debug, Clone)]
+pub struct Histogram;
+
+#[cfg(feature = "no-stats")]
+impl Histogram {
+    pub fn new() -> Self { Self }
+    #[inline(always)]
+    pub fn record(&self, _duration: Duration) {}
+    pub fn count(&self) -> u32 { 0 }
+    pub fn percentile(&self, _p: f64) -> Option<Duration> { None }
+    #[inline(always)]
+    pub fn add(&self, _other: &Self) {}
+    #[inline(always)]
+    pub fn sub(&self, _other: &Self) {}
+    #[inline(always)]
+    pub fn reset(&self) {}
+}
+
+#[derive(Debug, Clone)]
 pub struct Stats {
+    // `None` for aggregated statistics
+    pub id: Option<usize>,
     pub num_tasks_executed: Count,
+    // Steal requests sent
+    pub num_steal_requests: Count,
+    // Steal requests that returned one or more tasks, and those that
+    // returned more than one
+    pub num_steals: Count,
+    pub num_batch_steals: Count,
+    // Steal requests sent before our deque ran dry, see
+    // `Watermarks::steal_many_when_low`
+    pub num_early_steals: Count,
+    // Steal requests given up because the victims' queues were full
+    pub num_shed_requests: Count,
+    // Times a victim was left out of steal attempts after coming back
+    // empty-handed too often, see `Config::blacklist_after`
+    pub num_blacklisted_victims: Count,
+    // Urgent steal requests answered, see `Worker::help_until_urgent`
+    pub num_urgent_requests: Count,
+    // Tasks handed to another worker on retirement, see `Worker::retire`
+    pub num_tasks_handed_over: Count,
+    // Steal requests answered with a promise of future work, see
+    // `EmptyDequeResponse::Later`
+    pub num_work_promised: Count,
+    // Steal requests answered with part of our only queued task, see
+    // `Task::split`
+    pub num_tasks_split: Count,
+    // Times this worker checked for steal requests, and how many requests
+    // were pending each time (in total and at most)
+    pub num_polls: Count,
+    pub num_pending_requests: Count,
+    pub max_pending_requests: Count,
+    // Tasks created, heap bytes allocated for their closures (see
+    // `TaskKind::size_hint`), and the most tasks queued at once, to track
+    // down producers that run away from their consumers
+    pub num_tasks_created: Count,
+    pub task_bytes: Bytes,
+    pub max_queued_tasks: Count,
+    // Time spent waiting for steal responses, and idling in between (see
+    // `IdleStrategy`)
+    pub idle_time: Time,
+    // Wall-clock time from worker creation to termination
+    pub run_time: Time,
+    // CPU time the worker thread consumed over the same period (zero where
+    // we can't measure it), which includes time spent spinning while idle
+    pub cpu_time: Time,
+    // Delay between spawning a task and starting to run it
+    pub latency: Histogram,
+    // Longest delay between spawning a task and starting to run it
+    pub max_latency: Time,
+    // Longest stretch of unsuccessful steal attempts
+    pub max_idle_streak: Time,
+    // Tasks that started after their deadline (see `Task::deadline`)
+    pub num_late_starts: Count,
+    // Tasks that finished after their deadline
+    pub num_overruns: Count,
+    // Tasks run right away instead of being queued, see `SpawnHint::Serial`
+    pub num_tasks_inlined: Count,
+    // Tasks turned away by the admission policy, see `Builder::admission`
+    pub num_tasks_rejected: Count,
+    // Background tasks executed, see `Worker::push_background`
+    pub num_background_tasks: Count,
+    // Tasks taken from pools, see `Pool`
+    pub num_pooled_tasks: Count,
+    // Tasks pulled from task sources, see `TaskSource`
+    pub num_source_tasks: Count,
+    // Tasks submitted from outside the runtime, see `RuntimeHandle`
+    pub num_submitted_tasks: Count,
+    // Tasks that panicked, if panics are caught, see `PanicPolicy`
+    pub num_tasks_panicked: Count,
+    // Steal requests for one task instead of a batch, because the scope we
+    // were waiting for was almost done, see `Scope::wait_on`
+    pub num_steals_throttled: Count,
+    // Steal requests this worker answered with one or more tasks
+    pub num_steals_served: Count,
+    // Activations of recurring task families scheduled on the worker that
+    // ran the previous activation, and those that migrated, see `Family`
+    pub num_sticky_tasks: Count,
+    pub num_family_migrations: Count,
+    // Tasks sent to the worker that produced their data, see `Located`
+    pub num_located_tasks: Count,
+    // Failed attempts of retrying tasks that were run again, and retrying
+    // tasks that still failed after their last retry, see `RetryingTask`
+    pub num_task_retries: Count,
+    pub num_retries_exhausted: Count,
+    // Time spent running tasks, not counting tasks run while waiting in a
+    // task, and the longest chain of tasks and the tasks they spawned (for
+    // aggregated statistics only). Both require `Builder::track_tasks`, see
+    // `TaskGraph`.
+    pub task_work: Time,
+    pub critical_path: Time,
+    // Application-defined counters by name, see `Stats::register_counter`
+    custom: RefCell<BTreeMap<&'static str, u64>>,
+    // Scopes left on this worker, by scope level, see `Stats::scope_levels`
+    scopes: RefCell<BTreeMap<u32, ScopeLevel>>,
+    // Statistics of the individual workers, sorted by id
+    workers: RefCell<Vec<Stats>>,
 }
 
 impl Stats {
     pub fn new() -> Self {
-        Self { num_tasks_executed: Count::new(0) }
+        Self {
+            id: None,
+            num_tasks_executed: Count::new(0),
+            num_steal_requests: Count::new(0),
+            num_steals: Count::new(0),
+            num_batch_steals: Count::new(0),
+            num_early_steals: Count::new(0),
+            num_shed_requests: Count::new(0),
+            num_blacklisted_victims: Count::new(0),
+            num_urgent_requests: Count::new(0),
+            num_tasks_handed_over: Count::new(0),
+            num_work_promised: Count::new(0),
+            num_tasks_split: Count::new(0),
+            num_polls: Count::new(0),
+            num_pending_requests: Count::new(0),
+            max_pending_requests: Count::new(0),
+            num_tasks_created: Count::new(0),
+            task_bytes: Bytes::new(0),
+            max_queued_tasks: Count::new(0),
+            idle_time: Time::new(),
+            run_time: Time::new(),
+            cpu_time: Time::new(),
+            latency: Histogram::new(),
+            max_latency: Time::new(),
+            max_idle_streak: Time::new(),
+            num_late_starts: Count::new(0),
+            num_overruns: Count::new(0),
+            num_tasks_inlined: Count::new(0),
+            num_tasks_rejected: Count::new(0),
+            num_background_tasks: Count::new(0),
+            num_pooled_tasks: Count::new(0),
+            num_source_tasks: Count::new(0),
+            num_submitted_tasks: Count::new(0),
+            num_tasks_panicked: Count::new(0),
+            num_steals_throttled: Count::new(0),
+            num_steals_served: Count::new(0),
+            num_sticky_tasks: Count::new(0),
+            num_family_migrations: Count::new(0),
+            num_located_tasks: Count::new(0),
+            num_task_retries: Count::new(0),
+            num_retries_exhausted: Count::new(0),
+            task_work: Time::new(),
+            critical_path: Time::new(),
+            custom: RefCell::new(BTreeMap::new()),
+            scopes: RefCell::new(BTreeMap::new()),
+            workers: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn with_id(id: usize) -> Self {
+        Self { id: Some(id), ..Self::new() }
+    }
+
+    // Start over from zero
+    pub fn reset(&self) {
+        self.num_tasks_executed.set(0);
+        self.num_steal_requests.set(0);
+        self.num_steals.set(0);
+        self.num_batch_steals.set(0);
+        self.num_early_steals.set(0);
+        self.num_shed_requests.set(0);
+        self.num_blacklisted_victims.set(0);
+        self.num_urgent_requests.set(0);
+        self.num_tasks_handed_over.set(0);
+        self.num_work_promised.set(0);
+        self.num_tasks_split.set(0);
+        self.num_polls.set(0);
+        self.num_pending_requests.set(0);
+        self.max_pending_requests.set(0);
+        self.num_tasks_created.set(0);
+        self.task_bytes.set(0);
+        self.max_queued_tasks.set(0);
+        self.idle_time.set(Duration::ZERO);
+        self.run_time.set(Duration::ZERO);
+        self.cpu_time.set(Duration::ZERO);
+        self.latency.reset();
+        self.max_latency.set(Duration::ZERO);
+        self.max_idle_streak.set(Duration::ZERO);
+        self.num_late_starts.set(0);
+        self.num_overruns.set(0);
+        self.num_tasks_inlined.set(0);
+        self.num_tasks_rejected.set(0);
+        self.num_background_tasks.set(0);
+        self.num_pooled_tasks.set(0);
+        self.num_source_tasks.set(0);
+        self.num_submitted_tasks.set(0);
+        self.num_tasks_panicked.set(0);
+        self.num_steals_throttled.set(0);
+        self.num_steals_served.set(0);
+        self.num_sticky_tasks.set(0);
+        self.num_family_migrations.set(0);
+        self.num_located_tasks.set(0);
+        self.num_task_retries.set(0);
+        self.num_retries_exhausted.set(0);
+        self.task_work.set(Duration::ZERO);
+        self.critical_path.set(Duration::ZERO);
+        self.custom.borrow_mut().clear();
+        self.scopes.borrow_mut().clear();
+        self.workers.borrow_mut().clear();
     }
 
     pub fn update(&self, other: &Self) {
         self.num_tasks_executed.add(other.num_tasks_executed.get());
+        self.num_steal_requests.add(other.num_steal_requests.get());
+        self.num_steals.add(other.num_steals.get());
+        self.num_batch_steals.add(other.num_batch_steals.get());
+        self.num_early_steals.add(other.num_early_steals.get());
+        self.num_shed_requests.add(other.num_shed_requests.get());
+        self.num_blacklisted_victims.add(other.num_blacklisted_victims.get());
+        self.num_urgent_requests.add(other.num_urgent_requests.get());
+        self.num_tasks_handed_over.add(other.num_tasks_handed_over.get());
+        self.num_work_promised.add(other.num_work_promised.get());
+        self.num_tasks_split.add(other.num_tasks_split.get());
+        self.num_polls.add(other.num_polls.get());
+        self.num_pending_requests.add(other.num_pending_requests.get());
+        self.max_pending_requests.max(other.max_pending_requests.get());
+        self.num_tasks_created.add(other.num_tasks_created.get());
+        self.task_bytes.add(other.task_bytes.get());
+        self.max_queued_tasks.max(other.max_queued_tasks.get());
+        self.idle_time.add(other.idle_time.get());
+        self.run_time.add(other.run_time.get());
+        self.cpu_time.add(other.cpu_time.get());
+        self.latency.add(&other.latency);
+        self.max_latency.max(other.max_latency.get());
+        self.max_idle_streak.max(other.max_idle_streak.get());
+        self.num_late_starts.add(other.num_late_starts.get());
+        self.num_overruns.add(other.num_overruns.get());
+        self.num_tasks_inlined.add(other.num_tasks_inlined.get());
+        self.num_tasks_rejected.add(other.num_tasks_rejected.get());
+        self.num_background_tasks.add(other.num_background_tasks.get());
+        self.num_pooled_tasks.add(other.num_pooled_tasks.get());
+        self.num_source_tasks.add(other.num_source_tasks.get());
+        self.num_submitted_tasks.add(other.num_submitted_tasks.get());
+        self.num_tasks_panicked.add(other.num_tasks_panicked.get());
+        self.num_steals_throttled.add(other.num_steals_throttled.get());
+        self.num_steals_served.add(other.num_steals_served.get());
+        self.num_sticky_tasks.add(other.num_sticky_tasks.get());
+        self.num_family_migrations.add(other.num_family_migrations.get());
+        self.num_located_tasks.add(other.num_located_tasks.get());
+        self.num_task_retries.add(other.num_task_retries.get());
+        self.num_retries_exhausted.add(other.num_retries_exhausted.get());
+        self.task_work.add(other.task_work.get());
+        self.critical_path.max(other.critical_path.get());
+        let mut custom = self.custom.borrow_mut();
+        for (&name, &value) in other.custom.borrow().iter() {
+            *custom.entry(name).or_insert(0) += value;
+        }
+        let mut scopes = self.scopes.borrow_mut();
+        for (&level, other) in other.scopes.borrow().iter() {
+            scopes.entry(level).or_default().add(other);
+        }
+    }
+
+    // A handle for counting application events (cache misses, retries, ...)
+    // along with the built-in statistics. Tasks increment the counter in the
+    // statistics of whatever worker runs them, and counters with the same
+    // name are summed up when statistics are aggregated.
+    pub fn register_counter(name: &'static str) -> Counter {
+        Counter { name }
+    }
+
+    // Value of the custom counter `name` (zero if never incremented)
+    pub fn counter(&self, name: &str) -> u64 {
+        self.custom.borrow().get(name).copied().unwrap_or(0)
+    }
+
+    // All custom counters, sorted by name
+    pub fn counters(&self) -> Vec<(&'static str, u64)> {
+        self.custom.borrow().iter().map(|(&name, &value)| (name, value)).collect()
+    }
+
+    fn add_to_counter(&self, name: &'static str, value: u64) {
+        *self.custom.borrow_mut().entry(name).or_insert(0) += value;
+    }
+
+    // What happened in the scopes left at each scope level (1 for the
+    // outermost `finish!`, 2 for a `finish!` nested in it or in one of its
+    // tasks, and so on, no matter which worker runs the task), sorted by
+    // level. This shows where `finish!` regions spend their time: lots of
+    // waiting at a level with few stolen tasks means the spawning worker ran
+    // most of the tasks itself.
+    pub fn scope_levels(&self) -> Vec<(u32, ScopeLevel)> {
+        self.scopes.borrow().iter().map(|(&level, &scope)| (level, scope)).collect()
+    }
+
+    pub fn record_scope(&self, level: u32, stats: &ScopeStats) {
+        let mut scopes = self.scopes.borrow_mut();
+        let scope = scopes.entry(level).or_default();
+        scope.num_scopes += 1;
+        scope.num_tasks_spawned += stats.num_tasks_spawned as u64;
+        scope.num_tasks_stolen += stats.num_tasks_stolen as u64;
+        scope.wait_time += stats.wait_time;
+    }
+
+    // Did any task wait longer than `threshold` to run, or any worker go
+    // without work for longer than `threshold`?
+    pub fn starved(&self, threshold: Duration) -> bool {
+        self.max_latency.get() > threshold || self.max_idle_streak.get() > threshold
+    }
+
+    // Ids of workers that went without work for longer than `threshold`
+    pub fn starved_workers(&self, threshold: Duration) -> Vec<usize> {
+        self.workers()
+            .iter()
+            .filter(|w| w.max_idle_streak.get() > threshold)
+            .map(|w| w.id.unwrap())
+            .collect()
+    }
+
+    // Average number of steal requests found pending per check. Values well
+    // above one mean that thieves have to queue up for busy victims.
+    pub fn avg_pending_requests(&self) -> f64 {
+        match self.num_polls.get() {
+            0 => 0.0,
+            n => self.num_pending_requests.get() as f64 / n as f64,
+        }
+    }
+
+    // Time not spent waiting for work
+    pub fn busy_time(&self) -> Duration {
+        self.run_time.get().saturating_sub(self.idle_time.get())
+    }
+
+    // Fraction of run time spent busy, between 0 and 1 (for aggregated
+    // statistics, averaged over all workers)
+    pub fn utilization(&self) -> f64 {
+        let run_time = self.run_time.get().as_secs_f64();
+        if run_time == 0.0 { return 0.0; }
+        self.busy_time().as_secs_f64() / run_time
+    }
+
+    // Fraction of run time spent on the CPU, busy or not. Where this is much
+    // higher than `utilization`, workers burn CPU time spinning for work.
+    pub fn cpu_utilization(&self) -> f64 {
+        let run_time = self.run_time.get().as_secs_f64();
+        if run_time == 0.0 { return 0.0; }
+        self.cpu_time.get().as_secs_f64() / run_time
+    }
+
+    // Upper bound of the speedup the task decomposition allows: total work
+    // divided by the critical path (zero without `Builder::track_tasks`)
+    pub fn parallelism(&self) -> f64 {
+        let critical_path = self.critical_path.get().as_secs_f64();
+        if critical_path == 0.0 { return 0.0; }
+        self.task_work.get().as_secs_f64() / critical_path
+    }
+
+    // What happened since `before`, an earlier snapshot of the same
+    // runtime (see `Runtime::region`). Maxima can't be taken apart, so they
+    // cover everything up to this snapshot.
+    pub fn diff(&self, before: &Self) -> Self {
+        let stats = self.clone();
+        macro_rules! since {
+            ($($field:ident),*) => {
+                $(stats.$field.set(self.$field.get().saturating_sub(before.$field.get()));)*
+            };
+        }
+        since!(
+            num_tasks_executed, num_steal_requests, num_steals, num_batch_steals, num_early_steals,
+            num_shed_requests,
+            num_blacklisted_victims, num_urgent_requests, num_tasks_handed_over,
+            num_work_promised, num_tasks_split, num_polls, num_pending_requests, num_tasks_created,
+            task_bytes, idle_time, run_time, cpu_time, num_late_starts, num_overruns,
+            num_tasks_inlined, num_tasks_rejected, num_background_tasks, num_pooled_tasks,
+            num_source_tasks, num_submitted_tasks, num_tasks_panicked, num_steals_throttled,
+            num_steals_served, num_sticky_tasks, num_family_migrations, num_located_tasks,
+            num_task_retries, num_retries_exhausted, task_work
+        );
+        stats.latency.sub(&before.latency);
+        for (name, value) in stats.custom.borrow_mut().iter_mut() {
+            *value = value.saturating_sub(before.counter(name));
+        }
+        for (level, scope) in stats.scopes.borrow_mut().iter_mut() {
+            if let Some(old) = before.scopes.borrow().get(level) {
+                scope.sub(old);
+            }
+        }
+        let before_workers = before.workers();
+        for worker in stats.workers.borrow_mut().iter_mut() {
+            if let Some(old) = before_workers.iter().find(|w| w.id == worker.id) {
+                *worker = worker.diff(old);
+            }
+        }
+        stats
+    }
+
+    // Like `update`, but also keep a copy of a worker's statistics
+    pub fn collect(&self, worker: &Self) {
+        assert!(worker.id.is_some());
+        self.update(worker);
+        let mut workers = self.workers.borrow_mut();
+        workers.push(worker.clone());
+        workers.sort_by_key(|w| w.id);
+    }
+
+    pub fn workers(&self) -> Ref<'_, Vec<Stats>> {
+        self.workers.borrow()
+    }
+
+    // How evenly the collected workers shared the work, see `Balance`
+    pub fn balance(&self) -> Balance {
+        let workers = self.workers();
+        let mut executed: Vec<f64> = workers.iter().map(|w| w.num_tasks_executed.get() as f64).collect();
+        executed.sort_by(f64::total_cmp);
+        let n = executed.len() as f64;
+        let total: f64 = executed.iter().sum();
+        let (imbalance, gini) = match total {
+            0.0 => (1.0, 0.0),
+            _ => {
+                let max = executed.last().unwrap();
+                // With tasks sorted in ascending order,
+                // G = 2 * sum(i * x_i) / (n * sum(x_i)) - (n + 1) / n
+                let weighted: f64 = executed.iter().enumerate().map(|(i, x)| (i + 1) as f64 * x).sum();
+                (max * n / total, 2.0 * weighted / (n * total) - (n + 1.0) / n)
+            }
+        };
+        let mut victims: Vec<(usize, u32)> = workers
+            .iter()
+            .map(|w| (w.id.unwrap(), w.num_steals_served.get()))
+            .filter(|&(_, served)| served > 0)
+            .collect();
+        victims.sort_by_key(|&(id, served)| (std::cmp::Reverse(served), id));
+        Balance { imbalance, gini, victims }
+    }
+}
+
+// How evenly tasks were executed across workers, to make load-balancing
+// regressions obvious
+#[derive(Debug, Clone, PartialEq)]
+pub struct Balance {
+    // Most tasks executed by any worker, relative to the average (one if
+    // all workers executed equally many)
+    pub imbalance: f64,
+    // Gini coefficient of the tasks executed per worker, from zero (all
+    // equal) to almost one (one worker did everything)
+    pub gini: f64,
+    // Workers that answered steal requests with tasks, and how often, most
+    // frequent first
+    pub victims: Vec<(usize, u32)>,
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "imbalance {:.2}, gini {:.3}, steals served by", self.imbalance, self.gini)?;
+        if self.victims.is_empty() {
+            return write!(f, " nobody");
+        }
+        for (i, (id, served)) in self.victims.iter().take(3).enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{}{} ({})", sep, id, served)?;
+        }
+        Ok(())
     }
 }
 
+// Per-worker table followed by totals
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>6} {:>10} {:>10} {:>10} {:>12} {:>8} {:>8}",
+                 "worker", "tasks", "requests", "steals", "idle (ms)", "util %", "cpu %")?;
+        let row = |f: &mut fmt::Formatter, name: String, stats: &Stats| {
+            writeln!(f, "{:>6} {:>10} {:>10} {:>10} {:>12.3} {:>8.1} {:>8.1}",
+                     name,
+                     stats.num_tasks_executed.get(),
+                     stats.num_steal_requests.get(),
+                     stats.num_steals.get(),
+                     stats.idle_time.get().as_secs_f64() * 1e3,
+                     stats.utilization() * 100.0,
+                     stats.cpu_utilization() * 100.0)
+        };
+        for worker in self.workers().iter() {
+            row(f, worker.id.unwrap().to_string(), worker)?;
+        }
+        let name = match self.id {
+            Some(id) => id.to_string(),
+            None => String::from("total"),
+        };
+        row(f, name, self)
+    }
+}
+
+// Scopes left at one scope level, see `Stats::scope_levels`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScopeLevel {
+    pub num_scopes: u64,
+    pub num_tasks_spawned: u64,
+    // Tasks stolen away from the worker that spawned them
+    pub num_tasks_stolen: u64,
+    // Time spent in `Scope::leave` waiting for tasks
+    pub wait_time: Duration,
+}
+
+impl ScopeLevel {
+    fn add(&mut self, other: &Self) {
+        self.num_scopes += other.num_scopes;
+        self.num_tasks_spawned += other.num_tasks_spawned;
+        self.num_tasks_stolen += other.num_tasks_stolen;
+        self.wait_time += other.wait_time;
+    }
+
+    fn sub(&mut self, other: &Self) {
+        self.num_scopes = self.num_scopes.saturating_sub(other.num_scopes);
+        self.num_tasks_spawned = self.num_tasks_spawned.saturating_sub(other.num_tasks_spawned);
+        self.num_tasks_stolen = self.num_tasks_stolen.saturating_sub(other.num_tasks_stolen);
+        self.wait_time = self.wait_time.saturating_sub(other.wait_time);
+    }
+}
+
+// See `Stats::register_counter`
+#[derive(Debug, Clone, Copy)]
+pub struct Counter {
+    name: &'static str,
+}
+
+impl Counter {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // Outside of a runtime (no current worker), there are no statistics to
+    // add to, so nothing is counted. Unlike the built-in counters, custom
+    // counters are kept even with the `no-stats` feature.
+    pub fn add(&self, value: u64) {
+        if let Some(worker) = Worker::try_current() {
+            worker.stats.add_to_counter(self.name, value);
+        }
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+}
+
+// Tasks and the tasks that spawned them, see `Config::track_tasks`
+//
+// A task depends on the task that spawned it, so the longest chain of tasks
+// from a root (a task spawned outside of any task) down to a leaf bounds the
+// time the tasks take on any number of workers. Every task counts with its
+// own work, which excludes tasks it ran while waiting and time spent
+// waiting for steal responses, so that the critical path doesn't count
+// anything twice. Tasks that wait for tasks they didn't spawn make this an
+// estimate.
+//
+// Once a task and all tasks it spawned have completed, nothing can extend
+// its chains anymore, so it's removed from the graph, leaving only the
+// length of its longest chain with its parent. The graph thus holds the
+// tasks that are queued or running and their ancestors, not every task
+// since the last `clear`.
+#[derive(Debug, Default)]
+pub struct TaskGraph {
+    tasks: HashMap<TaskId, TaskNode>,
+    // Longest chain from a removed root
+    longest: Duration,
+}
+
+#[derive(Debug, Default)]
+struct TaskNode {
+    parent: Option<TaskId>,
+    work: Duration,
+    // Times the task is queued to run, and spawned tasks still in the graph
+    runs: u32,
+    children: u32,
+    // Longest chain starting at a removed child
+    longest: Duration,
+}
+
+impl TaskGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    // Record a task when it's queued. A task queued again (see
+    // `Worker::defer`) keeps its parent.
+    pub fn spawn(&mut self, id: TaskId, parent: Option<TaskId>) {
+        if let Some(task) = self.tasks.get_mut(&id) {
+            task.runs += 1;
+            return;
+        }
+        let parent = parent.filter(|&parent| parent != id && self.tasks.contains_key(&parent));
+        if let Some(parent) = parent {
+            self.tasks.get_mut(&parent).unwrap().children += 1;
+        }
+        self.tasks.insert(id, TaskNode { parent, runs: 1, ..TaskNode::default() });
+    }
+
+    // Add to a task's work when it has run
+    pub fn complete(&mut self, id: TaskId, work: Duration) {
+        let task = self.tasks.entry(id).or_default();
+        task.work += work;
+        task.runs = task.runs.saturating_sub(1);
+        self.prune(id);
+    }
+
+    // Remove a task that won't run again and has no spawned tasks left, and
+    // its ancestors that are done as well
+    fn prune(&mut self, mut id: TaskId) {
+        loop {
+            let task = &self.tasks[&id];
+            if task.runs > 0 || task.children > 0 {
+                return;
+            }
+            let task = self.tasks.remove(&id).unwrap();
+            let path = task.work + task.longest;
+            let Some(parent) = task.parent else {
+                self.longest = self.longest.max(path);
+                return;
+            };
+            let parent_task = self.tasks.get_mut(&parent).unwrap();
+            parent_task.children -= 1;
+            parent_task.longest = parent_task.longest.max(path);
+            id = parent;
+        }
+    }
+
+    // Length of the longest chain of tasks, by work
+    pub fn critical_path(&self) -> Duration {
+        let mut children: HashMap<TaskId, Vec<TaskId>> = HashMap::new();
+        let mut roots = vec![];
+        for (&id, task) in self.tasks.iter() {
+            match task.parent {
+                Some(parent) => children.entry(parent).or_default().push(id),
+                None => roots.push(id),
+            }
+        }
+        // Longest chain starting at each task, computed after its children's
+        let mut paths: HashMap<TaskId, Duration> = HashMap::new();
+        let mut stack: Vec<(TaskId, bool)> = roots.iter().map(|&id| (id, false)).collect();
+        while let Some((id, visited)) = stack.pop() {
+            let spawned = children.get(&id).map(Vec::as_slice).unwrap_or(&[]);
+            if visited {
+                let task = &self.tasks[&id];
+                let longest = spawned.iter().map(|child| paths[child]).fold(task.longest, Duration::max);
+                paths.insert(id, task.work + longest);
+            } else {
+                stack.push((id, true));
+                stack.extend(spawned.iter().map(|&child| (child, false)));
+            }
+        }
+        roots.iter().map(|root| paths[root]).fold(self.longest, Duration::max)
+    }
+
+    pub fn clear(&mut self) {
+        self.tasks.clear();
+        self.longest = Duration::ZERO;
+    }
+}
+
+// CPU time consumed by the calling thread so far, if supported
+#[cfg(target_os = "linux")]
+pub fn thread_cpu_time() -> Option<Duration> {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } != 0 {
+        return None;
+    }
+    Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn thread_cpu_time() -> Option<Duration> {
+    None
+}
+
 impl AddAssign for Stats {
     fn add_assign(&mut self, other: Self) {
         self.update(&other);
@@ -60,6 +924,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[cfg(not(feature = "no-stats"))]
     fn count_up_and_down() {
         let c = Count::new(0);
         for i in 1..=10 {
@@ -71,6 +936,15 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(feature = "no-stats"))]
+    fn bytes_beyond_u32() {
+        let b = Bytes::new(u32::MAX as u64);
+        b.add(1);
+        assert_eq!(b.get(), 1 << 32);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-stats"))]
     fn update_stats() {
         let s = Stats::new();
         s.num_tasks_executed.add(100);
@@ -80,4 +954,142 @@ mod tests {
         // `s` has been moved
         assert_eq!(t.num_tasks_executed.get(), 100);
     }
+
+    #[test]
+    #[cfg(not(feature = "no-stats"))]
+    fn collect_stats() {
+        let total = Stats::new();
+        for id in [2, 0, 1] {
+            let s = Stats::with_id(id);
+            s.num_tasks_executed.add(10 * id as u32);
+            s.idle_time.add(Duration::from_millis(1));
+            total.collect(&s);
+        }
+
+        assert_eq!(total.num_tasks_executed.get(), 30);
+        assert_eq!(total.idle_time.get(), Duration::from_millis(3));
+        let ids = total.workers().iter().map(|w| w.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![Some(0), Some(1), Some(2)]);
+
+        assert_eq!(total.utilization(), 0.0);
+        total.run_time.add(Duration::from_millis(12));
+        assert_eq!(total.busy_time(), Duration::from_millis(9));
+        assert!((total.utilization() - 0.75).abs() < 1e-9);
+
+        let report = total.to_string();
+        assert_eq!(report.lines().count(), 5);
+        assert!(report.lines().last().unwrap().trim_start().starts_with("total"));
+    }
+
+    #[test]
+    fn critical_path() {
+        let id = |seq| TaskId { worker: 0, seq };
+        let ms = Duration::from_millis;
+        let mut graph = TaskGraph::new();
+        // 0 spawns 1 and 2, 2 spawns 3; 4 is another root
+        graph.spawn(id(0), None);
+        graph.spawn(id(1), Some(id(0)));
+        graph.spawn(id(2), Some(id(0)));
+        graph.spawn(id(3), Some(id(2)));
+        graph.spawn(id(4), None);
+        // Queued again, twice, see `Worker::defer`
+        graph.spawn(id(3), Some(id(3)));
+        graph.spawn(id(3), Some(id(3)));
+        assert_eq!(graph.len(), 5);
+        for (seq, work) in [(0, 1), (1, 5), (2, 2), (3, 2), (4, 4)] {
+            graph.complete(id(seq), ms(work));
+        }
+        // 1 and 4 are done, 0 and 2 wait for 3
+        assert_eq!(graph.len(), 3);
+        assert_eq!(graph.critical_path(), ms(6));
+        graph.complete(id(3), ms(1));
+        assert_eq!(graph.critical_path(), ms(6));
+        graph.complete(id(3), ms(1));
+        assert_eq!(graph.critical_path(), ms(7));
+        assert!(graph.is_empty());
+        graph.clear();
+        assert_eq!(graph.critical_path(), Duration::ZERO);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-stats"))]
+    fn load_balance() {
+        let total = Stats::new();
+        for (id, tasks, served) in [(0, 10, 4), (1, 10, 0), (2, 10, 7), (3, 10, 4)] {
+            let s = Stats::with_id(id);
+            s.num_tasks_executed.add(tasks);
+            s.num_steals_served.add(served);
+            total.collect(&s);
+        }
+        let balance = total.balance();
+        assert_eq!((balance.imbalance, balance.gini), (1.0, 0.0));
+        assert_eq!(balance.victims, vec![(2, 7), (0, 4), (3, 4)]);
+        assert_eq!(balance.to_string(), "imbalance 1.00, gini 0.000, steals served by 2 (7), 0 (4), 3 (4)");
+
+        // One worker did everything
+        let total = Stats::new();
+        for id in 0..4 {
+            let s = Stats::with_id(id);
+            s.num_tasks_executed.add(if id == 0 { 40 } else { 0 });
+            total.collect(&s);
+        }
+        let balance = total.balance();
+        assert_eq!(balance.imbalance, 4.0);
+        assert!((balance.gini - 0.75).abs() < 1e-9);
+        assert!(balance.to_string().ends_with("served by nobody"));
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-stats"))]
+    fn latency_percentiles() {
+        let h = Histogram::new();
+        assert_eq!(h.percentile(50.0), None);
+
+        for us in 1..=100 {
+            h.record(Duration::from_micros(us));
+        }
+        assert_eq!(h.count(), 100);
+        // 50 us is about 2^15.6 ns, 99 us about 2^16.6 ns
+        assert_eq!(h.percentile(50.0), Some(Duration::from_nanos(1 << 16)));
+        assert_eq!(h.percentile(99.0), Some(Duration::from_nanos(1 << 17)));
+
+        let s = Stats::new();
+        s.latency.add(&h);
+        s.latency.add(&h);
+        assert_eq!(s.latency.count(), 200);
+        s.reset();
+        assert_eq!(s.latency.count(), 0);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    #[cfg(not(feature = "no-stats"))]
+    fn cpu_time() {
+        let start = thread_cpu_time().unwrap();
+        let wall = std::time::Instant::now();
+        while wall.elapsed() < Duration::from_millis(5) {}
+        assert!(thread_cpu_time().unwrap() - start >= Duration::from_millis(1));
+
+        let s = Stats::new();
+        s.run_time.add(Duration::from_millis(10));
+        s.cpu_time.add(Duration::from_millis(5));
+        assert!((s.cpu_utilization() - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-stats"))]
+    fn starvation() {
+        let total = Stats::new();
+        for (id, ms) in [(0, 1), (1, 20), (2, 5)] {
+            let s = Stats::with_id(id);
+            s.max_idle_streak.max(Duration::from_millis(ms));
+            s.max_latency.max(Duration::from_millis(2));
+            total.collect(&s);
+        }
+        assert_eq!(total.max_idle_streak.get(), Duration::from_millis(20));
+        assert_eq!(total.max_latency.get(), Duration::from_millis(2));
+        assert!(total.starved(Duration::from_millis(10)));
+        assert!(!total.starved(Duration::from_millis(20)));
+        assert_eq!(total.starved_workers(Duration::from_millis(2)), vec![1, 2]);
+    }
 }
@@ -0,0 +1,368 @@
+use crate::worker::Worker;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering::{AcqRel, Acquire, Release, Relaxed}};
+use std::thread;
+
+// Synchronization primitives whose waiting operations help executing tasks,
+// so that waiting tasks don't block their workers
+
+// An event that tasks can wait for. Notifications either wake up current
+// waiters only (`notify`), or leave the event set until it is reset (`set`):
+//
+//     let ready = Notify::new();
+//     let r = ready.clone();
+//     spawn!(r.wait(); ...);
+//     ...
+//     ready.set();
+//
+// Waiters that must not miss a notification that happens before they start
+// waiting can remember the epoch and wait for it to change (`wait_after`).
+pub struct Notify {
+    inner: Arc<Event>,
+}
+
+struct Event {
+    // Number of notifications so far (wrapping)
+    epoch: AtomicU32,
+    set: AtomicBool,
+}
+
+impl Notify {
+    pub fn new() -> Self {
+        Self { inner: Arc::new(Event { epoch: AtomicU32::new(0), set: AtomicBool::new(false) }) }
+    }
+
+    // Wake up all current waiters
+    pub fn notify(&self) {
+        self.inner.epoch.fetch_add(1, Release);
+    }
+
+    // Wake up all current waiters, and let future waiters pass until `reset`
+    pub fn set(&self) {
+        self.inner.set.store(true, Release);
+        self.notify();
+    }
+
+    pub fn reset(&self) {
+        self.inner.set.store(false, Release);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.inner.set.load(Acquire)
+    }
+
+    pub fn epoch(&self) -> u32 {
+        self.inner.epoch.load(Acquire)
+    }
+
+    // Help until the next notification, unless the event is set
+    pub fn wait(&self) {
+        self.wait_after(self.epoch());
+    }
+
+    // Help until there has been a notification since `epoch`, unless the
+    // event is set (or yield on threads without a worker). Returns the
+    // current epoch.
+    pub fn wait_after(&self, epoch: u32) -> u32 {
+        let done = || self.is_set() || self.epoch() != epoch;
+        if !done() {
+            match Worker::try_current() {
+                Some(worker) => worker.help_until(done),
+                None => while !done() { thread::yield_now() },
+            }
+        }
+        self.epoch()
+    }
+}
+
+impl Clone for Notify {
+    fn clone(&self) -> Self {
+        Self { inner: Arc::clone(&self.inner) }
+    }
+}
+
+// A counting semaphore for limiting concurrency, for example, to at most `n`
+// tasks doing I/O at a time. Tasks that find no permit available help
+// executing other tasks until a permit is released, rather than blocking
+// their workers (which could deadlock the pool if the tasks holding permits
+// are queued behind them):
+//
+//     let io = Semaphore::new(4);
+//     let io = io.clone();
+//     spawn! {
+//         let _permit = io.acquire();
+//         ...
+//     }
+pub struct Semaphore {
+    inner: Arc<Permits>,
+}
+
+struct Permits {
+    permits: u32,
+    in_use: AtomicU32,
+}
+
+// A permit, released when dropped
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub fn new(permits: u32) -> Self {
+        Self { inner: Arc::new(Permits { permits, in_use: AtomicU32::new(0) }) }
+    }
+
+    pub fn permits(&self) -> u32 {
+        self.inner.permits
+    }
+
+    pub fn available(&self) -> u32 {
+        self.inner.permits - self.inner.in_use.load(Acquire)
+    }
+
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        // Not `then_some`, which would drop (and release) an unused permit
+        self.take().then(|| Permit { semaphore: self })
+    }
+
+    // Help until a permit is available (or yield on threads without a
+    // worker)
+    pub fn acquire(&self) -> Permit<'_> {
+        if !self.take() {
+            match Worker::try_current() {
+                Some(worker) => worker.help_until(|| self.take()),
+                None => while !self.take() { thread::yield_now() },
+            }
+        }
+        Permit { semaphore: self }
+    }
+
+    // Take a permit if one is available
+    fn take(&self) -> bool {
+        let permits = self.inner.permits;
+        self.inner.in_use
+            .fetch_update(Acquire, Relaxed, |n| if n < permits { Some(n + 1) } else { None })
+            .is_ok()
+    }
+
+    fn release(&self) {
+        self.inner.in_use.fetch_sub(1, Release);
+    }
+}
+
+impl Clone for Semaphore {
+    fn clone(&self) -> Self {
+        Self { inner: Arc::clone(&self.inner) }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
+
+// A barrier for `n` tasks: `wait` helps executing other tasks until all `n`
+// participants have arrived, so that the participants need not run on
+// different threads. Threads without a worker yield while waiting instead.
+// The barrier can be reused for bulk-synchronous phases, but note that a
+// worker that helps while waiting may pick up another participant and run
+// it on top of the waiting one, which then can't continue before the other
+// participant has finished all phases. Participants that reach the barrier
+// more than once should therefore run on different workers (see
+// `Runtime::broadcast`).
+pub struct TaskBarrier {
+    inner: Arc<Phases>,
+}
+
+struct Phases {
+    n: u32,
+    arrived: AtomicU32,
+    // Number of completed phases (wrapping)
+    generation: AtomicU32,
+}
+
+impl TaskBarrier {
+    pub fn new(n: u32) -> Self {
+        assert!(n > 0);
+        Self {
+            inner: Arc::new(Phases { n, arrived: AtomicU32::new(0), generation: AtomicU32::new(0) }),
+        }
+    }
+
+    pub fn participants(&self) -> u32 {
+        self.inner.n
+    }
+
+    // Wait for all participants. Returns `true` for the participant that
+    // arrived last (one per phase), like `std::sync::BarrierWaitResult`.
+    pub fn wait(&self) -> bool {
+        let inner = &self.inner;
+        let generation = inner.generation.load(Acquire);
+        if inner.arrived.fetch_add(1, AcqRel) + 1 == inner.n {
+            // Participants can't arrive for the next phase before they have
+            // seen the new generation
+            inner.arrived.store(0, Relaxed);
+            inner.generation.fetch_add(1, Release);
+            return true;
+        }
+        let done = || inner.generation.load(Acquire) != generation;
+        match Worker::try_current() {
+            Some(worker) => worker.help_until(done),
+            None => while !done() { thread::yield_now() },
+        }
+        false
+    }
+}
+
+impl Clone for TaskBarrier {
+    fn clone(&self) -> Self {
+        Self { inner: Arc::clone(&self.inner) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+    use super::*;
+
+    #[test]
+    fn semaphore() {
+        let runtime = Runtime::init(4);
+
+        let io = Semaphore::new(2);
+        let running = Arc::new(AtomicU32::new(0));
+        let max_running = Arc::new(AtomicU32::new(0));
+        finish! {
+            for _ in 0..20 {
+                let io = io.clone();
+                let running = Arc::clone(&running);
+                let max_running = Arc::clone(&max_running);
+                scoped_spawn! {
+                    let _permit = io.acquire();
+                    let n = running.fetch_add(1, Relaxed) + 1;
+                    max_running.fetch_max(n, Relaxed);
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                    running.fetch_sub(1, Relaxed);
+                }
+            }
+        }
+        assert!(max_running.load(Relaxed) <= 2);
+        assert_eq!(io.available(), 2);
+
+        let permit = io.try_acquire().unwrap();
+        let _other = io.acquire();
+        assert!(io.try_acquire().is_none());
+        drop(permit);
+        assert_eq!(io.available(), 1);
+
+        // Threads without a worker wait, too
+        let permit = io.acquire();
+        let t = {
+            let io = io.clone();
+            thread::spawn(move || drop(io.acquire()))
+        };
+        drop(permit);
+        t.join().unwrap();
+        assert_eq!(io.available(), 1);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn task_barrier() {
+        let runtime = Runtime::init(2);
+
+        // More participants than workers
+        let barrier = TaskBarrier::new(8);
+        let arrived = Arc::new(AtomicU32::new(0));
+        let last = Arc::new(AtomicU32::new(0));
+        finish! {
+            for _ in 0..8 {
+                let barrier = barrier.clone();
+                let arrived = Arc::clone(&arrived);
+                let last = Arc::clone(&last);
+                scoped_spawn! {
+                    arrived.fetch_add(1, Relaxed);
+                    if barrier.wait() {
+                        last.fetch_add(1, Relaxed);
+                    }
+                    assert_eq!(arrived.load(Relaxed), 8);
+                }
+            }
+        }
+        assert_eq!(last.load(Relaxed), 1);
+
+        // Several phases, one participant per worker
+        let barrier = TaskBarrier::new(2);
+        let phases = Arc::new(AtomicU32::new(0));
+        {
+            let phases = Arc::clone(&phases);
+            runtime.broadcast(move |_| {
+                for phase in 0..10 {
+                    assert_eq!(phases.load(Relaxed) / 2, phase);
+                    barrier.wait();
+                    phases.fetch_add(1, Relaxed);
+                    barrier.wait();
+                }
+            });
+        }
+        assert_eq!(phases.load(Relaxed), 20);
+
+        let _stats = runtime.join();
+    }
+
+    #[test]
+    fn notify() {
+        let runtime = Runtime::init(2);
+
+        // Waiters pass once the event is set, and keep passing
+        let ready = Notify::new();
+        let passed = Arc::new(AtomicU32::new(0));
+        finish! {
+            for _ in 0..4 {
+                let ready = ready.clone();
+                let passed = Arc::clone(&passed);
+                scoped_spawn! {
+                    ready.wait();
+                    passed.fetch_add(1, Relaxed);
+                }
+            }
+            ready.set();
+        }
+        assert_eq!(passed.load(Relaxed), 4);
+        assert!(ready.is_set());
+        ready.wait();
+        ready.reset();
+
+        // Repeated notifications are not missed with `wait_after`
+        let tick = Notify::new();
+        let ticks = Arc::new(AtomicU32::new(0));
+        {
+            let tick = tick.clone();
+            let ticks = Arc::clone(&ticks);
+            spawn! {
+                for _ in 0..10 {
+                    ticks.fetch_add(1, Relaxed);
+                    tick.notify();
+                }
+            }
+        }
+        let mut epoch = 0;
+        while epoch != 10 {
+            epoch = tick.wait_after(epoch);
+        }
+        assert_eq!(ticks.load(Relaxed), 10);
+
+        // Threads without a worker wait, too
+        let t = {
+            let tick = tick.clone();
+            thread::spawn(move || tick.wait_after(10))
+        };
+        tick.notify();
+        assert_eq!(t.join().unwrap(), 11);
+
+        let _stats = runtime.join();
+    }
+}
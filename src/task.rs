@@ -1,39 +1,938 @@
 use crate::atomic;
+use crate::batch::Batch;
 use crate::future::Promise;
-use crate::scope::{TaskCount, NumTasks, Scope};
+use crate::runtime::{DEFAULT_MAX_RETRY_BACKOFF, DEFAULT_RETRY_BACKOFF};
+use crate::scope::{TaskCount, NumTasks, Permit, Scope, ScopeCounters};
+use crate::worker::Worker;
 use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Range;
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 // Storing closures requires generics and trait bounds. All closures implement
-// at least one of the traits `Fn`, `FnMut`, or `FnOnce`. For instance, a
-// closure that implements `FnMut` may capture variables by reference or
-// mutable reference.
+// at least one of the traits `Fn`, `FnMut`, or `FnOnce`. Tasks run exactly
+// once, so `FnOnce` is all we need, which lets closures move their captured
+// values out (consume them).
 
 // From TRPL: "[...] we need `Send` to transfer the closure from one thread to
 // another and `'static` (a lifetime bound) because we don’t know how long the
 // thread will take to execute."
-pub type Thunk<T> = dyn FnMut() -> T + Send + 'static;
+pub type Thunk<T> = dyn FnOnce() -> T + Send + 'static;
 
 // `Send` is a supertrait of `Task`, which means that only those task types
 // that can be sent between threads safely are allowed to implement `Task`.
 pub trait Task: Send {
     fn run(self: Box<Self>);
     fn promote(&mut self);
+
+    // Promote one of several sibling tasks that leave the worker together,
+    // sharing `batch` for their results (see `Promise::promote_into`). Only
+    // tasks with a result need a slot.
+    fn promote_batched(&mut self, _batch: &Arc<Batch>) {
+        self.promote();
+    }
+
+    // An optional name for debugging
+    fn name(&self) -> Option<&'static str> {
+        None
+    }
+
+    // Estimated run time in microseconds, see `CostModel`
+    fn cost(&self) -> u32 {
+        1
+    }
+
+    // Higher values are more urgent, see `StealPolicy`
+    fn priority(&self) -> u32 {
+        0
+    }
+
+    // Soft deadline: tasks that start or finish late are only counted, see
+    // `Stats::num_late_starts` and `Stats::num_overruns`
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    // When the task was created, for measuring scheduling delay
+    fn spawned(&self) -> Option<Instant> {
+        None
+    }
+
+    // How often the task has been stolen (or otherwise moved to another
+    // worker), see `StealPolicy::FewestMigrations`
+    fn migrations(&self) -> u32 {
+        0
+    }
+
+    // See `TaskId`. Task types that don't store an id remain anonymous.
+    fn id(&self) -> Option<TaskId> {
+        None
+    }
+
+    fn set_id(&mut self, _id: TaskId) {}
+
+    // See `TaskContext`. Task types that don't store a context have none
+    // to pass on.
+    fn context(&self) -> TaskContext {
+        TaskContext::default()
+    }
+
+    // Split off part of the task's work as a task of its own, if possible,
+    // leaving the rest to this task. A worker whose deque holds only this
+    // task uses it to answer a steal request, so that one large task (a
+    // range, a tree traversal) can feed several thieves.
+    fn split(&mut self) -> Option<Box<dyn Task>> {
+        None
+    }
+}
+
+// Identifies a task across the runtime: the worker that queued the task
+// first, and the number of tasks that worker had queued before. Ids are
+// assigned when tasks are queued (tasks run right away don't get one), see
+// `Runtime::find_task`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId {
+    pub worker: usize,
+    pub seq: u64,
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.worker, self.seq)
+    }
+}
+
+// Where a task comes from, for correlating what tasks do (log output, for
+// example) with the code that spawned them: the spawning task, if any, and
+// a label and a correlation id that tasks inherit from their parent, unless
+// they set their own, see `Async::with_label` and `Async::with_correlation`.
+// The context is captured when a task is created and travels with the task,
+// so it's the same no matter which worker ends up running the task:
+//
+//     Worker::current().push(Async::detached(handle_request).with_correlation(request.id));
+//
+//     // Deep inside `handle_request`, or any task it spawns
+//     if let Some(context) = Worker::current_task_context() {
+//         log::info!("[{:?}] ...", context.correlation);
+//     }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TaskContext {
+    pub label: Option<&'static str>,
+    pub parent: Option<TaskId>,
+    pub correlation: Option<u64>,
+}
+
+impl TaskContext {
+    // The context of a task created now: the current task as its parent,
+    // and its label and correlation id
+    pub fn capture() -> Self {
+        match Worker::try_current() {
+            Some(worker) => Self {
+                parent: worker.current_task(),
+                ..worker.task_context().unwrap_or_default()
+            },
+            None => Self::default(),
+        }
+    }
+}
+
+impl fmt::Debug for dyn Task {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_task(f, false, self.name())
+    }
+}
+
+// The closure of an `Async` or `ScopedAsync` task together with its promise,
+// in a single allocation. Only the job knows the type of the result, so that
+// tasks with any result fit into `TaskKind` without being boxed again.
+trait Job: Send {
+    // Run the closure and deliver the result
+    fn run(self: Box<Self>);
+    // See `Promise::promote_into`
+    fn promote_into(&mut self, batch: Option<&Arc<Batch>>);
+    fn has_promise(&self) -> bool;
+    // See `Promise::inherited_priority`
+    fn inherited_priority(&self) -> u32;
+}
+
+struct Closure<T, F> {
+    task: F,
+    promise: Option<Promise<T>>,
+}
+
+impl<T, F> Job for Closure<T, F> where F: FnOnce() -> T + Send {
+    fn run(self: Box<Self>) {
+        let result = (self.task)();
+        if let Some(promise) = self.promise {
+            promise.set(result)
+        }
+    }
+
+    fn promote_into(&mut self, batch: Option<&Arc<Batch>>) {
+        if let Some(ref mut promise) = self.promise {
+            promise.promote_into(batch);
+        }
+    }
+
+    fn has_promise(&self) -> bool {
+        self.promise.is_some()
+    }
+
+    fn inherited_priority(&self) -> u32 {
+        self.promise.as_ref().map_or(0, Promise::inherited_priority)
+    }
+}
+
+fn job<T, F>(task: F, promise: Option<Promise<T>>) -> Box<dyn Job>
+where F: FnOnce() -> T + Send + 'static, T: 'static {
+    Box::new(Closure { task, promise })
+}
+
+// A task with return type `T`
+pub struct Async<T> {
+    job: Box<dyn Job>,
+    result: PhantomData<fn() -> T>,
+    name: Option<&'static str>,
+    cost: u32,
+    priority: u32,
+    deadline: Option<Instant>,
+    spawned: Instant,
+    migrations: u32,
+    id: Option<TaskId>,
+    context: TaskContext,
+}
+
+impl<T> Async<T> {
+    pub fn new<F>(task: F, promise: Option<Promise<T>>) -> Self
+    where F: FnOnce() -> T + Send + 'static, T: 'static {
+        Self::from_job(job(task, promise))
+    }
+
+    fn from_job(job: Box<dyn Job>) -> Self {
+        Self {
+            job,
+            result: PhantomData,
+            name: None,
+            cost: 1,
+            priority: 0,
+            deadline: None,
+            spawned: Instant::now(),
+            migrations: 0,
+            id: None,
+            context: TaskContext::capture(),
+        }
+    }
+
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    pub fn with_cost(mut self, cost: u32) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    // Label the task and, unless they set their own, the tasks it spawns,
+    // see `TaskContext`
+    pub fn with_label(mut self, label: &'static str) -> Self {
+        self.context.label = Some(label);
+        self
+    }
+
+    // Like `with_label`, but with a correlation id, such as a request id
+    pub fn with_correlation(mut self, correlation: u64) -> Self {
+        self.context.correlation = Some(correlation);
+        self
+    }
+
+    // The task's own priority, or the priority inherited from a task waiting
+    // for its result, whichever is higher
+    pub fn priority(&self) -> u32 {
+        self.priority.max(self.job.inherited_priority())
+    }
+
+    // The task should be done within `timeout` after being spawned. The
+    // deadline is soft: it doesn't change when the task runs, but tasks
+    // that start or finish late are counted, see `Stats::num_late_starts`
+    // and `Stats::num_overruns`.
+    pub fn with_deadline(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(self.spawned + timeout);
+        self
+    }
+
+    pub fn run(self) {
+        self.job.run();
+    }
+
+    pub fn promote(&mut self) {
+        self.promote_into(None);
+    }
+
+    // See `Promise::promote_into`
+    pub fn promote_into(&mut self, batch: Option<&Arc<Batch>>) {
+        self.job.promote_into(batch);
+        self.migrations += 1;
+    }
+
+    // Forget the type of the result, which only the job needs to know, see
+    // `TaskKind::Async`
+    fn erase(self) -> Async<()> {
+        Async {
+            job: self.job,
+            result: PhantomData,
+            name: self.name,
+            cost: self.cost,
+            priority: self.priority,
+            deadline: self.deadline,
+            spawned: self.spawned,
+            migrations: self.migrations,
+            id: self.id,
+            context: self.context,
+        }
+    }
+}
+
+impl Async<()> {
+    // A task that borrows from its spawner, see `algorithms::join`
+    //
+    // Safety: the task must have run (or been dropped) before anything it
+    // borrows goes away.
+    pub(crate) unsafe fn new_unchecked<'a, F>(task: F) -> Self
+    where F: FnOnce() + Send + 'a {
+        let job: Box<dyn Job + 'a> = Box::new(Closure { task, promise: None::<Promise<()>> });
+        Self::from_job(unsafe { mem::transmute::<Box<dyn Job + 'a>, Box<dyn Job>>(job) })
+    }
+
+    // A task without result (whatever `task` returns is dropped). `Detached`
+    // does the same without a promise to check.
+    pub fn detached<R, F>(task: F) -> Self
+    where F: FnOnce() -> R + Send + 'static {
+        Self::new(move || { task(); }, None)
+    }
+}
+
+impl<T> fmt::Debug for Async<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_task(f, self.job.has_promise(), self.name)
+    }
+}
+
+fn fmt_task(f: &mut fmt::Formatter, future: bool, name: Option<&str>) -> fmt::Result {
+    let kind = if future { "Future" } else { "Task" };
+    match name {
+        Some(name) => write!(f, "<{} {}>", kind, name),
+        None => write!(f, "<{}>", kind),
+    }
+}
+
+impl<T> Task for Async<T> where T: Send {
+    fn run(self: Box<Self>) {
+        (*self).run();
+    }
+
+    fn promote(&mut self) {
+        (*self).promote();
+    }
+
+    fn promote_batched(&mut self, batch: &Arc<Batch>) {
+        self.promote_into(Some(batch));
+    }
+
+    fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    fn cost(&self) -> u32 {
+        self.cost
+    }
+
+    fn priority(&self) -> u32 {
+        self.priority()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    fn spawned(&self) -> Option<Instant> {
+        Some(self.spawned)
+    }
+
+    fn migrations(&self) -> u32 {
+        self.migrations
+    }
+
+    fn id(&self) -> Option<TaskId> {
+        self.id
+    }
+
+    fn set_id(&mut self, id: TaskId) {
+        self.id = Some(id);
+    }
+
+    fn context(&self) -> TaskContext {
+        self.context
+    }
+}
+
+// A fire-and-forget task, like `Async<()>` without a promise: nothing to
+// check when the task is run or promoted when it is stolen. This is what
+// `spawn!(...)` creates.
+pub struct Detached {
+    task: Box<Thunk<()>>,
+    name: Option<&'static str>,
+    cost: u32,
+    priority: u32,
+    deadline: Option<Instant>,
+    spawned: Instant,
+    migrations: u32,
+    id: Option<TaskId>,
+    context: TaskContext,
+}
+
+impl Detached {
+    // Whatever `task` returns is dropped
+    pub fn new<R, F>(task: F) -> Self
+    where F: FnOnce() -> R + Send + 'static {
+        Self {
+            task: Box::new(move || { task(); }),
+            name: None,
+            cost: 1,
+            priority: 0,
+            deadline: None,
+            spawned: Instant::now(),
+            migrations: 0,
+            id: None,
+            context: TaskContext::capture(),
+        }
+    }
+
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    pub fn with_cost(mut self, cost: u32) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    // See `Async::with_label`
+    pub fn with_label(mut self, label: &'static str) -> Self {
+        self.context.label = Some(label);
+        self
+    }
+
+    // See `Async::with_correlation`
+    pub fn with_correlation(mut self, correlation: u64) -> Self {
+        self.context.correlation = Some(correlation);
+        self
+    }
+
+    // See `Async::with_deadline`
+    pub fn with_deadline(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(self.spawned + timeout);
+        self
+    }
+
+    pub fn run(self) {
+        (self.task)();
+    }
+
+    pub fn promote(&mut self) {
+        self.migrations += 1;
+    }
+}
+
+impl fmt::Debug for Detached {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_task(f, false, self.name)
+    }
+}
+
+impl Task for Detached {
+    fn run(self: Box<Self>) {
+        (*self).run();
+    }
+
+    fn promote(&mut self) {
+        (*self).promote();
+    }
+
+    fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    fn cost(&self) -> u32 {
+        self.cost
+    }
+
+    fn priority(&self) -> u32 {
+        self.priority
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    fn spawned(&self) -> Option<Instant> {
+        Some(self.spawned)
+    }
+
+    fn migrations(&self) -> u32 {
+        self.migrations
+    }
+
+    fn id(&self) -> Option<TaskId> {
+        self.id
+    }
+
+    fn set_id(&mut self, id: TaskId) {
+        self.id = Some(id);
+    }
+
+    fn context(&self) -> TaskContext {
+        self.context
+    }
+}
+
+// A scoped task with return type `T`
+pub struct ScopedAsync<T> {
+    job: Box<dyn Job>,
+    result: PhantomData<fn() -> T>,
+    // The task count of the task's scope, once the scope is shared between
+    // workers (see `Scope::share`). Until then, the scope is found by level
+    // on the spawning worker, see `Scope::at_level`.
+    num_tasks_in_scope: Option<Arc<atomic::Count>>,
+    // See `Scope::stats`
+    counters: Arc<ScopeCounters>,
+    level: u32,
+    // See `Scope::nesting`
+    nesting: u32,
+    migrations: u32,
+    id: Option<TaskId>,
+    // See `Scope::depth`
+    depth: u32,
+    name: Option<&'static str>,
+    cost: u32,
+    priority: u32,
+    deadline: Option<Instant>,
+    spawned: Instant,
+    context: TaskContext,
+    // Acquired before running, if the scope has a concurrency limit, see
+    // `acquire_permit`
+    permit: Option<Permit>,
+}
+
+impl<T> ScopedAsync<T> {
+    pub fn new<F>(task: F, promise: Option<Promise<T>>) -> Self
+    where F: FnOnce() -> T + Send + 'static, T: 'static {
+        let scope = Scope::current();
+        scope.num_tasks.inc();
+        scope.counters().spawned();
+        //println!("{}", Scope::current().num_tasks.get());
+        Self {
+            job: job(task, promise),
+            result: PhantomData,
+            num_tasks_in_scope: scope.shared_count(),
+            counters: Arc::clone(scope.counters()),
+            level: scope.level(),
+            nesting: scope.nesting(),
+            migrations: 0,
+            id: None,
+            depth: scope.depth() + 1,
+            name: None,
+            cost: 1,
+            priority: 0,
+            deadline: None,
+            spawned: Instant::now(),
+            context: TaskContext::capture(),
+            permit: None,
+        }
+    }
+
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    pub fn with_cost(mut self, cost: u32) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    // See `Async::with_label`
+    pub fn with_label(mut self, label: &'static str) -> Self {
+        self.context.label = Some(label);
+        self
+    }
+
+    // See `Async::with_correlation`
+    pub fn with_correlation(mut self, correlation: u64) -> Self {
+        self.context.correlation = Some(correlation);
+        self
+    }
+
+    // Can the task run now, or is its scope at its concurrency limit (see
+    // `Scope::enter_with_limit`)? If not, the task waits for a permit (see
+    // `ScopeCounters::wait_for_permit`). Without a worker, it runs anyway.
+    pub fn acquire_permit(&mut self) -> bool {
+        if self.counters.limit().is_none() || self.permit.is_some() {
+            return true;
+        }
+        self.permit = self.counters.try_acquire();
+        self.permit.is_some() || Worker::try_current().is_none()
+    }
+
+    // The task's own priority, or the priority inherited from a task waiting
+    // for its result, whichever is higher
+    pub fn priority(&self) -> u32 {
+        self.priority.max(self.job.inherited_priority())
+    }
+
+    // See `Async::with_deadline`
+    pub fn with_deadline(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(self.spawned + timeout);
+        self
+    }
+
+    pub fn run(mut self) {
+        // Let the next task of the scope run once we're done
+        let _permit = self.permit.take();
+        // Tasks of shared scopes run in a copy of their scope (unless the
+        // current scope is such a copy already), and so do tasks whose scope
+        // isn't the current scope anymore, because the worker is waiting in a
+        // nested scope, running other tasks in the meantime. That way, their
+        // children are spawned into the right scope. A copy must not outlive
+        // its task, or other tasks would find it on top of their own scopes.
+        let count = match self.num_tasks_in_scope.take() {
+            Some(count) => (!Scope::current().shares(&count)).then_some(count),
+            None => {
+                let scope = Scope::at_level(self.level);
+                (!std::ptr::eq(scope, Scope::current())).then(|| scope.share())
+            }
+        };
+        let copied = count.map(|count| {
+            let num_tasks = NumTasks::with_count(TaskCount::Shared(count));
+            Scope::with_num_tasks(num_tasks, Arc::clone(&self.counters), self.nesting).push();
+        }).is_some();
+        let scope = Scope::current();
+        let finish = Finish { scope, depth: scope.set_depth(self.depth), copied };
+        match self.job.has_promise() {
+            false if self.counters.is_cancelling() => self.counters.cancelled(),
+            _ => {
+                let start = Instant::now();
+                self.job.run();
+                self.counters.executed(start.elapsed());
+            }
+        }
+        drop(finish);
+    }
+
+    // The task's scope, see `PanicPolicy::CancelScope`
+    pub fn counters(&self) -> &Arc<ScopeCounters> {
+        &self.counters
+    }
+
+    pub fn promote(&mut self) {
+        self.promote_into(None);
+    }
+
+    // See `Promise::promote_into`
+    pub fn promote_into(&mut self, batch: Option<&Arc<Batch>>) {
+        self.job.promote_into(batch);
+        // Tasks can be stolen more than once (as part of `Tasks::Many`), but
+        // only the first steal leaves the task's scope
+        if self.num_tasks_in_scope.is_none() {
+            self.num_tasks_in_scope = Some(Scope::at_level(self.level).share());
+        }
+        if self.migrations == 0 {
+            self.counters.stolen();
+        }
+        self.migrations += 1;
+    }
+
+    // Drop the task without running it, like a task cancelled because its
+    // scope's deadline has passed, so that the scope doesn't wait for it
+    pub fn cancel(self) {
+        self.counters.cancelled();
+        match self.num_tasks_in_scope {
+            Some(count) => {
+                let n = count.dec_acq_rel();
+                invariant!(n > 0, "scope task count underflow (cancelled task)");
+            }
+            None => { Scope::at_level(self.level).num_tasks.dec(); }
+        }
+    }
+
+    // See `Async::erase`
+    fn erase(self) -> ScopedAsync<()> {
+        ScopedAsync {
+            job: self.job,
+            result: PhantomData,
+            num_tasks_in_scope: self.num_tasks_in_scope,
+            counters: self.counters,
+            level: self.level,
+            nesting: self.nesting,
+            migrations: self.migrations,
+            id: self.id,
+            depth: self.depth,
+            name: self.name,
+            cost: self.cost,
+            priority: self.priority,
+            deadline: self.deadline,
+            spawned: self.spawned,
+            context: self.context,
+            permit: self.permit,
+        }
+    }
+}
+
+// Leaves the current scope the way a scoped task found it, even if the task
+// panics (and the panic is caught, see `PanicPolicy`), so that the scope
+// doesn't wait for the task forever
+struct Finish<'a> {
+    scope: &'a Scope,
+    depth: u32,
+    copied: bool,
+}
+
+impl Drop for Finish<'_> {
+    fn drop(&mut self) {
+        self.scope.set_depth(self.depth);
+        self.scope.num_tasks.dec();
+        if self.copied {
+            Scope::pop();
+        }
+    }
+}
+
+impl ScopedAsync<()> {
+    // A scoped task without result (whatever `task` returns is dropped)
+    pub fn detached<R, F>(task: F) -> Self
+    where F: FnOnce() -> R + Send + 'static {
+        Self::new(move || { task(); }, None)
+    }
+}
+
+impl<T> fmt::Debug for ScopedAsync<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_task(f, self.job.has_promise(), self.name)
+    }
+}
+
+impl<T> Task for ScopedAsync<T> where T: Send {
+    fn run(mut self: Box<Self>) {
+        if self.acquire_permit() {
+            return (*self).run();
+        }
+        let counters = Arc::clone(&self.counters);
+        let task: Box<dyn Task + '_> = self;
+        // Only the result type may be short-lived (the closure is 'static),
+        // and the task doesn't outlive its scope, which waits for it
+        let task: Box<dyn Task> = unsafe { mem::transmute(task) };
+        counters.wait_for_permit(TaskKind::Dyn(task));
+    }
+
+    fn promote(&mut self) {
+        (*self).promote();
+    }
+
+    fn promote_batched(&mut self, batch: &Arc<Batch>) {
+        self.promote_into(Some(batch));
+    }
+
+    fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    fn cost(&self) -> u32 {
+        self.cost
+    }
+
+    fn priority(&self) -> u32 {
+        self.priority()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    fn spawned(&self) -> Option<Instant> {
+        Some(self.spawned)
+    }
+
+    fn migrations(&self) -> u32 {
+        self.migrations
+    }
+
+    fn id(&self) -> Option<TaskId> {
+        self.id
+    }
+
+    fn set_id(&mut self, id: TaskId) {
+        self.id = Some(id);
+    }
+
+    fn context(&self) -> TaskContext {
+        self.context
+    }
 }
 
-// A task with return type `T`
-pub struct Async<T> {
-    task: Box<Thunk<T>>,
+// A task that applies `body` to every index in `range`. Range tasks can be
+// split in half to be shared with other workers.
+pub struct RangeTask {
+    range: Range<usize>,
+    body: Arc<dyn Fn(usize) + Send + Sync>,
+    spawned: Instant,
+    migrations: u32,
+    id: Option<TaskId>,
+    context: TaskContext,
+}
+
+impl RangeTask {
+    pub fn new<F>(range: Range<usize>, body: F) -> Self
+    where F: Fn(usize) + Send + Sync + 'static {
+        Self {
+            range,
+            body: Arc::new(body),
+            spawned: Instant::now(),
+            migrations: 0,
+            id: None,
+            context: TaskContext::capture(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+
+    // Split off the upper half of the range, unless there is nothing to split
+    pub fn split(&mut self) -> Option<Self> {
+        if self.len() < 2 { return None; }
+        let mid = self.range.start + self.len() / 2;
+        let upper = mid..self.range.end;
+        self.range.end = mid;
+        Some(Self {
+            range: upper,
+            body: Arc::clone(&self.body),
+            spawned: Instant::now(),
+            migrations: 0,
+            id: None,
+            context: self.context,
+        })
+    }
+
+    pub fn run(self) {
+        for i in self.range {
+            (self.body)(i);
+        }
+    }
+
+    pub fn promote(&mut self) {
+        self.migrations += 1;
+    }
+}
+
+impl fmt::Debug for RangeTask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<Range {:?}>", self.range)
+    }
+}
+
+impl Task for RangeTask {
+    fn run(self: Box<Self>) {
+        (*self).run();
+    }
+
+    fn promote(&mut self) {
+        (*self).promote();
+    }
+
+    fn cost(&self) -> u32 {
+        self.len().min(u32::MAX as usize) as u32
+    }
+
+    fn spawned(&self) -> Option<Instant> {
+        Some(self.spawned)
+    }
+
+    fn migrations(&self) -> u32 {
+        self.migrations
+    }
+
+    fn id(&self) -> Option<TaskId> {
+        self.id
+    }
+
+    fn set_id(&mut self, id: TaskId) {
+        self.id = Some(id);
+    }
+
+    fn context(&self) -> TaskContext {
+        self.context
+    }
+
+    fn split(&mut self) -> Option<Box<dyn Task>> {
+        RangeTask::split(self).map(|upper| Box::new(upper) as Box<dyn Task>)
+    }
+}
+
+// What the closure of a `YieldingTask` returns: `Yield` to be run again
+// later, or `Done` with the task's result
+pub enum Step<T> {
+    Yield,
+    Done(T),
+}
+
+// A task that can give up its worker without finishing, for cooperative
+// multitasking: whenever the closure returns `Step::Yield`, the task is
+// queued again behind all other queued tasks (see `Worker::defer`)
+pub struct YieldingTask<T> {
+    task: Box<dyn FnMut() -> Step<T> + Send + 'static>,
     promise: Option<Promise<T>>,
 }
 
-impl<T> Async<T> {
-    pub fn new(task: Box<Thunk<T>>, promise: Option<Promise<T>>) -> Self {
-        Self { task, promise }
+impl<T> YieldingTask<T> where T: Send + 'static {
+    pub fn new<F>(task: F, promise: Option<Promise<T>>) -> Self
+    where F: FnMut() -> Step<T> + Send + 'static {
+        Self { task: Box::new(task), promise }
     }
 
     pub fn run(mut self) {
-        let result = (self.task)();
+        let result = loop {
+            match (self.task)() {
+                // Without a worker, there are no other tasks to yield to, so
+                // the task keeps going
+                Step::Yield => if let Some(worker) = Worker::try_current() {
+                    return worker.defer(Box::new(self));
+                },
+                Step::Done(result) => break result,
+            }
+        };
         if let Some(promise) = self.promise {
             promise.set(result)
         }
@@ -46,16 +945,13 @@ impl<T> Async<T> {
     }
 }
 
-impl<T> fmt::Debug for Async<T> {
+impl<T> fmt::Debug for YieldingTask<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.promise {
-            Some(_) => write!(f, "<Future>"),
-            None => write!(f, "<Task>"),
-        }
+        fmt_task(f, self.promise.is_some(), None)
     }
 }
 
-impl<T> Task for Async<T> where T: Send {
+impl<T> Task for YieldingTask<T> where T: Send + 'static {
     fn run(self: Box<Self>) {
         (*self).run();
     }
@@ -65,52 +961,94 @@ impl<T> Task for Async<T> where T: Send {
     }
 }
 
-// A scoped task with return type `T`
-pub struct ScopedAsync<T> {
-    task: Box<Thunk<T>>,
-    promise: Option<Promise<T>>,
-    num_tasks_in_scope: Option<Arc<atomic::Count>>,
+// A task for operations that may fail temporarily, such as requests to a
+// flaky service, see `spawn_retrying!`. Whenever the closure returns an
+// error, the task is run again, up to `max_retries` times, with exponential
+// backoff in between (see `Config::retry_backoff`). Until a retry is due,
+// the task waits on its worker's timers (see `Worker::defer_until`), so that
+// it doesn't hold up its worker, and retries don't count as new tasks. The
+// task's result is that of the last attempt.
+pub struct RetryingTask<T, E> {
+    task: Box<dyn FnMut() -> Result<T, E> + Send + 'static>,
+    promise: Option<Promise<Result<T, E>>>,
+    max_retries: u32,
+    retries: u32,
+    // When the next retry is due
+    due: Option<Instant>,
 }
 
-impl<T> ScopedAsync<T> {
-    pub fn new(task: Box<Thunk<T>>, promise: Option<Promise<T>>) -> Self {
-        Scope::current().num_tasks.inc();
-        //println!("{}", Scope::current().num_tasks.get());
-        Self { task, promise, num_tasks_in_scope: None }
+impl<T, E> RetryingTask<T, E> where T: Send + 'static, E: Send + 'static {
+    pub fn new<F>(max_retries: u32, task: F, promise: Option<Promise<Result<T, E>>>) -> Self
+    where F: FnMut() -> Result<T, E> + Send + 'static {
+        Self { task: Box::new(task), promise, max_retries, retries: 0, due: None }
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries
     }
 
     pub fn run(mut self) {
-        if let Some(count) = self.num_tasks_in_scope.take() {
-            let num_tasks = NumTasks::with_count(TaskCount::Shared(count));
-            Scope::with_num_tasks(num_tasks).push();
+        // Without a worker, there's nothing else to do in the meantime
+        let Some(worker) = Worker::try_current() else {
+            return self.run_in_place();
+        };
+        if let Some(due) = self.due {
+            // Handed over by a retiring worker before it was due
+            if Instant::now() < due {
+                return worker.defer_until(due, Box::new(self));
+            }
+            worker.stats.num_task_retries.inc();
+        }
+        match (self.task)() {
+            Err(_) if self.retries < self.max_retries => {
+                self.retries += 1;
+                let config = &worker.shared().config;
+                let due = Instant::now() + backoff(config.retry_backoff, config.max_retry_backoff, self.retries);
+                self.due = Some(due);
+                worker.defer_until(due, Box::new(self));
+            }
+            result => {
+                if result.is_err() {
+                    worker.stats.num_retries_exhausted.inc();
+                }
+                if let Some(promise) = self.promise {
+                    promise.set(result);
+                }
+            }
+        }
+    }
+
+    fn run_in_place(mut self) {
+        let mut result = (self.task)();
+        while result.is_err() && self.retries < self.max_retries {
+            self.retries += 1;
+            thread::sleep(backoff(DEFAULT_RETRY_BACKOFF, DEFAULT_MAX_RETRY_BACKOFF, self.retries));
+            result = (self.task)();
         }
-        let result = (self.task)();
         if let Some(promise) = self.promise {
-            promise.set(result)
+            promise.set(result);
         }
-        //println!("{}", Scope::current().num_tasks.get());
-        Scope::current().num_tasks.dec();
     }
 
     pub fn promote(&mut self) {
         if let Some(ref mut promise) = self.promise {
             promise.promote();
         }
-        assert!(self.num_tasks_in_scope.is_none());
-        self.num_tasks_in_scope = Some(Scope::current().share());
     }
 }
 
-impl<T> fmt::Debug for ScopedAsync<T> {
+// Backoff before the given retry (counting from one)
+fn backoff(initial: Duration, max: Duration, retry: u32) -> Duration {
+    initial.saturating_mul(1 << (retry - 1).min(31)).min(max)
+}
+
+impl<T, E> fmt::Debug for RetryingTask<T, E> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.promise {
-            Some(_) => write!(f, "<Future>"),
-            None => write!(f, "<Task>"),
-        }
+        fmt_task(f, self.promise.is_some(), None)
     }
 }
 
-impl<T> Task for ScopedAsync<T> where T: Send {
+impl<T, E> Task for RetryingTask<T, E> where T: Send + 'static, E: Send + 'static {
     fn run(self: Box<Self>) {
         (*self).run();
     }
@@ -120,6 +1058,387 @@ impl<T> Task for ScopedAsync<T> where T: Send {
     }
 }
 
+// Tasks that should run back-to-back on the same worker, typically because
+// they work on the same data, see `gang_spawn!`. A gang is queued, and
+// stolen, as a single task, so victims hand out either all of its members
+// or none of them, and whoever ends up with the gang runs the members one
+// after the other, in spawn order.
+pub struct Gang {
+    members: Vec<TaskKind>,
+    spawned: Instant,
+    migrations: u32,
+    id: Option<TaskId>,
+    context: TaskContext,
+}
+
+impl Gang {
+    pub fn new<I>(members: I) -> Self
+    where I: IntoIterator, I::Item: Into<TaskKind> {
+        Self {
+            members: members.into_iter().map(Into::into).collect(),
+            spawned: Instant::now(),
+            migrations: 0,
+            id: None,
+            context: TaskContext::capture(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+// Members of a gang that haven't run yet. If a member panics (and the panic
+// is caught, see `PanicPolicy`), the others are rejected, so that scoped
+// members don't keep their scope waiting.
+struct Remaining(std::vec::IntoIter<TaskKind>);
+
+impl Drop for Remaining {
+    fn drop(&mut self) {
+        self.0.by_ref().for_each(TaskKind::reject);
+    }
+}
+
+impl fmt::Debug for Gang {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Gang({:?})", self.members)
+    }
+}
+
+impl Task for Gang {
+    fn run(self: Box<Self>) {
+        let mut remaining = Remaining(self.members.into_iter());
+        for task in remaining.0.by_ref() {
+            task.run();
+        }
+    }
+
+    fn promote(&mut self) {
+        self.members.iter_mut().for_each(TaskKind::promote);
+        self.migrations += 1;
+    }
+
+    fn cost(&self) -> u32 {
+        self.members.iter().fold(0, |cost, task| cost.saturating_add(task.cost()))
+    }
+
+    fn priority(&self) -> u32 {
+        self.members.iter().map(TaskKind::priority).max().unwrap_or(0)
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.members.iter().filter_map(TaskKind::deadline).min()
+    }
+
+    fn spawned(&self) -> Option<Instant> {
+        Some(self.spawned)
+    }
+
+    fn migrations(&self) -> u32 {
+        self.migrations
+    }
+
+    fn id(&self) -> Option<TaskId> {
+        self.id
+    }
+
+    fn set_id(&mut self, id: TaskId) {
+        self.id = Some(id);
+    }
+
+    fn context(&self) -> TaskContext {
+        self.context
+    }
+}
+
+// What middleware gets to know about a task, see `runtime::Middleware`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskInfo {
+    // The worker running the task
+    pub worker: usize,
+    pub id: Option<TaskId>,
+    pub name: Option<&'static str>,
+    pub priority: u32,
+    pub deadline: Option<Instant>,
+    pub spawned: Option<Instant>,
+}
+
+// Spawn-site hint for whether a task is worth queueing (see `spawn!`):
+// `Serial` tasks are run right away, like function calls, unless the worker
+// has nothing else to share with thieves that are waiting for work, while
+// `Parallel` tasks are always queued, where other workers can steal them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnHint {
+    Serial,
+    Parallel,
+}
+
+// The tasks that workers queue and exchange. Built-in task kinds are
+// dispatched statically (no virtual call, no extra box), other task types
+// go through `Dyn`.
+pub enum TaskKind {
+    Async(Async<()>),
+    Detached(Detached),
+    ScopedAsync(ScopedAsync<()>),
+    Range(RangeTask),
+    Dyn(Box<dyn Task>),
+}
+
+impl TaskKind {
+    pub fn run(self) {
+        match self {
+            Self::Async(task) => task.run(),
+            Self::Detached(task) => task.run(),
+            Self::ScopedAsync(mut task) => match task.acquire_permit() {
+                true => task.run(),
+                false => Arc::clone(task.counters()).wait_for_permit(Self::ScopedAsync(task)),
+            },
+            Self::Range(task) => task.run(),
+            Self::Dyn(task) => task.run(),
+        }
+    }
+
+    // Drop the task without running it. Scoped tasks are cancelled (see
+    // `ScopedAsync::cancel`), other tasks are just dropped, along with their
+    // promises, if any.
+    pub fn reject(self) {
+        if let Self::ScopedAsync(task) = self {
+            task.cancel();
+        }
+    }
+
+    // The scope of a scoped task
+    pub fn scope_counters(&self) -> Option<Arc<ScopeCounters>> {
+        match self {
+            Self::ScopedAsync(task) => Some(Arc::clone(task.counters())),
+            _ => None,
+        }
+    }
+
+    pub fn promote(&mut self) {
+        match self {
+            Self::Async(task) => task.promote(),
+            Self::Detached(task) => task.promote(),
+            Self::ScopedAsync(task) => task.promote(),
+            Self::Range(task) => task.promote(),
+            Self::Dyn(task) => task.promote(),
+        }
+    }
+
+    // See `Task::promote_batched`
+    pub fn promote_batched(&mut self, batch: &Arc<Batch>) {
+        match self {
+            Self::Async(task) => task.promote_into(Some(batch)),
+            Self::ScopedAsync(task) => task.promote_into(Some(batch)),
+            Self::Dyn(task) => task.promote_batched(batch),
+            _ => self.promote(),
+        }
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Self::Async(task) => task.name,
+            Self::Detached(task) => task.name,
+            Self::ScopedAsync(task) => task.name,
+            Self::Range(_) => None,
+            Self::Dyn(task) => task.name(),
+        }
+    }
+
+    pub fn cost(&self) -> u32 {
+        match self {
+            Self::Async(task) => task.cost,
+            Self::Detached(task) => task.cost,
+            Self::ScopedAsync(task) => task.cost,
+            Self::Range(task) => Task::cost(task),
+            Self::Dyn(task) => task.cost(),
+        }
+    }
+
+    pub fn priority(&self) -> u32 {
+        match self {
+            Self::Async(task) => task.priority(),
+            Self::Detached(task) => task.priority,
+            Self::ScopedAsync(task) => task.priority(),
+            Self::Range(task) => Task::priority(task),
+            Self::Dyn(task) => task.priority(),
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        match self {
+            Self::Async(task) => task.deadline,
+            Self::Detached(task) => task.deadline,
+            Self::ScopedAsync(task) => task.deadline,
+            Self::Range(task) => Task::deadline(task),
+            Self::Dyn(task) => task.deadline(),
+        }
+    }
+
+    pub fn info(&self, worker: usize) -> TaskInfo {
+        TaskInfo {
+            worker,
+            id: self.id(),
+            name: self.name(),
+            priority: self.priority(),
+            deadline: self.deadline(),
+            spawned: self.spawned(),
+        }
+    }
+
+    // Heap bytes allocated for the task's closure (and, for `Async` and
+    // `ScopedAsync` tasks, its promise), as far as we can tell (a `Dyn` task
+    // may own more than its own box)
+    pub fn size_hint(&self) -> usize {
+        match self {
+            Self::Async(task) => mem::size_of_val(&*task.job),
+            Self::Detached(task) => mem::size_of_val(&*task.task),
+            Self::ScopedAsync(task) => mem::size_of_val(&*task.job),
+            Self::Range(_) => 0,
+            Self::Dyn(task) => mem::size_of_val(&**task),
+        }
+    }
+
+    pub fn id(&self) -> Option<TaskId> {
+        match self {
+            Self::Async(task) => task.id,
+            Self::Detached(task) => task.id,
+            Self::ScopedAsync(task) => task.id,
+            Self::Range(task) => task.id,
+            Self::Dyn(task) => task.id(),
+        }
+    }
+
+    pub fn set_id(&mut self, id: TaskId) {
+        match self {
+            Self::Async(task) => task.id = Some(id),
+            Self::Detached(task) => task.id = Some(id),
+            Self::ScopedAsync(task) => task.id = Some(id),
+            Self::Range(task) => task.id = Some(id),
+            Self::Dyn(task) => task.set_id(id),
+        }
+    }
+
+    pub fn context(&self) -> TaskContext {
+        match self {
+            Self::Async(task) => task.context,
+            Self::Detached(task) => task.context,
+            Self::ScopedAsync(task) => task.context,
+            Self::Range(task) => task.context,
+            Self::Dyn(task) => task.context(),
+        }
+    }
+
+    // Hint that the task will run soon, so that the task and its closure
+    // are loaded into the cache ahead of time, see `Worker::accept`
+    #[cfg(feature = "prefetch")]
+    pub fn prefetch(&self) {
+        prefetch(self);
+        match self {
+            Self::Async(task) => prefetch(&*task.job),
+            Self::Detached(task) => prefetch(&*task.task),
+            Self::ScopedAsync(task) => prefetch(&*task.job),
+            Self::Range(task) => prefetch(&*task.body),
+            Self::Dyn(task) => prefetch(&**task),
+        }
+    }
+
+    // See `Task::split`
+    pub fn split(&mut self) -> Option<TaskKind> {
+        match self {
+            Self::Range(task) => task.split().map(Self::Range),
+            Self::Dyn(task) => task.split().map(Self::Dyn),
+            _ => None,
+        }
+    }
+
+    // See `Task::migrations`
+    pub fn migrations(&self) -> u32 {
+        match self {
+            Self::Async(task) => task.migrations,
+            Self::Detached(task) => task.migrations,
+            Self::ScopedAsync(task) => task.migrations,
+            Self::Range(task) => task.migrations,
+            Self::Dyn(task) => task.migrations(),
+        }
+    }
+
+    pub fn spawned(&self) -> Option<Instant> {
+        match self {
+            Self::Async(task) => Some(task.spawned),
+            Self::Detached(task) => Some(task.spawned),
+            Self::ScopedAsync(task) => Some(task.spawned),
+            Self::Range(task) => Some(task.spawned),
+            Self::Dyn(task) => task.spawned(),
+        }
+    }
+}
+
+// Only a hint: prefetching never faults and has no effect other than on
+// timing
+#[cfg(feature = "prefetch")]
+fn prefetch<T: ?Sized>(data: &T) {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+        _mm_prefetch::<_MM_HINT_T0>((data as *const T).cast());
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = data;
+}
+
+impl fmt::Debug for TaskKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Async(task) => task.fmt(f),
+            Self::Detached(task) => task.fmt(f),
+            Self::ScopedAsync(task) => task.fmt(f),
+            Self::Range(task) => task.fmt(f),
+            Self::Dyn(task) => task.fmt(f),
+        }
+    }
+}
+
+impl<T> From<Async<T>> for TaskKind {
+    fn from(task: Async<T>) -> Self {
+        Self::Async(task.erase())
+    }
+}
+
+impl From<Detached> for TaskKind {
+    fn from(task: Detached) -> Self {
+        Self::Detached(task)
+    }
+}
+
+impl<T> From<ScopedAsync<T>> for TaskKind {
+    fn from(task: ScopedAsync<T>) -> Self {
+        Self::ScopedAsync(task.erase())
+    }
+}
+
+impl From<RangeTask> for TaskKind {
+    fn from(task: RangeTask) -> Self {
+        Self::Range(task)
+    }
+}
+
+impl From<Box<dyn Task>> for TaskKind {
+    fn from(task: Box<dyn Task>) -> Self {
+        Self::Dyn(task)
+    }
+}
+
+impl<T: Task + 'static> From<Box<T>> for TaskKind {
+    fn from(task: Box<T>) -> Self {
+        Self::Dyn(task)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::channel::one_shot_channel;
@@ -137,14 +1456,14 @@ mod tests {
             Self(task)
         }
 
-        fn run(mut self) {
+        fn run(self) {
             // Ignore result
             let _ = self.0();
         }
     }
 
     impl<T> Task for SimpleTask<T> {
-        fn run(mut self: Box<Self>) {
+        fn run(self: Box<Self>) {
             // Ignore result
             let _ = (*self).0();
         }
@@ -165,6 +1484,29 @@ mod tests {
         // `a` has been consumed
     }
 
+    #[test]
+    fn inherited_priority() {
+        let (sender, receiver) = one_shot_channel();
+        let task: TaskKind = Async::new(|| (), Some(Promise::Chan(sender)))
+            .with_priority(2)
+            .into();
+        assert_eq!(task.priority(), 2);
+        receiver.raise_priority(7);
+        assert_eq!(task.priority(), 7);
+        receiver.raise_priority(1);
+        assert_eq!(task.priority(), 7);
+    }
+
+    #[test]
+    fn typed_task_kind() {
+        // Tasks with results other than `()` aren't boxed into `Dyn`
+        let (sender, receiver) = one_shot_channel();
+        let task = TaskKind::from(Async::new(|| 42, Some(Promise::Chan(sender))));
+        assert!(matches!(task, TaskKind::Async(_)));
+        task.run();
+        assert_eq!(Future::Chan(receiver).get(), 42);
+    }
+
     #[test]
     fn simple_task_thread() {
         // Unboxed task + boxed closure
@@ -214,24 +1556,66 @@ mod tests {
 
     #[test]
     fn async_task() {
-        let a = Async::new(Box::new(|| ()), None);
+        let a = Async::new(|| (), None);
         a.run();
         // `a` has been consumed
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn async_future() {
         let (sender, receiver) = one_shot_channel();
-        let a = Async::new(Box::new(|| 3.14), Some(Promise::from(sender)));
+        let a = Async::new(|| 3.14, Some(Promise::from(sender)));
         a.run();
         // `a` has been consumed
         assert_eq!(Future::Chan(receiver).get(), 3.14);
     }
 
     #[test]
+    fn named_task() {
+        let a = Async::new(|| (), None);
+        assert_eq!(format!("{:?}", a), "<Task>");
+        let (sender, _receiver) = one_shot_channel();
+        let a = Async::new(|| 1, Some(Promise::from(sender)));
+        let a = a.with_name("one");
+        assert_eq!(format!("{:?}", a), "<Future one>");
+        assert_eq!(Task::name(&a), Some("one"));
+    }
+
+    #[test]
+    fn task_kinds() {
+        use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+        let sum = Arc::new(AtomicUsize::new(0));
+        let mut range = {
+            let sum = Arc::clone(&sum);
+            RangeTask::new(0..10, move |i| { sum.fetch_add(i, Relaxed); })
+        };
+        let upper = range.split().unwrap();
+        assert_eq!((range.len(), upper.len()), (5, 5));
+
+        let tasks: Vec<TaskKind> = vec![
+            range.into(),
+            upper.into(),
+            Detached::new(|| 1).with_name("one").into(),
+            (Box::new(SimpleTask::new(Box::new(|| ()))) as Box<dyn Task>).into(),
+        ];
+        assert_eq!(format!("{:?}", tasks[0]), "<Range 0..5>");
+        assert_eq!(tasks[2].name(), Some("one"));
+        assert!(matches!(tasks[2], TaskKind::Detached(_)));
+        assert_eq!(format!("{:?}", tasks[2]), "<Task one>");
+        assert_eq!(tasks[0].cost(), 5);
+        for task in tasks {
+            task.run();
+        }
+        assert_eq!(sum.load(Relaxed), 45);
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
     fn async_future_lazy() {
-        let mut f = Future::Lazy(None);
-        let a = Async::new(Box::new(|| 3.14), Some(Promise::from(&mut f)));
+        let mut f = Future::lazy();
+        let a = Async::new(|| 3.14, Some(Promise::from(&mut f)));
         a.run();
         // `a` has been consumed
         assert_eq!(f.get(), 3.14);
@@ -240,7 +1624,7 @@ mod tests {
     #[test]
     fn async_future_thread() {
         let (sender, receiver) = one_shot_channel();
-        let a = Async::new(Box::new(|| "hi"), Some(Promise::from(sender)));
+        let a = Async::new(|| "hi", Some(Promise::from(sender)));
         let t = thread::spawn(|| a.run());
         assert_eq!(Future::Chan(receiver).get(), "hi");
         t.join().unwrap();
@@ -248,8 +1632,8 @@ mod tests {
 
     #[test]
     fn async_future_lazy_thread() {
-        let mut f = Future::Lazy(None);
-        let mut a = Async::new(Box::new(|| "hi"), Some(Promise::from(&mut f)));
+        let mut f = Future::lazy();
+        let mut a = Async::new(|| "hi", Some(Promise::from(&mut f)));
         a.promote();
         let t = thread::spawn(|| a.run());
         t.join().unwrap();
@@ -260,7 +1644,7 @@ mod tests {
     fn scoped_async_task() {
         Scope::init();
         // {
-        let a = ScopedAsync::new(Box::new(|| ()), None);
+        let a = ScopedAsync::new(|| (), None);
         assert_eq!(Scope::current().num_tasks.get(), 1);
         a.run();
         assert_eq!(Scope::current().num_tasks.get(), 0);
@@ -272,7 +1656,7 @@ mod tests {
     fn scoped_async_task_thread() {
         Scope::init();
         // {
-        let mut a = ScopedAsync::new(Box::new(|| ()), None);
+        let mut a = ScopedAsync::new(|| (), None);
         assert_eq!(Scope::current().num_tasks.get(), 1);
         a.promote();
 
@@ -288,4 +1672,91 @@ mod tests {
         // }
         Scope::pop();
     }
+
+    #[test]
+    fn retrying_task() {
+        use crate::runtime::Runtime;
+        use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+
+        let runtime = Runtime::builder()
+            .num_workers(2)
+            .retry_backoff(Duration::from_micros(100), Duration::from_millis(1))
+            .init();
+
+        // Succeeds on the third attempt
+        let attempts = Arc::new(AtomicU32::new(0));
+        let mut f = {
+            let attempts = Arc::clone(&attempts);
+            spawn_retrying!(5, one_shot_channel, {
+                match attempts.fetch_add(1, Relaxed) {
+                    n if n < 2 => Err(n),
+                    n => Ok(n),
+                }
+            })
+        };
+        assert_eq!(f.wait(), Ok(2));
+
+        // Gives up after two retries
+        let mut f = spawn_retrying!(2, one_shot_channel, Err::<(), _>("unavailable"));
+        assert_eq!(f.wait(), Err("unavailable"));
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_task_retries.get(), 4);
+        assert_stat_eq!(stats.num_retries_exhausted.get(), 1);
+        // Retries aren't counted as tasks
+        assert_stat_eq!(stats.num_tasks_created.get(), 2);
+        assert_stat_eq!(stats.num_tasks_executed.get(), 2);
+    }
+
+    #[test]
+    fn retry_backoff() {
+        let (initial, max) = (Duration::from_millis(1), Duration::from_millis(10));
+        let backoffs = (1..=5).map(|retry| backoff(initial, max, retry).as_millis()).collect::<Vec<_>>();
+        assert_eq!(backoffs, vec![1, 2, 4, 8, 10]);
+        assert_eq!(backoff(initial, Duration::MAX, 100), initial * (1 << 31));
+    }
+
+    #[test]
+    fn yielding_task() {
+        use crate::runtime::Runtime;
+
+        let runtime = Runtime::init(2);
+
+        let (sender, receiver) = one_shot_channel();
+        let mut polls = 0;
+        let task = YieldingTask::new(move || {
+            polls += 1;
+            if polls < 5 { Step::Yield } else { Step::Done(polls) }
+        }, Some(Promise::from(sender)));
+        Worker::current().push(Box::new(task));
+        assert_eq!(Future::Chan(receiver).wait(), 5);
+
+        let stats = runtime.join();
+        assert_stat_eq!(stats.num_tasks_executed.get(), 5);
+    }
+
+    #[cfg(feature = "prefetch")]
+    #[test]
+    fn prefetch() {
+        use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = |count: &Arc<AtomicUsize>| {
+            let count = Arc::clone(count);
+            move || { count.fetch_add(1, Relaxed); }
+        };
+        let tasks: Vec<TaskKind> = vec![
+            Async::new(counter(&count), None).into(),
+            Async::detached(counter(&count)).into(),
+            RangeTask::new(0..2, {
+                let count = Arc::clone(&count);
+                move |_| { count.fetch_add(1, Relaxed); }
+            }).into(),
+            TaskKind::from(Box::new(SimpleTask::new(Box::new(counter(&count))))),
+        ];
+        // Prefetching has no effect other than on timing
+        tasks.iter().for_each(TaskKind::prefetch);
+        tasks.into_iter().for_each(TaskKind::run);
+        assert_eq!(count.load(Relaxed), 5);
+    }
 }
@@ -0,0 +1,365 @@
+use crate::task::TaskId;
+use crate::worker::Worker;
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::io::{self, Read, Write};
+use std::panic;
+use std::sync::{Arc, Mutex, Once, Weak};
+use std::time::{Duration, Instant};
+
+// Number of events kept per worker
+pub const TRACE_LEN: usize = 32;
+
+// Scheduler events, recorded per worker in a small ring buffer if enabled
+// (see `Config::trace`). When a task panics on a worker thread, a panic hook
+// dumps the most recent events of all workers of all live runtimes to
+// stderr, to give some context to the crash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    // Started running a task
+    Run { name: Option<&'static str>, id: Option<TaskId> },
+    // Sent a steal request to `victim`
+    Steal { victim: usize },
+    // Responded to a steal request from `thief`
+    Respond { thief: usize, num_tasks: usize },
+}
+
+#[derive(Debug)]
+pub struct Traces {
+    start: Instant,
+    rings: Vec<Mutex<VecDeque<(Instant, Event)>>>,
+}
+
+// Traces of all live runtimes
+static TRACES: Mutex<Vec<Weak<Traces>>> = Mutex::new(Vec::new());
+static HOOK: Once = Once::new();
+
+impl Traces {
+    pub fn new(num_workers: usize) -> Arc<Self> {
+        let traces = Arc::new(Self {
+            start: Instant::now(),
+            rings: (0..num_workers)
+                .map(|_| Mutex::new(VecDeque::with_capacity(TRACE_LEN)))
+                .collect(),
+        });
+        register(&traces);
+        traces
+    }
+
+    // Traces that record nothing, for runtimes that don't trace
+    pub fn disabled() -> Arc<Self> {
+        Arc::new(Self { start: Instant::now(), rings: vec![] })
+    }
+
+    pub fn record(&self, id: usize, event: Event) {
+        // Helper threads (see `runtime::Helper`) have no ring of their own,
+        // and neither does anyone without tracing
+        let mut ring = match self.rings.get(id) {
+            Some(ring) => ring.lock().unwrap(),
+            None => return,
+        };
+        if ring.len() == TRACE_LEN {
+            ring.pop_front();
+        }
+        ring.push_back((Instant::now(), event));
+    }
+
+    // Events of worker `id`, oldest first
+    pub fn events(&self, id: usize) -> Vec<Event> {
+        self.rings[id].lock().unwrap().iter().map(|&(_, e)| e).collect()
+    }
+}
+
+// Binary trace format, written by `Traces::write_to` and read by
+// `read_trace` (see the `trace-view` binary). All integers are little-endian:
+//
+//     magic    b"RTTRACE2"
+//     workers  u32
+//     for every worker:
+//         events  u32
+//         for every event:
+//             time  u64 (nanoseconds since the runtime started)
+//             tag   u8, followed by
+//                   0 (Run):     name length u16 (0xffff: no name), name,
+//                                id flag u8, followed by (if 1) the task id:
+//                                worker u32, sequence number u64
+//                   1 (Steal):   victim u32
+//                   2 (Respond): thief u32, number of tasks u32
+const MAGIC: &[u8; 8] = b"RTTRACE2";
+const NO_NAME: u16 = u16::MAX;
+
+// An event read back from a binary trace, see `read_trace`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recorded {
+    Run { name: Option<String>, id: Option<TaskId> },
+    Steal { victim: usize },
+    Respond { thief: usize, num_tasks: usize },
+}
+
+impl Traces {
+    // Write the recorded events of all workers in the binary trace format
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&(self.rings.len() as u32).to_le_bytes())?;
+        for ring in &self.rings {
+            let ring = ring.lock().unwrap().clone();
+            out.write_all(&(ring.len() as u32).to_le_bytes())?;
+            for (time, event) in ring {
+                let nanos = time.duration_since(self.start).as_nanos() as u64;
+                out.write_all(&nanos.to_le_bytes())?;
+                match event {
+                    Event::Run { name, id } => {
+                        out.write_all(&[0])?;
+                        match name {
+                            Some(name) => {
+                                let name = &name.as_bytes()[..name.len().min(NO_NAME as usize - 1)];
+                                out.write_all(&(name.len() as u16).to_le_bytes())?;
+                                out.write_all(name)?;
+                            }
+                            None => out.write_all(&NO_NAME.to_le_bytes())?,
+                        }
+                        match id {
+                            Some(id) => {
+                                out.write_all(&[1])?;
+                                out.write_all(&(id.worker as u32).to_le_bytes())?;
+                                out.write_all(&id.seq.to_le_bytes())?;
+                            }
+                            None => out.write_all(&[0])?,
+                        }
+                    }
+                    Event::Steal { victim } => {
+                        out.write_all(&[1])?;
+                        out.write_all(&(victim as u32).to_le_bytes())?;
+                    }
+                    Event::Respond { thief, num_tasks } => {
+                        out.write_all(&[2])?;
+                        out.write_all(&(thief as u32).to_le_bytes())?;
+                        out.write_all(&(num_tasks as u32).to_le_bytes())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Read a binary trace, returning the events of every worker, oldest first
+pub fn read_trace(input: &mut impl Read) -> io::Result<Vec<Vec<(Duration, Recorded)>>> {
+    if &read::<8>(input)? != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a trace"));
+    }
+    let num_workers = read_u32(input)?;
+    let mut workers = vec![];
+    for _ in 0..num_workers {
+        let num_events = read_u32(input)?;
+        let mut events = vec![];
+        for _ in 0..num_events {
+            let time = Duration::from_nanos(u64::from_le_bytes(read(input)?));
+            let event = match read::<1>(input)?[0] {
+                0 => {
+                    let name = match u16::from_le_bytes(read(input)?) {
+                        NO_NAME => None,
+                        len => {
+                            let mut name = vec![0; len as usize];
+                            input.read_exact(&mut name)?;
+                            Some(String::from_utf8_lossy(&name).into_owned())
+                        }
+                    };
+                    let id = match read::<1>(input)?[0] {
+                        0 => None,
+                        _ => Some(TaskId {
+                            worker: read_u32(input)? as usize,
+                            seq: u64::from_le_bytes(read(input)?),
+                        }),
+                    };
+                    Recorded::Run { name, id }
+                }
+                1 => Recorded::Steal { victim: read_u32(input)? as usize },
+                2 => Recorded::Respond {
+                    thief: read_u32(input)? as usize,
+                    num_tasks: read_u32(input)? as usize,
+                },
+                tag => {
+                    let msg = format!("Unknown event tag {}", tag);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+                }
+            };
+            events.push((time, event));
+        }
+        workers.push(events);
+    }
+    Ok(workers)
+}
+
+fn read<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    read(input).map(u32::from_le_bytes)
+}
+
+// Render a trace as an HTML page with an SVG timeline: one row per worker,
+// with a bar for every task (lasting until the worker's next event), a dot
+// for every steal request, and a line from victim to thief for every
+// successful steal
+pub fn render_html(workers: &[Vec<(Duration, Recorded)>]) -> String {
+    const WIDTH: f64 = 1000.0;
+    const ROW: f64 = 30.0;
+    const LEFT: f64 = 80.0;
+
+    let times = workers.iter().flatten().map(|&(time, _)| time.as_secs_f64());
+    let start = times.clone().fold(f64::INFINITY, f64::min);
+    let end = times.fold(f64::NEG_INFINITY, f64::max);
+    let span = if end > start { end - start } else { 1.0 };
+    let x = |time: Duration| LEFT + (time.as_secs_f64() - start) / span * WIDTH;
+    let y = |id: usize| id as f64 * ROW + ROW / 2.0;
+
+    let mut svg = String::new();
+    for (id, events) in workers.iter().enumerate() {
+        let _ = write!(svg, "<text x=\"0\" y=\"{:.1}\">Worker {}</text>", y(id) + 5.0, id);
+        for (i, (time, event)) in events.iter().enumerate() {
+            match event {
+                Recorded::Run { name, id: task } => {
+                    let next = events.get(i + 1).map_or(x(*time) + 2.0, |&(t, _)| x(t));
+                    let mut title = escape(name.as_deref().unwrap_or("task"));
+                    if let Some(task) = task {
+                        let _ = write!(title, " ({})", task);
+                    }
+                    let _ = write!(
+                        svg,
+                        "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" \
+                         fill=\"steelblue\"><title>{}</title></rect>",
+                        x(*time), y(id) - ROW / 3.0, (next - x(*time)).max(1.0), ROW * 2.0 / 3.0,
+                        title,
+                    );
+                }
+                Recorded::Steal { victim } => {
+                    let _ = write!(
+                        svg,
+                        "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"3\" fill=\"firebrick\">\
+                         <title>steal from {}</title></circle>",
+                        x(*time), y(id), victim,
+                    );
+                }
+                Recorded::Respond { thief, num_tasks } if *num_tasks > 0 => {
+                    let _ = write!(
+                        svg,
+                        "<line x1=\"{0:.1}\" y1=\"{1:.1}\" x2=\"{0:.1}\" y2=\"{2:.1}\" \
+                         stroke=\"seagreen\"><title>{3} task(s) to {4}</title></line>",
+                        x(*time), y(id), y(*thief), num_tasks, thief,
+                    );
+                }
+                Recorded::Respond { .. } => (),
+            }
+        }
+    }
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Trace</title></head>\n\
+         <body><svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" \
+         font-family=\"sans-serif\" font-size=\"12\">{}</svg></body></html>\n",
+        LEFT + WIDTH + 10.0,
+        workers.len() as f64 * ROW,
+        svg,
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+impl fmt::Display for Traces {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (id, ring) in self.rings.iter().enumerate() {
+            writeln!(f, "Worker {}:", id)?;
+            // Don't block if the panic happened while recording
+            match ring.try_lock() {
+                Ok(ring) => for (time, event) in ring.iter() {
+                    writeln!(f, "  {:>12?} {:?}", time.duration_since(self.start), event)?;
+                },
+                Err(_) => writeln!(f, "  (unavailable)")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn register(traces: &Arc<Traces>) {
+    HOOK.call_once(|| {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            hook(info);
+            // Other threads' panics have nothing to do with the runtime
+            if Worker::is_worker_thread() {
+                dump();
+            }
+        }));
+    });
+    let mut all = TRACES.lock().unwrap_or_else(|e| e.into_inner());
+    all.retain(|t| t.strong_count() > 0);
+    all.push(Arc::downgrade(traces));
+}
+
+fn dump() {
+    let all = match TRACES.try_lock() {
+        Ok(all) => all,
+        Err(_) => return,
+    };
+    for traces in all.iter().filter_map(Weak::upgrade) {
+        eprint!("Recent scheduler events\n{}", traces);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring() {
+        let traces = Traces::new(2);
+        for victim in 0..TRACE_LEN + 2 {
+            traces.record(0, Event::Steal { victim });
+        }
+        traces.record(1, Event::Run { name: None, id: None });
+
+        let events = traces.events(0);
+        assert_eq!(events.len(), TRACE_LEN);
+        assert_eq!(events[0], Event::Steal { victim: 2 });
+        assert_eq!(events[TRACE_LEN - 1], Event::Steal { victim: TRACE_LEN + 1 });
+        assert_eq!(traces.events(1), vec![Event::Run { name: None, id: None }]);
+
+        let dump = traces.to_string();
+        assert!(dump.contains("Worker 1:"));
+        assert!(dump.contains("Run { name: None, id: None }"));
+    }
+
+    #[test]
+    fn binary_format() {
+        let traces = Traces::new(2);
+        let id = TaskId { worker: 1, seq: 3 };
+        traces.record(0, Event::Run { name: Some("fib"), id: Some(id) });
+        traces.record(0, Event::Respond { thief: 1, num_tasks: 2 });
+        traces.record(1, Event::Steal { victim: 0 });
+        traces.record(1, Event::Run { name: None, id: None });
+
+        let mut bytes = vec![];
+        traces.write_to(&mut bytes).unwrap();
+        let workers = read_trace(&mut bytes.as_slice()).unwrap();
+        let events = |id: usize| workers[id].iter().map(|(_, e)| e.clone()).collect::<Vec<_>>();
+        assert_eq!(events(0), vec![
+            Recorded::Run { name: Some(String::from("fib")), id: Some(id) },
+            Recorded::Respond { thief: 1, num_tasks: 2 },
+        ]);
+        assert_eq!(events(1), vec![Recorded::Steal { victim: 0 }, Recorded::Run { name: None, id: None }]);
+        assert!(workers[0][0].0 <= workers[0][1].0);
+        assert!(read_trace(&mut &bytes[1..]).is_err());
+
+        let html = render_html(&workers);
+        assert_eq!(html.matches("<rect").count(), 2);
+        assert_eq!(html.matches("<circle").count(), 1);
+        assert_eq!(html.matches("<line").count(), 1);
+        assert!(html.contains("<title>fib (1.3)</title>"));
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+use std::sync::Arc;
+
+// Deque occupancy watermarks
+//
+// Every worker classifies the number of tasks in its deque as low (below the
+// low watermark), high (above the high watermark), or normal, and whenever
+// that changes, it tells everyone who subscribed to the change. The workers'
+// own adaptive features react to the same signals rather than checking the
+// deque's length against thresholds of their own:
+//
+//     Runtime::builder()
+//         .watermarks(2, 1000)
+//         .steal_many_when_low()   // Refill in batches before running dry
+//         .inline_when_high()      // Stop queueing when there's plenty
+//         .on_watermark(|id, occupancy| eprintln!("Worker {}: {:?}", id, occupancy))
+//
+// Listeners run on the worker whose occupancy changed, so they should be
+// quick. Changes are reported as they happen, not on every push and pop.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Occupancy {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+// Called with the worker's id and its new occupancy
+pub type WatermarkListener = Arc<dyn Fn(usize, Occupancy) + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct Watermarks {
+    // Low and high watermark, if enabled
+    levels: Option<(usize, usize)>,
+    // Steal a batch of tasks (as with `Config::steal_many`) while occupancy
+    // is low
+    pub steal_many_when_low: bool,
+    // Run spawned tasks right away instead of queueing them while occupancy
+    // is high, counted in `Stats::num_tasks_inlined`
+    pub inline_when_high: bool,
+    listeners: Vec<WatermarkListener>,
+}
+
+impl Watermarks {
+    pub fn new(low: usize, high: usize) -> Self {
+        assert!(low <= high);
+        Self { levels: Some((low, high)), ..Self::default() }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.levels.is_some()
+    }
+
+    // Set both watermarks, keeping listeners and reactions
+    pub fn set_levels(&mut self, low: usize, high: usize) {
+        assert!(low <= high);
+        self.levels = Some((low, high));
+    }
+
+    pub fn subscribe(&mut self, listener: WatermarkListener) {
+        self.listeners.push(listener);
+    }
+
+    // Classify a deque of length `len`
+    pub fn occupancy(&self, len: usize) -> Occupancy {
+        match self.levels {
+            Some((low, _)) if len < low => Occupancy::Low,
+            Some((_, high)) if len > high => Occupancy::High,
+            _ => Occupancy::Normal,
+        }
+    }
+
+    pub fn notify(&self, worker: usize, occupancy: Occupancy) {
+        for listener in &self.listeners {
+            listener(worker, occupancy);
+        }
+    }
+}
+
+impl fmt::Debug for Watermarks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watermarks")
+            .field("levels", &self.levels)
+            .field("steal_many_when_low", &self.steal_many_when_low)
+            .field("inline_when_high", &self.inline_when_high)
+            .field("listeners", &self.listeners.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn occupancy() {
+        let watermarks = Watermarks::new(2, 4);
+        let levels: Vec<_> = (0..6).map(|len| watermarks.occupancy(len)).collect();
+        use Occupancy::*;
+        assert_eq!(levels, [Low, Low, Normal, Normal, Normal, High]);
+        assert_eq!(Watermarks::default().occupancy(0), Normal);
+    }
+
+    #[test]
+    fn notify() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let mut watermarks = Watermarks::new(1, 2);
+        for _ in 0..2 {
+            let seen = Arc::clone(&seen);
+            watermarks.subscribe(Arc::new(move |id, occupancy| seen.lock().unwrap().push((id, occupancy))));
+        }
+        watermarks.notify(3, Occupancy::High);
+        assert_eq!(*seen.lock().unwrap(), [(3, Occupancy::High); 2]);
+    }
+}
@@ -1,230 +1,2301 @@
+use crate::affinity::Family;
+use crate::atomic;
+use crate::batch::Batch;
+use crate::channel::{self, one_shot_channel};
+use crate::cost::CostModel;
 use crate::deque::*;
+use crate::future::Completions;
+use crate::quiescence;
+use crate::runtime::{Admission, Config, CoreClass, EmptyDequeResponse, PollInterval, PoolLoad, PanicPolicy, PopOrder, RequestServicing, SchedulerConfig, Shared, Snapshot, StealPolicy, StealRequestOverflow, TaskStatus};
+use crate::source::TaskSource;
 use crate::stats::*;
 use crate::task::*;
+use crate::trace::Event;
+use crate::watermark::Occupancy;
+#[cfg(feature = "rand")]
 use rand::Rng;
-use std::cell::RefCell;
-use std::sync::mpsc::{channel, Sender, Receiver};
+use std::cmp::Ordering;
+use std::cell::{Cell, RefCell};
+use std::collections::{LinkedList, VecDeque};
+use std::rc::Rc;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread::{self, Thread};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{fence, AtomicBool, AtomicU64, Ordering::{Acquire, Relaxed, Release, SeqCst}};
+use std::sync::mpsc::{channel, sync_channel, Sender, SyncSender, Receiver, TrySendError};
+use std::time::{Duration, Instant};
+
+// How many times to poll for a steal response before blocking on the channel
+const SPIN_ATTEMPTS: u32 = 100;
+
+// How long to block on the channel before checking for incoming steal
+// requests again
+const RECV_TIMEOUT: Duration = Duration::from_micros(100);
+
+// How many stolen tasks to prefetch when a batch arrives, see `accept`
+#[cfg(feature = "prefetch")]
+const PREFETCH_TASKS: usize = 4;
 
 #[derive(Debug)]
 pub struct StealRequest {
     thief: usize,
     steal_many: bool,
-    response: Sender<Tasks>,
+    // Number of tasks the thief has queued already, which count toward its
+    // share of a steal-many response
+    load: usize,
+    // Number of tasks the thief would like to receive (0 if it doesn't care),
+    // based on its recent throughput
+    desired: usize,
+    // Whether the thief is blocked on a result it needs to make progress,
+    // see `Worker::help_until_urgent`. Victims answer urgent requests first.
+    urgent: bool,
+    response: channel::Sender<Tasks>,
 }
 
 // Possible responses to a steal request
 pub enum Tasks {
     None,
-    One(Box<dyn Task>),
+    One(TaskKind),
     Many(TaskDeque),
+    // Nothing yet, but the victim is busy producing more, see
+    // `EmptyDequeResponse::Later`
+    Later,
     Exit,
 }
 
-type TaskDeque = Deque<Box<dyn Task>>;
+type TaskDeque = Deque<TaskKind>;
+
+// Approximate number of queued and running tasks of a worker, maintained by
+// the worker and readable by anyone holding its `Coworker` handle
+#[derive(Debug)]
+pub struct Load {
+    queued: atomic::Count,
+    // Greater than one if tasks are nested, e.g., because a task waits for a
+    // future and executes other tasks in the meantime
+    running: atomic::Count,
+    // Tasks pushed by this worker
+    created: atomic::Count,
+    // Tasks run to completion by this worker
+    completed: atomic::Count,
+    // Steal requests queued for this worker
+    pending_requests: atomic::Count,
+    // Task ids handed out by this worker, see `TaskId`
+    issued: AtomicU64,
+    // The worker's thread while it is parked, see `Worker::park`
+    sleeper: Mutex<Option<Thread>>,
+    // Whether `sleeper` is set, so that waking up a worker that isn't parked
+    // (the common case) doesn't take the lock
+    parked: AtomicBool,
+    // Set once the worker has been asked to retire, see `Worker::retire`
+    retired: AtomicBool,
+    // Nesting level of the worker's current scope
+    #[cfg(feature = "debug-server")]
+    scope_level: atomic::Count,
+}
+
+impl quiescence::Counts for Load {
+    fn created(&self) -> u32 {
+        self.created()
+    }
+
+    fn completed(&self) -> u32 {
+        self.completed()
+    }
+}
+
+impl Load {
+    pub fn new() -> Self {
+        Self {
+            queued: atomic::Count::new(0),
+            running: atomic::Count::new(0),
+            created: atomic::Count::new(0),
+            completed: atomic::Count::new(0),
+            pending_requests: atomic::Count::new(0),
+            issued: AtomicU64::new(0),
+            sleeper: Mutex::new(None),
+            parked: AtomicBool::new(false),
+            retired: AtomicBool::new(false),
+            #[cfg(feature = "debug-server")]
+            scope_level: atomic::Count::new(0),
+        }
+    }
+
+    pub fn queued(&self) -> u32 {
+        self.queued.get()
+    }
+
+    pub fn running(&self) -> u32 {
+        self.running.get()
+    }
+
+    pub fn created(&self) -> u32 {
+        self.created.get()
+    }
+
+    // See `quiescence::Counts`
+    pub fn completed(&self) -> u32 {
+        self.completed.get_acquire()
+    }
+
+    // Count a task created outside of any worker, see `RuntimeHandle`
+    pub fn count_created(&self) {
+        self.created.inc();
+    }
+
+    pub fn uncount_created(&self) {
+        self.created.dec();
+    }
+
+    pub fn pending_requests(&self) -> u32 {
+        self.pending_requests.get()
+    }
+
+    // Number of task ids handed out so far, which are numbered from zero
+    pub fn issued(&self) -> u64 {
+        self.issued.load(Relaxed)
+    }
+
+    fn issue(&self) -> u64 {
+        self.issued.fetch_add(1, Relaxed)
+    }
+
+    #[cfg(feature = "debug-server")]
+    pub fn scope_level(&self) -> u32 {
+        self.scope_level.get()
+    }
+
+    #[cfg(feature = "debug-server")]
+    pub fn set_scope_level(&self, level: u32) {
+        self.scope_level.set(level);
+    }
+
+    pub fn is_retired(&self) -> bool {
+        self.retired.load(Acquire)
+    }
+
+    // Ask the worker to retire, waking it up if necessary
+    pub fn retire(&self) {
+        self.retired.store(true, Release);
+        self.unpark();
+    }
+
+    pub fn is_parked(&self) -> bool {
+        self.parked.load(Relaxed)
+    }
+
+    // Register the current thread as the worker's sleeper. The fence pairs
+    // with the one in `unpark`: either the waker sees the worker parked, or
+    // the worker sees whatever the waker sent before (and checks for it
+    // again before parking).
+    fn register_sleeper(&self) {
+        *self.sleeper.lock().unwrap() = Some(thread::current());
+        self.parked.store(true, Relaxed);
+        fence(SeqCst);
+    }
+
+    fn unregister_sleeper(&self) {
+        *self.sleeper.lock().unwrap() = None;
+        self.parked.store(false, Relaxed);
+    }
+
+    // Wake up the worker if it is parked. Returns whether it was.
+    pub fn unpark(&self) -> bool {
+        fence(SeqCst);
+        if !self.parked.load(Relaxed) {
+            return false;
+        }
+        match self.sleeper.lock().unwrap().take() {
+            Some(thread) => {
+                thread.unpark();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// The receiving ends of a worker's channels
+#[derive(Debug)]
+pub struct Inbox {
+    steal_requests: Receiver<StealRequest>,
+    urgent_steal_requests: Receiver<StealRequest>,
+    // Tasks pushed to this worker by other workers
+    injected: Receiver<TaskKind>,
+}
+
+// Create channels for `N` workers. Every worker can queue up to `N` steal
+// requests, enough for one request from every other worker.
+pub fn connect(num_workers: usize) -> (Vec<Inbox>, Vec<Coworker>) {
+    connect_with_capacity(num_workers, num_workers)
+}
+
+// Like `connect`, but queue at most `capacity` steal requests per worker, see
+// `StealRequestOverflow`
+pub fn connect_with_capacity(num_workers: usize, capacity: usize) -> (Vec<Inbox>, Vec<Coworker>) {
+    assert!(capacity > 0);
+    let mut inboxes = Vec::with_capacity(num_workers);
+    let mut coworkers = Vec::with_capacity(num_workers);
+
+    for id in 0..num_workers {
+        let (steal_requests_tx, steal_requests) = sync_channel(capacity);
+        let (urgent_steal_requests_tx, urgent_steal_requests) = sync_channel(capacity);
+        let (injected_tx, injected) = channel();
+        inboxes.push(Inbox { steal_requests, urgent_steal_requests, injected });
+        coworkers.push(Coworker::new(id, steal_requests_tx, urgent_steal_requests_tx, injected_tx));
+    }
+
+    (inboxes, coworkers)
+}
 
 struct WorkerChannels {
     steal_requests: Receiver<StealRequest>,
-    tasks: (Sender<Tasks>, Receiver<Tasks>),
+    urgent_steal_requests: Receiver<StealRequest>,
+    injected: Receiver<TaskKind>,
 }
 
+impl From<Inbox> for WorkerChannels {
+    fn from(inbox: Inbox) -> Self {
+        Self {
+            steal_requests: inbox.steal_requests,
+            urgent_steal_requests: inbox.urgent_steal_requests,
+            injected: inbox.injected,
+        }
+    }
+}
+
+impl From<WorkerChannels> for Inbox {
+    fn from(channels: WorkerChannels) -> Self {
+        Self {
+            steal_requests: channels.steal_requests,
+            urgent_steal_requests: channels.urgent_steal_requests,
+            injected: channels.injected,
+        }
+    }
+}
+
+// A task that never leaves its worker, see `local::spawn_local`
+pub type LocalTask = Box<dyn FnOnce()>;
+
 pub struct Worker {
     pub id: usize,
     deque: RefCell<TaskDeque>,
+    // Tasks that can't be stolen, run when the deque is empty
+    local: RefCell<Vec<LocalTask>>,
+    // Low-priority tasks that never leave this worker, see `push_background`
+    background: RefCell<VecDeque<TaskKind>>,
+    // Tasks waiting for a point in time, see `defer_until`
+    timers: RefCell<Vec<(Instant, TaskKind)>>,
+    // Results of tasks that haven't left this worker, see `Completions`
+    completions: Rc<RefCell<Completions>>,
+    load: Arc<Load>,
     channels: WorkerChannels,
+    // Channels of retired workers, which we answer in their place, see
+    // `adopt_orphans`
+    adopted: RefCell<Vec<WorkerChannels>>,
     coworkers: Vec<Coworker>,
-    children: Vec<Sender<Tasks>>,
+    // Indices into `coworkers` of potential victims (same partition), one
+    // list per locality group, ordered from the closest group to all victims
+    victims: Vec<Vec<usize>>,
+    // Indices into `coworkers` of workers on performance cores, if we run
+    // on an efficiency core, see `CoreClass`
+    fast_workers: Vec<usize>,
+    // Consecutive steal attempts that came back empty-handed
+    failed_steals: Cell<u32>,
+    // Per coworker, consecutive empty-handed responses and the steal attempt
+    // from which on it is a candidate again, see `Config::blacklist_after`
+    victim_failures: Vec<Cell<u32>>,
+    blacklisted_until: Vec<Cell<u64>>,
+    steal_attempts: Cell<u64>,
+    // Index into `coworkers` of the victim of the pending steal request
+    last_victim: Cell<Option<usize>>,
+    // Index into `coworkers` of the victim that promised future work, and
+    // how many promises in a row we have followed, see `Tasks::Later`
+    promised: Cell<Option<usize>>,
+    promises_followed: Cell<u32>,
+    // Number of tasks executed between the last two steal requests
+    throughput: Cell<u32>,
+    // Consecutive tasks popped from the local end, see `pop_fair`
+    lifo_streak: Cell<u32>,
+    // Tasks executed and time of the last check for steal requests, and how
+    // often the poll interval has been doubled, see `Config::poll_interval`
+    unpolled: Cell<u32>,
+    polled: Cell<Instant>,
+    poll_backoff: Cell<u32>,
+    // Round-robin counter replacing random numbers, see `pick`
+    #[cfg(not(feature = "rand"))]
+    next_pick: Cell<usize>,
+    // Set when `Tasks::Exit` was received outside of `go`
+    exit: Cell<bool>,
+    // Value of `Shared::stops` when this worker was started; any other value
+    // means we have been asked to stop
+    epoch: Cell<u32>,
+    // Measured run times of named tasks
+    costs: CostModel,
+    shared: Arc<Shared>,
+    created: Cell<Instant>,
+    // This worker's copy of the scheduling parameters, and the number of
+    // reconfigurations it has seen, see `refresh_scheduler`
+    scheduler: Cell<SchedulerConfig>,
+    reconfigurations: Cell<u32>,
+    // Priority of the task currently running, see `Future::wait`
+    current_priority: Cell<u32>,
+    // Nesting depth of urgent waits, see `help_until_urgent`
+    urgent_waits: Cell<u32>,
+    // Don't wait for busy victims past this point, see `help_until_deadline`
+    deadline: Cell<Option<Instant>>,
+    // Occupancy of the deque as of the last `update_load`, see `watermark`
+    occupancy: Cell<Occupancy>,
+    // Response to a steal request sent before our deque ran dry, see
+    // `steal_early`
+    early_steal: RefCell<Option<channel::Receiver<Tasks>>>,
+    // Set when a task buffered a result since we last flushed our buffers,
+    // see `buffered_channel`
+    buffered: Cell<bool>,
+    // See `current_task` and `last_spawned`
+    current_task: Cell<Option<TaskId>>,
+    // See `task_context`
+    current_context: Cell<Option<TaskContext>>,
+    last_spawned: Cell<Option<TaskId>>,
+    // Time the current task has spent running other tasks and waiting for
+    // work, see `Stats::task_work`
+    nested_time: Cell<Duration>,
+    // Number of tasks started so far, to tell tasks apart in spans
+    #[cfg(feature = "tracing")]
+    num_runs: Cell<u64>,
+    // Thread CPU time when this worker was created, see `Stats::cpu_time`
+    cpu_created: Cell<Option<Duration>>,
+    // Start of the current streak of unsuccessful steal attempts, see
+    // `Stats::max_idle_streak`
+    idle_since: Cell<Option<Instant>>,
+    // When statistics were last published, see `Config::report_interval`
+    published: Cell<Instant>,
+    published_epoch: Cell<u32>,
     pub stats: Stats,
 }
 
 thread_local! {
-    static WORKER: RefCell<Option<Worker>> = RefCell::new(None);
+    // One worker per runtime this thread participates in; the most recently
+    // created runtime's worker is the current worker. Like `SCOPE`, we use a
+    // linked list to avoid invalidating references returned by
+    // Worker::current()
+    static WORKER: RefCell<LinkedList<Worker>> = const { RefCell::new(LinkedList::new()) };
 }
 
 impl Worker {
-    pub fn new(
+    pub fn new(id: usize, inbox: Inbox, coworkers: Vec<Coworker>) -> Self {
+        let shared = Arc::new(Shared::new(Config::new(coworkers.len())));
+        Self::with_shared(id, inbox, coworkers, shared)
+    }
+
+    pub fn with_shared(
         id: usize,
-        steal_requests: Receiver<StealRequest>,
-        coworkers: Vec<Coworker>
+        inbox: Inbox,
+        coworkers: Vec<Coworker>,
+        shared: Arc<Shared>
     ) -> Self {
-        let mut worker = Self {
-            id,
-            deque: RefCell::new(Deque::new()),
-            channels: WorkerChannels { steal_requests, tasks: channel() },
-            coworkers: coworkers.into_iter().filter(|c| c.id != id).collect(),
-            children: vec![],
-            stats: Stats::new(),
+        let config = &shared.config;
+        let partition = config.partition_of(id);
+        let load = Arc::clone(&coworkers.iter().find(|c| c.id == id).unwrap().load);
+        let coworkers: Vec<Coworker> = coworkers
+            .into_iter()
+            .filter(|c| c.id != id)
+            .collect();
+        // Coworkers are ordered by id, with a gap for us, see `coworker_index`
+        assert!(coworkers.iter().enumerate().all(|(i, c)| c.id == i + (i >= id) as usize));
+        let victims_in_group = |size: usize| -> Vec<usize> {
+            coworkers
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| config.partition_of(c.id) == partition)
+                .filter(|(_, c)| c.id / size == id / size)
+                .map(|(i, _)| i)
+                .collect()
+        };
+        let mut victims = config.locality_groups
+            .iter()
+            .map(|&size| victims_in_group(size))
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>();
+        victims.push(victims_in_group(usize::MAX));
+        victims.dedup();
+        let fast_workers = match config.core_class(id) {
+            CoreClass::Performance => vec![],
+            CoreClass::Efficiency => coworkers
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| config.core_class(c.id) == CoreClass::Performance)
+                .map(|(i, _)| i)
+                .collect(),
         };
 
-        if id > 0 {
-            // Determine parent and send a dummy steal request
-            let parent_id = (id - 1) / 2;
-            let parent = &worker.coworkers[parent_id];
-            parent.send_steal_request(StealRequest {
-                thief: id,
-                steal_many: false,
-                response: worker.channels.tasks.0.clone(),
-            });
+        Self {
+            id,
+            deque: RefCell::new(Deque::new()),
+            local: RefCell::new(vec![]),
+            background: RefCell::new(VecDeque::new()),
+            timers: RefCell::new(vec![]),
+            completions: Rc::default(),
+            load,
+            channels: WorkerChannels::from(inbox),
+            adopted: RefCell::new(vec![]),
+            victim_failures: coworkers.iter().map(|_| Cell::new(0)).collect(),
+            blacklisted_until: coworkers.iter().map(|_| Cell::new(0)).collect(),
+            steal_attempts: Cell::new(0),
+            last_victim: Cell::new(None),
+            promised: Cell::new(None),
+            promises_followed: Cell::new(0),
+            coworkers,
+            victims,
+            fast_workers,
+            failed_steals: Cell::new(0),
+            throughput: Cell::new(0),
+            lifo_streak: Cell::new(0),
+            unpolled: Cell::new(0),
+            polled: Cell::new(Instant::now()),
+            poll_backoff: Cell::new(0),
+            #[cfg(not(feature = "rand"))]
+            next_pick: Cell::new(id),
+            exit: Cell::new(false),
+            epoch: Cell::new(shared.stops()),
+            scheduler: Cell::new(shared.scheduler()),
+            reconfigurations: Cell::new(shared.reconfigurations()),
+            costs: CostModel::new(),
+            shared,
+            idle_since: Cell::new(None),
+            created: Cell::new(Instant::now()),
+            cpu_created: Cell::new(thread_cpu_time()),
+            current_priority: Cell::new(0),
+            urgent_waits: Cell::new(0),
+            deadline: Cell::new(None),
+            occupancy: Cell::new(Occupancy::Normal),
+            early_steal: RefCell::new(None),
+            buffered: Cell::new(false),
+            current_task: Cell::new(None),
+            current_context: Cell::new(None),
+            last_spawned: Cell::new(None),
+            nested_time: Cell::new(Duration::ZERO),
+            #[cfg(feature = "tracing")]
+            num_runs: Cell::new(0),
+            published: Cell::new(Instant::now()),
+            published_epoch: Cell::new(0),
+            stats: Stats::with_id(id),
         }
+    }
 
-        // Receive dummy steal requests from children
-        let num_workers = worker.coworkers.len() + 1;
-        let children = (2 * id + 1, 2 * id + 2);
-        if children.0 < num_workers {
-            let req = worker.channels.steal_requests.recv().unwrap();
-            worker.children.push(req.response);
-            if children.1 < num_workers {
-                let req = worker.channels.steal_requests.recv().unwrap();
-                worker.children.push(req.response);
-            }
-        }
+    // A temporary worker for an application thread, see `runtime::Helper`.
+    // Helpers take ids after the last worker, one each, and steal from all
+    // other workers, but nobody steals from them. They share one load, so
+    // that workers can tell whether the runtime is quiescent without
+    // knowing about every helper.
+    pub fn helper(mut coworkers: Vec<Coworker>, shared: Arc<Shared>) -> Self {
+        let id = shared.config.num_workers() + shared.num_helpers.inc() as usize;
+        let (steal_requests_tx, steal_requests) = sync_channel(1);
+        let (urgent_steal_requests_tx, urgent_steal_requests) = sync_channel(1);
+        let (injected_tx, injected) = channel();
+        coworkers.push(Coworker {
+            id,
+            steal_requests: steal_requests_tx,
+            urgent_steal_requests: urgent_steal_requests_tx,
+            injected: injected_tx,
+            load: Arc::clone(&shared.helper_load),
+        });
+        let inbox = Inbox { steal_requests, urgent_steal_requests, injected };
+        Self::with_shared(id, inbox, coworkers, shared)
+    }
+
+    pub fn is_helper(&self) -> bool {
+        self.id >= self.shared.config.num_workers()
+    }
 
-        worker
+    // Help until `until` returns true and all tasks taken on in the meantime
+    // have been run, since nobody else can get at a helper's tasks
+    pub fn help_and_leave(&self, until: impl Fn() -> bool) {
+        self.help_until(|| {
+            until() && !self.has_tasks() && self.local.borrow().is_empty() && self.background.borrow().is_empty()
+                && self.timers.borrow().is_empty()
+        });
     }
 
     // Move stack-allocated worker to thread-local storage
     pub fn make_current(self) {
         WORKER.with(|worker| {
             let mut worker = worker.borrow_mut();
-            *worker = Some(self);
+            worker.push_front(self);
         });
     }
 
-    // Get a handle to the current worker
+    // Remove the current worker from thread-local storage, making the
+    // previous worker (if any) current again
+    pub fn release_current() -> Option<Self> {
+        WORKER.with(|worker| {
+            let mut worker = worker.borrow_mut();
+            worker.pop_front()
+        })
+    }
+
+    // Remove `worker` from thread-local storage, wherever it is, for runtimes
+    // that are shut down out of order (see `Runtime::join`). Returns the
+    // worker and how many workers were added after it.
+    pub fn release_worker(worker: *const Worker) -> Option<(Self, usize)> {
+        WORKER.with(|workers| {
+            let mut workers = workers.borrow_mut();
+            let index = workers.iter().position(|w| std::ptr::eq(w, worker))?;
+            // Relinking doesn't move the other workers
+            let mut rest = workers.split_off(index);
+            let released = rest.pop_front();
+            workers.append(&mut rest);
+            released.map(|w| (w, index))
+        })
+    }
+
+    // Get a handle to the current worker. Panics if the calling thread
+    // doesn't participate in a runtime.
     pub fn current<'a>() -> &'a Self {
+        Self::try_current().expect("No current worker (is there a runtime?)")
+    }
+
+    // Like `current`, but returns `None` instead of panicking
+    pub fn try_current<'a>() -> Option<&'a Self> {
         WORKER.with(|worker| {
             // Any attempt of trying to borrow from `worker` and have it
             // outlive the closure will fail -> `unsafe` to the rescue
             // (1) Get a raw pointer to thread-local `WORKER`
-            let ptr = match worker.borrow().as_ref() {
-                Some(worker) => worker as *const Self,
-                None => std::ptr::null(),
-            };
+            let ptr = worker.borrow().front().map(|worker| worker as *const Self);
             // (2) Convert this pointer to a borrowed reference
-            unsafe { &*ptr }
+            ptr.map(|ptr| unsafe { &*ptr })
         })
     }
 
-    pub fn select_victim(&self, id: usize) -> Option<&Coworker> {
-        self.coworkers.iter().find(|&c| c.id == id)
+    // Does the calling thread participate in a runtime? Unlike `try_current`,
+    // safe to call from anywhere, including panic hooks.
+    pub fn is_worker_thread() -> bool {
+        WORKER
+            .try_with(|worker| worker.try_borrow().is_ok_and(|worker| !worker.is_empty()))
+            .unwrap_or(false)
+    }
+
+    pub fn coworker(&self, id: usize) -> Option<&Coworker> {
+        self.coworker_index(id).map(|i| &self.coworkers[i])
+    }
+
+    // Index into `coworkers` of the worker with the given id. Coworkers are
+    // ordered by id and skip our own.
+    fn coworker_index(&self, id: usize) -> Option<usize> {
+        match id.cmp(&self.id) {
+            Ordering::Less => Some(id),
+            Ordering::Equal => None,
+            Ordering::Greater => Some(id - 1).filter(|&i| i < self.coworkers.len()),
+        }
+    }
+
+    pub fn random_coworker(&self) -> Option<&Coworker> {
+        match self.coworkers.len() {
+            0 => None,
+            n => Some(&self.coworkers[self.pick(n)]),
+        }
+    }
+
+    // Coworkers that share the locality group of the given level with us,
+    // regardless of partition, see `Builder::locality_groups`. Levels beyond
+    // the widest group include all coworkers.
+    pub fn coworkers_in_group(&self, level: usize) -> impl Iterator<Item = &Coworker> {
+        let n = self.coworkers.len() + 1;
+        let size = self.shared.config.locality_groups.get(level).copied().unwrap_or(n);
+        let start = self.id / size * size;
+        (start..n.min(start + size)).filter_map(|id| self.coworker(id))
+    }
+
+    // Ids of the workers we currently consider stealing from. The more steal
+    // attempts have failed, the wider the locality group.
+    pub fn victim_candidates(&self) -> Vec<usize> {
+        self.victims[self.locality_level()]
+            .iter()
+            .map(|&i| self.coworkers[i].id)
+            .collect()
+    }
+
+    // Whether we leave the worker with the given id out of our next steal
+    // attempt, see `Config::blacklist_after`
+    pub fn is_blacklisted(&self, id: usize) -> bool {
+        let next = self.steal_attempts.get() + 1;
+        self.coworker_index(id).is_some_and(|i| self.blacklisted(i, next))
+    }
+
+    fn blacklisted(&self, index: usize, attempt: u64) -> bool {
+        self.blacklisted_until[index].get() > attempt
+    }
+
+    // Book-keeping after a steal attempt came back empty-handed
+    fn steal_failed(&self, start: Instant) {
+        self.promises_followed.set(0);
+        self.failed_steals.set(self.failed_steals.get().saturating_add(1));
+        self.record_steal_response(false);
+        if self.idle_since.get().is_none() {
+            self.idle_since.set(Some(start));
+        }
+    }
+
+    // Time spent waiting for steal responses or idling in between, see
+    // `Stats::idle_time`. It doesn't count as work of the task we're
+    // waiting in, if any.
+    fn add_idle_time(&self, idle: Duration) {
+        self.stats.idle_time.add(idle);
+        if self.shared.config.track_tasks {
+            self.nested_time.set(self.nested_time.get() + idle);
+        }
+    }
+
+    // See `Stats::max_idle_streak`
+    fn end_idle_streak(&self) {
+        if let Some(since) = self.idle_since.take() {
+            self.stats.max_idle_streak.max(since.elapsed());
+        }
+    }
+
+    // Wait a while for a victim that promised tasks (see `Tasks::Later`) to
+    // queue some, or to finish what it's running, rather than sending it
+    // another steal request right away
+    fn await_promise(&self, index: usize) {
+        let victim = &self.coworkers[index].load;
+        for attempt in 0..SPIN_ATTEMPTS {
+            if victim.queued() > 0 || victim.running() == 0 || self.stop_requested() || self.has_tasks() {
+                return;
+            }
+            self.try_handle_steal_request();
+            self.idle(attempt);
+        }
+    }
+
+    // Keep track of how the victim of the last steal request responded
+    fn record_steal_response(&self, stolen: bool) {
+        let Some(index) = self.last_victim.take() else { return };
+        let failures = &self.victim_failures[index];
+        if stolen {
+            failures.set(0);
+            return;
+        }
+        failures.set(failures.get().saturating_add(1));
+        let config = &self.shared.config;
+        if config.blacklist_after.is_some_and(|after| failures.get() >= after) {
+            failures.set(0);
+            let until = self.steal_attempts.get() + config.blacklist_for as u64 + 1;
+            self.blacklisted_until[index].set(until);
+            self.stats.num_blacklisted_victims.inc();
+        }
+    }
+
+    fn locality_level(&self) -> usize {
+        let level = self.failed_steals.get() / self.scheduler().attempts_per_group;
+        (level as usize).min(self.victims.len() - 1)
     }
 
-    // Send steal request to random worker != self
+    // Pick an index below `n` at random
+    #[cfg(feature = "rand")]
+    fn pick(&self, n: usize) -> usize {
+        rand::thread_rng().gen_range(0, n)
+    }
+
+    // Without `rand`, cycle through all indices below `n`, starting from an
+    // offset that depends on our id, for reproducible scheduling
+    #[cfg(not(feature = "rand"))]
+    fn pick(&self, n: usize) -> usize {
+        let next = self.next_pick.get();
+        self.next_pick.set(next.wrapping_add(1));
+        next % n
+    }
+
+    // Send steal request to random worker != self in the same partition,
+    // preferring workers in the same locality group
     pub fn send_steal_request(&self, req: StealRequest) {
-        let rand_idx: usize = rand::thread_rng().gen_range(0, self.coworkers.len());
-        let victim = &self.coworkers[rand_idx];
-        victim.send_steal_request(req);
+        self.last_victim.set(None);
+        let victims = &self.victims[self.locality_level()];
+        if victims.is_empty() {
+            // Nobody to steal from
+            req.response.send(Tasks::None);
+            return;
+        }
+        let attempt = self.steal_attempts.get() + 1;
+        self.steal_attempts.set(attempt);
+        let mut req = req;
+        // Go back to a victim that promised more tasks, see `Tasks::Later`
+        if let Some(index) = self.promised.take() {
+            let victim = &self.coworkers[index];
+            if !victim.load.is_retired() {
+                self.shared.traces.record(self.id, Event::Steal { victim: victim.id });
+                match victim.send_steal_request(req) {
+                    Ok(()) => {
+                        self.last_victim.set(Some(index));
+                        return;
+                    }
+                    Err(r) => req = r,
+                }
+            }
+        }
+        let skip_blacklisted = victims.iter().any(|&v| !self.blacklisted(v, attempt));
+        let first = self.pick(victims.len());
+        for i in 0..victims.len() {
+            let index = victims[(first + i) % victims.len()];
+            if skip_blacklisted && self.blacklisted(index, attempt) {
+                continue;
+            }
+            let victim = &self.coworkers[index];
+            if victim.load.is_retired() {
+                continue;
+            }
+            self.shared.traces.record(self.id, Event::Steal { victim: victim.id });
+            match victim.send_steal_request(req) {
+                Ok(()) => {
+                    self.last_victim.set(Some(index));
+                    return;
+                }
+                // The victim is busy; try the next one, or give up
+                Err(r) => req = r,
+            }
+            if self.shared.config.steal_request_overflow == StealRequestOverflow::Shed {
+                break;
+            }
+        }
+        self.stats.num_shed_requests.inc();
+        req.response.send(Tasks::None);
     }
 
-    pub fn steal_one(&self) -> StealResponse {
+    fn request_tasks(&self, steal_many: bool) -> StealResponse<'_> {
+        // Only one steal request at a time: wait for the one we sent early
+        if let Some(tasks) = self.early_steal.take() {
+            return StealResponse { worker: self, tasks };
+        }
+        let (response, tasks) = one_shot_channel();
+        // Once we have been asked to stop, there is nothing left to steal
+        if self.stop_requested() {
+            response.send(Tasks::Exit);
+            return StealResponse { worker: self, tasks };
+        }
+        // Tasks pushed to us take precedence over stealing
+        if let Ok(task) = self.channels.injected.try_recv() {
+            response.send(Tasks::One(task));
+            return StealResponse { worker: self, tasks };
+        }
         self.send_steal_request(StealRequest {
             thief: self.id,
-            steal_many: false,
-            response: self.channels.tasks.0.clone(),
+            steal_many,
+            load: self.deque.borrow().len(),
+            desired: self.throughput.get() as usize,
+            urgent: self.urgent_waits.get() > 0,
+            response,
         });
-        StealResponse(&self.channels.tasks.1)
+        self.stats.num_steal_requests.inc();
+        StealResponse { worker: self, tasks }
     }
 
-    pub fn steal_many(&self) -> StealResponse {
-        self.send_steal_request(StealRequest {
-            thief: self.id,
-            steal_many: true,
-            response: self.channels.tasks.0.clone(),
-        });
-        StealResponse(&self.channels.tasks.1)
+    pub fn steal_one(&self) -> StealResponse<'_> {
+        self.request_tasks(false)
+    }
+
+    pub fn steal_many(&self) -> StealResponse<'_> {
+        self.request_tasks(true)
+    }
+
+    pub fn partition(&self) -> &str {
+        self.shared.config.partition_name(self.shared.config.partition_of(self.id))
+    }
+
+    // Push a task to a random worker of the given partition. The task is
+    // promoted if it leaves the current worker.
+    pub fn push_to(&self, partition: &str, task: impl Into<TaskKind>) {
+        let workers = self.shared.config.partition_workers(partition)
+            .unwrap_or_else(|| panic!("Unknown partition {:?}", partition));
+        let id = workers.start + self.pick(workers.len());
+        self.push_to_worker(id, task);
+    }
+
+    // Push a task to the given worker, bypassing stealing. The task is
+    // promoted if it leaves the current worker.
+    pub fn push_to_worker(&self, id: usize, task: impl Into<TaskKind>) {
+        let mut task = task.into();
+        if id == self.id {
+            self.push(task);
+        } else if self.shared.config.queue_capacity
+            .is_some_and(|capacity| self.coworker(id).unwrap().load.queued() as usize >= capacity) {
+            // Like `queue` does when our own queue is full
+            self.run_inline(task);
+        } else {
+            task.promote();
+            self.load.created.inc();
+            self.count_task(&mut task);
+            self.track(&task, TaskStatus::Queued { worker: id });
+            self.coworker(id).unwrap().inject(task);
+        }
+    }
+
+    // Push the next activation of a recurring task family to the worker that
+    // ran the last one, unless that worker is too busy, in which case the
+    // activation migrates here, see `Family`
+    pub fn push_to_family(&self, family: &Family, task: impl Into<TaskKind>) {
+        if let Some(last) = family.last_worker().filter(|&id| id != self.id) {
+            if let Some(coworker) = self.coworker(last) {
+                if self.within_slack(coworker) {
+                    self.stats.num_sticky_tasks.inc();
+                    return self.push_to_worker(last, task);
+                }
+                self.stats.num_family_migrations.inc();
+            }
+        }
+        self.push(task);
+    }
+
+    // Push a task that works on `Located` data to the worker that produced
+    // the data, if known, unless that worker is too busy (like
+    // `push_to_family`)
+    pub fn push_located(&self, worker: Option<usize>, task: impl Into<TaskKind>) {
+        if let Some(id) = worker.filter(|&id| id != self.id) {
+            if self.coworker(id).is_some_and(|coworker| self.within_slack(coworker)) {
+                self.stats.num_located_tasks.inc();
+                return self.push_to_worker(id, task);
+            }
+        }
+        self.push(task);
+    }
+
+    // Is `coworker` at most `Config::affinity_slack` tasks busier than we
+    // are?
+    fn within_slack(&self, coworker: &Coworker) -> bool {
+        let slack = self.shared.config.affinity_slack;
+        coworker.load().queued() <= self.load.queued().saturating_add(slack)
+    }
+
+    // Queue a task in one of the runtime's pools, see `Pool`. Any worker
+    // may take it from there, so it is promoted right away.
+    pub fn push_to_pool(&self, pool: usize, task: impl Into<TaskKind>) {
+        let mut task = task.into();
+        task.promote();
+        self.load.created.inc();
+        self.count_task(&mut task);
+        self.shared.pools.push(pool, task);
+        self.wake_any();
+    }
+
+    // Wake up a parked worker, if any, to take a task that any worker may
+    // take (from a pool or a task source). The fence pairs with the one
+    // `park` issues after counting itself in `num_parked`: either we see
+    // the worker parked, or it sees the task before parking.
+    fn wake_any(&self) {
+        fence(SeqCst);
+        if self.shared.num_parked.get() > 0 {
+            self.coworkers.iter().any(|c| c.load().unpark());
+        }
+    }
+
+    // Take a task from the pool whose turn it is, if any
+    fn take_pooled(&self) -> Option<TaskKind> {
+        let task = self.shared.pools.take();
+        if task.is_some() {
+            self.stats.num_pooled_tasks.inc();
+        }
+        task
+    }
+
+    // Note that a result waits in this worker's buffer of a buffered
+    // channel, to be flushed when we run out of work
+    pub fn mark_buffered(&self) {
+        self.buffered.set(true);
+    }
+
+    // Register a source of tasks with the runtime, see `TaskSource`
+    pub fn add_source(&self, source: impl TaskSource + 'static) {
+        self.shared.sources.add(Box::new(source));
+        self.wake_any();
+    }
+
+    // Pull the next task from a task source, if any. Tasks are new to the
+    // runtime, so they are counted as if they had been spawned here.
+    fn take_from_source(&self) -> Option<TaskKind> {
+        self.shared.sources.take(|task| {
+            let mut task = TaskKind::Dyn(task);
+            self.load.created.inc();
+            self.count_task(&mut task);
+            self.stats.num_source_tasks.inc();
+            task
+        })
     }
 
     pub fn handle_steal_request(&self, req: StealRequest) {
+        let thief = req.thief;
+        invariant!(thief != self.id, "worker {} received its own steal request", thief);
         let response = req.response;
+        if req.urgent {
+            self.stats.num_urgent_requests.inc();
+        }
+        if !self.is_efficiency_worker(thief) {
+            if let Some(task) = self.split_only_task() {
+                self.trace_response(thief, 1);
+                self.respond(response, Tasks::One(task));
+                self.update_load();
+                return;
+            }
+        }
         if req.steal_many {
-            match self.deque.borrow_mut().steal_many() {
-                Some(mut tasks) => {
-                    for task in tasks.iter_mut() {
-                        task.promote();
-                    }
-                    response.send(Tasks::Many(tasks)).unwrap();
+            let scheduler = self.scheduler();
+            let len = self.deque.borrow().len();
+            // A thief that has tasks queued already gets its share of all
+            // tasks minus what it has, and no more than it would like (see
+            // `StealRequest`). Urgent thieves get a full share.
+            let (load, desired) = match req.urgent {
+                true => (0, 0),
+                false => (req.load, req.desired.saturating_sub(req.load)),
+            };
+            let share = scheduler.steal_share(len + load).saturating_sub(load).min(len);
+            let n = match desired {
+                0 => share,
+                n => n.min(share),
+            };
+            let n = n.min(scheduler.max_steal_many);
+            let loot = match scheduler.steal_policy {
+                _ if n == 0 => None,
+                _ if self.is_efficiency_worker(thief) => self.steal_light(n),
+                StealPolicy::LowestPriority => self.deque.borrow_mut().steal_lowest(n, TaskKind::priority),
+                StealPolicy::FewestMigrations => self.deque.borrow_mut().steal_lowest(n, TaskKind::migrations),
+                StealPolicy::Newest => Some(self.deque.borrow_mut().split_front(n)),
+                StealPolicy::Oldest => Some(self.deque.borrow_mut().split_off(len - n)),
+                StealPolicy::CostWeighted => self.deque
+                    .borrow_mut()
+                    .steal_weighted(scheduler.steal_percent, |task| self.costs.estimate(task)),
+            };
+            // Give back what exceeds the limit, keeping the oldest tasks.
+            // Unless the thief has tasks or a wish of its own, a share of the
+            // estimated work may well be more tasks than `n`.
+            let limit = match scheduler.steal_policy {
+                StealPolicy::CostWeighted if load == 0 && desired == 0 => scheduler.max_steal_many,
+                _ => n,
+            };
+            let loot = loot.map(|mut tasks| match tasks.len() > limit {
+                true => {
+                    let kept = tasks.split_off(tasks.len() - limit);
+                    self.deque.borrow_mut().extend(tasks);
+                    kept
+                }
+                false => tasks,
+            });
+            match loot {
+                Some(tasks) => {
+                    self.trace_response(thief, tasks.len());
+                    self.respond(response, Tasks::Many(tasks));
                 },
-                None => response.send(Tasks::None).unwrap(),
+                None => {
+                    self.trace_response(thief, 0);
+                    self.decline(response);
+                }
             }
         } else {
-            match self.deque.borrow_mut().steal() {
-                Some(mut task) => {
-                    task.promote();
-                    response.send(Tasks::One(task)).unwrap();
+            let task = match self.scheduler().steal_policy {
+                _ if self.is_efficiency_worker(thief) => {
+                    self.steal_light(1).and_then(|mut loot| loot.pop())
+                }
+                StealPolicy::Oldest | StealPolicy::CostWeighted => self.deque.borrow_mut().steal(),
+                StealPolicy::Newest => self.deque.borrow_mut().pop(),
+                StealPolicy::LowestPriority => self.deque
+                    .borrow_mut()
+                    .steal_lowest(1, TaskKind::priority)
+                    .and_then(|mut loot| loot.pop()),
+                StealPolicy::FewestMigrations => self.deque
+                    .borrow_mut()
+                    .steal_lowest(1, TaskKind::migrations)
+                    .and_then(|mut loot| loot.pop()),
+            };
+            match task {
+                Some(task) => {
+                    self.trace_response(thief, 1);
+                    self.respond(response, Tasks::One(task));
                 },
-                None => response.send(Tasks::None).unwrap(),
+                None => {
+                    self.trace_response(thief, 0);
+                    self.decline(response);
+                }
+            }
+        }
+        self.update_load();
+        // Still more than enough to do? Then there may be more thieves to
+        // come, but some of them are asleep (see `idle::EnergySaving`).
+        if self.load.queued() > 1 {
+            self.wake_sleeper();
+        }
+    }
+
+    // With only one task queued, split off part of its work for a thief
+    // rather than handing over the whole task, see `Task::split`
+    fn split_only_task(&self) -> Option<TaskKind> {
+        let mut part = {
+            let mut deque = self.deque.borrow_mut();
+            if deque.len() != 1 { return None; }
+            let part = deque.iter_mut().next()?.split();
+            part?
+        };
+        part.set_id(self.next_task_id());
+        self.stats.num_tasks_created.inc();
+        self.stats.num_tasks_split.inc();
+        self.load.created.inc();
+        Some(part)
+    }
+
+    // Answer a steal request that got no tasks, see `EmptyDequeResponse`
+    fn decline(&self, response: channel::Sender<Tasks>) {
+        let later = matches!(self.shared.config.empty_deque_response, EmptyDequeResponse::Later { .. })
+            && self.load.running() > 0
+            && self.deque.borrow().is_empty();
+        match later {
+            true => {
+                self.stats.num_work_promised.inc();
+                response.send(Tasks::Later);
             }
+            false => response.send(Tasks::None),
         }
     }
 
-    pub fn try_handle_steal_request(&self) {
-        let req = self.channels.steal_requests.try_recv();
+    // Does worker `id` run on an efficiency core, and do we care?
+    fn is_efficiency_worker(&self, id: usize) -> bool {
+        let config = &self.shared.config;
+        config.heavy_task_cost < u32::MAX && config.core_class(id) == CoreClass::Efficiency
+    }
+
+    // Steal up to `n` of the cheapest tasks for an efficiency worker, but no
+    // heavy tasks
+    fn steal_light(&self, n: usize) -> Option<TaskDeque> {
+        let mut loot = self.deque.borrow_mut().steal_lowest(n, |task| self.costs.estimate(task))?;
+        let mut light = Deque::new();
+        while let Some(task) = loot.pop() {
+            match self.is_heavy(&task) {
+                true => self.deque.borrow_mut().push_back(task),
+                false => light.push_back(task),
+            }
+        }
+        if light.is_empty() { None } else { Some(light) }
+    }
+
+    fn trace_response(&self, thief: usize, num_tasks: usize) {
+        self.shared.traces.record(self.id, Event::Respond { thief, num_tasks });
+    }
+
+    // Promote stolen tasks and send them to the thief, or take them back
+    // unpromoted if the thief has stopped waiting for them (see
+    // `StealResponse::wait`). Promotion allocates a channel for every lazy
+    // promise, which tasks that stay here don't need. (Deferring promotion
+    // to the thief is not an option: lazy futures live on our stack, and
+    // promoting means replacing them, which only we can do without racing
+    // with our own readiness checks.)
+    fn respond(&self, response: channel::Sender<Tasks>, mut tasks: Tasks) {
+        if response.is_closed() {
+            match tasks {
+                Tasks::One(task) => self.deque.borrow_mut().push(task),
+                Tasks::Many(tasks) => self.deque.borrow_mut().extend(tasks),
+                _ => (),
+            }
+            return;
+        }
+        match &mut tasks {
+            Tasks::One(task) => task.promote(),
+            // Siblings that are stolen together send their results back
+            // through one batch, see `batch`
+            Tasks::Many(tasks) => {
+                let batch = Batch::new(tasks.len());
+                tasks.iter_mut().for_each(|task| task.promote_batched(&batch));
+            }
+            _ => (),
+        }
+        // The thief may still give up in the meantime
+        match response.try_send(tasks) {
+            Ok(()) => self.stats.num_steals_served.inc(),
+            Err(Tasks::One(task)) => self.deque.borrow_mut().push(task),
+            Err(Tasks::Many(tasks)) => self.deque.borrow_mut().extend(tasks),
+            Err(_) => (),
+        }
+    }
+
+    pub fn try_handle_steal_request(&self) -> bool {
+        self.record_poll();
+        let req = self.channels.urgent_steal_requests
+            .try_recv()
+            .or_else(|_| self.channels.steal_requests.try_recv());
         if let Ok(req) = req {
+            self.load.pending_requests.dec();
             self.handle_steal_request(req);
+            return true;
         }
+        false
     }
 
-    pub fn has_tasks(&self) -> bool {
-        !self.deque.borrow_mut().is_empty()
+    // See `Stats::avg_pending_requests`
+    fn record_poll(&self) {
+        let pending = self.load.pending_requests();
+        self.stats.num_polls.inc();
+        self.stats.num_pending_requests.add(pending);
+        self.stats.max_pending_requests.max(pending);
     }
 
-    pub fn push(&self, task: Box<dyn Task>) {
-        self.deque.borrow_mut().push(task);
+    // Answer one or all pending steal requests, returning whether there were
+    // any
+    fn service_steal_requests(&self, handle_all: bool) -> bool {
+        match handle_all {
+            true => self.handle_all_steal_requests() > 0,
+            false => self.try_handle_steal_request(),
+        }
     }
 
-    pub fn pop(&self) -> Option<Box<dyn Task>> {
-        self.deque.borrow_mut().pop()
+    // Check for steal requests if the poll interval has passed (called by a
+    // busy worker after popping a task)
+    fn poll_steal_requests(&self, handle_all: bool) {
+        let config = self.scheduler();
+        let scale = 1 << self.poll_backoff.get();
+        let due = match config.poll_interval {
+            PollInterval::Tasks(n) => {
+                let unpolled = self.unpolled.get() + 1;
+                self.unpolled.set(unpolled);
+                unpolled >= n.saturating_mul(scale)
+            }
+            PollInterval::Time(t) => self.polled.get().elapsed() >= t * scale,
+        };
+        if !due { return; }
+
+        let found = self.service_steal_requests(handle_all);
+        self.unpolled.set(0);
+        if let PollInterval::Time(_) = config.poll_interval {
+            self.polled.set(Instant::now());
+        }
+        if found {
+            self.poll_backoff.set(0);
+        } else if config.adaptive_polling {
+            self.poll_backoff.set((self.poll_backoff.get() + 1).min(config.max_poll_backoff));
+        }
     }
 
-    pub fn finalize(&self) {
-        for child in self.children.iter() {
-            child.send(Tasks::Exit).unwrap();
+    // Handle all pending steal requests at once. With several thieves
+    // waiting, answering one request at a time would hand half of the tasks
+    // to the first thief, a quarter to the second, and so on. Instead, split
+    // the tasks evenly among all thieves and this worker.
+    pub fn handle_all_steal_requests(&self) -> usize {
+        self.record_poll();
+        // Urgent requests first, so that they are served even if we run out
+        // of tasks, see `StealRequest::urgent`
+        let reqs: Vec<_> = self.channels.urgent_steal_requests
+            .try_iter()
+            .chain(self.channels.steal_requests.try_iter())
+            .collect();
+        let num_reqs = reqs.len();
+        self.load.pending_requests.sub(num_reqs as u32);
+        let share = self.deque.borrow().len() / (reqs.len() + 1);
+        for mut req in reqs {
+            if req.steal_many && share > 0 && !req.urgent {
+                let n = match req.desired.saturating_sub(req.load) {
+                    0 => share,
+                    n => n.min(share),
+                };
+                req.desired = req.load + n;
+            }
+            self.handle_steal_request(req);
         }
+        num_reqs
     }
 
-    // General worker loop
-    pub fn go(&self) {
-        let mut num_tasks_executed = 0;
-        loop {
-            // (1) Do local work
-            while let Some(task) = self.pop() {
-                self.try_handle_steal_request();
-                task.run();
-                num_tasks_executed += 1;
-            }
-            // (2) Request/steal work
-            match self.steal_one().wait() {
-                Tasks::None => (),
-                Tasks::One(task) => {
-                    task.run();
-                    num_tasks_executed += 1;
-                }
-                Tasks::Many(tasks) => {
-                    let _ = self.deque.replace(tasks);
+    pub fn scheduler(&self) -> SchedulerConfig {
+        self.scheduler.get()
+    }
+
+    // Adopt new scheduling parameters if the runtime has been reconfigured
+    // (called between tasks, see `Runtime::reconfigure`)
+    pub fn refresh_scheduler(&self) {
+        let reconfigurations = self.shared.reconfigurations();
+        if reconfigurations != self.reconfigurations.get() {
+            self.reconfigurations.set(reconfigurations);
+            self.scheduler.set(self.shared.scheduler());
+        }
+    }
+
+    // Called when there is nothing to do; see `IdleStrategy`
+    pub fn idle(&self, attempt: u32) {
+        self.notify_if_quiescent();
+        self.shared.config.idle.idle(attempt);
+    }
+
+    // Steal one task, unless we have been asked to exit, in which case no
+    // more tasks can be stolen
+    pub fn try_steal_one(&self) -> Tasks {
+        match self.exit.get() {
+            true => Tasks::None,
+            false => self.steal_one().wait(),
+        }
+    }
+
+    // Steal a batch of tasks with `Config::steal_many` (or while our deque
+    // is low, see `Watermarks::steal_many_when_low`), unless `batch` is
+    // false, or else one task (see `try_steal_one`)
+    fn try_steal(&self, batch: bool) -> Tasks {
+        let watermarks = &self.shared.config.watermarks;
+        // Not `occupancy`, which starts out normal until the deque changes
+        let low = watermarks.steal_many_when_low && watermarks.occupancy(self.deque.borrow().len()) == Occupancy::Low;
+        match (self.shared.config.steal_many || low) && !self.exit.get() {
+            true if batch => self.steal_many().wait(),
+            true => {
+                self.stats.num_steals_throttled.inc();
+                self.steal_one().wait()
+            }
+            false => self.try_steal_one(),
+        }
+    }
+
+    // Handle a steal response received while waiting for something (outside
+    // of `go`): stolen tasks are kept, and `Tasks::Exit` is deferred until
+    // we are back in `go`. Returns a task to run next, if any.
+    pub fn accept(&self, tasks: Tasks) -> Option<TaskKind> {
+        match tasks {
+            Tasks::None | Tasks::Later => None,
+            Tasks::One(task) => {
+                self.track(&task, TaskStatus::Queued { worker: self.id });
+                Some(task)
+            }
+            Tasks::Many(tasks) => {
+                if self.shared.config.track_tasks {
+                    tasks.iter().for_each(|task| self.track(task, TaskStatus::Queued { worker: self.id }));
                 }
-                Tasks::Exit => {
-                    assert!(self.deque.borrow().is_empty());
-                    break;
+                // The tasks were created on another core, so whatever they
+                // need is likely not in our cache. Start loading the tasks
+                // we'll run first while we get ready: the front of the batch
+                // is popped first, unless we pop in FIFO order (see `pop`).
+                #[cfg(feature = "prefetch")]
+                match self.scheduler().pop_order {
+                    PopOrder::Lifo => tasks.iter().take(PREFETCH_TASKS).for_each(TaskKind::prefetch),
+                    PopOrder::Fifo => tasks.iter().rev().take(PREFETCH_TASKS).for_each(TaskKind::prefetch),
                 }
+                self.deque.borrow_mut().extend(tasks);
+                let task = self.pop();
+                // Other thieves may be waiting for tasks too, for example, at
+                // the end of a scope (see `Scope::wait_on`). Share the loot
+                // before running the first task, which may take a while.
+                if self.has_tasks() {
+                    self.handle_all_steal_requests();
+                }
+                task
+            }
+            Tasks::Exit => {
+                self.exit.set(true);
+                None
+            }
+        }
+    }
+
+    pub fn has_tasks(&self) -> bool {
+        !self.deque.borrow_mut().is_empty()
+    }
+
+    pub fn push(&self, task: impl Into<TaskKind>) {
+        self.queue(task.into());
+    }
+
+    // Like `push`, but subject to the admission policy, if any (see
+    // `Builder::admission`). This is what the spawn macros call. The
+    // runtime's own tasks, and tasks that others wait for (such as the
+    // spawned half of `algorithms::join`), are pushed as is.
+    pub fn spawn(&self, task: impl Into<TaskKind>) {
+        let task = task.into();
+        match self.admit(&task) {
+            Admission::Queue => self.queue(task),
+            Admission::Inline => self.run_inline(task),
+            Admission::Reject => {
+                self.stats.num_tasks_rejected.inc();
+                task.reject();
+            }
+        }
+    }
+
+    // Ask the admission policy, if any, what to do with `task`
+    fn admit(&self, task: &TaskKind) -> Admission {
+        let admission = &self.shared.config.admission;
+        if !admission.is_enabled() {
+            return Admission::Queue;
+        }
+        let loads = || std::iter::once(&*self.load).chain(self.coworkers.iter().map(|c| c.load()));
+        let load = PoolLoad {
+            queued: loads().map(Load::queued).sum(),
+            running: loads().map(Load::running).sum(),
+            local_queued: self.deque.borrow().len() as u32,
+        };
+        admission.decide(&task.info(self.id), &load)
+    }
+
+    fn queue(&self, mut task: TaskKind) {
+        // Heavy tasks belong on performance cores, see `CoreClass`
+        if !self.fast_workers.is_empty() && self.is_heavy(&task) {
+            let fast = self.fast_workers[self.pick(self.fast_workers.len())];
+            return self.push_to_worker(self.coworkers[fast].id, task);
+        }
+        let plenty = self.shared.config.watermarks.inline_when_high && self.occupancy() == Occupancy::High;
+        if self.is_full() || plenty {
+            return self.run_inline(task);
+        }
+        self.count_task(&mut task);
+        self.deque.borrow_mut().push(task);
+        self.load.created.inc();
+        self.update_load();
+        self.service_in_background();
+    }
+
+    // With `Config::service_on_spawn`, answer a pending steal request, if
+    // any. Called on spawns and at scope boundaries.
+    pub fn service_in_background(&self) {
+        if self.shared.config.service_on_spawn && self.load.pending_requests() > 0 {
+            self.try_handle_steal_request();
+        }
+    }
+
+    // Like `spawn`, but if the queue is full (see `Config::queue_capacity`),
+    // or if the admission policy rejects the task (see `Builder::admission`),
+    // give the task back, leaving it to the caller to drop it, retry later,
+    // or run it elsewhere
+    pub fn try_push(&self, task: impl Into<TaskKind>) -> Result<(), TaskKind> {
+        let task = task.into();
+        match self.admit(&task) {
+            Admission::Queue if self.is_full() => Err(task),
+            Admission::Queue => {
+                self.queue(task);
+                Ok(())
+            }
+            Admission::Inline => {
+                self.run_inline(task);
+                Ok(())
+            }
+            Admission::Reject => {
+                self.stats.num_tasks_rejected.inc();
+                Err(task)
+            }
+        }
+    }
+
+    // Run `task` right away instead of queueing it, like a function call,
+    // but in its own context (see `TaskContext`)
+    fn run_inline(&self, task: TaskKind) {
+        self.stats.num_tasks_inlined.inc();
+        let context = self.current_context.replace(Some(task.context()));
+        task.run();
+        self.current_context.set(context);
+    }
+
+    // Has the queue reached `Config::queue_capacity`?
+    pub fn is_full(&self) -> bool {
+        self.room() == 0
+    }
+
+    // How many more tasks fit into the queue, see `Config::queue_capacity`
+    fn room(&self) -> usize {
+        let len = self.deque.borrow().len();
+        self.shared.config.queue_capacity.map_or(usize::MAX, |capacity| capacity.saturating_sub(len))
+    }
+
+    // Queue `task` or run it right away, depending on `hint`
+    pub fn push_with_hint(&self, task: impl Into<TaskKind>, hint: SpawnHint) {
+        let starving = || !self.has_tasks() && self.load.pending_requests() > 0;
+        match hint {
+            SpawnHint::Serial if !starving() => self.run_inline(task.into()),
+            _ => self.spawn(task),
+        }
+    }
+
+    // Like calling `push` for every task, but borrow the deque and update
+    // the load only once. The tasks are collected before the deque is
+    // borrowed: producing a task may well push another one. Tasks that
+    // don't fit into the queue run right away, as with `push`.
+    pub fn push_batch<I>(&self, tasks: I)
+    where I: IntoIterator, I::Item: Into<TaskKind> {
+        let mut tasks = tasks.into_iter().map(Into::into).collect::<Vec<TaskKind>>();
+        let overflow = tasks.split_off(tasks.len().min(self.room()));
+        tasks.iter_mut().for_each(|task| self.count_task(task));
+        let n = tasks.len();
+        self.deque.borrow_mut().push_all(tasks);
+        self.load.created.add(n as u32);
+        self.update_load();
+        self.service_in_background();
+        overflow.into_iter().for_each(|task| self.run_inline(task));
+    }
+
+    // Like `push`, but queue the task behind all other queued tasks (at the
+    // steal end), for example, to retry a task whose input isn't ready yet
+    pub fn defer(&self, task: impl Into<TaskKind>) {
+        let mut task = task.into();
+        self.count_task(&mut task);
+        self.deque.borrow_mut().push_back(task);
+        self.load.created.inc();
+        self.update_load();
+    }
+
+    // Run a task on this worker once `due` has passed, for example, to retry
+    // a task with backoff (see `RetryingTask`). Until then, the task waits
+    // outside the deque, so it can't be stolen and doesn't keep the worker
+    // busy, and it doesn't count as a new task: only its eventual completion
+    // is counted, so that the runtime doesn't look quiescent in the meantime.
+    pub fn defer_until(&self, due: Instant, task: impl Into<TaskKind>) {
+        self.timers.borrow_mut().push((due, task.into()));
+        self.load.created.inc();
+    }
+
+    // Count a task that waits outside of any queue until it's queued again
+    // with `release`, for example, for a permit of its scope (see
+    // `ScopeCounters::wait_for_permit`), so that the runtime doesn't look
+    // quiescent in the meantime. Waiting doesn't count as a new task.
+    pub fn hold(&self) {
+        self.load.created.inc();
+    }
+
+    // Queue a task that was held, on this or another worker, see `hold`
+    pub fn release(&self, task: TaskKind) {
+        self.deque.borrow_mut().push(task);
+        self.update_load();
+    }
+
+    // When the next deferred task is due, see `defer_until`
+    fn next_timer(&self) -> Option<Instant> {
+        self.timers.borrow().iter().map(|&(due, _)| due).min()
+    }
+
+    // Run the deferred tasks that are due. Returns whether there were any.
+    fn run_timers(&self) -> bool {
+        if self.timers.borrow().is_empty() {
+            return false;
+        }
+        let now = Instant::now();
+        let due: Vec<_> = {
+            let mut timers = self.timers.borrow_mut();
+            let (due, pending) = timers.drain(..).partition(|&(due, _)| due <= now);
+            *timers = pending;
+            due
+        };
+        let ran = !due.is_empty();
+        for (_, task) in due {
+            self.load.running.inc();
+            self.run_caught(task);
+            self.load.running.dec();
+            self.load.completed.inc_release();
+        }
+        ran
+    }
+
+    // Queue a task that only this worker can run, so neither the task nor
+    // its result need to be `Send`
+    pub fn push_local(&self, task: LocalTask) {
+        self.stats.num_tasks_created.inc();
+        self.stats.task_bytes.add(std::mem::size_of_val(&*task) as u64);
+        self.local.borrow_mut().push(task);
+        self.load.created.inc();
+    }
+
+    // Queue a maintenance task that only runs when this worker would
+    // otherwise be idle: its deque and local tasks are exhausted, the last
+    // steal attempt came back empty, and no task is running (a task waiting
+    // for a future, say, would be held up). Background tasks run in FIFO order
+    // and are never stolen, so they don't compete with foreground tasks
+    // (here or elsewhere).
+    pub fn push_background(&self, task: impl Into<TaskKind>) {
+        let mut task = task.into();
+        self.count_task(&mut task);
+        self.background.borrow_mut().push_back(task);
+        self.load.created.inc();
+    }
+
+    pub fn num_background_tasks(&self) -> usize {
+        self.background.borrow().len()
+    }
+
+    // Run the oldest background task, if any
+    fn run_background(&self) -> bool {
+        let task = self.background.borrow_mut().pop_front();
+        match task {
+            Some(task) => {
+                self.run(task);
+                self.stats.num_tasks_executed.inc();
+                self.stats.num_background_tasks.inc();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Run the most recently pushed local task, if any
+    fn run_local(&self) -> bool {
+        let task = self.local.borrow_mut().pop();
+        match task {
+            Some(task) => {
+                self.load.running.inc();
+                task();
+                self.load.running.dec();
+                self.load.completed.inc_release();
+                self.stats.num_tasks_executed.inc();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Take the newest or the oldest task, see `PopOrder`
+    pub fn pop(&self) -> Option<TaskKind> {
+        let task = match self.scheduler().pop_order {
+            PopOrder::Lifo => self.deque.borrow_mut().pop(),
+            PopOrder::Fifo => self.deque.borrow_mut().steal(),
+        };
+        self.update_load();
+        task
+    }
+
+    // Like `pop`, but for tasks that the caller runs on its own: such tasks
+    // count as completed right away, so that they don't keep the runtime
+    // from becoming quiescent (see `quiescent`)
+    pub fn take(&self) -> Option<TaskKind> {
+        let task = self.pop();
+        if task.is_some() {
+            self.load.completed.inc_release();
+        }
+        task
+    }
+
+    // Like `pop`, but after `Config::max_lifo_streak` consecutive pops, take
+    // an injected task or the oldest local task instead
+    pub fn pop_fair(&self) -> Option<TaskKind> {
+        let streak = self.lifo_streak.get();
+        if streak >= self.scheduler().max_lifo_streak {
+            self.lifo_streak.set(0);
+            if let Ok(task) = self.channels.injected.try_recv() {
+                return Some(task);
+            }
+            let task = self.deque.borrow_mut().steal();
+            if task.is_some() {
+                self.update_load();
+                return task;
+            }
+        }
+        let task = self.pop();
+        if task.is_some() {
+            self.lifo_streak.set(self.lifo_streak.get() + 1);
+        }
+        task
+    }
+
+    // Run the queued tasks one at a time, see `Drain`
+    pub fn drain(&self) -> Drain<'_> {
+        Drain { worker: self }
+    }
+
+    // Run a task, keeping track of the number of running tasks
+    pub fn run(&self, task: TaskKind) {
+        #[cfg(not(feature = "no-stats"))]
+        if let Some(spawned) = task.spawned() {
+            let latency = spawned.elapsed();
+            self.stats.latency.record(latency);
+            self.stats.max_latency.max(latency);
+        }
+        let id = task.id();
+        self.shared.traces.record(self.id, Event::Run { name: task.name(), id });
+        self.track(&task, TaskStatus::Running { worker: self.id });
+        let deadline = task.deadline();
+        if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            self.stats.num_late_starts.inc();
+        }
+        self.load.running.inc();
+        let priority = self.current_priority.replace(task.priority());
+        let current = self.current_task.replace(id);
+        let context = self.current_context.replace(Some(task.context()));
+        let tracked = self.shared.config.track_tasks;
+        let start = tracked.then(Instant::now);
+        let nested = tracked.then(|| self.nested_time.replace(Duration::ZERO));
+        #[cfg(feature = "tracing")]
+        let _span = {
+            let seq = self.num_runs.get();
+            self.num_runs.set(seq + 1);
+            tracing::trace_span!("task", worker = self.id, task = seq, id = ?id, name = task.name(), correlation = ?task.context().correlation).entered()
+        };
+        match task.name() {
+            // Only named tasks are timed, see `CostModel`
+            Some(name) => {
+                let start = Instant::now();
+                self.run_caught(task);
+                self.costs.record(name, start.elapsed());
+            }
+            None => self.run_caught(task),
+        }
+        if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            self.stats.num_overruns.inc();
+        }
+        self.current_priority.set(priority);
+        self.current_task.set(current);
+        self.current_context.set(context);
+        if let (Some(start), Some(nested)) = (start, nested) {
+            let elapsed = start.elapsed();
+            let work = elapsed.saturating_sub(self.nested_time.replace(nested + elapsed));
+            self.stats.task_work.add(work);
+            if let Some(id) = id {
+                self.shared.untrack(id);
+                self.shared.task_graph().complete(id, work);
+            }
+        }
+        self.load.running.dec();
+        self.load.completed.inc_release();
+    }
+
+    // Priority of the task this worker is running (zero between tasks)
+    pub fn current_priority(&self) -> u32 {
+        self.current_priority.get()
+    }
+
+    // Catch panics unless they are supposed to unwind the worker's thread,
+    // see `PanicPolicy`
+    fn run_caught(&self, task: TaskKind) {
+        let policy = &self.shared.config.panic_policy;
+        if let PanicPolicy::Unwind = policy {
+            return self.run_with_middleware(task);
+        }
+        let info = task.info(self.id);
+        let scope = task.scope_counters();
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| self.run_with_middleware(task))) {
+            self.stats.num_tasks_panicked.inc();
+            policy.handle(&info, scope.as_deref(), payload);
+        }
+    }
+
+    // See `runtime::Middleware`
+    fn run_with_middleware(&self, task: TaskKind) {
+        let middleware = &self.shared.config.middleware;
+        match middleware.is_empty() {
+            true => task.run(),
+            false => middleware.run(&task.info(self.id), || task.run()),
+        }
+    }
+
+    // If all tasks created so far have been completed, returns the number of
+    // tasks created (see `quiescence`). The result is exact only if no new
+    // tasks are pushed from outside the runtime at the same time.
+    pub fn quiescent(&self) -> Option<u32> {
+        // Sources have more tasks to come
+        if !self.shared.sources.is_empty() {
+            return None;
+        }
+        // Helpers share a load, which is their own when they ask
+        let helpers = (!self.is_helper()).then_some(&*self.shared.helper_load);
+        let loads = || {
+            std::iter::once(&*self.load)
+                .chain(self.coworkers.iter().map(|c| c.load()))
+                .chain(helpers)
+        };
+        quiescence::check(loads)
+    }
+
+    // Notify listeners when the runtime has become quiescent
+    fn notify_if_quiescent(&self) {
+        if !self.shared.has_idle_listeners() { return; }
+        if let Some(num_tasks) = self.quiescent() {
+            self.shared.notify_idle(num_tasks);
+        }
+    }
+
+    pub fn completions(&self) -> &Rc<RefCell<Completions>> {
+        &self.completions
+    }
+
+    pub fn costs(&self) -> &CostModel {
+        &self.costs
+    }
+
+    pub fn load(&self) -> &Load {
+        &self.load
+    }
+
+    pub fn shared(&self) -> &Shared {
+        &self.shared
+    }
+
+    pub fn shared_handle(&self) -> Arc<Shared> {
+        Arc::clone(&self.shared)
+    }
+
+    fn update_load(&self) {
+        let queued = self.deque.borrow().len() as u32;
+        self.load.queued.set(queued);
+        self.stats.max_queued_tasks.max(queued);
+        self.update_occupancy(queued as usize);
+    }
+
+    // Occupancy of our deque, see `watermark`
+    pub fn occupancy(&self) -> Occupancy {
+        self.occupancy.get()
+    }
+
+    // Tell everyone who subscribed to our occupancy when it changes
+    fn update_occupancy(&self, queued: usize) {
+        let watermarks = &self.shared.config.watermarks;
+        if !watermarks.is_enabled() { return; }
+        let occupancy = watermarks.occupancy(queued);
+        if self.occupancy.replace(occupancy) != occupancy {
+            watermarks.notify(self.id, occupancy);
+            // Refill before running dry, see `Watermarks::steal_many_when_low`
+            if occupancy == Occupancy::Low && queued > 0 && watermarks.steal_many_when_low {
+                self.steal_early();
+            }
+        }
+    }
+
+    // Send a request for a batch of tasks while we still have tasks to run,
+    // so that the response is likely to have arrived by the time we need it
+    // (see `poll_early_steal`). Helpers don't, since they may leave anytime,
+    // and neither do victims that are still handing out tasks.
+    fn steal_early(&self) {
+        if self.is_helper() || self.exit.get() || self.load.pending_requests() > 0
+            || self.early_steal.borrow().is_some() {
+            return;
+        }
+        let response = self.steal_many();
+        self.stats.num_early_steals.inc();
+        *self.early_steal.borrow_mut() = Some(response.tasks);
+    }
+
+    // Queue the tasks that arrived in response to `steal_early`, if any
+    fn poll_early_steal(&self) {
+        let ready = self.early_steal.borrow().as_ref().is_some_and(|tasks| tasks.is_ready());
+        if !ready {
+            return;
+        }
+        let tasks = self.early_steal.take().unwrap();
+        // We haven't been idle, whatever the response
+        let idle_since = self.idle_since.get();
+        let tasks = StealResponse { worker: self, tasks }.wait();
+        self.idle_since.set(idle_since);
+        if let Some(task) = self.accept(tasks) {
+            self.deque.borrow_mut().push(task);
+            self.update_load();
+        }
+    }
+
+    fn is_heavy(&self, task: &TaskKind) -> bool {
+        self.costs.estimate(task) >= self.shared.config.heavy_task_cost
+    }
+
+    // Count a task that is about to be queued (see
+    // `Stats::num_tasks_created`), giving it an id unless it has one already
+    // (from an earlier attempt, see `defer`)
+    fn count_task(&self, task: &mut TaskKind) {
+        self.stats.num_tasks_created.inc();
+        self.stats.task_bytes.add(task.size_hint() as u64);
+        let id = self.task_id(task);
+        self.last_spawned.set(Some(id));
+        self.track(task, TaskStatus::Queued { worker: self.id });
+        if self.shared.config.track_tasks {
+            self.shared.task_graph().spawn(id, self.current_task.get());
+        }
+    }
+
+    // Record where a task is, if the runtime keeps track of tasks, see
+    // `Runtime::find_task`
+    fn track(&self, task: &TaskKind, status: TaskStatus) {
+        if self.shared.config.track_tasks {
+            if let Some(id) = task.id() {
+                self.shared.track(id, status);
+            }
+        }
+    }
+
+    // A fresh task id. Tasks get one when they are queued, but giving them
+    // one before (see `Scope::spawn`) lets the spawner keep it.
+    pub fn next_task_id(&self) -> TaskId {
+        TaskId { worker: self.id, seq: self.load.issue() }
+    }
+
+    // The id of `task`, giving it one if it has none yet
+    pub fn task_id(&self, task: &mut TaskKind) -> TaskId {
+        let id = task.id().unwrap_or_else(|| self.next_task_id());
+        task.set_id(id);
+        id
+    }
+
+    // Id of the task queued last by this worker, if any, to identify a task
+    // right after spawning it. The spawn macros return the id as well.
+    pub fn last_spawned(&self) -> Option<TaskId> {
+        self.last_spawned.get()
+    }
+
+    // Id of the task this worker is running, if it has one
+    pub fn current_task(&self) -> Option<TaskId> {
+        self.current_task.get()
+    }
+
+    // Context of the task this worker is running, see `TaskContext`
+    pub fn task_context(&self) -> Option<TaskContext> {
+        self.current_context.get()
+    }
+
+    // Context of the task running on the current thread, if any, for code
+    // that has no worker at hand (logging deep inside a task, for example)
+    pub fn current_task_context() -> Option<TaskContext> {
+        Self::try_current()?.task_context()
+    }
+
+    // Park until a steal request or a task arrives, another worker wakes us
+    // up (see `wake_sleeper`), or the runtime stops. Only for idle workers,
+    // since parked workers don't respond to steal requests.
+    pub fn park(&self) {
+        // Nobody would wake up a helper
+        if self.is_helper() {
+            return thread::yield_now();
+        }
+        // Parked workers can't publish snapshots when asked to, so publish
+        // one now (parking is expensive anyway)
+        self.publish_snapshot(true);
+        // Count ourselves before `register_sleeper` issues its fence, which
+        // also pairs with the one in `wake_any`
+        self.shared.num_parked.inc();
+        self.load.register_sleeper();
+        // Check again after registering, so that nothing that arrived in the
+        // meantime goes unnoticed (a wake-up after registering makes `park`
+        // return immediately). Injected tasks have been counted and admitted
+        // by whoever pushed them, so they go straight to the deque.
+        if let Ok(task) = self.channels.injected.try_recv() {
+            self.deque.borrow_mut().push(task);
+            self.update_load();
+        } else if self.load.pending_requests() == 0 && self.shared.pools.is_empty()
+            && self.shared.sources.is_empty() && !self.stop_requested() {
+            // Nobody would wake us up for a deferred task either
+            match self.next_timer() {
+                Some(due) => thread::park_timeout(due.saturating_duration_since(Instant::now())),
+                None => thread::park(),
+            }
+        }
+        self.load.unregister_sleeper();
+        self.shared.num_parked.dec();
+        // Our statistics are about to change
+        self.publish_snapshot(false);
+    }
+
+    // If another worker has tasks queued, wake up one parked worker to steal
+    // them. Returns whether a worker was woken up.
+    pub fn wake_sleeper(&self) -> bool {
+        if self.shared.num_parked.get() == 0 {
+            return false;
+        }
+        if self.load.queued() < 2 && self.coworkers.iter().all(|c| c.load().queued() == 0) {
+            return false;
+        }
+        self.coworkers.iter().any(|c| c.load().unpark())
+    }
+
+    // Wake up all parked workers, for example, to let them stop
+    pub fn wake_all(&self) {
+        self.load.unpark();
+        for c in self.coworkers.iter() {
+            c.load().unpark();
+        }
+    }
+
+    // Whether the runtime has asked all workers to stop, see
+    // `Shared::request_stop`
+    pub fn stop_requested(&self) -> bool {
+        self.shared.stops() != self.epoch.get()
+    }
+
+    pub fn finalize(&self) {
+        self.stats.run_time.add(self.created.get().elapsed());
+        self.stats.cpu_time.add(self.cpu_time());
+    }
+
+    // CPU time consumed since this worker was created (or resumed)
+    fn cpu_time(&self) -> Duration {
+        match (self.cpu_created.get(), thread_cpu_time()) {
+            (Some(start), Some(now)) => now.saturating_sub(start),
+            _ => Duration::ZERO,
+        }
+    }
+
+    // Hand out our statistics, starting over with fresh ones
+    pub fn take_stats(&mut self) -> Stats {
+        std::mem::replace(&mut self.stats, Stats::with_id(self.id))
+    }
+
+    // Publish a snapshot of our statistics if one is due, or if one has
+    // been requested (see `Shared::request_snapshot`)
+    fn publish_stats(&self) {
+        let epoch = self.shared.snapshot_epoch();
+        if epoch == self.published_epoch.get() {
+            let interval = match self.shared.config.report_interval {
+                Some(interval) => interval,
+                None => return,
+            };
+            if self.published.get().elapsed() < interval { return; }
+        }
+        self.publish_snapshot(false);
+    }
+
+    // Publish a snapshot of our statistics right away. The requested epoch
+    // is read first, so that the snapshot can't be older than its epoch.
+    pub fn publish_snapshot(&self, parked: bool) {
+        let epoch = self.shared.snapshot_epoch();
+        self.published.set(Instant::now());
+        self.published_epoch.set(epoch);
+        let stats = self.stats.clone();
+        stats.run_time.add(self.created.get().elapsed());
+        stats.cpu_time.add(self.cpu_time());
+        self.shared.publish(Snapshot { epoch, parked, stats });
+    }
+
+    // Prepare for another run of `go` after `Tasks::Exit`, with fresh stats
+    pub fn resume(&self) {
+        assert!(self.deque.borrow().is_empty());
+        self.exit.set(false);
+        self.epoch.set(self.shared.stops());
+        self.stats.reset();
+        self.created.set(Instant::now());
+        self.cpu_created.set(thread_cpu_time());
+        self.published.set(Instant::now());
+    }
+
+    // General worker loop
+    pub fn go(&self) {
+        self.help_until_with(|| self.exit.get() || self.load.is_retired(), true, || true);
+        // Don't leave a steal request hanging, see `steal_early`
+        if let Some(tasks) = self.early_steal.take() {
+            if let Some(task) = self.accept(StealResponse { worker: self, tasks }.wait()) {
+                self.deque.borrow_mut().push(task);
+                self.update_load();
+            }
+        }
+        if self.load.is_retired() {
+            // Local tasks can't move to another worker, see `retire`
+            while self.run_local() {}
+            return;
+        }
+        invariant!(
+            self.local.borrow().is_empty() && self.background.borrow().is_empty(),
+            "worker {} exits with {} local and {} background tasks",
+            self.id, self.local.borrow().len(), self.background.borrow().len()
+        );
+        assert!(
+            self.deque.borrow().is_empty(),
+            "worker {} exits with {} queued tasks", self.id, self.deque.borrow().len()
+        );
+    }
+
+    // Hand everything we are responsible for to a surviving worker, after we
+    // have been asked to retire (see `Runtime::retire`) and have left `go`:
+    // queued, background, deferred, and injected tasks, pending steal
+    // requests, and the channels of workers that retired before us. Tasks
+    // are promoted on the way, like stolen tasks, so that their scopes keep
+    // counting them in shared counters (see `TaskCount::Shared`). Our
+    // channels stay open for whoever adopts them, so that nothing sent to us
+    // in the meantime is lost. Returns our statistics.
+    pub fn retire(mut self) -> Stats {
+        assert!(self.load.is_retired());
+        // Spread our tasks (and steal requests) over the survivors, rather
+        // than handing everything to the first one
+        let heirs: Vec<_> = self.coworkers
+            .iter()
+            .filter(|c| !c.load.is_retired())
+            .cloned()
+            .collect();
+        assert!(!heirs.is_empty(), "No worker left to retire to");
+        let mut heirs = heirs.iter().cycle();
+        let mut tasks: Vec<_> = std::iter::from_fn(|| self.deque.get_mut().steal()).collect();
+        tasks.extend(self.background.get_mut().drain(..));
+        // Deferred tasks are handed over early, and defer themselves again
+        tasks.extend(self.timers.get_mut().drain(..).map(|(_, task)| task));
+        tasks.extend(self.channels.injected.try_iter());
+        self.stats.num_tasks_handed_over.add(tasks.len() as u32);
+        for mut task in tasks {
+            task.promote();
+            heirs.next().unwrap().inject(task);
+        }
+        let reqs = self.channels.urgent_steal_requests.try_iter().chain(self.channels.steal_requests.try_iter());
+        for req in reqs {
+            self.load.pending_requests.dec();
+            let heir = heirs.next().unwrap();
+            if req.thief == heir.id {
+                req.response.send(Tasks::None);
+            } else if let Err(req) = heir.send_steal_request(req) {
+                req.response.send(Tasks::None);
+            }
+        }
+        self.update_load();
+        // We won't publish snapshots anymore, so leave a final one that
+        // doesn't hold up anyone waiting for snapshots (like a parked
+        // worker's), see `Shared::snapshot_at`
+        self.publish_snapshot(true);
+        let stats = self.take_stats();
+        let mut orphans = self.shared.orphans.lock().unwrap();
+        orphans.extend(self.adopted.take().into_iter().map(Inbox::from));
+        orphans.push(Inbox::from(self.channels));
+        self.shared.num_orphans.set(orphans.len() as u32);
+        stats
+    }
+
+    // Take over the channels of retired workers, and handle whatever has
+    // been sent to them since, see `retire`. Helpers come and go, so they
+    // leave this to the workers.
+    fn adopt_orphans(&self) {
+        if self.shared.num_orphans.get() > 0 && !self.is_helper() {
+            let mut orphans = self.shared.orphans.lock().unwrap();
+            self.shared.num_orphans.set(0);
+            self.adopted.borrow_mut().extend(orphans.drain(..).map(WorkerChannels::from));
+        }
+        let (tasks, reqs): (Vec<_>, Vec<_>) = {
+            let adopted = self.adopted.borrow();
+            if adopted.is_empty() { return; }
+            let tasks = adopted.iter().flat_map(|c| c.injected.try_iter()).collect();
+            let reqs = adopted
+                .iter()
+                .flat_map(|c| c.urgent_steal_requests.try_iter().chain(c.steal_requests.try_iter()))
+                .collect();
+            (tasks, reqs)
+        };
+        if !tasks.is_empty() {
+            self.deque.borrow_mut().push_all(tasks);
+            self.update_load();
+        }
+        for req in reqs {
+            match req.thief == self.id {
+                true => req.response.send(Tasks::None),
+                false => self.handle_steal_request(req),
+            }
+        }
+    }
+
+    // Execute local or stolen tasks until `done` returns true. This is the
+    // loop behind `go`, `Scope::wait`, `Future::wait`, and everything else
+    // that needs to wait for tasks to complete.
+    pub fn help_until(&self, done: impl Fn() -> bool) {
+        self.help_until_with(done, false, || true);
+    }
+
+    // Like `help_until`, but steal one task at a time whenever `batch`
+    // returns false, even with `Config::steal_many`
+    pub fn help_until_batched(&self, done: impl Fn() -> bool, batch: impl Fn() -> bool) {
+        self.help_until_with(done, false, batch);
+    }
+
+    // Like `help_until`, but mark our steal requests as urgent while we
+    // wait, because whoever waits is blocked on the result (see
+    // `Future::wait`)
+    pub fn help_until_urgent(&self, done: impl Fn() -> bool) {
+        let _wait = UrgentWait::new(&self.urgent_waits);
+        self.help_until(done);
+    }
+
+    // Like `help_until`, but give up at `deadline`, even if `done` is still
+    // false. Steal requests to busy victims are abandoned after a while as
+    // well, as helpers do (see `StealResponse::wait`), so that a victim stuck
+    // in a long task can't hold us up.
+    pub fn help_until_deadline(&self, done: impl Fn() -> bool, deadline: Instant) {
+        let previous = self.deadline.replace(Some(deadline));
+        self.help_until(|| done() || Instant::now() >= deadline);
+        self.deadline.set(previous);
+    }
+
+    fn past_deadline(&self) -> bool {
+        self.deadline.get().is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    // Like `help_until`, optionally answering all pending steal requests
+    // after each task (see `handle_all_steal_requests`)
+    fn help_until_with(&self, done: impl Fn() -> bool, handle_all: bool, batch: impl Fn() -> bool) {
+        if done() { return; }
+
+        let mut throughput = 0;
+        let mut attempt = 0;
+
+        loop {
+            self.refresh_scheduler();
+            self.adopt_orphans();
+            self.poll_early_steal();
+            let before_pop = self.scheduler().request_servicing == RequestServicing::BeforePop;
+            if before_pop {
+                self.service_steal_requests(handle_all);
+            }
+            // (1) Do local work, or else (2) take pooled work or work from
+            // task sources, or else (3) request/steal work
+            let task = match self.pop_fair() {
+                Some(task) => {
+                    if !before_pop {
+                        self.poll_steal_requests(handle_all);
+                    }
+                    throughput += 1;
+                    Some(task)
+                }
+                // (1b) Do local-only work before trying to steal
+                None if self.run_local() => {
+                    attempt = 0;
+                    self.publish_stats();
+                    if done() { return; }
+                    continue;
+                }
+                None => {
+                    if throughput > 0 {
+                        self.throughput.set(throughput);
+                        throughput = 0;
+                    }
+                    // Don't hold back results while we look for work, see
+                    // `buffered_channel`
+                    if self.buffered.replace(false) {
+                        self.shared.flushers.flush(Some(self.id));
+                    }
+                    match self.take_pooled().or_else(|| self.take_from_source()) {
+                        Some(task) => Some(task),
+                        None => self.accept(self.try_steal(batch())),
+                    }
+                }
+            };
+            match task {
+                Some(task) => {
+                    self.run(task);
+                    self.stats.num_tasks_executed.inc();
+                    attempt = 0;
+                }
+                None => {
+                    self.try_handle_steal_request();
+                    // (4) Nothing else to do, so do deferred tasks that are
+                    // due, or else background work, unless we wait inside a
+                    // task (helpers share the count of running tasks)
+                    let between_tasks = self.load.running() == 0;
+                    if self.run_timers() || (between_tasks && self.run_background()) {
+                        attempt = 0;
+                    } else {
+                        let start = Instant::now();
+                        self.idle(attempt);
+                        self.add_idle_time(start.elapsed());
+                        attempt = attempt.saturating_add(1);
+                    }
+                }
+            }
+            self.publish_stats();
+            if done() { return; }
+        }
+    }
+}
+
+// Counts a wait in `help_until_urgent` for as long as it lasts, even if it
+// ends in a panic that is caught further up (see `PanicPolicy`)
+struct UrgentWait<'a>(&'a Cell<u32>);
+
+impl<'a> UrgentWait<'a> {
+    fn new(urgent_waits: &'a Cell<u32>) -> Self {
+        urgent_waits.set(urgent_waits.get() + 1);
+        Self(urgent_waits)
+    }
+}
+
+impl Drop for UrgentWait<'_> {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
+}
+
+// Runs a worker's queued tasks, one per call to `next`, answering a pending
+// steal request (if any) before each, and yields what it ran. Ends when the
+// deque is empty, without waiting for stolen tasks, so that the caller can
+// interleave its own work:
+//
+//     for task in runtime.drain() {
+//         println!("Ran {:?}", task.name);
+//     }
+pub struct Drain<'a> {
+    worker: &'a Worker,
+}
+
+impl Iterator for Drain<'_> {
+    type Item = TaskInfo;
+
+    fn next(&mut self) -> Option<TaskInfo> {
+        let worker = self.worker;
+        worker.try_handle_steal_request();
+        let task = worker.pop()?;
+        let info = task.info(worker.id);
+        worker.run(task);
+        worker.stats.num_tasks_executed.inc();
+        Some(info)
+    }
+}
+
+// Anything that refers to a worker, so that tasks can be pushed to (and
+// waited for on) a worker other than the current one
+pub trait AsWorker {
+    fn as_worker(&self) -> &Worker;
+}
+
+impl AsWorker for Worker {
+    fn as_worker(&self) -> &Worker {
+        self
+    }
+}
+
+impl<T: AsWorker + ?Sized> AsWorker for &T {
+    fn as_worker(&self) -> &Worker {
+        (**self).as_worker()
+    }
+}
+
+#[derive(Debug)]
+pub struct Coworker {
+    id: usize,
+    steal_requests: SyncSender<StealRequest>,
+    urgent_steal_requests: SyncSender<StealRequest>,
+    injected: Sender<TaskKind>,
+    load: Arc<Load>,
+}
+
+impl Coworker {
+    pub fn new(
+        id: usize,
+        steal_requests: SyncSender<StealRequest>,
+        urgent_steal_requests: SyncSender<StealRequest>,
+        injected: Sender<TaskKind>
+    ) -> Self {
+        Self { id, steal_requests, urgent_steal_requests, injected, load: Arc::new(Load::new()) }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn load(&self) -> &Load {
+        &self.load
+    }
+
+    // Keep an eye on the worker's load without holding on to its channels
+    pub fn load_handle(&self) -> Arc<Load> {
+        Arc::clone(&self.load)
+    }
+
+    // Hands the request back if the worker's queue of steal requests is full
+    // or the worker has retired. Urgent requests have a queue of their own.
+    pub fn send_steal_request(&self, req: StealRequest) -> Result<(), StealRequest> {
+        assert_ne!(self.id, req.thief);
+        if self.load.is_retired() {
+            return Err(req);
+        }
+        let lane = match req.urgent {
+            true => &self.urgent_steal_requests,
+            false => &self.steal_requests,
+        };
+        // Count the request before the victim can receive it
+        self.load.pending_requests.inc();
+        match lane.try_send(req) {
+            Ok(()) => {
+                self.load.unpark();
+                Ok(())
             }
+            Err(TrySendError::Full(req)) => {
+                self.load.pending_requests.dec();
+                Err(req)
+            }
+            Err(TrySendError::Disconnected(_)) => panic!("Worker {} has terminated", self.id),
         }
-        self.stats.num_tasks_executed.add(num_tasks_executed);
     }
-}
-
-#[derive(Debug)]
-pub struct Coworker {
-    id: usize,
-    steal_requests: Sender<StealRequest>,
-}
 
-impl Coworker {
-    pub fn new(id: usize, steal_requests: Sender<StealRequest>) -> Self {
-        Self { id, steal_requests }
+    pub fn inject(&self, task: TaskKind) {
+        self.injected.send(task).unwrap();
+        self.load.unpark();
     }
 
-    pub fn send_steal_request(&self, req: StealRequest) {
-        assert_ne!(self.id, req.thief);
-        self.steal_requests.send(req).unwrap();
+    // Hands the task back if the worker has terminated
+    pub fn try_inject(&self, task: TaskKind) -> Result<(), TaskKind> {
+        self.injected.send(task).map_err(|err| err.0)?;
+        self.load.unpark();
+        Ok(())
     }
 }
 
@@ -232,50 +2303,133 @@ impl Clone for Coworker {
     fn clone(&self) -> Self {
         Self {
             id: self.id,
-            steal_requests: Sender::clone(&self.steal_requests),
+            steal_requests: SyncSender::clone(&self.steal_requests),
+            urgent_steal_requests: SyncSender::clone(&self.urgent_steal_requests),
+            injected: Sender::clone(&self.injected),
+            load: Arc::clone(&self.load),
         }
     }
 }
 
 // The result of asynchronous work stealing
-pub struct StealResponse<'a>(&'a Receiver<Tasks>);
+pub struct StealResponse<'a> {
+    worker: &'a Worker,
+    tasks: channel::Receiver<Tasks>,
+}
 
 impl<'a> StealResponse<'a> {
     pub fn wait(self) -> Tasks {
-        let worker = Worker::current();
-        loop {
-            match self.0.try_recv().ok() {
-                Some(tasks) => break tasks,
-                None => worker.try_handle_steal_request(),
+        let worker = self.worker;
+        let start = Instant::now();
+        let mut attempt = 0;
+        let tasks = loop {
+            // Spin for a while, then block with a timeout, so that idle
+            // workers don't burn CPU between infrequent responses
+            let ready = if attempt < SPIN_ATTEMPTS {
+                self.tasks.is_ready()
+            } else {
+                self.tasks.wait_timeout(RECV_TIMEOUT)
+            };
+            if ready {
+                break self.tasks.receive();
+            }
+            // Helpers don't wait for busy victims, but try another one (after
+            // checking whether they can leave, see `help_and_leave`), and
+            // neither does anyone who's past their deadline
+            if (worker.is_helper() || worker.past_deadline()) && attempt >= SPIN_ATTEMPTS {
+                match self.tasks.close() {
+                    true => break Tasks::None,
+                    false => break self.tasks.receive(),
+                }
+            }
+            match worker.stop_requested() {
+                // Stop waiting, unless the victim has responded in the
+                // meantime; in that case, keep the tasks and exit next time
+                true => match self.tasks.close() {
+                    true => break Tasks::Exit,
+                    false => break self.tasks.receive(),
+                },
+                false => {
+                    // Tasks pushed to us take precedence over waiting for a
+                    // busy victim, see `request_tasks`. If the victim has
+                    // responded in the meantime, keep both. The task may
+                    // have been sent after a reconfiguration we haven't seen.
+                    if let Ok(task) = worker.channels.injected.try_recv() {
+                        worker.refresh_scheduler();
+                        match self.tasks.close() {
+                            true => break Tasks::One(task),
+                            false => {
+                                worker.deque.borrow_mut().push(task);
+                                worker.update_load();
+                                break self.tasks.receive();
+                            }
+                        }
+                    }
+                    worker.try_handle_steal_request();
+                    match attempt < SPIN_ATTEMPTS {
+                        true => worker.idle(attempt),
+                        // Still let `on_idle` listeners know
+                        false => worker.notify_if_quiescent(),
+                    }
+                    attempt = attempt.saturating_add(1);
+                }
+            }
+        };
+        worker.add_idle_time(start.elapsed());
+        match tasks {
+            Tasks::One(_) | Tasks::Many(_) => {
+                if let Tasks::Many(_) = tasks {
+                    worker.stats.num_batch_steals.inc();
+                }
+                worker.stats.num_steals.inc();
+                worker.failed_steals.set(0);
+                worker.promises_followed.set(0);
+                worker.record_steal_response(true);
+                worker.end_idle_streak();
+            }
+            // Neither a success nor a failure: the victim will likely have
+            // tasks soon, so we wait for it and ask it again, rather than
+            // widening the search. Once we've run out of patience, the
+            // promise counts as an empty-handed response.
+            Tasks::Later => {
+                let followed = worker.promises_followed.get() + 1;
+                let patience = match worker.shared.config.empty_deque_response {
+                    EmptyDequeResponse::Later { patience } => patience,
+                    EmptyDequeResponse::None => 0,
+                };
+                match followed <= patience {
+                    true => {
+                        let index = worker.last_victim.take();
+                        worker.promises_followed.set(followed);
+                        worker.promised.set(index);
+                        if let Some(index) = index {
+                            worker.await_promise(index);
+                        }
+                        if worker.idle_since.get().is_none() {
+                            worker.idle_since.set(Some(start));
+                        }
+                    }
+                    false => worker.steal_failed(start),
+                }
             }
+            Tasks::None => worker.steal_failed(start),
+            Tasks::Exit => worker.end_idle_streak(),
         }
+        tasks
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::{Arc, Barrier};
+    use crate::future::Promise;
+    use std::mem;
+    use std::sync::{atomic::AtomicUsize, Arc, Barrier};
     use std::thread;
     use super::*;
 
-    fn setup(num_workers: usize) -> (Vec<Receiver<StealRequest>>, Vec<Coworker>) {
+    fn setup(num_workers: usize) -> (Vec<Inbox>, Vec<Coworker>) {
         // `N` workers communicate using `N` channels
-        let channels = (0..num_workers)
-            .map(|_| channel())
-            .collect::<Vec<(Sender<StealRequest>, _)>>();
-
-        let coworkers = channels
-            .iter()
-            .enumerate()
-            .map(|(i, (chan, _))| Coworker::new(i, Sender::clone(&chan)))
-            .collect::<Vec<Coworker>>();
-
-        let channels = channels
-            .into_iter()
-            .map(|(_, r)| r)
-            .collect::<Vec<Receiver<StealRequest>>>();
-
-        (channels, coworkers)
+        connect(num_workers)
     }
 
     #[test]
@@ -294,14 +2448,18 @@ mod tests {
                 barrier.wait();
                 // ===== Worker loop =====
                 loop {
-                    let victim = worker.select_victim(0).unwrap();
+                    let victim = worker.coworker(0).unwrap();
+                    let (response, tasks) = one_shot_channel();
                     victim.send_steal_request(StealRequest {
                         thief: worker.id,
                         steal_many: false,
-                        response: worker.channels.tasks.0.clone(),
-                    });
-                    match worker.channels.tasks.1.recv().unwrap() {
-                        Tasks::None => (),
+                        load: 0,
+                        desired: 0,
+                        urgent: false,
+                        response,
+                    }).unwrap();
+                    match tasks.recv_blocking() {
+                        Tasks::None | Tasks::Later => (),
                         Tasks::Exit => break,
                         _ => unreachable!(),
                     }
@@ -315,13 +2473,13 @@ mod tests {
         // Respond to the first ten steal requests with `Tasks::None`
         for _ in 0..10 {
             let req = leader.channels.steal_requests.recv().unwrap();
-            req.response.send(Tasks::None).unwrap();
+            req.response.send(Tasks::None);
         }
 
         // Respond with `Tasks::Exit` and join the workers
         for _ in 0..2 {
             let req = leader.channels.steal_requests.recv().unwrap();
-            req.response.send(Tasks::Exit).unwrap();
+            req.response.send(Tasks::Exit);
         }
 
         for worker in workers {
@@ -346,14 +2504,18 @@ mod tests {
                 // ===== Worker loop =====
                 loop {
                     // Worker 1 asks for single tasks, worker 2 asks for more
-                    let victim = worker.select_victim(0).unwrap();
+                    let victim = worker.coworker(0).unwrap();
+                    let (response, tasks) = one_shot_channel();
                     victim.send_steal_request(StealRequest {
                         thief: worker.id,
-                        steal_many: if worker.id == 1 { false } else { true },
-                        response: worker.channels.tasks.0.clone(),
-                    });
-                    match worker.channels.tasks.1.recv().unwrap() {
-                        Tasks::None => (),
+                        steal_many: worker.id != 1,
+                        load: 0,
+                        desired: 0,
+                        urgent: false,
+                        response,
+                    }).unwrap();
+                    match tasks.recv_blocking() {
+                        Tasks::None | Tasks::Later => (),
                         Tasks::One(task) => {
                             assert_eq!(worker.id, 1);
                             task.run();
@@ -375,8 +2537,8 @@ mod tests {
 
         // Create a few dummy tasks
         for _ in 0..10 {
-            let task = Async::new(Box::new(|| ()), None);
-            leader.push(Box::new(task));
+            let task = Async::new(|| (), None);
+            leader.push(task);
         }
 
         // Distribute tasks until deque is empty
@@ -389,7 +2551,7 @@ mod tests {
         // Ask workers to terminate
         for _ in 0..2 {
             let req = leader.channels.steal_requests.recv().unwrap();
-            req.response.send(Tasks::Exit).unwrap();
+            req.response.send(Tasks::Exit);
         }
 
         for worker in workers {
@@ -412,14 +2574,18 @@ mod tests {
                     // Worker 1 sends steal requests to worker 2
                     1 => {
                         loop {
-                            let victim = worker.select_victim(2).unwrap();
+                            let victim = worker.coworker(2).unwrap();
+                            let (response, tasks) = one_shot_channel();
                             victim.send_steal_request(StealRequest {
                                 thief: worker.id,
                                 steal_many: true,
-                                response: worker.channels.tasks.0.clone(),
-                            });
-                            match worker.channels.tasks.1.recv().unwrap() {
-                                Tasks::None => (),
+                                load: 0,
+                                desired: 0,
+                                urgent: false,
+                                response,
+                            }).unwrap();
+                            match tasks.recv_blocking() {
+                                Tasks::None | Tasks::Later => (),
                                 Tasks::One(task) => task.run(),
                                 Tasks::Many(mut loot) => {
                                     while let Some(task) = loot.pop() {
@@ -434,8 +2600,8 @@ mod tests {
                     // steal requests
                     2 => {
                         for _ in 0..10 {
-                            let task = Async::new(Box::new(|| ()), None);
-                            worker.push(Box::new(task));
+                            let task = Async::new(|| (), None);
+                            worker.push(task);
                         }
                         while worker.has_tasks() {
                             let req = worker.channels.steal_requests.recv().unwrap();
@@ -443,7 +2609,7 @@ mod tests {
                         }
                         // Send `Tasks::Exit` to worker 1 and exit
                         let req = worker.channels.steal_requests.recv().unwrap();
-                        req.response.send(Tasks::Exit).unwrap();
+                        req.response.send(Tasks::Exit);
                     }
                     _ => unreachable!()
                 }
@@ -455,9 +2621,745 @@ mod tests {
         }
     }
 
+    #[test]
+    fn steal_desired_number_of_tasks() {
+        let (mut channels, coworkers) = setup(2);
+
+        let channel = channels.remove(1);
+        let thief_coworkers = coworkers.clone();
+        let thief = thread::spawn(move || {
+            let worker = Worker::new(1, channel, thief_coworkers);
+            let mut loot_sizes = vec![];
+            for desired in [3, 0, 100] {
+                let victim = worker.coworker(0).unwrap();
+                let (response, tasks) = one_shot_channel();
+                victim.send_steal_request(StealRequest {
+                    thief: worker.id,
+                    steal_many: true,
+                    load: 0,
+                    desired,
+                    urgent: false,
+                    response,
+                }).unwrap();
+                match tasks.recv_blocking() {
+                    Tasks::Many(loot) => loot_sizes.push(loot.len()),
+                    _ => unreachable!(),
+                }
+            }
+            loot_sizes
+        });
+
+        let leader = Worker::new(0, channels.remove(0), coworkers);
+        for _ in 0..12 {
+            let task = Async::new(|| (), None);
+            leader.push(task);
+        }
+
+        for _ in 0..3 {
+            let req = leader.channels.steal_requests.recv().unwrap();
+            leader.handle_steal_request(req);
+        }
+
+        // 12 tasks: 3 desired, then half of 9, then at most half of 4
+        assert_eq!(thief.join().unwrap(), vec![3, 5, 2]);
+    }
+
+    #[test]
+    fn steal_by_thief_load() {
+        let (mut channels, coworkers) = setup(1);
+        let worker = Worker::new(0, channels.remove(0), coworkers);
+        for _ in 0..12 {
+            worker.push(Async::new(|| (), None));
+        }
+
+        let steal = |load, desired| {
+            let (response, tasks) = one_shot_channel();
+            worker.handle_steal_request(StealRequest {
+                thief: 1,
+                steal_many: true,
+                load,
+                desired,
+                urgent: false,
+                response,
+            });
+            match tasks.receive() {
+                Tasks::Many(loot) => loot.len(),
+                _ => 0,
+            }
+        };
+        // Half of 12 + 4 tasks, minus the thief's 4
+        assert_eq!(steal(4, 0), 4);
+        // Half of 8 + 2 tasks, minus 2, and no more than 3 on top of 2
+        assert_eq!(steal(2, 5), 3);
+        // A thief with more tasks than we have gets nothing
+        assert_eq!(steal(10, 0), 0);
+        assert_eq!(worker.deque.borrow().len(), 5);
+    }
+
+    #[test]
+    fn max_steal_many() {
+        let (mut channels, coworkers) = setup(1);
+        let mut config = Config::new(1);
+        config.max_steal_many = 2;
+        let shared = Arc::new(Shared::new(config));
+        let worker = Worker::with_shared(0, channels.remove(0), coworkers, shared);
+        for _ in 0..12 {
+            let task = Async::new(|| (), None);
+            worker.push(task);
+        }
+
+        for desired in [0, 5] {
+            let (response, tasks) = one_shot_channel();
+            worker.handle_steal_request(StealRequest {
+                thief: 1,
+                steal_many: true,
+                load: 0,
+                desired,
+                urgent: false,
+                response,
+            });
+            match tasks.receive() {
+                Tasks::Many(loot) => assert_eq!(loot.len(), 2),
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(worker.deque.borrow().len(), 8);
+    }
+
+    #[test]
+    fn steal_percent() {
+        let (mut channels, coworkers) = setup(1);
+        let shared = Arc::new(Shared::new(Config::new(1)));
+        let worker = Worker::with_shared(0, channels.remove(0), coworkers, Arc::clone(&shared));
+        for _ in 0..20 {
+            worker.push(Async::new(|| (), None));
+        }
+
+        let steal = |desired| {
+            let (response, tasks) = one_shot_channel();
+            worker.handle_steal_request(StealRequest {
+                thief: 1,
+                steal_many: true,
+                load: 0,
+                desired,
+                urgent: false,
+                response,
+            });
+            match tasks.receive() {
+                Tasks::Many(loot) => loot.len(),
+                _ => unreachable!(),
+            }
+        };
+        let mut config = shared.scheduler();
+        config.steal_percent = 25;
+        shared.reconfigure(config);
+        // Not until the victim picks up the new parameters
+        assert_eq!(steal(0), 10);
+        worker.refresh_scheduler();
+        assert_eq!(steal(0), 3);
+        assert_eq!(steal(4), 2);
+        config.steal_percent = 100;
+        config.max_steal_many = 3;
+        shared.reconfigure(config);
+        worker.refresh_scheduler();
+        assert_eq!(steal(0), 3);
+        assert_eq!(worker.deque.borrow().len(), 2);
+    }
+
+    #[test]
+    fn steal_newest() {
+        let (mut channels, coworkers) = setup(1);
+        let mut config = Config::new(1);
+        config.steal_policy = StealPolicy::Newest;
+        config.steal_percent = 25;
+        let shared = Arc::new(Shared::new(config));
+        let worker = Worker::with_shared(0, channels.remove(0), coworkers, shared);
+        for priority in 0..9 {
+            // Use priorities to tell tasks apart
+            worker.push(Async::new(|| (), None).with_priority(priority));
+        }
+
+        let steal = |steal_many| {
+            let (response, tasks) = one_shot_channel();
+            worker.handle_steal_request(StealRequest {
+                thief: 1,
+                steal_many,
+                load: 0,
+                desired: 0,
+                urgent: false,
+                response,
+            });
+            match tasks.receive() {
+                Tasks::One(task) => vec![task.priority()],
+                Tasks::Many(loot) => loot.iter().map(TaskKind::priority).collect(),
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(steal(false), vec![8]);
+        assert_eq!(steal(true), vec![7, 6]);
+        assert_eq!(worker.pop().unwrap().priority(), 5);
+    }
+
+    #[test]
+    fn withdrawn_steal_request() {
+        use crate::future::{Future, Promise};
+
+        let (mut channels, coworkers) = setup(1);
+        let worker = Worker::new(0, channels.remove(0), coworkers);
+        let mut f = Future::lazy();
+        worker.push(Async::new(|| 1, Some(Promise::from(&mut f))));
+
+        let steal = |closed| {
+            let (response, tasks) = one_shot_channel();
+            if closed {
+                assert!(tasks.close());
+            }
+            worker.handle_steal_request(StealRequest {
+                thief: 1,
+                steal_many: false,
+                load: 0,
+                desired: 0,
+                urgent: false,
+                response,
+            });
+            tasks
+        };
+        // Tasks that the thief no longer wants stay unpromoted
+        let _ = steal(true);
+        assert!(matches!(&f, Future::Lazy(lazy) if lazy.is_pending()));
+        match steal(false).receive() {
+            Tasks::One(task) => task.run(),
+            _ => unreachable!(),
+        }
+        assert!(matches!(&f, Future::Lazy(lazy) if lazy.is_promoted()));
+        assert_eq!(f.get(), 1);
+    }
+
+    #[test]
+    fn batched_results() {
+        use crate::batch::RemoteReceiver;
+        use crate::future::{Future, Promise};
+
+        let (mut channels, coworkers) = setup(1);
+        let worker = Worker::new(0, channels.remove(0), coworkers);
+        let mut futures = (0..8).map(|_| Future::lazy()).collect::<Vec<_>>();
+        for (i, f) in futures.iter_mut().enumerate() {
+            worker.push(Async::new(move || i, Some(Promise::from(f))));
+        }
+
+        let (response, tasks) = one_shot_channel();
+        worker.handle_steal_request(StealRequest {
+            thief: 1,
+            steal_many: true,
+            load: 0,
+            desired: 0,
+            urgent: false,
+            response,
+        });
+        let mut loot = match tasks.receive() {
+            Tasks::Many(loot) => loot,
+            _ => unreachable!(),
+        };
+        assert!(loot.len() > 1);
+        thread::spawn(move || {
+            while let Some(task) = loot.pop() {
+                task.run();
+            }
+        }).join().unwrap();
+
+        // The stolen siblings share a batch, the others are run in place
+        let batches = futures
+            .iter()
+            .filter_map(|f| match f {
+                Future::Lazy(lazy) => match lazy.receiver() {
+                    Some(RemoteReceiver::Batch(receiver)) => Some(Arc::as_ptr(receiver.batch())),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert!(batches.len() > 1 && batches.iter().all(|&batch| batch == batches[0]));
+        while let Some(task) = worker.pop() {
+            task.run();
+        }
+        let results = futures.into_iter().map(Future::get).collect::<Vec<_>>();
+        assert_eq!(results, (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn steal_for_efficiency_workers() {
+        let (mut channels, coworkers) = setup(2);
+        let mut config = Config::new(2);
+        config.efficiency_workers.push(1..2);
+        config.heavy_task_cost = 10;
+        let shared = Arc::new(Shared::new(config));
+        let worker = Worker::with_shared(0, channels.remove(0), coworkers, shared);
+        for cost in [1, 50, 3, 20, 2] {
+            worker.push(Async::new(|| (), None).with_cost(cost));
+        }
+
+        let steal = |steal_many| {
+            let (response, tasks) = one_shot_channel();
+            worker.handle_steal_request(StealRequest {
+                thief: 1,
+                steal_many,
+                load: 0,
+                desired: 0,
+                urgent: false,
+                response,
+            });
+            match tasks.receive() {
+                Tasks::One(task) => vec![task.cost()],
+                Tasks::Many(loot) => loot.iter().map(TaskKind::cost).collect(),
+                _ => vec![],
+            }
+        };
+        assert_eq!(steal(false), vec![1]);
+        // Half of the remaining tasks, the cheapest two of which are light
+        assert_eq!(steal(true), vec![2, 3]);
+        assert_eq!(steal(true), vec![]);
+        assert_eq!(worker.deque.borrow().len(), 2);
+    }
+
+    #[test]
+    fn steal_lowest_priority() {
+        let (mut channels, coworkers) = setup(1);
+        let mut config = Config::new(1);
+        config.steal_policy = StealPolicy::LowestPriority;
+        let shared = Arc::new(Shared::new(config));
+        let worker = Worker::with_shared(0, channels.remove(0), coworkers, shared);
+        for priority in [0, 9, 1, 8, 2, 7] {
+            let task = Async::new(|| (), None).with_priority(priority);
+            worker.push(task);
+        }
+
+        let steal = |steal_many| {
+            let (response, tasks) = one_shot_channel();
+            worker.handle_steal_request(StealRequest {
+                thief: 1,
+                steal_many,
+                load: 0,
+                desired: 0,
+                urgent: false,
+                response,
+            });
+            match tasks.receive() {
+                Tasks::One(task) => vec![task.priority()],
+                Tasks::Many(loot) => loot.iter().map(TaskKind::priority).collect(),
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(steal(false), vec![0]);
+        // deque: [7, 2, 8, 1, 9]
+        assert_eq!(steal(true), vec![7, 2, 1]);
+        assert_eq!(worker.deque.borrow().iter().map(TaskKind::priority).collect::<Vec<_>>(), vec![8, 9]);
+    }
+
+    #[test]
+    fn steal_fewest_migrations() {
+        let (mut channels, coworkers) = setup(1);
+        let mut config = Config::new(1);
+        config.steal_policy = StealPolicy::FewestMigrations;
+        let shared = Arc::new(Shared::new(config));
+        let worker = Worker::with_shared(0, channels.remove(0), coworkers, shared);
+        // Tasks identified by priority, some of which have been stolen before
+        for (priority, migrations) in [(0, 1), (1, 0), (2, 2), (3, 0), (4, 1), (5, 0)] {
+            let mut task = Async::new(|| (), None).with_priority(priority);
+            for _ in 0..migrations {
+                task.promote();
+            }
+            worker.push(task);
+        }
+
+        let steal = |steal_many| {
+            let (response, tasks) = one_shot_channel();
+            worker.handle_steal_request(StealRequest {
+                thief: 1,
+                steal_many,
+                load: 0,
+                desired: 0,
+                urgent: false,
+                response,
+            });
+            match tasks.receive() {
+                Tasks::One(task) => vec![(task.priority(), task.migrations())],
+                Tasks::Many(loot) => loot.iter().map(|t| (t.priority(), t.migrations())).collect(),
+                _ => unreachable!(),
+            }
+        };
+        // The oldest task that has never migrated (and now has)
+        assert_eq!(steal(false), vec![(1, 1)]);
+        // deque: [5, 4, 3, 2, 0], of which half (three tasks) are stolen:
+        // the two that have never migrated, and the oldest of the others
+        assert_eq!(steal(true), vec![(5, 1), (3, 1), (0, 2)]);
+        let left = worker.deque.borrow().iter().map(TaskKind::migrations).collect::<Vec<_>>();
+        assert_eq!(left, vec![1, 2]);
+    }
+
+    #[test]
+    fn handle_all_steal_requests() {
+        let (mut channels, coworkers) = setup(4);
+        let shared = Arc::new(Shared::new(Config::new(4)));
+        let worker = Worker::with_shared(0, channels.remove(0), coworkers.clone(), shared);
+        for _ in 0..12 {
+            worker.push(Async::new(|| (), None));
+        }
+
+        let receivers: Vec<_> = (1..4).map(|thief| {
+            let (response, tasks) = one_shot_channel();
+            let req = StealRequest { thief, steal_many: true, load: 0, desired: 0, urgent: false, response };
+            coworkers[0].send_steal_request(req).unwrap();
+            tasks
+        }).collect();
+        assert_eq!(worker.load.pending_requests(), 3);
+        worker.handle_all_steal_requests();
+        assert_eq!(worker.load.pending_requests(), 0);
+        assert_stat_eq!(worker.stats.max_pending_requests.get(), 3);
+        worker.handle_all_steal_requests();
+        assert_stat_eq!(worker.stats.num_polls.get(), 2);
+        assert_stat_eq!(worker.stats.avg_pending_requests(), 1.5);
+
+        for tasks in receivers {
+            match tasks.receive() {
+                Tasks::Many(loot) => assert_eq!(loot.len(), 3),
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(worker.deque.borrow().len(), 3);
+    }
+
+    #[test]
+    fn urgent_wait_panics() {
+        let (mut channels, coworkers) = setup(1);
+        let worker = Worker::new(0, channels.remove(0), coworkers);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            worker.help_until_urgent(|| panic!("Oops"));
+        }));
+        assert!(result.is_err());
+        assert_eq!(worker.urgent_waits.get(), 0);
+    }
+
+    #[test]
+    fn urgent_steal_requests() {
+        let (mut channels, coworkers) = setup(3);
+        let shared = Arc::new(Shared::new(Config::new(3)));
+        let worker = Worker::with_shared(0, channels.remove(0), coworkers.clone(), shared);
+        worker.push(Async::new(|| (), None));
+
+        let receivers: Vec<_> = [(1, false), (2, true)].into_iter().map(|(thief, urgent)| {
+            let (response, tasks) = one_shot_channel();
+            let req = StealRequest { thief, steal_many: false, load: 0, desired: 0, urgent, response };
+            coworkers[0].send_steal_request(req).unwrap();
+            tasks
+        }).collect();
+        assert_eq!(worker.load.pending_requests(), 2);
+        // The urgent request was sent last, but is answered first
+        assert!(worker.try_handle_steal_request());
+        assert!(worker.try_handle_steal_request());
+        assert!(matches!(receivers[0].receive(), Tasks::None));
+        assert!(matches!(receivers[1].receive(), Tasks::One(_)));
+        assert_stat_eq!(worker.stats.num_urgent_requests.get(), 1);
+
+        // Urgent thieves get a full share, regardless of how many tasks they
+        // asked for
+        for _ in 0..8 {
+            worker.push(Async::new(|| (), None));
+        }
+        for (urgent, expected) in [(false, 1), (true, 4)] {
+            let (response, tasks) = one_shot_channel();
+            let req = StealRequest { thief: 1, steal_many: true, load: 0, desired: 1, urgent, response };
+            worker.handle_steal_request(req);
+            match tasks.receive() {
+                Tasks::Many(loot) => assert_eq!(loot.len(), expected),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn retire_worker() {
+        let (mut channels, coworkers) = setup(3);
+        let shared = Arc::new(Shared::new(Config::new(3)));
+        let heir = Worker::with_shared(0, channels.remove(0), coworkers.clone(), Arc::clone(&shared));
+        let worker = Worker::with_shared(1, channels.remove(0), coworkers.clone(), shared);
+        let other = channels.remove(0);
+        for _ in 0..3 {
+            worker.push(Async::new(|| (), None));
+        }
+        worker.push_background(Async::new(|| (), None));
+        let (response, tasks) = one_shot_channel();
+        let req = StealRequest { thief: 2, steal_many: false, load: 0, desired: 0, urgent: false, response };
+        coworkers[1].send_steal_request(req).unwrap();
+
+        worker.load.retire();
+        let stats = worker.retire();
+        assert_stat_eq!(stats.num_tasks_handed_over.get(), 4);
+        // Tasks are spread over the survivors
+        assert_eq!(heir.channels.injected.try_iter().count(), 2);
+        assert_eq!(other.injected.try_iter().count(), 2);
+        // The pending request has been forwarded, but no more are accepted
+        assert_eq!(heir.load.pending_requests(), 1);
+        let (response, _) = one_shot_channel();
+        let req = StealRequest { thief: 2, steal_many: false, load: 0, desired: 0, urgent: false, response };
+        assert!(coworkers[1].send_steal_request(req).is_err());
+        heir.push(Async::new(|| (), None));
+        assert!(heir.try_handle_steal_request());
+        assert!(matches!(tasks.receive(), Tasks::One(_)));
+
+        // Whatever is sent to the retired worker ends up with the heir
+        coworkers[1].inject(Async::new(|| (), None).into());
+        heir.adopt_orphans();
+        assert_eq!(heir.deque.borrow().len(), 1);
+        assert_eq!(heir.shared.num_orphans.get(), 0);
+    }
+
+    #[test]
+    fn park_with_injected_task() {
+        let (mut channels, coworkers) = setup(2);
+        let worker = Worker::new(0, channels.remove(0), coworkers.clone());
+        // Counted by whoever pushed it, see `push_to_worker`
+        coworkers[0].inject(Async::detached(|| ()).into());
+        worker.park();
+        assert!(worker.has_tasks());
+        assert_eq!(worker.load.created(), 0);
+        assert_eq!(worker.stats.num_tasks_created.get(), 0);
+        assert!(!worker.load.is_parked());
+    }
+
+    #[test]
+    fn split_only_task() {
+        let (mut channels, coworkers) = setup(2);
+        let worker = Worker::new(0, channels.remove(0), coworkers);
+        worker.push(RangeTask::new(0..8, |_| ()));
+
+        // One large task feeds several thieves
+        for (steal_many, stolen) in [(true, "<Range 4..8>"), (false, "<Range 2..4>")] {
+            let (response, tasks) = one_shot_channel();
+            worker.handle_steal_request(StealRequest { thief: 1, steal_many, load: 0, desired: 0, urgent: false, response });
+            match tasks.receive() {
+                Tasks::One(task) => assert_eq!(format!("{:?}", task), stolen),
+                _ => panic!("Expected part of the range"),
+            }
+        }
+        assert_eq!(format!("{:?}", worker.pop().unwrap()), "<Range 0..2>");
+        assert_stat_eq!(worker.stats.num_tasks_split.get(), 2);
+        assert_eq!(worker.load.created(), 3);
+
+        // Tasks that can't be split are stolen as a whole
+        worker.push(RangeTask::new(0..1, |_| ()));
+        let (response, tasks) = one_shot_channel();
+        worker.handle_steal_request(StealRequest { thief: 1, steal_many: false, load: 0, desired: 0, urgent: false, response });
+        assert!(matches!(tasks.receive(), Tasks::One(_)));
+        assert!(!worker.has_tasks());
+    }
+
+    #[test]
+    fn share_loot() {
+        let (mut channels, coworkers) = setup(3);
+        let worker = Worker::new(0, channels.remove(0), coworkers.clone());
+        let (response, tasks) = one_shot_channel();
+        let req = StealRequest { thief: 2, steal_many: true, load: 0, desired: 0, urgent: false, response };
+        coworkers[0].send_steal_request(req).unwrap();
+
+        let mut loot = Deque::new();
+        for _ in 0..4 {
+            loot.push(TaskKind::from(Async::detached(|| ())));
+        }
+        assert!(worker.accept(Tasks::Many(loot)).is_some());
+        // Three tasks left, of which the thief gets half
+        match tasks.receive() {
+            Tasks::Many(tasks) => assert_eq!(tasks.len(), 1),
+            Tasks::One(_) => (),
+            _ => panic!("Thief went away empty-handed"),
+        }
+        assert_stat_eq!(worker.stats.num_steals_served.get(), 1);
+    }
+
+    #[test]
+    fn task_memory_stats() {
+        let (mut channels, coworkers) = setup(1);
+        let worker = Worker::new(0, channels.remove(0), coworkers);
+        let data = [0u64; 8];
+        for _ in 0..3 {
+            worker.push(Async::detached(move || data.len()));
+        }
+        worker.pop();
+        worker.push(RangeTask::new(0..10, |_| ()));
+
+        assert_stat_eq!(worker.stats.num_tasks_created.get(), 4);
+        // The closure and the (absent) promise share an allocation
+        let bytes = 64 + mem::size_of::<Option<Promise<usize>>>();
+        assert_stat_eq!(worker.stats.task_bytes.get(), 3 * bytes as u64);
+        assert_stat_eq!(worker.stats.max_queued_tasks.get(), 3);
+    }
+
+    #[test]
+    fn poll_interval() {
+        let (mut channels, coworkers) = setup(2);
+        let mut config = Config::new(2);
+        config.poll_interval = PollInterval::Tasks(2);
+        config.adaptive_polling = true;
+        let shared = Arc::new(Shared::new(config));
+        let worker = Worker::with_shared(0, channels.remove(0), coworkers.clone(), shared);
+        for _ in 0..8 {
+            worker.push(Async::new(|| (), None));
+        }
+
+        // Nobody is stealing: check after 2 tasks, then after 4 more
+        worker.poll_steal_requests(false);
+        worker.poll_steal_requests(false);
+        assert_eq!(worker.poll_backoff.get(), 1);
+        let (response, tasks) = one_shot_channel();
+        let req = StealRequest { thief: 1, steal_many: false, load: 0, desired: 0, urgent: false, response };
+        coworkers[0].send_steal_request(req).unwrap();
+        for _ in 0..3 {
+            worker.poll_steal_requests(false);
+            assert!(!tasks.is_ready());
+        }
+        worker.poll_steal_requests(false);
+        assert!(matches!(tasks.receive(), Tasks::One(_)));
+        assert_eq!(worker.poll_backoff.get(), 0);
+    }
+
+    #[test]
+    fn full_steal_request_queues() {
+        let (mut channels, coworkers) = connect_with_capacity(3, 1);
+        let request = |thief| {
+            let (response, tasks) = one_shot_channel();
+            (StealRequest { thief, steal_many: false, load: 0, desired: 0, urgent: false, response }, tasks)
+        };
+        let (req, _pending) = request(2);
+        coworkers[0].send_steal_request(req).unwrap();
+        let (req, _) = request(2);
+        assert!(coworkers[0].send_steal_request(req).is_err());
+
+        let shared = Arc::new(Shared::new(Config::new(3)));
+        let worker = Worker::with_shared(1, channels.remove(1), coworkers, shared);
+
+        // Worker 0 is busy, so the request ends up with worker 2
+        let (req, tasks) = request(1);
+        worker.send_steal_request(req);
+        assert!(!tasks.is_ready());
+        assert_eq!(worker.stats.num_shed_requests.get(), 0);
+
+        // Everyone is busy
+        let (req, tasks) = request(1);
+        worker.send_steal_request(req);
+        assert!(matches!(tasks.receive(), Tasks::None));
+        assert_stat_eq!(worker.stats.num_shed_requests.get(), 1);
+        assert!(channels[1].steal_requests.try_recv().is_ok());
+    }
+
+    #[test]
+    fn blacklisted_victims() {
+        let (mut channels, coworkers) = setup(3);
+        let mut config = Config::new(3);
+        config.blacklist_after = Some(2);
+        config.blacklist_for = 3;
+        let shared = Arc::new(Shared::new(config));
+        let worker = Worker::with_shared(0, channels.remove(0), coworkers, shared);
+
+        // Worker 1 never has anything to share, worker 2 always does. Returns
+        // the victim of the steal attempt.
+        let steal = || {
+            let response = worker.request_tasks(false);
+            let (victim, req) = channels
+                .iter()
+                .enumerate()
+                .find_map(|(i, inbox)| inbox.steal_requests.try_recv().ok().map(|req| (i + 1, req)))
+                .unwrap();
+            match victim {
+                1 => req.response.send(Tasks::None),
+                _ => req.response.send(Tasks::One(TaskKind::from(Async::detached(|| ())))),
+            }
+            response.wait();
+            victim
+        };
+        let mut attempts = 0;
+        while attempts < 2 {
+            if steal() == 1 {
+                attempts += 1;
+            }
+        }
+        assert!(worker.is_blacklisted(1) && !worker.is_blacklisted(2));
+        assert_stat_eq!(worker.stats.num_blacklisted_victims.get(), 1);
+        for _ in 0..3 {
+            assert_eq!(steal(), 2);
+        }
+        assert!(!worker.is_blacklisted(1));
+    }
+
+    #[test]
+    fn promised_work() {
+        let (mut channels, coworkers) = setup(3);
+        let mut config = Config::new(3);
+        config.empty_deque_response = EmptyDequeResponse::Later { patience: 2 };
+        let shared = Arc::new(Shared::new(config));
+        let worker = Worker::with_shared(0, channels.remove(0), coworkers.clone(), Arc::clone(&shared));
+
+        // A victim that runs a task promises more
+        let victim = Worker::with_shared(1, channels.remove(0), coworkers, shared);
+        let (response, tasks) = one_shot_channel();
+        let req = StealRequest { thief: 0, steal_many: false, load: 0, desired: 0, urgent: false, response };
+        victim.handle_steal_request(req);
+        assert!(matches!(tasks.receive(), Tasks::None));
+        victim.load.running.inc();
+        let (response, tasks) = one_shot_channel();
+        let req = StealRequest { thief: 0, steal_many: false, load: 0, desired: 0, urgent: false, response };
+        victim.handle_steal_request(req);
+        assert!(matches!(tasks.receive(), Tasks::Later));
+        assert_stat_eq!(victim.stats.num_work_promised.get(), 1);
+
+        // Worker 1 keeps promising. Returns the victim of the steal attempt.
+        let inboxes = [&victim.channels.steal_requests, &channels[0].steal_requests];
+        let steal = || {
+            let response = worker.request_tasks(false);
+            let (victim, req) = inboxes
+                .iter()
+                .enumerate()
+                .find_map(|(i, inbox)| inbox.try_recv().ok().map(|req| (i + 1, req)))
+                .unwrap();
+            match victim {
+                1 => req.response.send(Tasks::Later),
+                _ => req.response.send(Tasks::None),
+            }
+            response.wait();
+            victim
+        };
+        while steal() != 1 {}
+        // Two more requests go to the same victim, then we look elsewhere.
+        // Promises we follow don't count as failed steal attempts, but the
+        // last one does.
+        let failed_steals = worker.failed_steals.get();
+        assert_eq!(steal(), 1);
+        assert_eq!(worker.failed_steals.get(), failed_steals);
+        assert_eq!(steal(), 1);
+        assert!(worker.promised.get().is_none());
+        assert_eq!(worker.failed_steals.get(), failed_steals + 1);
+    }
+
+    #[test]
+    fn coworker_lookup() {
+        let (mut channels, coworkers) = setup(5);
+        let mut config = Config::new(5);
+        config.locality_groups = vec![2];
+        let shared = Arc::new(Shared::new(config));
+        let worker = Worker::with_shared(2, channels.remove(2), coworkers, shared);
+
+        assert_eq!(worker.coworker(0).map(Coworker::id), Some(0));
+        assert_eq!(worker.coworker(4).map(Coworker::id), Some(4));
+        assert!(worker.coworker(2).is_none() && worker.coworker(5).is_none());
+        for _ in 0..10 {
+            assert_ne!(worker.random_coworker().unwrap().id(), 2);
+        }
+        let group = |level| worker.coworkers_in_group(level).map(Coworker::id).collect::<Vec<_>>();
+        assert_eq!(group(0), vec![3]);
+        assert_eq!(group(1), vec![0, 1, 3, 4]);
+    }
+
     thread_local! {
         // See interior mutability pattern
-        static ID: RefCell<usize> = RefCell::new(0);
+        static ID: RefCell<usize> = const { RefCell::new(0) };
     }
 
     fn get_id() -> usize {
@@ -496,4 +3398,148 @@ mod tests {
             worker.join().unwrap();
         }
     }
+
+    #[test]
+    fn accept_steal_responses() {
+        let (mut channels, coworkers) = setup(1);
+        let worker = Worker::new(0, channels.remove(0), coworkers);
+
+        assert!(worker.accept(Tasks::None).is_none());
+
+        let mut loot = Deque::new();
+        for _ in 0..3 {
+            let task = Async::new(|| (), None);
+            loot.push(TaskKind::from(task));
+        }
+        // One task to run next, the rest end up in the local deque
+        assert!(worker.accept(Tasks::Many(loot)).is_some());
+        assert_eq!(worker.deque.borrow().len(), 2);
+
+        // Exit is deferred, and no more tasks are stolen
+        assert!(worker.accept(Tasks::Exit).is_none());
+        assert!(matches!(worker.try_steal_one(), Tasks::None));
+        assert_eq!(worker.stats.num_steal_requests.get(), 0);
+    }
+
+    #[test]
+    fn stop_requested() {
+        let (mut channels, coworkers) = setup(2);
+        let worker = Worker::new(0, channels.remove(0), coworkers);
+        assert!(!worker.stop_requested());
+
+        // No more steal requests are sent once a stop has been requested
+        worker.shared().request_stop();
+        assert!(worker.stop_requested());
+        assert!(matches!(worker.steal_one().wait(), Tasks::Exit));
+        assert_eq!(worker.stats.num_steal_requests.get(), 0);
+
+        worker.resume();
+        assert!(!worker.stop_requested());
+    }
+
+    #[test]
+    fn defer_task() {
+        let (mut channels, coworkers) = setup(1);
+        let worker = Worker::new(0, channels.remove(0), coworkers);
+        worker.push(RangeTask::new(0..1, |_| ()));
+        worker.defer(RangeTask::new(1..2, |_| ()));
+        worker.push(RangeTask::new(2..3, |_| ()));
+
+        let order = std::iter::from_fn(|| worker.pop())
+            .map(|task| format!("{:?}", task))
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec!["<Range 2..3>", "<Range 0..1>", "<Range 1..2>"]);
+        assert_eq!(worker.load().created(), 3);
+    }
+
+    #[test]
+    fn task_deadlines() {
+        let (mut channels, coworkers) = setup(1);
+        let worker = Worker::new(0, channels.remove(0), coworkers);
+        let sleep = |ms| move || std::thread::sleep(Duration::from_millis(ms));
+
+        // On time, overrun, late start (and overrun)
+        worker.run(Async::new(sleep(0), None).with_deadline(Duration::from_secs(60)).into());
+        worker.run(Async::new(sleep(5), None).with_deadline(Duration::from_millis(1)).into());
+        let late = Async::new(sleep(0), None).with_deadline(Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(1));
+        worker.run(late.into());
+        worker.run(Async::new(sleep(0), None).into());
+
+        assert_stat_eq!(worker.stats.num_late_starts.get(), 1);
+        assert_stat_eq!(worker.stats.num_overruns.get(), 2);
+    }
+
+    #[test]
+    fn push_batch() {
+        let (mut channels, coworkers) = setup(1);
+        let worker = Worker::new(0, channels.remove(0), coworkers);
+        worker.push_batch((0..3).map(|i| RangeTask::new(i..i + 1, |_| ())));
+        assert_eq!(worker.load().created(), 3);
+        assert_eq!(worker.load().queued(), 3);
+
+        // Same order as pushing one by one
+        let order = std::iter::from_fn(|| worker.pop())
+            .map(|task| format!("{:?}", task))
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec!["<Range 2..3>", "<Range 1..2>", "<Range 0..1>"]);
+
+        // Producing a task pushes another one
+        worker.push_batch((0..3).map(|i| {
+            worker.push(RangeTask::new(i..i + 1, |_| ()));
+            RangeTask::new(i..i + 1, |_| ())
+        }));
+        assert_eq!(worker.load().created(), 9);
+        assert_eq!(worker.load().queued(), 6);
+    }
+
+    #[test]
+    fn queue_capacity() {
+        let (mut channels, coworkers) = setup(2);
+        let mut config = Config::new(2);
+        config.queue_capacity = Some(2);
+        let shared = Arc::new(Shared::new(config));
+        let worker = Worker::with_shared(0, channels.remove(0), coworkers.clone(), Arc::clone(&shared));
+        let other = Worker::with_shared(1, channels.remove(0), coworkers, shared);
+
+        // Tasks that don't fit run right away
+        let count = Arc::new(AtomicUsize::new(0));
+        let task = || {
+            let count = Arc::clone(&count);
+            Detached::new(move || { count.fetch_add(1, Relaxed); })
+        };
+        worker.push_batch((0..3).map(|_| task()));
+        assert_eq!(worker.load().queued(), 2);
+        assert_eq!(worker.load().created(), 2);
+        assert_eq!(count.load(Relaxed), 1);
+
+        // Also when pushed to another worker whose queue is full
+        other.push(Detached::new(|| ()));
+        other.push(Detached::new(|| ()));
+        worker.push_to_worker(1, task());
+        assert_eq!(count.load(Relaxed), 2);
+        assert_eq!(worker.load().created(), 2);
+        assert_stat_eq!(worker.stats.num_tasks_inlined.get(), 2);
+    }
+
+    #[test]
+    fn pop_fair() {
+        let (mut channels, coworkers) = setup(1);
+        let mut config = Config::new(1);
+        config.max_lifo_streak = 2;
+        let shared = Arc::new(Shared::new(config));
+        let worker = Worker::with_shared(0, channels.remove(0), coworkers, shared);
+        for i in 0..6 {
+            worker.push(RangeTask::new(i..i + 1, |_| ()));
+        }
+
+        // Two from the top, one from the bottom, and so on
+        let order = std::iter::from_fn(|| worker.pop_fair())
+            .map(|task| format!("{:?}", task))
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec![
+            "<Range 5..6>", "<Range 4..5>", "<Range 0..1>",
+            "<Range 3..4>", "<Range 2..3>", "<Range 1..2>",
+        ]);
+    }
 }
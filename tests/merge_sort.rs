@@ -0,0 +1,26 @@
+use rusty_tasking::algorithms::par_merge_sort;
+use rusty_tasking::runtime::Runtime;
+
+#[test]
+fn merge_sort() {
+    // Thieves take several tasks at once, and the recursion is deep enough
+    // for every worker to get its share
+    let runtime = Runtime::builder().num_workers(4).max_steal_many(4).init();
+
+    let mut x = 88_172_645_463_325_252_u64;
+    let mut v: Vec<u64> = (0..1 << 20)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        })
+        .collect();
+    let mut expected = v.clone();
+    expected.sort_unstable();
+    par_merge_sort(&mut v);
+    assert_eq!(v, expected);
+
+    let stats = runtime.join();
+    assert!(cfg!(feature = "no-stats") || stats.num_tasks_executed.get() > 0);
+}
@@ -1,20 +1,18 @@
-#[macro_use]
-extern crate rusty_tasking;
+// Checks the number of tasks executed, which `no-stats` doesn't count
+#![cfg(not(feature = "no-stats"))]
+
 extern crate utils;
 
+use rusty_tasking::{finish, scoped_spawn};
 use rusty_tasking::runtime::Runtime;
-use rusty_tasking::scope::Scope;
-use rusty_tasking::task::ScopedAsync;
-use rusty_tasking::worker::Worker;
 use std::time::Duration;
+use utils::workloads::{self, Job, Workload};
 
 static NUM_TASKS: u32 = 100;
 static TASK_LENGTH: Duration = Duration::from_micros(10);
 
-fn produce() {
-    for _ in 0..NUM_TASKS {
-        scoped_spawn!(utils::compute(TASK_LENGTH));
-    }
+fn spawn(job: Job) {
+    scoped_spawn!(job());
 }
 
 #[test]
@@ -22,7 +20,46 @@ fn spc() {
     // Create three additional workers
     let runtime = Runtime::init(4);
 
-    finish! { produce(); }
+    let workload = Workload::new(NUM_TASKS).task_length(TASK_LENGTH);
+    finish! { workloads::spc(&workload, spawn); }
+
+    let stats = runtime.join();
+    assert_eq!(stats.num_tasks_executed.get(), NUM_TASKS);
+
+    // What `Runtime::report` prints: a row per worker, then the totals
+    let report = stats.to_string();
+    let rows = report.lines().skip(1).map(|row| row.split_whitespace().collect::<Vec<_>>());
+    let ids = rows.clone().map(|row| row[0]).collect::<Vec<_>>();
+    assert_eq!(ids, ["0", "1", "2", "3", "total"]);
+    let tasks = rows.map(|row| row[1].parse::<u32>().unwrap()).collect::<Vec<_>>();
+    assert_eq!(tasks[..4].iter().sum::<u32>(), tasks[4]);
+    assert_eq!(tasks[4], NUM_TASKS);
+    assert!(stats.balance().to_string().starts_with("imbalance"));
+}
+
+#[test]
+fn spc_imbalanced() {
+    let runtime = Runtime::init(4);
+
+    let workload = Workload::new(NUM_TASKS)
+        .task_length(TASK_LENGTH)
+        .imbalance(4.0)
+        .footprint(1 << 16);
+    finish! { workloads::spc(&workload, spawn); }
+
+    let stats = runtime.join();
+    assert_eq!(stats.num_tasks_executed.get(), NUM_TASKS);
+}
+
+#[test]
+fn spc_memory_bound() {
+    let runtime = Runtime::init(4);
+
+    let workload = Workload::new(NUM_TASKS)
+        .task_length(TASK_LENGTH)
+        .footprint(1 << 20)
+        .memory_ratio(0.75);
+    finish! { workloads::spc(&workload, spawn); }
 
     let stats = runtime.join();
     assert_eq!(stats.num_tasks_executed.get(), NUM_TASKS);
@@ -0,0 +1,17 @@
+// Helpers shared by integration tests (see `virtual_time.rs`)
+
+use rusty_tasking::runtime::Runtime;
+use rusty_tasking::stats::Stats;
+use utils::virtual_time::{Time, VirtualClock};
+
+// Run `f` in virtual time on a runtime with `num_workers` workers, and
+// return its result along with the clock and the runtime's statistics. With
+// a single worker, tasks run in an order that doesn't depend on timing, so
+// the virtual timeline is the same from run to run.
+pub fn simulate<R>(num_workers: usize, f: impl FnOnce(&Time) -> R) -> (R, VirtualClock, Stats) {
+    let runtime = Runtime::init(num_workers);
+    let clock = VirtualClock::new();
+    let result = f(&Time::Virtual(clock.clone()));
+    let stats = runtime.join();
+    (result, clock, stats)
+}
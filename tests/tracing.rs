@@ -0,0 +1,55 @@
+#![cfg(feature = "tracing")]
+
+use rusty_tasking::{finish, scoped_spawn};
+use rusty_tasking::runtime::Runtime;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use tracing::{Event, Metadata};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::{self, Subscriber};
+
+// Records the names of all spans created
+#[derive(Default)]
+struct Spans {
+    names: Mutex<Vec<&'static str>>,
+    next_id: AtomicU64,
+}
+
+struct Recorder(Arc<Spans>);
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        self.0.names.lock().unwrap().push(span.metadata().name());
+        Id::from_u64(self.0.next_id.fetch_add(1, Relaxed) + 1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+    fn event(&self, _: &Event<'_>) {}
+    fn enter(&self, _: &Id) {}
+    fn exit(&self, _: &Id) {}
+}
+
+#[test]
+fn task_and_scope_spans() {
+    let spans = Arc::new(Spans::default());
+    // Only the leader's spans are recorded (the default subscriber is set
+    // for the current thread only), so use a single worker
+    subscriber::with_default(Recorder(Arc::clone(&spans)), || {
+        let runtime = Runtime::init(1);
+        finish! {
+            for _ in 0..3 {
+                scoped_spawn!(());
+            }
+        }
+        let _stats = runtime.join();
+    });
+
+    let names = spans.names.lock().unwrap();
+    assert_eq!(names.iter().filter(|&&n| n == "scope").count(), 1);
+    assert_eq!(names.iter().filter(|&&n| n == "task").count(), 3);
+}
@@ -0,0 +1,100 @@
+extern crate utils;
+
+mod support;
+
+use rusty_tasking::{finish, scoped_spawn};
+use rusty_tasking::worker::Worker;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering::{Acquire, Release}};
+use std::time::{Duration, Instant};
+use support::simulate;
+use utils::virtual_time::{Time, VirtualClock};
+use utils::workloads::Workload;
+
+static NUM_TASKS: u32 = 100;
+// Much too long to run for real
+static TASK_LENGTH: Duration = Duration::from_secs(1);
+
+// Run the workload's tasks, recording when each of them started (in virtual
+// time)
+fn run(workload: &Workload, time: &Time) -> Vec<(u32, Duration)> {
+    let Time::Virtual(clock) = time else { unreachable!() };
+    let starts = Arc::new(Mutex::new(vec![]));
+    finish! {
+        for i in 0..workload.num_tasks {
+            let work = workload.work(i);
+            let (time, starts) = (time.clone(), Arc::clone(&starts));
+            let task = clock.timed({
+                let clock = clock.clone();
+                move || {
+                    starts.lock().unwrap().push((i, clock.now()));
+                    work.run_in(&time);
+                }
+            });
+            scoped_spawn!(task());
+        }
+    }
+    Arc::try_unwrap(starts).unwrap().into_inner().unwrap()
+}
+
+// One link of a chain of `n` tasks, each of which spawns the next when done.
+// With `hand_off`, a link waits for another worker to take the next link.
+fn chain(clock: VirtualClock, n: u32, hand_off: bool) {
+    clock.compute(TASK_LENGTH);
+    if n > 1 {
+        let started = Arc::new(AtomicBool::new(false));
+        let task = clock.timed({
+            let (clock, started) = (clock.clone(), Arc::clone(&started));
+            move || {
+                started.store(true, Release);
+                chain(clock, n - 1, hand_off)
+            }
+        });
+        scoped_spawn!(task());
+        // Busy workers only hand out tasks when they check for steal requests
+        while hand_off && !started.load(Acquire) {
+            Worker::current().try_handle_steal_request();
+        }
+    }
+}
+
+#[test]
+fn virtual_spc() {
+    let workload = Workload::new(NUM_TASKS).task_length(TASK_LENGTH).imbalance(1.0);
+    let total: Duration = (0..NUM_TASKS).map(|i| workload.work(i).length).sum();
+    let start = Instant::now();
+
+    // One worker runs the tasks newest first, each as soon as the one before
+    // is done, and does so every time
+    let (starts, clock, _) = simulate(1, |time| run(&workload, time));
+    let expected = (0..NUM_TASKS)
+        .rev()
+        .scan(Duration::ZERO, |t, i| {
+            let start = *t;
+            *t += workload.work(i).length;
+            Some((i, start))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(starts, expected);
+    assert_eq!(clock.elapsed(), total);
+    let (again, _, _) = simulate(1, |time| run(&workload, time));
+    assert_eq!(starts, again);
+
+    assert!(start.elapsed() < total / 10);
+}
+
+#[test]
+fn virtual_chain() {
+    // No link of the chain starts before the link that spawned it is done,
+    // even if every link runs on a different worker than the one before
+    for num_workers in [1, 4] {
+        let (_, clock, _) = simulate(num_workers, |time| {
+            let Time::Virtual(clock) = time else { unreachable!() };
+            let clock = clock.clone();
+            finish! { scoped_spawn!(chain(clock, NUM_TASKS, num_workers > 1)); }
+        });
+        assert_eq!(clock.num_threads() > 1, num_workers > 1);
+        assert_eq!(clock.busy(), TASK_LENGTH * NUM_TASKS);
+        assert_eq!(clock.elapsed(), TASK_LENGTH * NUM_TASKS);
+    }
+}
@@ -1,5 +1,14 @@
+use std::hint::black_box;
 use std::time::{Duration, Instant};
 
+pub mod virtual_time;
+pub mod workloads;
+
+// Size of a cache line on most current CPUs
+const CACHE_LINE: usize = 64;
+// How long `compute_mixed` computes or accesses memory at a stretch
+const MIXED_SLICE: Duration = Duration::from_micros(20);
+
 fn fib(n: u64) -> u64 {
     if n < 2 { return n; }
     let mut f = (0, 1);
@@ -15,6 +24,70 @@ pub fn compute(duration: Duration) {
     // println!("{:?}", start.elapsed());
 }
 
+// A cache line of a `WorkingSet`, holding the index of the next line to visit
+#[repr(align(64))]
+struct Line(usize);
+
+// A working set of `footprint` bytes whose cache lines are visited in a
+// random cyclic order, so that hardware prefetchers can't hide the latency
+// of going to memory once the working set exceeds the caches
+pub struct WorkingSet {
+    lines: Vec<Line>,
+    pos: usize,
+}
+
+impl WorkingSet {
+    pub fn new(footprint: usize) -> Self {
+        let n = (footprint / CACHE_LINE).max(1);
+        // Sattolo's algorithm: a random permutation that is a single cycle
+        let mut next: Vec<usize> = (0..n).collect();
+        let mut x = 0x2545_f491_4f6c_dd1d_u64;
+        for i in (1..n).rev() {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            next.swap(i, (x % i as u64) as usize);
+        }
+        Self { lines: next.into_iter().map(Line).collect(), pos: 0 }
+    }
+
+    // Size in bytes, rounded down to whole cache lines (but at least one)
+    pub fn footprint(&self) -> usize {
+        self.lines.len() * CACHE_LINE
+    }
+
+    // Chase pointers through the working set for `duration`
+    pub fn thrash(&mut self, duration: Duration) {
+        let start = Instant::now();
+        while start.elapsed() < duration {
+            for _ in 0..256 {
+                self.pos = black_box(self.lines[self.pos].0);
+            }
+        }
+    }
+}
+
+// Like `compute`, but memory-bound: access a working set of `footprint`
+// bytes for `duration`, not counting the time to set it up
+pub fn compute_memory(footprint: usize, duration: Duration) {
+    WorkingSet::new(footprint).thrash(duration);
+}
+
+// Alternate between `compute` and `compute_memory` in short slices, spending
+// `memory_ratio` of `duration` on memory accesses
+pub fn compute_mixed(footprint: usize, duration: Duration, memory_ratio: f64) {
+    assert!((0.0..=1.0).contains(&memory_ratio));
+    let mut working_set = WorkingSet::new(footprint);
+    let start = Instant::now();
+    loop {
+        let left = duration.saturating_sub(start.elapsed());
+        if left.is_zero() { break; }
+        let slice = left.min(MIXED_SLICE);
+        working_set.thrash(slice.mul_f64(memory_ratio));
+        compute(slice.mul_f64(1.0 - memory_ratio));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -27,4 +100,28 @@ mod tests {
             println!("{:?}", start.elapsed());
         }
     }
+
+    #[test]
+    fn working_set_is_one_cycle() {
+        let mut working_set = WorkingSet::new(1000 * CACHE_LINE + 10);
+        assert_eq!(working_set.footprint(), 1000 * CACHE_LINE);
+        let mut visited = vec![false; 1000];
+        for _ in 0..1000 {
+            assert!(!visited[working_set.pos]);
+            visited[working_set.pos] = true;
+            working_set.pos = working_set.lines[working_set.pos].0;
+        }
+        assert_eq!(working_set.pos, 0);
+        assert_eq!(WorkingSet::new(0).footprint(), CACHE_LINE);
+    }
+
+    #[test]
+    fn memory_bound_duration() {
+        let duration = Duration::from_millis(2);
+        for f in [|d| compute_memory(1 << 20, d), |d| compute_mixed(1 << 20, d, 0.5)] {
+            let start = Instant::now();
+            f(duration);
+            assert!(start.elapsed() >= duration);
+        }
+    }
 }
\ No newline at end of file
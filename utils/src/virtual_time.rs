@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+// Virtual time for tests
+//
+// `compute` spins for as long as it's asked to, which makes tests of
+// scheduler features that depend on task lengths slow and timing-dependent.
+// `VirtualClock::compute` returns right away instead and advances the
+// calling thread's virtual time, so that seconds of simulated work take
+// microseconds:
+//
+//     let clock = VirtualClock::new();
+//     let time = Time::Virtual(clock.clone());
+//     finish! {
+//         for i in 0..n {
+//             let (time, work) = (time.clone(), workload.work(i));
+//             let task = clock.timed(move || work.run_in(&time));
+//             scoped_spawn!(task());
+//         }
+//     }
+//     assert_eq!(clock.busy(), total);
+//
+// Every thread has a virtual time of its own, starting from zero. A task
+// must not start before it was spawned, so tasks wrapped with `timed` catch
+// up with their spawner's time first, whichever thread runs them. Together
+// with a runtime whose scheduling doesn't depend on timing (a single
+// worker), this gives reproducible timelines.
+//
+// Only the time that tasks spend in `compute` and `sleep` is virtual. The
+// runtime keeps timing itself in real time (steal backoff, idle strategies,
+// scope deadlines, retry delays), so tests of those features can't use a
+// virtual clock to run faster.
+
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    inner: Arc<Mutex<Clocks>>,
+}
+
+#[derive(Debug, Default)]
+struct Clocks {
+    threads: HashMap<ThreadId, Duration>,
+    // Total time spent in `compute`, across all threads
+    busy: Duration,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The calling thread's virtual time
+    pub fn now(&self) -> Duration {
+        let clocks = self.inner.lock().unwrap();
+        clocks.threads.get(&thread::current().id()).copied().unwrap_or_default()
+    }
+
+    // Like `utils::compute`, but in virtual time
+    pub fn compute(&self, duration: Duration) {
+        let mut clocks = self.inner.lock().unwrap();
+        *clocks.threads.entry(thread::current().id()).or_default() += duration;
+        clocks.busy += duration;
+    }
+
+    // Let the calling thread's virtual time pass without computing, for
+    // example, to simulate a backoff
+    pub fn sleep(&self, duration: Duration) {
+        let mut clocks = self.inner.lock().unwrap();
+        *clocks.threads.entry(thread::current().id()).or_default() += duration;
+    }
+
+    // Move the calling thread's virtual time forward to `time`, unless it
+    // is there already. Returns the thread's new time.
+    pub fn catch_up(&self, time: Duration) -> Duration {
+        let mut clocks = self.inner.lock().unwrap();
+        let now = clocks.threads.entry(thread::current().id()).or_default();
+        *now = (*now).max(time);
+        *now
+    }
+
+    // Wrap `f` so that it runs no earlier (in virtual time) than now, on
+    // whichever thread it ends up running, see `catch_up`
+    pub fn timed<R>(&self, f: impl FnOnce() -> R) -> impl FnOnce() -> R {
+        let (clock, spawned) = (self.clone(), self.now());
+        move || {
+            clock.catch_up(spawned);
+            f()
+        }
+    }
+
+    // Latest virtual time of any thread, that is, the makespan of whatever
+    // ran on this clock
+    pub fn elapsed(&self) -> Duration {
+        self.inner.lock().unwrap().threads.values().copied().max().unwrap_or_default()
+    }
+
+    // Total virtual time spent computing, across all threads
+    pub fn busy(&self) -> Duration {
+        self.inner.lock().unwrap().busy
+    }
+
+    // Number of threads that have used this clock
+    pub fn num_threads(&self) -> usize {
+        self.inner.lock().unwrap().threads.len()
+    }
+}
+
+// Real or virtual time, so that the same test code can run either way
+#[derive(Debug, Clone, Default)]
+pub enum Time {
+    #[default]
+    Real,
+    Virtual(VirtualClock),
+}
+
+impl Time {
+    pub fn compute(&self, duration: Duration) {
+        match self {
+            Self::Real => crate::compute(duration),
+            Self::Virtual(clock) => clock.compute(duration),
+        }
+    }
+
+    pub fn sleep(&self, duration: Duration) {
+        match self {
+            Self::Real => thread::sleep(duration),
+            Self::Virtual(clock) => clock.sleep(duration),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn virtual_compute() {
+        let clock = VirtualClock::new();
+        let start = Instant::now();
+        clock.compute(Duration::from_secs(10));
+        clock.sleep(Duration::from_secs(1));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(clock.now(), Duration::from_secs(11));
+        assert_eq!(clock.busy(), Duration::from_secs(10));
+
+        let other = {
+            let clock = clock.clone();
+            thread::spawn(move || {
+                assert_eq!(clock.now(), Duration::ZERO);
+                clock.catch_up(Duration::from_secs(5));
+                clock.compute(Duration::from_secs(10));
+                clock.now()
+            })
+        };
+        assert_eq!(other.join().unwrap(), Duration::from_secs(15));
+        assert_eq!(clock.elapsed(), Duration::from_secs(15));
+        assert_eq!(clock.busy(), Duration::from_secs(20));
+        assert_eq!(clock.num_threads(), 2);
+        // Catching up never goes back in time
+        assert_eq!(clock.catch_up(Duration::ZERO), Duration::from_secs(11));
+
+        // A timed closure starts at the time it was created
+        let f = clock.timed(|| ());
+        let other = {
+            let clock = clock.clone();
+            thread::spawn(move || {
+                f();
+                clock.now()
+            })
+        };
+        assert_eq!(other.join().unwrap(), Duration::from_secs(11));
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{compute, compute_mixed};
+use crate::virtual_time::Time;
+use std::hint::black_box;
+use std::time::Duration;
+
+// Synthetic workloads for stress-testing a runtime configuration
+//
+// Generators don't depend on any particular runtime: they hand out jobs to a
+// `spawn` function, which is expected to run each job as a separate task.
+// With rusty-tasking, for example:
+//
+//     fn spawn(job: Job) {
+//         scoped_spawn!(job());
+//     }
+//
+//     finish! { workloads::spc(&Workload::new(100), spawn); }
+
+pub type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Workload {
+    // Total number of tasks, including producers
+    pub num_tasks: u32,
+    // Duration of the shortest task
+    pub task_length: Duration,
+    // Task `i` runs `1 + imbalance * i / (num_tasks - 1)` times as long as
+    // the shortest task, so zero means all tasks take equally long
+    pub imbalance: f64,
+    // Number of bytes each task allocates and touches
+    pub footprint: usize,
+    // Fraction of each task's run time spent accessing its footprint rather
+    // than computing, see `compute_mixed`
+    pub memory_ratio: f64,
+}
+
+impl Workload {
+    pub fn new(num_tasks: u32) -> Self {
+        Self {
+            num_tasks,
+            task_length: Duration::from_micros(10),
+            imbalance: 0.0,
+            footprint: 0,
+            memory_ratio: 0.0,
+        }
+    }
+
+    pub fn task_length(mut self, task_length: Duration) -> Self {
+        self.task_length = task_length;
+        self
+    }
+
+    pub fn imbalance(mut self, imbalance: f64) -> Self {
+        assert!(imbalance >= 0.0);
+        self.imbalance = imbalance;
+        self
+    }
+
+    pub fn footprint(mut self, footprint: usize) -> Self {
+        self.footprint = footprint;
+        self
+    }
+
+    pub fn memory_ratio(mut self, memory_ratio: f64) -> Self {
+        assert!((0.0..=1.0).contains(&memory_ratio));
+        self.memory_ratio = memory_ratio;
+        self
+    }
+
+    // The work done by task `i`
+    pub fn work(&self, i: u32) -> Work {
+        let scale = match self.num_tasks {
+            0 | 1 => 1.0,
+            n => 1.0 + self.imbalance * i as f64 / (n - 1) as f64,
+        };
+        Work {
+            length: self.task_length.mul_f64(scale),
+            footprint: self.footprint,
+            memory_ratio: self.memory_ratio,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Work {
+    pub length: Duration,
+    pub footprint: usize,
+    pub memory_ratio: f64,
+}
+
+impl Work {
+    pub fn run(&self) {
+        if self.footprint > 0 && self.memory_ratio > 0.0 {
+            return compute_mixed(self.footprint, self.length, self.memory_ratio);
+        }
+        if self.footprint > 0 {
+            let mut memory = vec![0u8; self.footprint];
+            // Touch every page
+            for byte in memory.iter_mut().step_by(4096) {
+                *byte = 1;
+            }
+            black_box(&memory);
+        }
+        compute(self.length);
+    }
+
+    // Like `run`, but in virtual time, if so desired, see `virtual_time`.
+    // Virtual work doesn't touch its footprint.
+    pub fn run_in(&self, time: &Time) {
+        match time {
+            Time::Real => self.run(),
+            Time::Virtual(clock) => clock.compute(self.length),
+        }
+    }
+}
+
+// Single Producer Consumer: one producer spawns all tasks
+pub fn spc<S>(workload: &Workload, spawn: S)
+where S: Fn(Job) {
+    for i in 0..workload.num_tasks {
+        let work = workload.work(i);
+        spawn(Box::new(move || work.run()));
+    }
+}
+
+// Bouncing Producer Consumer: every producer spawns the next producer,
+// followed by a share of the tasks, over `levels` levels
+pub fn bpc<S>(workload: &Workload, levels: u32, spawn: S)
+where S: Fn(Job) + Copy + Send + 'static {
+    assert!(levels > 0 && workload.num_tasks >= levels);
+    produce(*workload, levels, levels, spawn);
+}
+
+fn produce<S>(workload: Workload, levels: u32, level: u32, spawn: S)
+where S: Fn(Job) + Copy + Send + 'static {
+    if level == 0 { return; }
+    spawn(Box::new(move || produce(workload, levels, level - 1, spawn)));
+    // Producer tasks count towards the total
+    let per_level = workload.num_tasks / levels - 1;
+    let first = (levels - level) * per_level;
+    for i in first..first + per_level {
+        let work = workload.work(i);
+        spawn(Box::new(move || work.run()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+    use super::*;
+
+    static NUM_JOBS: AtomicU32 = AtomicU32::new(0);
+
+    // Run jobs right away
+    fn run(job: Job) {
+        NUM_JOBS.fetch_add(1, Relaxed);
+        job();
+    }
+
+    #[test]
+    fn imbalance() {
+        let w = Workload::new(11).task_length(Duration::from_micros(10)).imbalance(1.0);
+        assert_eq!(w.work(0).length, Duration::from_micros(10));
+        assert_eq!(w.work(5).length, Duration::from_micros(15));
+        assert_eq!(w.work(10).length, Duration::from_micros(20));
+    }
+
+    #[test]
+    fn generators() {
+        let w = Workload::new(100).task_length(Duration::ZERO).footprint(1 << 16);
+        spc(&w, run);
+        bpc(&w, 10, run);
+        assert_eq!(NUM_JOBS.load(Relaxed), 200);
+    }
+}